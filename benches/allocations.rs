@@ -0,0 +1,111 @@
+// Counts the heap allocations made while the game is played by a bot, to catch per-turn and
+// per-frame temporary buffers creeping back in. Run with `cargo bench --bench allocations`.
+use chargrid_roguelike_tutorial_2020::{
+    ActionResult, Bot, Challenges, DungeonConfig, GameState, Observation, PlayerAction, SimpleBot,
+    VisibilityAlgorithm,
+};
+use coord_2d::Size;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const NUM_GAMES: u64 = 20;
+const MAX_ACTIONS: u64 = 2000;
+
+// Counts each allocation and reallocation before passing it on to the system allocator
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Returns the result of `f` along with the number of allocations it made
+fn count_allocations<T, F: FnOnce() -> T>(f: F) -> (T, u64) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let result = f();
+    (result, ALLOCATIONS.load(Ordering::Relaxed) - before)
+}
+
+#[derive(Default)]
+struct Counts {
+    turns: u64,
+    turn_allocations: u64,
+    animation_ticks: u64,
+    animation_allocations: u64,
+    visibility_updates: u64,
+    visibility_allocations: u64,
+}
+
+fn per(allocations: u64, count: u64) -> f64 {
+    allocations as f64 / count.max(1) as f64
+}
+
+fn play(rng_seed: u64, counts: &mut Counts) {
+    let mut game_state = GameState::new(
+        Size::new(40, 24),
+        rng_seed,
+        VisibilityAlgorithm::Shadowcast,
+        false,
+        DungeonConfig::default(),
+        Challenges::default(),
+    );
+    let mut bot = SimpleBot;
+    for _ in 0..MAX_ACTIONS {
+        if !game_state.is_player_alive() {
+            break;
+        }
+        let action = bot.act(&Observation::new(&game_state));
+        let (_, allocations) = count_allocations(|| {
+            if game_state.handle_action(action) != ActionResult::Done {
+                game_state.handle_action(PlayerAction::Wait);
+            }
+        });
+        counts.turns += 1;
+        counts.turn_allocations += allocations;
+        let first_frame = game_state.animation_frame();
+        let (_, allocations) = count_allocations(|| game_state.resolve_animations());
+        counts.animation_ticks += game_state.animation_frame().wrapping_sub(first_frame);
+        counts.animation_allocations += allocations;
+        let (_, allocations) =
+            count_allocations(|| game_state.update_visibility(VisibilityAlgorithm::Shadowcast));
+        counts.visibility_updates += 1;
+        counts.visibility_allocations += allocations;
+    }
+}
+
+fn main() {
+    let mut counts = Counts::default();
+    for rng_seed in 0..NUM_GAMES {
+        play(rng_seed, &mut counts);
+    }
+    println!(
+        "{} turns, {} animation ticks over {} games",
+        counts.turns, counts.animation_ticks, NUM_GAMES
+    );
+    println!(
+        "allocations per turn:              {:.2}",
+        per(counts.turn_allocations, counts.turns)
+    );
+    println!(
+        "allocations per animation tick:    {:.2}",
+        per(counts.animation_allocations, counts.animation_ticks)
+    );
+    println!(
+        "allocations per visibility update: {:.2}",
+        per(counts.visibility_allocations, counts.visibility_updates)
+    );
+}
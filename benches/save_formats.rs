@@ -0,0 +1,66 @@
+// Compares the size of json and binary saves, and how long each takes to write and load. Saves
+// are compressed when they're stored, so the sizes on disk are smaller than those reported here.
+// Run with `cargo bench --bench save_formats`.
+use chargrid_roguelike_tutorial_2020::{
+    save, Bot, Challenges, DungeonConfig, GameState, Observation, SimpleBot, VisibilityAlgorithm,
+};
+use coord_2d::Size;
+use std::time::{Duration, Instant};
+
+const NUM_REPETITIONS: u32 = 20;
+
+// A game some way into its first few levels, so that there's a realistic amount of explored map,
+// message log and inventory to save
+fn game_in_progress() -> GameState {
+    let mut game_state = GameState::new(
+        Size::new(40, 24),
+        0,
+        VisibilityAlgorithm::Shadowcast,
+        false,
+        DungeonConfig::default(),
+        Challenges::default(),
+    );
+    let mut bot = SimpleBot;
+    for _ in 0..1000 {
+        if !game_state.is_player_alive() {
+            break;
+        }
+        let action = bot.act(&Observation::new(&game_state));
+        game_state.handle_action(action);
+        game_state.resolve_animations();
+        game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+    }
+    game_state
+}
+
+// The mean time taken by `f` over `NUM_REPETITIONS` calls
+fn time<T, F: FnMut() -> T>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..NUM_REPETITIONS {
+        f();
+    }
+    start.elapsed() / NUM_REPETITIONS
+}
+
+fn main() {
+    let game_state = game_in_progress();
+    let json = serde_json::to_vec(&save::to_json(&game_state)).unwrap();
+    let binary = bincode::serialize(&save::to_binary(&game_state)).unwrap();
+    let json_write = time(|| serde_json::to_vec(&save::to_json(&game_state)).unwrap());
+    let binary_write = time(|| bincode::serialize(&save::to_binary(&game_state)).unwrap());
+    let json_load = time(|| save::from_json(serde_json::from_slice(&json).unwrap()).unwrap());
+    let binary_load = time(|| save::from_binary(bincode::deserialize(&binary).unwrap()).unwrap());
+    println!("format  size (bytes)  write      load");
+    println!(
+        "json    {:>12}  {:>9.2?}  {:>9.2?}",
+        json.len(),
+        json_write,
+        json_load
+    );
+    println!(
+        "binary  {:>12}  {:>9.2?}  {:>9.2?}",
+        binary.len(),
+        binary_write,
+        binary_load
+    );
+}
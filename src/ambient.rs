@@ -0,0 +1,45 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum AmbientEvent {
+    Tremor,
+    MonsterMigration,
+    ItemsTeleport,
+}
+
+// The number of turns between consecutive ambient events is chosen uniformly from this range
+const MIN_TURNS_BETWEEN_EVENTS: u32 = 200;
+const MAX_TURNS_BETWEEN_EVENTS: u32 = 400;
+
+#[derive(Serialize, Deserialize)]
+pub struct AmbientEventScheduler {
+    turns_until_next_event: u32,
+}
+
+fn choose_turns_until_next_event<R: Rng>(rng: &mut R) -> u32 {
+    rng.gen_range(MIN_TURNS_BETWEEN_EVENTS..=MAX_TURNS_BETWEEN_EVENTS)
+}
+
+impl AmbientEventScheduler {
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        Self {
+            turns_until_next_event: choose_turns_until_next_event(rng),
+        }
+    }
+
+    // Called once per turn. Returns the event that should happen this turn, if any.
+    pub fn tick<R: Rng>(&mut self, rng: &mut R) -> Option<AmbientEvent> {
+        if let Some(turns_until_next_event) = self.turns_until_next_event.checked_sub(1) {
+            self.turns_until_next_event = turns_until_next_event;
+            return None;
+        }
+        self.turns_until_next_event = choose_turns_until_next_event(rng);
+        let event = match rng.gen_range(0..3) {
+            0 => AmbientEvent::Tremor,
+            1 => AmbientEvent::MonsterMigration,
+            _ => AmbientEvent::ItemsTeleport,
+        };
+        Some(event)
+    }
+}
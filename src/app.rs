@@ -1,11 +1,21 @@
-use crate::game::{GameState, LevelUp};
-use crate::ui::{StatsData, UiData, UiView};
-use crate::visibility::{CellVisibility, VisibilityAlgorithm};
-use crate::world::{ItemType, ItemUsage, Layer, NpcType, ProjectileType, Tile};
+use crate::bug_report::BugReport;
+use crate::controls::{key_name, ControlAction};
+use crate::daily::DailyChallenge;
+use crate::hint::{Hint, Profile};
+use crate::options::{OptionOverrides, Options, Palette};
+use crate::rescue;
+#[cfg(feature = "spectate")]
+use crate::spectate::Spectate;
+use crate::sync::SaveSync;
+use crate::ui::{
+    self, EnemyListEntry, EnemyListView, ExamineStack, HotbarSlot, Layout, MessageHistory,
+    MessageHistoryView, StatsData, UiData, UiView,
+};
 use chargrid::{
     app::App as ChargridApp,
     decorator::{
-        AlignView, Alignment, BorderStyle, BorderView, BoundView, FillBackgroundView, MinSizeView,
+        AlignView, Alignment, AlignmentX, AlignmentY, BorderStyle, BorderView, BoundView,
+        FillBackgroundView, MinSizeView,
     },
     event_routine::{
         self,
@@ -15,26 +25,201 @@ use chargrid::{
     },
     input::{keys, Input, KeyboardInput, MouseButton, MouseInput},
     menu::{
-        self, ChooseSelector, MenuIndexFromScreenCoord, MenuInstanceBuilder, MenuInstanceChoose,
-        MenuInstanceChooseOrEscape, MenuInstanceMouseTracker, MenuInstanceRoutine,
+        self, ChooseSelector, MenuIndexFromScreenCoord, MenuInstance, MenuInstanceBuilder,
+        MenuInstanceChoose, MenuInstanceChooseOrEscape, MenuInstanceMouseTracker,
+        MenuInstanceRoutine,
     },
     render::{blend_mode, ColModify, ColModifyMap, Frame, Style, View, ViewCell, ViewContext},
-    text::{RichTextPart, RichTextViewSingleLine, StringViewSingleLine},
+    text::{wrap, RichTextPart, RichTextViewSingleLine, StringView, StringViewSingleLine},
+};
+use chargrid_roguelike_tutorial_2020::{
+    save::{self, SaveFormat},
+    ActionResult, CellVisibility, Challenges, ContextAction, DungeonConfig, ExamineCell, GameState,
+    HitPoints, ItemCondition, ItemType, Layer, LevelUp, LogEntry, LogMessage, Npc, NpcType,
+    PlayerAction, ProjectileType, Task, Tile, TimeOfDay, Tint, Trace, VisibilityAlgorithm, Weather,
+    MOTION_TRAIL_FRAMES, NUM_HOTBAR_SLOTS,
 };
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
+use entity_table::Entity;
 use general_storage_file::{format, FileStorage, IfDirectoryMissing, Storage};
 use maplit::hashmap;
 use rgb24::Rgb24;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::Duration;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-const UI_NUM_ROWS: u32 = 5;
 const BETWEEN_ANIMATION_TICKS: Duration = Duration::from_millis(33);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnimationSpeed {
+    Normal,
+    Fast,
+    // Projectiles and fading effects resolve within a single frame
+    Instant,
+}
+
+impl AnimationSpeed {
+    fn between_animation_ticks(self) -> Duration {
+        match self {
+            Self::Normal => BETWEEN_ANIMATION_TICKS,
+            Self::Fast | Self::Instant => BETWEEN_ANIMATION_TICKS / 3,
+        }
+    }
+}
+
+// Holding a key makes the operating system repeat its press at its own rate. This limits how
+// often repeated presses of a movement key are acted on, so holding an arrow key walks the player
+// at a steady pace. Chargrid doesn't report key releases, so a held key is recognised by the same
+// key being pressed repeatedly.
+struct KeyRepeat {
+    // Minimum time between the first press of a key and the first repeat that's acted on
+    delay: Duration,
+    // Minimum time between subsequent repeats that are acted on
+    interval: Duration,
+    last_key: Option<KeyboardInput>,
+    since_last_accepted: Duration,
+    num_repeats: u32,
+}
+
+impl KeyRepeat {
+    fn new(delay: Duration, interval: Duration) -> Self {
+        Self {
+            delay,
+            interval,
+            last_key: None,
+            since_last_accepted: Duration::from_millis(0),
+            num_repeats: 0,
+        }
+    }
+    fn frame(&mut self, period: Duration) {
+        self.since_last_accepted += period;
+    }
+    // Returns false if a press of `key` should be ignored because it came too soon after the last
+    // press of the same movement key
+    fn accept(&mut self, key: KeyboardInput, is_movement_key: bool) -> bool {
+        if is_movement_key && self.last_key == Some(key) {
+            let min_gap = if self.num_repeats == 0 {
+                self.delay
+            } else {
+                self.interval
+            };
+            if self.since_last_accepted < min_gap {
+                return false;
+            }
+            self.num_repeats += 1;
+        } else {
+            self.num_repeats = 0;
+        }
+        self.last_key = Some(key);
+        self.since_last_accepted = Duration::from_millis(0);
+        true
+    }
+}
+
+// Converts the time elapsed between frames into a whole number of animation ticks, carrying the
+// remainder over to the next frame. This keeps animations running at the same speed regardless of
+// the frame rate: slow frames run several ticks and fast frames may run none.
+#[derive(Default)]
+struct AnimationTimer {
+    accumulated: Duration,
+}
+
+impl AnimationTimer {
+    // After a long stall (e.g. the window being dragged), skip ahead rather than replaying every
+    // missed tick at once
+    const MAX_TICKS_PER_FRAME: u32 = 8;
+
+    fn ticks_for_frame(&mut self, period: Duration, between_ticks: Duration) -> u32 {
+        self.accumulated += period;
+        let mut ticks = 0;
+        while self.accumulated >= between_ticks {
+            self.accumulated -= between_ticks;
+            ticks += 1;
+            if ticks == Self::MAX_TICKS_PER_FRAME {
+                self.accumulated = Duration::from_millis(0);
+                break;
+            }
+        }
+        ticks
+    }
+}
+
 const SAVE_DIR: &str = "save";
 const SAVE_FILE: &str = "save";
-const SAVE_FORMAT: format::Compress<format::Json> = format::Compress(format::Json);
+// Where the panic hook saves the game in progress
+const RESCUE_FILE: &str = "rescue";
+const BINARY_SAVE_FILE: &str = "save-binary";
+const PROFILE_FILE: &str = "profile";
+const PROFILE_FORMAT: format::Json = format::Json;
+const JSON_SAVE_FORMAT: format::Compress<format::Json> = format::Compress(format::Json);
+const BINARY_SAVE_FORMAT: format::Compress<format::Bincode> = format::Compress(format::Bincode);
+// The format games are saved in. A save in either format is loaded, so changing this doesn't lose
+// an existing saved game.
+const SAVE_FORMAT: SaveFormat = SaveFormat::Json;
+// How many of the most recent messages are written to the morgue file
+const MORGUE_NUM_MESSAGES: usize = 20;
+
+// The file saves in `format` are written to, followed by the file for the other format
+fn save_files(format: SaveFormat) -> (&'static str, &'static str) {
+    match format {
+        SaveFormat::Json => (SAVE_FILE, BINARY_SAVE_FILE),
+        SaveFormat::Binary => (BINARY_SAVE_FILE, SAVE_FILE),
+    }
+}
+
+fn save_format_of_file(file: &str) -> SaveFormat {
+    if file == BINARY_SAVE_FILE {
+        SaveFormat::Binary
+    } else {
+        SaveFormat::Json
+    }
+}
+
+// Fetches copies of the save and profile from the sync location which are newer than the local ones
+fn pull_synced_files(save_sync: &SaveSync) {
+    let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
+        Ok(file_storage) => file_storage,
+        Err(error) => {
+            eprintln!("Failed to sync saves: {:?}", error);
+            return;
+        }
+    };
+    for &file in &[SAVE_FILE, BINARY_SAVE_FILE, PROFILE_FILE] {
+        save_sync.pull(&file_storage, file);
+    }
+}
+
+// Saves a game rescued after a crash, in a file of its own so that a game which can't be loaded
+// doesn't replace the regular save. Rescued games are saved as json, which is more likely than
+// bincode to still load after the code which crashed has been changed.
+pub fn store_rescued_game(game_state: &GameState) -> Result<PathBuf, String> {
+    let mut file_storage = FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        .map_err(|error| format!("{:?}", error))?;
+    file_storage
+        .store(RESCUE_FILE, &save::to_json(game_state), JSON_SAVE_FORMAT)
+        .map_err(|error| format!("{:?}", error))?;
+    Ok(file_storage.full_path(RESCUE_FILE))
+}
+
+// Writes the saved game to `path` as indented json, whichever format it was saved in, so that it
+// can be inspected
+pub fn export_save(path: &str) -> io::Result<()> {
+    let game_state = AppData::load_game()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no saved game could be loaded"))?;
+    let json = serde_json::to_string_pretty(&save::to_json(&game_state))?;
+    fs::write(path, json)
+}
+
+pub fn dump_world(path: &str) -> io::Result<()> {
+    let game_state = AppData::load_game()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no saved game could be loaded"))?;
+    fs::write(path, game_state.debug_dump())
+}
 
 fn level_up_menu_instance() -> MenuInstanceChooseOrEscape<LevelUp> {
     use LevelUp::*;
@@ -51,6 +236,8 @@ fn level_up_menu_instance() -> MenuInstanceChooseOrEscape<LevelUp> {
 #[derive(Default)]
 struct LevelUpMenuView {
     mouse_tracker: MenuInstanceMouseTracker,
+    // Each entry's text is written here rather than into a new string every frame
+    buf: String,
 }
 
 impl MenuIndexFromScreenCoord for LevelUpMenuView {
@@ -84,8 +271,10 @@ impl<'a> View<&'a AppData> for LevelUpMenuView {
                 LevelUp::Intelligence => "Intelligence",
                 LevelUp::Health => "Constitution",
             };
+            self.buf.clear();
+            write!(&mut self.buf, "{} {}", prefix, text).unwrap();
             let size = StringViewSingleLine::new(style).view_size(
-                format!("{} {}", prefix, text),
+                &self.buf,
                 context.add_offset(Coord::new(0, i as i32)),
                 frame,
             );
@@ -184,18 +373,247 @@ fn level_up_menu() -> impl EventRoutine<
         .decorated(LevelUpMenuDecorate)
 }
 
+fn context_action_menu_instance(
+    actions: Vec<ContextAction>,
+) -> MenuInstanceChooseOrEscape<ContextAction> {
+    let hotkeys = actions
+        .iter()
+        .cloned()
+        .zip('a'..)
+        .map(|(action, key)| (key, action))
+        .collect::<HashMap<_, _>>();
+    MenuInstanceBuilder {
+        items: actions,
+        hotkeys: Some(hotkeys),
+        selected_index: 0,
+    }
+    .build()
+    .unwrap()
+    .into_choose_or_escape()
+}
+
+fn direction_name(direction: CardinalDirection) -> &'static str {
+    match direction {
+        CardinalDirection::North => "north",
+        CardinalDirection::East => "east",
+        CardinalDirection::South => "south",
+        CardinalDirection::West => "west",
+    }
+}
+
+fn write_context_action_text(buf: &mut String, action: ContextAction) {
+    match action {
+        ContextAction::GetItem(item_type) => write!(buf, "Pick up {}", item_type.name()).unwrap(),
+        ContextAction::Descend => buf.push_str("Descend stairs"),
+        ContextAction::Ascend => buf.push_str("Climb up stairs"),
+        ContextAction::Pray => buf.push_str("Pray at altar"),
+        ContextAction::Drink => buf.push_str("Drink from fountain"),
+        ContextAction::Attack(npc, direction) => write!(
+            buf,
+            "Attack {} to the {}",
+            npc.name(),
+            direction_name(direction)
+        )
+        .unwrap(),
+        ContextAction::Kick(npc, direction) => write!(
+            buf,
+            "Kick {} to the {}",
+            npc.name(),
+            direction_name(direction)
+        )
+        .unwrap(),
+        ContextAction::Wait => buf.push_str("Wait"),
+    }
+}
+
+#[derive(Default)]
+struct ContextActionMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+    buf: String,
+}
+
+impl MenuIndexFromScreenCoord for ContextActionMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+    }
+}
+
+impl<'a> View<&'a AppData> for ContextActionMenuView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        self.mouse_tracker.new_frame(context.offset);
+        for ((i, &action, maybe_selected), key) in data
+            .context_action_menu
+            .menu_instance()
+            .enumerate()
+            .zip('a'..)
+        {
+            let (prefix, style) = if maybe_selected.is_some() {
+                (
+                    ">",
+                    Style::new()
+                        .with_foreground(Rgb24::new_grey(255))
+                        .with_bold(true),
+                )
+            } else {
+                (" ", Style::new().with_foreground(Rgb24::new_grey(187)))
+            };
+            self.buf.clear();
+            write!(&mut self.buf, "{} {}) ", prefix, key).unwrap();
+            write_context_action_text(&mut self.buf, action);
+            let size = StringViewSingleLine::new(style).view_size(
+                &self.buf,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+            self.mouse_tracker.on_entry_view_size(size);
+        }
+    }
+}
+
+struct ContextActionMenuSelect;
+
+impl ChooseSelector for ContextActionMenuSelect {
+    type ChooseOutput = MenuInstanceChooseOrEscape<ContextAction>;
+    fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
+        &mut input.context_action_menu
+    }
+}
+
+impl DataSelector for ContextActionMenuSelect {
+    type DataInput = AppData;
+    type DataOutput = AppData;
+    fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
+        input
+    }
+    fn data_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::DataOutput {
+        input
+    }
+}
+
+impl ViewSelector for ContextActionMenuSelect {
+    type ViewInput = AppView;
+    type ViewOutput = ContextActionMenuView;
+    fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
+        &input.context_action_menu_view
+    }
+    fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
+        &mut input.context_action_menu_view
+    }
+}
+
+struct ContextActionMenuDecorate;
+
+impl Decorate for ContextActionMenuDecorate {
+    type View = AppView;
+    type Data = AppData;
+    fn view<E, F, C>(
+        &self,
+        data: &Self::Data,
+        mut event_routine_view: EventRoutineView<E>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        E: EventRoutine<Data = Self::Data, View = Self::View>,
+        F: Frame,
+        C: ColModify,
+    {
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle {
+                            title: Some("Actions".to_string()),
+                            title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                            ..Default::default()
+                        },
+                        view: MinSizeView {
+                            size: Size::new(12, 0),
+                            view: &mut event_routine_view,
+                        },
+                    },
+                },
+            },
+        }
+        .view(data, context.add_depth(10), frame);
+        event_routine_view.view.game_view.view(
+            &data.game_state,
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        event_routine_view
+            .view
+            .render_ui(None, &data, context, frame);
+    }
+}
+
+// Lists the actions available to the player where they stand, rebuilding the list each time it's
+// opened
+fn context_action_menu() -> impl EventRoutine<
+    Return = Result<ContextAction, menu::Escape>,
+    Data = AppData,
+    View = AppView,
+    Event = CommonEvent,
+> {
+    SideEffectThen::new_with_view(|data: &mut AppData, _: &_| {
+        data.context_action_menu =
+            context_action_menu_instance(data.game_state.player_context_actions());
+        MenuInstanceRoutine::new(ContextActionMenuSelect)
+            .convert_input_to_common_event()
+            .decorated(ContextActionMenuDecorate)
+    })
+}
+
 #[derive(Clone, Copy, Debug)]
 enum MainMenuEntry {
     NewGame,
+    NewPeacefulGame,
     Resume,
+    Save,
     SaveAndQuit,
+    SpawnNpc,
+    SpawnItem,
+    Options,
 }
 
-fn main_menu_instance() -> MenuInstanceChooseOrEscape<MainMenuEntry> {
+// The arena is never saved, so offers spawning in place of saving
+fn main_menu_instance(arena: bool) -> MenuInstanceChooseOrEscape<MainMenuEntry> {
     use MainMenuEntry::*;
+    let (items, hotkeys) = if arena {
+        (
+            vec![Resume, SpawnNpc, SpawnItem, NewGame, Options, SaveAndQuit],
+            hashmap![
+                'r' => Resume,
+                'm' => SpawnNpc,
+                'i' => SpawnItem,
+                'n' => NewGame,
+                'o' => Options,
+                'q' => SaveAndQuit,
+            ],
+        )
+    } else {
+        (
+            vec![Resume, NewGame, NewPeacefulGame, Save, Options, SaveAndQuit],
+            hashmap![
+                'r' => Resume,
+                'n' => NewGame,
+                'p' => NewPeacefulGame,
+                's' => Save,
+                'o' => Options,
+                'q' => SaveAndQuit,
+            ],
+        )
+    };
     MenuInstanceBuilder {
-        items: vec![Resume, NewGame, SaveAndQuit],
-        hotkeys: Some(hashmap!['r' => Resume, 'n' => NewGame, 'q' => SaveAndQuit]),
+        items,
+        hotkeys: Some(hotkeys),
         selected_index: 0,
     }
     .build()
@@ -206,6 +624,7 @@ fn main_menu_instance() -> MenuInstanceChooseOrEscape<MainMenuEntry> {
 #[derive(Default)]
 struct MainMenuView {
     mouse_tracker: MenuInstanceMouseTracker,
+    buf: String,
 }
 
 impl MenuIndexFromScreenCoord for MainMenuView {
@@ -236,10 +655,18 @@ impl<'a> View<&'a AppData> for MainMenuView {
             let text = match entry {
                 MainMenuEntry::Resume => "(r) Resume",
                 MainMenuEntry::NewGame => "(n) New Game",
+                MainMenuEntry::NewPeacefulGame => "(p) New Peaceful Game",
+                MainMenuEntry::Save => "(s) Save",
+                MainMenuEntry::SaveAndQuit if data.game_state.is_arena() => "(q) Quit",
                 MainMenuEntry::SaveAndQuit => "(q) Save and Quit",
+                MainMenuEntry::SpawnNpc => "(m) Spawn Monster",
+                MainMenuEntry::SpawnItem => "(i) Spawn Item",
+                MainMenuEntry::Options => "(o) Options",
             };
+            self.buf.clear();
+            write!(&mut self.buf, "{} {}", prefix, text).unwrap();
             let size = StringViewSingleLine::new(style).view_size(
-                format!("{} {}", prefix, text),
+                &self.buf,
                 context.add_offset(Coord::new(0, i as i32)),
                 frame,
             );
@@ -333,27 +760,130 @@ fn main_menu() -> impl EventRoutine<
     View = AppView,
     Event = CommonEvent,
 > {
-    MenuInstanceRoutine::new(MainMenuSelect)
-        .convert_input_to_common_event()
-        .decorated(MainMenuDecorate)
+    SideEffectThen::new_with_view(|data: &mut AppData, _: &_| {
+        data.main_menu = main_menu_instance(data.game_state.is_arena());
+        MenuInstanceRoutine::new(MainMenuSelect)
+            .convert_input_to_common_event()
+            .decorated(MainMenuDecorate)
+    })
 }
 
+// Something which can be placed next to the player in the arena
 #[derive(Clone, Copy, Debug)]
-struct InventorySlotMenuEntry {
-    index: usize,
-    key: char,
+enum ArenaSpawn {
+    Npc(Npc),
+    Item(ItemType),
 }
 
-struct InventorySlotMenuSelect;
+impl ArenaSpawn {
+    // Each type of npc, followed by its elite version
+    fn all_npcs() -> Vec<Self> {
+        NpcType::ALL
+            .iter()
+            .flat_map(|&npc_type| {
+                vec![
+                    Self::Npc(Npc {
+                        npc_type,
+                        elite: false,
+                    }),
+                    Self::Npc(Npc {
+                        npc_type,
+                        elite: true,
+                    }),
+                ]
+            })
+            .collect()
+    }
+    fn all_items() -> Vec<Self> {
+        ItemType::ALL
+            .iter()
+            .map(|&item_type| Self::Item(item_type))
+            .collect()
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Self::Npc(npc) => npc.name(),
+            Self::Item(item_type) => item_type.name(),
+        }
+    }
+    fn colour(self) -> Rgb24 {
+        match self {
+            Self::Npc(npc) => colours::npc_colour(npc),
+            Self::Item(item_type) => colours::item_colour(item_type),
+        }
+    }
+}
 
-impl ChooseSelector for InventorySlotMenuSelect {
-    type ChooseOutput = MenuInstanceChooseOrEscape<InventorySlotMenuEntry>;
+fn arena_spawn_menu_instance(spawns: Vec<ArenaSpawn>) -> MenuInstanceChooseOrEscape<ArenaSpawn> {
+    let hotkeys = spawns
+        .iter()
+        .cloned()
+        .zip('a'..)
+        .map(|(spawn, key)| (key, spawn))
+        .collect::<HashMap<_, _>>();
+    MenuInstanceBuilder {
+        items: spawns,
+        hotkeys: Some(hotkeys),
+        selected_index: 0,
+    }
+    .build()
+    .unwrap()
+    .into_choose_or_escape()
+}
+
+#[derive(Default)]
+struct ArenaSpawnMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+    buf: String,
+}
+
+impl MenuIndexFromScreenCoord for ArenaSpawnMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+    }
+}
+
+impl<'a> View<&'a AppData> for ArenaSpawnMenuView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        self.mouse_tracker.new_frame(context.offset);
+        for ((i, &spawn, maybe_selected), key) in
+            data.arena_spawn_menu.menu_instance().enumerate().zip('a'..)
+        {
+            let (prefix, style) = if maybe_selected.is_some() {
+                (
+                    ">",
+                    Style::new().with_foreground(spawn.colour()).with_bold(true),
+                )
+            } else {
+                (" ", Style::new().with_foreground(spawn.colour()))
+            };
+            self.buf.clear();
+            write!(&mut self.buf, "{} {}) {}", prefix, key, spawn.name()).unwrap();
+            let size = StringViewSingleLine::new(style).view_size(
+                &self.buf,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+            self.mouse_tracker.on_entry_view_size(size);
+        }
+    }
+}
+
+struct ArenaSpawnMenuSelect;
+
+impl ChooseSelector for ArenaSpawnMenuSelect {
+    type ChooseOutput = MenuInstanceChooseOrEscape<ArenaSpawn>;
     fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
-        &mut input.inventory_slot_menu
+        &mut input.arena_spawn_menu
     }
 }
 
-impl DataSelector for InventorySlotMenuSelect {
+impl DataSelector for ArenaSpawnMenuSelect {
     type DataInput = AppData;
     type DataOutput = AppData;
     fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
@@ -364,22 +894,22 @@ impl DataSelector for InventorySlotMenuSelect {
     }
 }
 
-impl ViewSelector for InventorySlotMenuSelect {
+impl ViewSelector for ArenaSpawnMenuSelect {
     type ViewInput = AppView;
-    type ViewOutput = InventorySlotMenuView;
+    type ViewOutput = ArenaSpawnMenuView;
     fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
-        &input.inventory_slot_menu_view
+        &input.arena_spawn_menu_view
     }
     fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
-        &mut input.inventory_slot_menu_view
+        &mut input.arena_spawn_menu_view
     }
 }
 
-struct InventorySlotMenuDecorate<'a> {
-    title: &'a str,
+struct ArenaSpawnMenuDecorate {
+    title: &'static str,
 }
 
-impl<'a> Decorate for InventorySlotMenuDecorate<'a> {
+impl Decorate for ArenaSpawnMenuDecorate {
     type View = AppView;
     type Data = AppData;
     fn view<E, F, C>(
@@ -425,14 +955,559 @@ impl<'a> Decorate for InventorySlotMenuDecorate<'a> {
     }
 }
 
-#[derive(Default)]
-struct InventorySlotMenuView {
-    mouse_tracker: MenuInstanceMouseTracker,
+// Lets the player choose something to spawn in the arena, then spawns it
+fn arena_spawn_menu(
+    title: &'static str,
+    spawns: Vec<ArenaSpawn>,
+) -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent> {
+    SideEffectThen::new_with_view(move |data: &mut AppData, _: &_| {
+        data.arena_spawn_menu = arena_spawn_menu_instance(spawns);
+        MenuInstanceRoutine::new(ArenaSpawnMenuSelect)
+            .convert_input_to_common_event()
+            .decorated(ArenaSpawnMenuDecorate { title })
+    })
+    .and_then(|choice| {
+        SideEffect::new_with_view(move |data: &mut AppData, _: &_| match choice {
+            Ok(ArenaSpawn::Npc(npc)) => data.game_state.arena_spawn_npc(npc),
+            Ok(ArenaSpawn::Item(item_type)) => data.game_state.arena_spawn_item(item_type),
+            Err(menu::Escape) => (),
+        })
+    })
 }
 
-impl MenuIndexFromScreenCoord for InventorySlotMenuView {
-    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
-        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+#[derive(Clone, Copy, Debug)]
+enum TitleMenuEntry {
+    Continue,
+    NewGame,
+    Endless,
+    Arena,
+    Options,
+    Quit,
+}
+
+// Continue is only offered when there is a saved game to continue
+fn title_menu_instance(has_saved_game: bool) -> MenuInstanceChooseOrEscape<TitleMenuEntry> {
+    use TitleMenuEntry::*;
+    let mut items = vec![NewGame, Endless, Arena, Options, Quit];
+    let mut hotkeys = hashmap![
+        'n' => NewGame,
+        'e' => Endless,
+        'a' => Arena,
+        'o' => Options,
+        'q' => Quit,
+    ];
+    if has_saved_game {
+        items.insert(0, Continue);
+        hotkeys.insert('c', Continue);
+    }
+    MenuInstanceBuilder {
+        items,
+        hotkeys: Some(hotkeys),
+        selected_index: 0,
+    }
+    .build()
+    .unwrap()
+    .into_choose_or_escape()
+}
+
+const TITLE_ART: &[&str] = &[
+    "+--------------------------------+",
+    "|                                |",
+    "|     C  H  A  R  G  R  I  D     |",
+    "|                                |",
+    "|   R  O  G  U  E  L  I  K  E    |",
+    "|                                |",
+    "+--------------------------------+",
+];
+
+#[derive(Default)]
+struct TitleMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+    buf: String,
+}
+
+impl MenuIndexFromScreenCoord for TitleMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+    }
+}
+
+impl<'a> View<&'a AppData> for TitleMenuView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        self.mouse_tracker.new_frame(context.offset);
+        for (i, &entry, maybe_selected) in data.title_menu.menu_instance().enumerate() {
+            let (prefix, style) = if maybe_selected.is_some() {
+                (
+                    ">",
+                    Style::new()
+                        .with_foreground(Rgb24::new_grey(255))
+                        .with_bold(true),
+                )
+            } else {
+                (" ", Style::new().with_foreground(Rgb24::new_grey(187)))
+            };
+            self.buf.clear();
+            write!(&mut self.buf, "{} ", prefix).unwrap();
+            // The saved game is described so it's clear what continuing will resume
+            match entry {
+                TitleMenuEntry::Continue => write!(
+                    &mut self.buf,
+                    "(c) Continue (Level {})",
+                    data.game_state.dungeon_level()
+                )
+                .unwrap(),
+                TitleMenuEntry::NewGame => self.buf.push_str("(n) New Game"),
+                TitleMenuEntry::Endless => self.buf.push_str("(e) How deep can you go?"),
+                TitleMenuEntry::Arena => self.buf.push_str("(a) Arena"),
+                TitleMenuEntry::Options => self.buf.push_str("(o) Options"),
+                TitleMenuEntry::Quit => self.buf.push_str("(q) Quit"),
+            }
+            let size = StringViewSingleLine::new(style).view_size(
+                &self.buf,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+            self.mouse_tracker.on_entry_view_size(size);
+        }
+    }
+}
+
+struct TitleMenuSelect;
+
+impl ChooseSelector for TitleMenuSelect {
+    type ChooseOutput = MenuInstanceChooseOrEscape<TitleMenuEntry>;
+    fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
+        &mut input.title_menu
+    }
+}
+
+impl DataSelector for TitleMenuSelect {
+    type DataInput = AppData;
+    type DataOutput = AppData;
+    fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
+        input
+    }
+    fn data_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::DataOutput {
+        input
+    }
+}
+
+impl ViewSelector for TitleMenuSelect {
+    type ViewInput = AppView;
+    type ViewOutput = TitleMenuView;
+    fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
+        &input.title_menu_view
+    }
+    fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
+        &mut input.title_menu_view
+    }
+}
+
+// Draws the name of the game above the menu, on a screen of its own rather than over the game
+fn render_title_art<F: Frame, C: ColModify>(context: ViewContext<C>, frame: &mut F) {
+    for (i, line) in TITLE_ART.iter().enumerate() {
+        BoundView {
+            size: Size::new(context.size.width(), 1),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: StringViewSingleLine::new(
+                    Style::new()
+                        .with_foreground(Rgb24::new(255, 187, 63))
+                        .with_bold(true),
+                ),
+            },
+        }
+        .view(*line, context.add_offset(Coord::new(0, i as i32)), frame);
+    }
+}
+
+const TITLE_ART_Y: i32 = 4;
+const TITLE_MENU_Y: i32 = 14;
+
+struct TitleMenuDecorate;
+
+impl Decorate for TitleMenuDecorate {
+    type View = AppView;
+    type Data = AppData;
+    fn view<E, F, C>(
+        &self,
+        data: &Self::Data,
+        mut event_routine_view: EventRoutineView<E>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        E: EventRoutine<Data = Self::Data, View = Self::View>,
+        F: Frame,
+        C: ColModify,
+    {
+        render_title_art(context.add_offset(Coord::new(0, TITLE_ART_Y)), frame);
+        BoundView {
+            size: Size::new(
+                context.size.width(),
+                context.size.height() - TITLE_MENU_Y as u32,
+            ),
+            view: AlignView {
+                alignment: Alignment {
+                    x: AlignmentX::Centre,
+                    y: AlignmentY::Top,
+                },
+                view: MinSizeView {
+                    size: Size::new(14, 0),
+                    view: &mut event_routine_view,
+                },
+            },
+        }
+        .view(data, context.add_offset(Coord::new(0, TITLE_MENU_Y)), frame);
+    }
+}
+
+fn title_menu() -> impl EventRoutine<
+    Return = Result<TitleMenuEntry, menu::Escape>,
+    Data = AppData,
+    View = AppView,
+    Event = CommonEvent,
+> {
+    MenuInstanceRoutine::new(TitleMenuSelect)
+        .convert_input_to_common_event()
+        .decorated(TitleMenuDecorate)
+}
+
+// Settings which are changed by pressing their keys. Those above the gap are stored in the profile
+// as soon as they change, and the rest last until the game is closed.
+struct OptionsEventRoutine;
+
+impl EventRoutine for OptionsEventRoutine {
+    type Return = ();
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(key)) => match key {
+                KeyboardInput::Char(ch) if "advpc".contains(ch) => {
+                    // Changing an option which was given on the command line starts from the value
+                    // in effect, and replaces the command line's value for the rest of the game
+                    let shown = data.options();
+                    let options = &mut data.profile.options;
+                    match ch {
+                        'a' => {
+                            options.animation_speed = match shown.animation_speed {
+                                AnimationSpeed::Normal => AnimationSpeed::Fast,
+                                AnimationSpeed::Fast => AnimationSpeed::Instant,
+                                AnimationSpeed::Instant => AnimationSpeed::Normal,
+                            };
+                            data.option_overrides.animation_speed = None;
+                        }
+                        'd' => {
+                            options.show_damage_numbers = !shown.show_damage_numbers;
+                            data.option_overrides.hide_damage_numbers = false;
+                        }
+                        'v' => options.combat_verbosity = options.combat_verbosity.next(),
+                        'p' => options.auto_pickup = options.auto_pickup.next(),
+                        _ => options.palette = options.palette.next(),
+                    }
+                    data.store_profile();
+                    Handled::Continue(s)
+                }
+                KeyboardInput::Char('r') => {
+                    data.npc_respawning = !data.npc_respawning;
+                    data.game_state.set_npc_respawning(data.npc_respawning);
+                    Handled::Continue(s)
+                }
+                // Everyone playing the daily challenge plays by the same rules
+                KeyboardInput::Char(ch)
+                    if data.daily_challenge.is_none() && "ifbm".contains(ch) =>
+                {
+                    let challenges = &mut data.challenges;
+                    let challenge = match ch {
+                        'i' => &mut challenges.no_items,
+                        'f' => &mut challenges.fragile,
+                        'b' => &mut challenges.blind,
+                        _ => &mut challenges.double_monsters,
+                    };
+                    *challenge = !*challenge;
+                    Handled::Continue(s)
+                }
+                keys::ESCAPE | keys::RETURN | KeyboardInput::Char('o') => Handled::Return(()),
+                _ => Handled::Continue(s),
+            },
+            _ => Handled::Continue(s),
+        })
+    }
+
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        let options = data.options();
+        view.apply_options(&options);
+        render_title_art(context.add_offset(Coord::new(0, TITLE_ART_Y)), frame);
+        let animation_speed = match options.animation_speed {
+            AnimationSpeed::Normal => "Normal",
+            AnimationSpeed::Fast => "Fast",
+            AnimationSpeed::Instant => "Instant",
+        };
+        let on_off = |on: bool| if on { "On" } else { "Off" };
+        let mut lines = vec![
+            format!("(a) Animation speed: {}", animation_speed),
+            format!(
+                "(d) Damage numbers: {}",
+                on_off(options.show_damage_numbers)
+            ),
+            format!("(v) Combat messages: {}", options.combat_verbosity.name()),
+            format!("(p) Auto-pickup: {}", options.auto_pickup.name()),
+            format!("(c) Colours: {}", options.palette.name()),
+            String::new(),
+            format!("(r) Monster respawning: {}", on_off(data.npc_respawning)),
+        ];
+        if data.daily_challenge.is_none() {
+            let challenges = data.challenges;
+            lines.extend_from_slice(&[
+                String::new(),
+                "Challenges for new games:".to_string(),
+                format!("(i) No items: {}", on_off(challenges.no_items)),
+                format!("(f) Fragile: {}", on_off(challenges.fragile)),
+                format!("(b) Blind: {}", on_off(challenges.blind)),
+                format!(
+                    "(m) Double monsters: {}",
+                    on_off(challenges.double_monsters)
+                ),
+            ]);
+        }
+        lines.extend_from_slice(&[String::new(), "Press escape to go back".to_string()]);
+        for (i, line) in lines.iter().enumerate() {
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187))).view(
+                line,
+                context.add_offset(Coord::new(6, TITLE_MENU_Y + i as i32)),
+                frame,
+            );
+        }
+    }
+}
+
+// Shown at startup, before any game is played
+fn title_screen(
+) -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent> {
+    make_either!(Ei = A | B | C | D | E | F);
+    Loop::new(|| {
+        title_menu().and_then(|choice| match choice {
+            Err(menu::Escape) => Ei::A(Value::new(None)),
+            Ok(TitleMenuEntry::Continue) => Ei::B(game_loop().map(Some)),
+            Ok(TitleMenuEntry::NewGame) => Ei::C(
+                SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                    // The game generated at startup is still untouched if there was no save to
+                    // load in its place, and daily challenges depend on the seed it was made with.
+                    // It was made without challenges, so is replaced if any have been chosen.
+                    if data.has_saved_game || data.challenges != Challenges::default() {
+                        data.new_game(false, false);
+                    }
+                })
+                .and_then(|()| game_loop().map(Some)),
+            ),
+            Ok(TitleMenuEntry::Endless) => Ei::E(
+                SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                    // Endless runs don't count towards the daily challenge
+                    data.daily_challenge = None;
+                    data.new_game(false, true);
+                })
+                .and_then(|()| game_loop().map(Some)),
+            ),
+            Ok(TitleMenuEntry::Arena) => Ei::F(
+                SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                    // Nothing done in the arena counts towards the daily challenge
+                    data.daily_challenge = None;
+                    data.new_arena();
+                })
+                .and_then(|()| game_loop().map(Some)),
+            ),
+            Ok(TitleMenuEntry::Options) => Ei::D(OptionsEventRoutine.map(|()| None)),
+            Ok(TitleMenuEntry::Quit) => Ei::A(Value::new(Some(()))),
+        })
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+struct InventorySlotMenuEntry {
+    index: usize,
+    key: char,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum InventorySlotChoice {
+    Select(InventorySlotMenuEntry),
+    Inspect(InventorySlotMenuEntry),
+    Sort,
+    AssignHotbarSlot(InventorySlotMenuEntry, usize),
+}
+
+// The inventory menu, where pressing '?' or right-clicking a slot asks to inspect the item in that
+// slot instead of choosing it, pressing 'S' asks to sort the inventory, and pressing a key bound to
+// a hotbar slot asks to assign the selected item to that slot
+struct InventorySlotMenuChoose {
+    menu: MenuInstanceChooseOrEscape<InventorySlotMenuEntry>,
+    // Which hotbar slot each key is bound to, taken from the controls when the menu is created
+    hotbar_slots: Vec<(KeyboardInput, usize)>,
+}
+
+impl InventorySlotMenuChoose {
+    fn hotbar_slot_of_key(&self, key: KeyboardInput) -> Option<usize> {
+        self.hotbar_slots
+            .iter()
+            .find(|&&(bound_key, _)| bound_key == key)
+            .map(|&(_, slot)| slot)
+    }
+}
+
+impl MenuInstanceChoose for InventorySlotMenuChoose {
+    type Entry = InventorySlotMenuEntry;
+    type Output = Result<InventorySlotChoice, menu::Escape>;
+    fn choose<M>(&mut self, view: &M, input: Input) -> Option<Self::Output>
+    where
+        M: MenuIndexFromScreenCoord,
+    {
+        match input {
+            Input::Keyboard(KeyboardInput::Char('?')) => Some(Ok(InventorySlotChoice::Inspect(
+                *self.menu.menu_instance().selected(),
+            ))),
+            Input::Keyboard(KeyboardInput::Char('S')) => Some(Ok(InventorySlotChoice::Sort)),
+            Input::Keyboard(key) if self.hotbar_slot_of_key(key).is_some() => {
+                Some(Ok(InventorySlotChoice::AssignHotbarSlot(
+                    *self.menu.menu_instance().selected(),
+                    self.hotbar_slot_of_key(key).unwrap(),
+                )))
+            }
+            Input::Mouse(MouseInput::MousePress {
+                button: MouseButton::Right,
+                coord,
+            }) => {
+                let menu_instance = self.menu.menu_instance_mut();
+                let index = view.menu_index_from_screen_coord(menu_instance.len(), coord)?;
+                menu_instance.set_index(index);
+                Some(Ok(InventorySlotChoice::Inspect(*menu_instance.selected())))
+            }
+            other => self
+                .menu
+                .choose(view, other)
+                .map(|result| result.map(InventorySlotChoice::Select)),
+        }
+    }
+    fn menu_instance(&self) -> &MenuInstance<Self::Entry> {
+        self.menu.menu_instance()
+    }
+    fn menu_instance_mut(&mut self) -> &mut MenuInstance<Self::Entry> {
+        self.menu.menu_instance_mut()
+    }
+}
+
+struct InventorySlotMenuSelect;
+
+impl ChooseSelector for InventorySlotMenuSelect {
+    type ChooseOutput = InventorySlotMenuChoose;
+    fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
+        &mut input.inventory_slot_menu
+    }
+}
+
+impl DataSelector for InventorySlotMenuSelect {
+    type DataInput = AppData;
+    type DataOutput = AppData;
+    fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
+        input
+    }
+    fn data_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::DataOutput {
+        input
+    }
+}
+
+impl ViewSelector for InventorySlotMenuSelect {
+    type ViewInput = AppView;
+    type ViewOutput = InventorySlotMenuView;
+    fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
+        &input.inventory_slot_menu_view
+    }
+    fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
+        &mut input.inventory_slot_menu_view
+    }
+}
+
+struct InventorySlotMenuDecorate<'a> {
+    title: &'a str,
+}
+
+impl<'a> Decorate for InventorySlotMenuDecorate<'a> {
+    type View = AppView;
+    type Data = AppData;
+    fn view<E, F, C>(
+        &self,
+        data: &Self::Data,
+        mut event_routine_view: EventRoutineView<E>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        E: EventRoutine<Data = Self::Data, View = Self::View>,
+        F: Frame,
+        C: ColModify,
+    {
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle {
+                            title: Some(self.title.to_string()),
+                            title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                            ..Default::default()
+                        },
+                        view: MinSizeView {
+                            size: Size::new(12, 0),
+                            view: &mut event_routine_view,
+                        },
+                    },
+                },
+            },
+        }
+        .view(data, context.add_depth(10), frame);
+        event_routine_view.view.game_view.view(
+            &data.game_state,
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        event_routine_view
+            .view
+            .render_ui(None, &data, context, frame);
+    }
+}
+
+#[derive(Default)]
+struct InventorySlotMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+}
+
+impl MenuIndexFromScreenCoord for InventorySlotMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
     }
 }
 
@@ -446,6 +1521,7 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
         let player_inventory_slots = data.game_state.player_inventory().slots();
         self.mouse_tracker.new_frame(context.offset);
         let equipped_indices = data.game_state.player_equipped_inventory_indices();
+        let hotbar_indices = data.game_state.player_hotbar_inventory_indices();
         for ((i, entry, maybe_selected), &slot) in data
             .inventory_slot_menu
             .menu_instance()
@@ -457,9 +1533,25 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
                     .game_state
                     .item_type(item_entity)
                     .expect("non-item in player inventory");
-                (item_type.name(), colours::item_colour(item_type))
+                let mut name = match data.game_state.item_known_buc(item_entity) {
+                    Some(buc) => format!("{} {}", buc.name(), item_type.name()),
+                    None => item_type.name().to_string(),
+                };
+                if let Some(ammo) = data.game_state.item_ammo(item_entity) {
+                    name = format!("{} ({})", name, ammo);
+                }
+                match data
+                    .game_state
+                    .item_durability(item_entity)
+                    .map(|durability| durability.condition())
+                {
+                    Some(ItemCondition::Worn) => name.push_str(" (worn)"),
+                    Some(ItemCondition::Fragile) => name.push_str(" (fragile)"),
+                    _ => (),
+                }
+                (name, colours::item_colour(item_type))
             } else {
-                ("-", Rgb24::new_grey(187))
+                ("-".to_string(), Rgb24::new_grey(187))
             };
             let (selected_prefix, prefix_style, name_style) = if maybe_selected.is_some() {
                 (
@@ -484,19 +1576,28 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
             } else {
                 ""
             };
+            let hotbar_suffix = hotbar_indices
+                .iter()
+                .position(|&index| index == Some(i))
+                .map(|hotbar_slot| format!(" [{}]", hotbar_slot + 1))
+                .unwrap_or_default();
             let text = &[
                 RichTextPart {
                     text: &prefix,
                     style: prefix_style,
                 },
                 RichTextPart {
-                    text: name,
+                    text: &name,
                     style: name_style,
                 },
                 RichTextPart {
                     text: equipment_suffix,
                     style: name_style,
                 },
+                RichTextPart {
+                    text: &hotbar_suffix,
+                    style: prefix_style,
+                },
             ];
             let size = RichTextViewSingleLine::new().view_size(
                 text.into_iter().cloned(),
@@ -505,6 +1606,29 @@ impl<'a> View<&'a AppData> for InventorySlotMenuView {
             );
             self.mouse_tracker.on_entry_view_size(size);
         }
+        let help_style = Style::new().with_foreground(Rgb24::new_grey(127));
+        let help_offset = player_inventory_slots.len() as i32 + 1;
+        StringViewSingleLine::new(help_style).view(
+            "(S) Sort  (?) Inspect",
+            context.add_offset(Coord::new(0, help_offset)),
+            frame,
+        );
+        let hotbar_slots = &data.inventory_slot_menu.hotbar_slots;
+        let key_of_slot = |slot| {
+            hotbar_slots
+                .iter()
+                .find(|&&(_, bound_slot)| bound_slot == slot)
+                .map_or("?".to_string(), |&(key, _)| key_name(key))
+        };
+        StringViewSingleLine::new(help_style).view(
+            format!(
+                "({}-{}) Assign to hotbar",
+                key_of_slot(0),
+                key_of_slot(NUM_HOTBAR_SLOTS - 1)
+            ),
+            context.add_offset(Coord::new(0, help_offset + 1)),
+            frame,
+        );
     }
 }
 
@@ -512,7 +1636,7 @@ fn inventory_slot_menu<'a>(
     title: &'a str,
 ) -> impl 'a
        + EventRoutine<
-    Return = Result<InventorySlotMenuEntry, menu::Escape>,
+    Return = Result<InventorySlotChoice, menu::Escape>,
     Data = AppData,
     View = AppView,
     Event = CommonEvent,
@@ -522,6 +1646,224 @@ fn inventory_slot_menu<'a>(
         .decorated(InventorySlotMenuDecorate { title })
 }
 
+#[derive(Clone, Copy, Debug)]
+enum ItemAction {
+    Use,
+    Drop,
+}
+
+fn item_action_menu_instance() -> MenuInstanceChooseOrEscape<ItemAction> {
+    use ItemAction::*;
+    MenuInstanceBuilder {
+        items: vec![Use, Drop],
+        hotkeys: Some(hashmap!['u' => Use, 'd' => Drop]),
+        selected_index: 0,
+    }
+    .build()
+    .unwrap()
+    .into_choose_or_escape()
+}
+
+// Describes the item in the inspected inventory slot, followed by a menu of actions to perform
+// with it
+#[derive(Default)]
+struct ItemDetailMenuView {
+    mouse_tracker: MenuInstanceMouseTracker,
+    buf: String,
+}
+
+impl MenuIndexFromScreenCoord for ItemDetailMenuView {
+    fn menu_index_from_screen_coord(&self, len: usize, coord: Coord) -> Option<usize> {
+        self.mouse_tracker.menu_index_from_screen_coord(len, coord)
+    }
+}
+
+impl<'a> View<&'a AppData> for ItemDetailMenuView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a AppData,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        const DESCRIPTION_WIDTH: u32 = 28;
+        const DESCRIPTION_HEIGHT: u32 = 2;
+        let index = data.inspected_inventory_index;
+        let detail_style = Style::new().with_foreground(Rgb24::new_grey(187));
+        let mut lines = Vec::new();
+        if let Some(item_entity) = data.game_state.player_inventory().slots()[index] {
+            let item_type = data
+                .game_state
+                .item_type(item_entity)
+                .expect("non-item in player inventory");
+            let name = match data.game_state.item_known_buc(item_entity) {
+                Some(buc) => format!("{} {}", buc.name(), item_type.name()),
+                None => item_type.name().to_string(),
+            };
+            StringViewSingleLine::new(
+                Style::new()
+                    .with_foreground(colours::item_colour(item_type))
+                    .with_bold(true),
+            )
+            .view(name, context, frame);
+            BoundView {
+                size: Size::new(DESCRIPTION_WIDTH, DESCRIPTION_HEIGHT),
+                view: StringView::new(detail_style, wrap::Word::new()),
+            }
+            .view(
+                item_type.description(),
+                context.add_offset(Coord::new(0, 1)),
+                frame,
+            );
+            let enchantment = data.game_state.item_enchantment(item_entity);
+            if enchantment != 0 {
+                lines.push(format!("Enchantment: {:+}", enchantment));
+            }
+            if let Some(durability) = data.game_state.item_durability(item_entity) {
+                lines.push(format!(
+                    "Durability: {}/{}",
+                    durability.current, durability.max
+                ));
+            }
+            if let Some(ammo) = data.game_state.item_ammo(item_entity) {
+                lines.push(format!("Ammo: {}", ammo));
+            }
+            let equipped_indices = data.game_state.player_equipped_inventory_indices();
+            if equipped_indices.held == Some(index) {
+                lines.push("Held".to_string());
+            } else if equipped_indices.worn == Some(index) {
+                lines.push("Worn".to_string());
+            }
+        } else {
+            StringViewSingleLine::new(detail_style).view("Empty slot", context, frame);
+        }
+        let lines_offset = 1 + DESCRIPTION_HEIGHT as i32;
+        for (i, line) in lines.iter().enumerate() {
+            StringViewSingleLine::new(detail_style).view(
+                line,
+                context.add_offset(Coord::new(0, lines_offset + i as i32)),
+                frame,
+            );
+        }
+        // Leave a blank line between the details and the actions
+        let menu_offset = Coord::new(0, lines_offset + lines.len() as i32 + 1);
+        self.mouse_tracker.new_frame(context.offset + menu_offset);
+        for (i, &action, maybe_selected) in data.item_action_menu.menu_instance().enumerate() {
+            let (prefix, style) = if maybe_selected.is_some() {
+                (
+                    ">",
+                    Style::new()
+                        .with_foreground(Rgb24::new_grey(255))
+                        .with_bold(true),
+                )
+            } else {
+                (" ", Style::new().with_foreground(Rgb24::new_grey(187)))
+            };
+            let text = match action {
+                ItemAction::Use => "(u) Use",
+                ItemAction::Drop => "(d) Drop",
+            };
+            self.buf.clear();
+            write!(&mut self.buf, "{} {}", prefix, text).unwrap();
+            let size = StringViewSingleLine::new(style).view_size(
+                &self.buf,
+                context.add_offset(menu_offset + Coord::new(0, i as i32)),
+                frame,
+            );
+            self.mouse_tracker.on_entry_view_size(size);
+        }
+    }
+}
+
+struct ItemDetailMenuSelect;
+
+impl ChooseSelector for ItemDetailMenuSelect {
+    type ChooseOutput = MenuInstanceChooseOrEscape<ItemAction>;
+    fn choose_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::ChooseOutput {
+        &mut input.item_action_menu
+    }
+}
+
+impl DataSelector for ItemDetailMenuSelect {
+    type DataInput = AppData;
+    type DataOutput = AppData;
+    fn data<'a>(&self, input: &'a Self::DataInput) -> &'a Self::DataOutput {
+        input
+    }
+    fn data_mut<'a>(&self, input: &'a mut Self::DataInput) -> &'a mut Self::DataOutput {
+        input
+    }
+}
+
+impl ViewSelector for ItemDetailMenuSelect {
+    type ViewInput = AppView;
+    type ViewOutput = ItemDetailMenuView;
+    fn view<'a>(&self, input: &'a Self::ViewInput) -> &'a Self::ViewOutput {
+        &input.item_detail_menu_view
+    }
+    fn view_mut<'a>(&self, input: &'a mut Self::ViewInput) -> &'a mut Self::ViewOutput {
+        &mut input.item_detail_menu_view
+    }
+}
+
+struct ItemDetailMenuDecorate;
+
+impl Decorate for ItemDetailMenuDecorate {
+    type View = AppView;
+    type Data = AppData;
+    fn view<E, F, C>(
+        &self,
+        data: &Self::Data,
+        mut event_routine_view: EventRoutineView<E>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        E: EventRoutine<Data = Self::Data, View = Self::View>,
+        F: Frame,
+        C: ColModify,
+    {
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle {
+                            title: Some("Item".to_string()),
+                            title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                            ..Default::default()
+                        },
+                        view: MinSizeView {
+                            size: Size::new(12, 0),
+                            view: &mut event_routine_view,
+                        },
+                    },
+                },
+            },
+        }
+        .view(data, context.add_depth(10), frame);
+        event_routine_view.view.game_view.view(
+            &data.game_state,
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        event_routine_view
+            .view
+            .render_ui(None, &data, context, frame);
+    }
+}
+
+fn item_detail_menu() -> impl EventRoutine<
+    Return = Result<ItemAction, menu::Escape>,
+    Data = AppData,
+    View = AppView,
+    Event = CommonEvent,
+> {
+    MenuInstanceRoutine::new(ItemDetailMenuSelect)
+        .convert_input_to_common_event()
+        .decorated(ItemDetailMenuDecorate)
+}
+
 struct GameEventRoutine;
 
 enum GameReturn {
@@ -530,7 +1872,13 @@ enum GameReturn {
     DropItem,
     GameOver,
     Examine,
+    Fire,
     LevelUpAndDescend,
+    UseHotbarItem(usize),
+    ContextActionMenu,
+    Hint(Hint),
+    MessageHistory,
+    CharacterSheet,
 }
 
 impl EventRoutine for GameEventRoutine {
@@ -552,20 +1900,34 @@ impl EventRoutine for GameEventRoutine {
             CommonEvent::Input(input) => {
                 if let Some(game_return) = data.handle_input(input) {
                     Handled::Return(game_return)
+                } else if let Some(hint) = data.triggered_hint() {
+                    Handled::Return(GameReturn::Hint(hint))
                 } else {
                     Handled::Continue(s)
                 }
             }
             CommonEvent::Frame(period) => {
-                if let Some(until_next_animation_tick) =
-                    data.until_next_animation_tick.checked_sub(period)
-                {
-                    data.until_next_animation_tick = until_next_animation_tick;
-                } else {
-                    data.until_next_animation_tick = BETWEEN_ANIMATION_TICKS;
+                let animation_speed = data.options().animation_speed;
+                if animation_speed == AnimationSpeed::Instant {
+                    data.game_state.resolve_animations();
+                }
+                let between_ticks = animation_speed.between_animation_ticks();
+                for _ in 0..data.animation_timer.ticks_for_frame(period, between_ticks) {
                     data.game_state.tick_animations();
+                    data.task_step();
+                }
+                // Tasks can lead to the player's death without any input
+                if !data.is_spectating() && !data.game_state.is_player_alive() {
+                    return Handled::Return(GameReturn::GameOver);
+                }
+                data.spectate_frame(period);
+                data.update_rescue_snapshot();
+                data.key_repeat.frame(period);
+                if let Some(hint) = data.triggered_hint() {
+                    Handled::Return(GameReturn::Hint(hint))
+                } else {
+                    Handled::Continue(s)
                 }
-                Handled::Continue(s)
             }
         })
     }
@@ -581,7 +1943,11 @@ impl EventRoutine for GameEventRoutine {
         C: ColModify,
     {
         view.game_view.view(&data.game_state, context, frame);
-        view.render_ui(None, &data, context, frame);
+        let name = data.pending_direction_action.map(|action| match action {
+            DirectionAction::Charge => "CHARGE",
+            DirectionAction::Kick => "KICK",
+        });
+        view.render_ui(name, &data, context, frame);
     }
 }
 
@@ -622,8 +1988,19 @@ impl EventRoutine for TargetEventRoutine {
                                 data.cursor = None;
                                 return Handled::Return(None);
                             }
+                            KeyboardInput::Char('<') => {
+                                data.cycle_examine_index(-1);
+                                Coord::new(0, 0)
+                            }
+                            KeyboardInput::Char('>') => {
+                                data.cycle_examine_index(1);
+                                Coord::new(0, 0)
+                            }
                             _ => Coord::new(0, 0),
                         };
+                        if delta != Coord::new(0, 0) {
+                            data.examine_index = 0;
+                        }
                         data.cursor = Some(
                             data.cursor
                                 .unwrap_or_else(|| data.game_state.player_coord())
@@ -631,7 +2008,7 @@ impl EventRoutine for TargetEventRoutine {
                         );
                     }
                     Input::Mouse(mouse_input) => match mouse_input {
-                        MouseInput::MouseMove { coord, .. } => data.cursor = Some(coord),
+                        MouseInput::MouseMove { coord, .. } => data.move_cursor(coord),
                         MouseInput::MousePress {
                             button: MouseButton::Left,
                             coord,
@@ -658,28 +2035,406 @@ impl EventRoutine for TargetEventRoutine {
         F: Frame,
         C: ColModify,
     {
-        view.game_view.view(&data.game_state, context, frame);
-        view.render_ui(Some(self.name), &data, context, frame);
+        view.game_view.view(&data.game_state, context, frame);
+        view.render_ui(Some(self.name), &data, context, frame);
+    }
+}
+
+// Shows every message logged so far, along with the turn each was logged on. The arrow keys
+// scroll through older messages, and escape or 'm' closes it.
+struct MessageHistoryEventRoutine {
+    scroll: usize,
+}
+
+impl MessageHistoryEventRoutine {
+    // Leaves room for the border
+    fn num_rows(data: &AppData) -> usize {
+        data.game_state.size().height() as usize - 2
+    }
+}
+
+impl EventRoutine for MessageHistoryEventRoutine {
+    type Return = ();
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        let max_scroll = data
+            .num_shown_messages()
+            .saturating_sub(Self::num_rows(data));
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(KeyboardInput::Up)) => Handled::Continue(Self {
+                scroll: (s.scroll + 1).min(max_scroll),
+            }),
+            CommonEvent::Input(Input::Keyboard(KeyboardInput::Down)) => Handled::Continue(Self {
+                scroll: s.scroll.saturating_sub(1),
+            }),
+            CommonEvent::Input(Input::Keyboard(keys::ESCAPE))
+            | CommonEvent::Input(Input::Keyboard(KeyboardInput::Char('m'))) => Handled::Return(()),
+            _ => Handled::Continue(s),
+        })
+    }
+
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        data.shown_messages(&mut view.shown_messages);
+        BoundView {
+            size: data.game_state.size(),
+            view: FillBackgroundView {
+                rgb24: Rgb24::new_grey(0),
+                view: BorderView {
+                    style: &BorderStyle {
+                        title: Some("Message History".to_string()),
+                        title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                        ..Default::default()
+                    },
+                    view: &mut view.message_history_view,
+                },
+            },
+        }
+        .view(
+            MessageHistory {
+                messages: &view.shown_messages.entries,
+                scroll: self.scroll,
+                num_rows: Self::num_rows(data),
+                language: data.profile.language,
+            },
+            context.add_depth(10),
+            frame,
+        );
+        view.render_ui(None, &data, context, frame);
+    }
+}
+
+// The player's attributes and the statistics of the run so far, one per line
+fn character_sheet_text(game_state: &GameState) -> String {
+    let stats = game_state.stats();
+    let hit_points = game_state.player_hit_points();
+    let mut text = String::new();
+    writeln!(
+        &mut text,
+        "Level {}: {}",
+        game_state.dungeon_level(),
+        game_state.level_name()
+    )
+    .unwrap();
+    writeln!(
+        &mut text,
+        "Health: {}/{}",
+        hit_points.current, hit_points.max
+    )
+    .unwrap();
+    writeln!(&mut text, "Strength: {}", game_state.player_strength()).unwrap();
+    writeln!(&mut text, "Dexterity: {}", game_state.player_dexterity()).unwrap();
+    writeln!(
+        &mut text,
+        "Intelligence: {}",
+        game_state.player_intelligence()
+    )
+    .unwrap();
+    writeln!(&mut text).unwrap();
+    writeln!(&mut text, "Steps taken: {}", stats.steps_taken).unwrap();
+    writeln!(&mut text, "Damage dealt: {}", stats.total_damage_dealt()).unwrap();
+    for (npc_type, damage) in &stats.damage_dealt {
+        writeln!(&mut text, "  to {}s: {}", npc_type.name(), damage).unwrap();
+    }
+    writeln!(&mut text, "Damage taken: {}", stats.total_damage_taken()).unwrap();
+    for (npc_type, damage) in &stats.damage_taken {
+        writeln!(&mut text, "  from {}s: {}", npc_type.name(), damage).unwrap();
+    }
+    if stats.damage_taken_other > 0 {
+        writeln!(
+            &mut text,
+            "  from other things: {}",
+            stats.damage_taken_other
+        )
+        .unwrap();
+    }
+    writeln!(&mut text, "Items used: {}", stats.total_items_used()).unwrap();
+    for (item_type, count) in &stats.items_used {
+        writeln!(&mut text, "  {}: {}", item_type.name(), count).unwrap();
+    }
+    write!(&mut text, "Scrolls wasted: {}", stats.scrolls_wasted).unwrap();
+    text
+}
+
+// Shows the character sheet until escape or the key which opened it is pressed
+struct CharacterSheetEventRoutine;
+
+impl EventRoutine for CharacterSheetEventRoutine {
+    type Return = ();
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(keys::ESCAPE)) => Handled::Return(()),
+            CommonEvent::Input(Input::Keyboard(key))
+                if matches!(
+                    data.profile.controls.get(key),
+                    Some(ControlAction::CharacterSheet)
+                ) =>
+            {
+                Handled::Return(())
+            }
+            _ => Handled::Continue(s),
+        })
+    }
+
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        BoundView {
+            size: data.game_state.size(),
+            view: FillBackgroundView {
+                rgb24: Rgb24::new_grey(0),
+                view: BorderView {
+                    style: &BorderStyle {
+                        title: Some("Character".to_string()),
+                        title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                        ..Default::default()
+                    },
+                    view: StringView::new(
+                        Style::new().with_foreground(Rgb24::new_grey(187)),
+                        wrap::Word::new(),
+                    ),
+                },
+            },
+        }
+        .view(
+            character_sheet_text(&data.game_state),
+            context.add_depth(10),
+            frame,
+        );
+        view.render_ui(None, &data, context, frame);
+    }
+}
+
+// Shows a hint over the game until any key or mouse button is pressed
+struct HintEventRoutine {
+    hint: Hint,
+}
+
+impl EventRoutine for HintEventRoutine {
+    type Return = ();
+    type Data = AppData;
+    type View = AppView;
+    type Event = CommonEvent;
+
+    fn handle<EP>(
+        self,
+        data: &mut Self::Data,
+        _view: &Self::View,
+        event_or_peek: EP,
+    ) -> Handled<Self::Return, Self>
+    where
+        EP: EventOrPeek<Event = Self::Event>,
+    {
+        event_routine::event_or_peek_with_handled(event_or_peek, self, |s, event| match event {
+            CommonEvent::Input(Input::Keyboard(_))
+            | CommonEvent::Input(Input::Mouse(MouseInput::MousePress { .. })) => {
+                data.dismiss_hint(s.hint);
+                Handled::Return(())
+            }
+            _ => Handled::Continue(s),
+        })
+    }
+
+    fn view<F, C>(
+        &self,
+        data: &Self::Data,
+        view: &mut Self::View,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) where
+        F: Frame,
+        C: ColModify,
+    {
+        const HINT_WIDTH: u32 = 30;
+        const HINT_HEIGHT: u32 = 5;
+        BoundView {
+            size: data.game_state.size(),
+            view: AlignView {
+                alignment: Alignment::centre(),
+                view: FillBackgroundView {
+                    rgb24: Rgb24::new_grey(0),
+                    view: BorderView {
+                        style: &BorderStyle {
+                            title: Some("Hint".to_string()),
+                            title_style: Style::new().with_foreground(Rgb24::new_grey(255)),
+                            ..Default::default()
+                        },
+                        view: BoundView {
+                            size: Size::new(HINT_WIDTH, HINT_HEIGHT),
+                            view: StringView::new(
+                                Style::new().with_foreground(Rgb24::new_grey(187)),
+                                wrap::Word::new(),
+                            ),
+                        },
+                    },
+                },
+            },
+        }
+        .view(self.hint.text(), context.add_depth(10), frame);
+        view.game_view.view(
+            &data.game_state,
+            context.compose_col_modify(ColModifyMap(|c: Rgb24| c.saturating_scalar_mul_div(1, 2))),
+            frame,
+        );
+        view.render_ui(None, &data, context, frame);
     }
 }
 
+#[derive(Clone, Copy)]
+enum DirectionAction {
+    Charge,
+    Kick,
+}
+
+// Macros are named by a single key, which is pressed after the key to record or play one
+#[derive(Clone, Copy)]
+enum MacroAction {
+    Record,
+    Play,
+}
+
 struct AppData {
     game_state: GameState,
     visibility_algorithm: VisibilityAlgorithm,
-    inventory_slot_menu: MenuInstanceChooseOrEscape<InventorySlotMenuEntry>,
+    // When set, cells where this algorithm disagrees with `visibility_algorithm` are highlighted
+    fov_comparison: Option<VisibilityAlgorithm>,
+    // Enables debugging keys which aren't part of the normal game
+    wizard: bool,
+    coordinate_overlay: CoordinateOverlay,
+    // Tints the cells where enemies could attack the player on their next turn
+    threat_overlay: bool,
+    inventory_slot_menu: InventorySlotMenuChoose,
+    item_action_menu: MenuInstanceChooseOrEscape<ItemAction>,
+    // The inventory slot whose item is shown in the item detail screen
+    inspected_inventory_index: usize,
     cursor: Option<Coord>,
-    until_next_animation_tick: Duration,
+    // Set after pressing the key for an action which needs a direction, until one is chosen
+    pending_direction_action: Option<DirectionAction>,
+    // Set after pressing the key to record or play a macro, until its name is chosen
+    pending_macro_action: Option<MacroAction>,
+    // The name of the macro being recorded
+    recording_macro_name: Option<char>,
+    // The npc most recently aimed at, which 'f' fires at again without needing to aim
+    last_target: Option<Entity>,
+    examine_index: usize,
+    animation_timer: AnimationTimer,
+    npc_respawning: bool,
+    // Used for each new game, but a loaded game keeps the config it was started with
+    dungeon_config: DungeonConfig,
+    // Applied to each new game
+    challenges: Challenges,
+    key_repeat: KeyRepeat,
     main_menu: MenuInstanceChooseOrEscape<MainMenuEntry>,
+    arena_spawn_menu: MenuInstanceChooseOrEscape<ArenaSpawn>,
+    title_menu: MenuInstanceChooseOrEscape<TitleMenuEntry>,
+    // Whether the game was loaded from a save file when starting up
+    has_saved_game: bool,
     level_up_menu: MenuInstanceChooseOrEscape<LevelUp>,
+    context_action_menu: MenuInstanceChooseOrEscape<ContextAction>,
+    profile: Profile,
+    option_overrides: OptionOverrides,
+    layout: Layout,
     game_area_size: Size,
     rng_seed: u64,
+    // The seed of the game in progress, which isn't known if it was loaded from a save
+    game_rng_seed: Option<u64>,
+    // The turn on which the rescue snapshot was last taken
+    rescue_snapshot_turn: Option<u64>,
+    // Where the score of the game which just ended placed in the high score table
+    high_score_rank: Option<usize>,
+    daily_challenge: Option<DailyChallenge>,
+    save_sync: Option<SaveSync>,
+    #[cfg(feature = "spectate")]
+    spectate: Option<Spectate>,
 }
 
 impl AppData {
-    fn new(screen_size: Size, rng_seed: u64, visibility_algorithm: VisibilityAlgorithm) -> Self {
-        let game_area_size = screen_size.set_height(screen_size.height() - UI_NUM_ROWS);
-        let game_state = Self::load_game()
-            .unwrap_or_else(|| GameState::new(game_area_size, rng_seed, visibility_algorithm));
+    fn new(
+        layout: Layout,
+        rng_seed: u64,
+        visibility_algorithm: VisibilityAlgorithm,
+        fov_comparison: Option<VisibilityAlgorithm>,
+        wizard: bool,
+        hide_damage_numbers: bool,
+        animation_speed: Option<AnimationSpeed>,
+        npc_respawning: bool,
+        dungeon_config: DungeonConfig,
+        key_repeat_delay: Duration,
+        key_repeat_interval: Duration,
+        daily_challenge: Option<DailyChallenge>,
+        trace: Option<Trace>,
+        save_sync: Option<SaveSync>,
+        #[cfg(feature = "spectate")] spectate: Option<Spectate>,
+    ) -> Self {
+        let game_area_size = layout.game_area_size;
+        if let Some(save_sync) = save_sync.as_ref() {
+            pull_synced_files(save_sync);
+        }
+        // daily challenges always start from the beginning of the day's dungeon
+        let saved_game_state = if daily_challenge.is_none() {
+            Self::load_rescued_game().or_else(Self::load_game)
+        } else {
+            None
+        };
+        let has_saved_game = saved_game_state.is_some();
+        let mut game_state = saved_game_state.unwrap_or_else(|| {
+            GameState::new(
+                game_area_size,
+                rng_seed,
+                visibility_algorithm,
+                false,
+                dungeon_config,
+                Challenges::default(),
+            )
+        });
+        game_state.set_npc_respawning(npc_respawning);
+        if let Some(mut trace) = trace {
+            trace.record_game_start(if has_saved_game { None } else { Some(rng_seed) });
+            game_state.set_trace(Some(trace));
+        }
+        let profile = Self::load_profile();
+        let option_overrides = OptionOverrides {
+            animation_speed,
+            hide_damage_numbers,
+        };
         let player_inventory = game_state.player_inventory();
         let inventory_slot_menu = {
             let items = (0..player_inventory.slots().len())
@@ -690,36 +2445,297 @@ impl AppData {
                 .iter()
                 .map(|&entry| (entry.key, entry))
                 .collect::<HashMap<_, _>>();
-            MenuInstanceBuilder {
-                items,
-                hotkeys: Some(hotkeys),
-                selected_index: 0,
+            InventorySlotMenuChoose {
+                menu: MenuInstanceBuilder {
+                    items,
+                    hotkeys: Some(hotkeys),
+                    selected_index: 0,
+                }
+                .build()
+                .unwrap()
+                .into_choose_or_escape(),
+                hotbar_slots: profile.controls.hotbar_bindings(),
             }
-            .build()
-            .unwrap()
-            .into_choose_or_escape()
         };
         Self {
             game_state,
             visibility_algorithm,
+            fov_comparison,
+            wizard,
+            coordinate_overlay: CoordinateOverlay::Off,
+            threat_overlay: false,
             inventory_slot_menu,
+            item_action_menu: item_action_menu_instance(),
+            inspected_inventory_index: 0,
             cursor: None,
-            until_next_animation_tick: Duration::from_millis(0),
-            main_menu: main_menu_instance(),
+            pending_direction_action: None,
+            pending_macro_action: None,
+            recording_macro_name: None,
+            last_target: None,
+            examine_index: 0,
+            animation_timer: AnimationTimer::default(),
+            npc_respawning,
+            dungeon_config,
+            challenges: Challenges::default(),
+            key_repeat: KeyRepeat::new(key_repeat_delay, key_repeat_interval),
+            main_menu: main_menu_instance(false),
+            arena_spawn_menu: arena_spawn_menu_instance(ArenaSpawn::all_npcs()),
+            title_menu: title_menu_instance(has_saved_game),
+            has_saved_game,
             level_up_menu: level_up_menu_instance(),
+            context_action_menu: context_action_menu_instance(vec![ContextAction::Wait]),
+            profile,
+            option_overrides,
+            layout,
             game_area_size,
             rng_seed,
+            game_rng_seed: if has_saved_game { None } else { Some(rng_seed) },
+            rescue_snapshot_turn: None,
+            high_score_rank: None,
+            daily_challenge,
+            save_sync,
+            #[cfg(feature = "spectate")]
+            spectate,
+        }
+    }
+    // Only the first game played after starting with a daily challenge counts towards it
+    fn finish_daily_challenge(&mut self) {
+        if let Some(daily_challenge) = self.daily_challenge.take() {
+            daily_challenge.upload_score(self.game_state.score_inputs());
+        }
+    }
+    #[cfg(feature = "spectate")]
+    fn is_spectating(&self) -> bool {
+        matches!(self.spectate, Some(Spectate::Watch(_)))
+    }
+    #[cfg(not(feature = "spectate"))]
+    fn is_spectating(&self) -> bool {
+        false
+    }
+    #[cfg(feature = "spectate")]
+    fn spectate_frame(&mut self, period: Duration) {
+        match self.spectate.as_mut() {
+            None => (),
+            Some(Spectate::Host(host)) => host.tick(period, &self.game_state),
+            Some(Spectate::Watch(watch)) => {
+                if let Some(game_state) = watch.latest_game_state() {
+                    self.game_state = game_state;
+                }
+            }
+        }
+    }
+    #[cfg(not(feature = "spectate"))]
+    fn spectate_frame(&mut self, _period: Duration) {}
+    // Replaces the current game, carrying its trace over to the new one
+    fn start_game(&mut self, game_state: GameState) {
+        let trace = self.game_state.take_trace();
+        self.game_state = game_state;
+        self.game_rng_seed = Some(self.rng_seed);
+        self.rescue_snapshot_turn = None;
+        rescue::clear();
+        if let Some(mut trace) = trace {
+            trace.record_game_start(Some(self.rng_seed));
+            self.game_state.set_trace(Some(trace));
         }
     }
-    fn new_game(&mut self) {
+    fn new_game(&mut self, peaceful: bool, endless: bool) {
         self.rng_seed = self.rng_seed.wrapping_add(1);
-        self.game_state = GameState::new(
+        self.start_game(GameState::new(
             self.game_area_size,
             self.rng_seed,
             self.visibility_algorithm,
-        );
+            peaceful,
+            DungeonConfig {
+                endless,
+                ..self.dungeon_config
+            },
+            self.challenges,
+        ));
+        self.game_state.set_npc_respawning(self.npc_respawning);
+    }
+    fn new_arena(&mut self) {
+        self.rng_seed = self.rng_seed.wrapping_add(1);
+        self.start_game(GameState::new_arena(
+            self.game_area_size,
+            self.rng_seed,
+            self.visibility_algorithm,
+        ));
+    }
+    fn write_bug_report(&self) {
+        let mut info = String::new();
+        writeln!(info, "version: {}", env!("CARGO_PKG_VERSION")).unwrap();
+        match self.game_rng_seed {
+            Some(rng_seed) => writeln!(info, "rng seed: {}", rng_seed).unwrap(),
+            None => writeln!(info, "rng seed: unknown (loaded from a save)").unwrap(),
+        }
+        writeln!(info, "dungeon level: {}", self.game_state.dungeon_level()).unwrap();
+        writeln!(info, "turns: {}", self.game_state.turn_count()).unwrap();
+        writeln!(info, "arena: {}", self.game_state.is_arena()).unwrap();
+        writeln!(
+            info,
+            "visibility algorithm: {:?}",
+            self.visibility_algorithm
+        )
+        .unwrap();
+        writeln!(info, "dungeon config: {:?}", self.dungeon_config).unwrap();
+        writeln!(info, "challenges: {:?}", self.game_state.challenges()).unwrap();
+        writeln!(info, "npc respawning: {}", self.npc_respawning).unwrap();
+        writeln!(info, "daily challenge: {}", self.daily_challenge.is_some()).unwrap();
+        let message_log = self.game_state.message_log();
+        let start_index = message_log.len().saturating_sub(BugReport::NUM_MESSAGES);
+        let mut messages = String::new();
+        for entry in &message_log[start_index..] {
+            writeln!(
+                messages,
+                "T{}: {}",
+                entry.turn,
+                ui::message_text(entry.message, self.profile.language)
+            )
+            .unwrap();
+        }
+        let bug_report = BugReport {
+            info,
+            save: serde_json::to_string_pretty(&save::to_json(&self.game_state))
+                .expect("failed to serialize game state"),
+            profile: serde_json::to_string_pretty(&self.profile)
+                .expect("failed to serialize profile"),
+            trace: self.game_state.trace().map(|trace| {
+                trace
+                    .recent_lines()
+                    .map(|line| format!("{}\n", line))
+                    .collect()
+            }),
+            messages,
+        };
+        match bug_report.write() {
+            Ok(path) => println!("Wrote bug report to {:?}", path),
+            Err(error) => eprintln!("Failed to write bug report: {}", error),
+        }
+    }
+    // Writes a summary of the run which just ended next to the executable, so that it can be
+    // looked back on after the next game has started
+    fn write_morgue_file(&self) {
+        let mut morgue = String::new();
+        writeln!(
+            morgue,
+            "Died on level {}: {}",
+            self.game_state.dungeon_level(),
+            self.game_state.level_name()
+        )
+        .unwrap();
+        writeln!(
+            morgue,
+            "Peaceful: {}",
+            if self.game_state.is_peaceful() {
+                "yes"
+            } else {
+                "no"
+            }
+        )
+        .unwrap();
+        writeln!(
+            morgue,
+            "Endless: {}",
+            if self.game_state.is_endless() {
+                "yes"
+            } else {
+                "no"
+            }
+        )
+        .unwrap();
+        let challenge_names = self.game_state.challenges().names();
+        if challenge_names.is_empty() {
+            writeln!(morgue, "Challenges: none").unwrap();
+        } else {
+            writeln!(morgue, "Challenges: {}", challenge_names.join(", ")).unwrap();
+        }
+        let killed_by = self
+            .game_state
+            .message_log()
+            .iter()
+            .rev()
+            .find_map(|entry| match entry.message {
+                LogMessage::NpcKillsPlayer(npc) => Some(npc),
+                _ => None,
+            });
+        if let Some(npc) = killed_by {
+            writeln!(morgue, "Killed by: {}", npc.name()).unwrap();
+        }
+        let score_inputs = self.game_state.score_inputs();
+        let stats = self.game_state.stats();
+        writeln!(morgue, "Score: {}", score_inputs.score()).unwrap();
+        writeln!(morgue, "Deepest level: {}", score_inputs.depth).unwrap();
+        writeln!(morgue, "Turns: {}", self.game_state.turn_count()).unwrap();
+        writeln!(morgue, "Kills: {}", stats.kills).unwrap();
+        writeln!(morgue, "Damage dealt: {}", stats.total_damage_dealt()).unwrap();
+        writeln!(morgue, "Damage taken: {}", stats.total_damage_taken()).unwrap();
+        writeln!(morgue, "Items used: {}", stats.total_items_used()).unwrap();
+        writeln!(morgue, "\nLast messages:").unwrap();
+        let message_log = self.game_state.message_log();
+        let start_index = message_log.len().saturating_sub(MORGUE_NUM_MESSAGES);
+        for entry in &message_log[start_index..] {
+            writeln!(
+                morgue,
+                "{}",
+                ui::message_text(entry.message, self.profile.language)
+            )
+            .unwrap();
+        }
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let result = std::env::current_exe().and_then(|exe| {
+            let path = exe
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .join(format!("morgue-{}.txt", since_epoch.as_secs()));
+            fs::write(&path, morgue)?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => println!("Wrote morgue file to {:?}", path),
+            Err(error) => eprintln!("Failed to write morgue file: {}", error),
+        }
+    }
+    // Writes a text dump of the current level next to the executable
+    fn write_world_dump(&self) {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let result = std::env::current_exe().and_then(|exe| {
+            let path = exe
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .join(format!("world-dump-{}.txt", since_epoch.as_secs()));
+            fs::write(&path, self.game_state.debug_dump())?;
+            Ok(path)
+        });
+        match result {
+            Ok(path) => println!("Wrote world dump to {:?}", path),
+            Err(error) => eprintln!("Failed to write world dump: {}", error),
+        }
+    }
+    // The game being watched belongs to the host, daily challenges must be completed in a single
+    // sitting so that they can't be retried by reloading, and the arena is just for testing so
+    // mustn't replace a real saved game
+    fn can_save(&self) -> bool {
+        !(self.is_spectating() || self.daily_challenge.is_some() || self.game_state.is_arena())
+    }
+    // Keeps a copy of the game as of the latest turn for the panic hook to save
+    fn update_rescue_snapshot(&mut self) {
+        if !self.can_save() {
+            return;
+        }
+        let turn_count = self.game_state.turn_count();
+        if self.rescue_snapshot_turn != Some(turn_count) {
+            self.rescue_snapshot_turn = Some(turn_count);
+            rescue::update(&self.game_state);
+        }
     }
     fn save_game(&self) {
+        if !self.can_save() {
+            return;
+        }
         let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
         {
             Ok(file_storage) => file_storage,
@@ -728,14 +2744,67 @@ impl AppData {
                 return;
             }
         };
-        println!("Saving to {:?}", file_storage.full_path(SAVE_FILE));
-        match file_storage.store(SAVE_FILE, &self.game_state, SAVE_FORMAT) {
-            Ok(()) => (),
+        let (file, other_file) = save_files(SAVE_FORMAT);
+        println!("Saving to {:?}", file_storage.full_path(file));
+        let result = match SAVE_FORMAT {
+            SaveFormat::Json => {
+                file_storage.store(file, &save::to_json(&self.game_state), JSON_SAVE_FORMAT)
+            }
+            SaveFormat::Binary => {
+                file_storage.store(file, &save::to_binary(&self.game_state), BINARY_SAVE_FORMAT)
+            }
+        };
+        if let Err(error) = result {
+            eprintln!("Failed to save game: {:?}", error);
+            return;
+        }
+        if let Some(save_sync) = self.save_sync.as_ref() {
+            save_sync.push(&file_storage, file);
+        }
+        // A save left over from before the format was changed would otherwise be loaded in
+        // preference to this one if the format is changed back
+        if file_storage.exists(other_file) {
+            if let Err(error) = file_storage.remove(other_file) {
+                eprintln!("Failed to remove old save: {:?}", error);
+            }
+        }
+    }
+    // A game saved by the panic hook takes the place of the regular save, which is from before the
+    // crash. The rescue file is removed once loaded, and the game is saved normally from then on.
+    fn load_rescued_game() -> Option<GameState> {
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
             Err(error) => {
-                eprintln!("Failed to save game: {:?}", error);
-                return;
+                eprintln!("Failed to load rescued game: {:?}", error);
+                return None;
             }
+        };
+        if !file_storage.exists(RESCUE_FILE) {
+            return None;
+        }
+        println!(
+            "Loading game rescued after a crash from {:?}",
+            file_storage.full_path(RESCUE_FILE)
+        );
+        let game_state = match file_storage
+            .load(RESCUE_FILE, JSON_SAVE_FORMAT)
+            .map(save::from_json)
+        {
+            Ok(Ok(game_state)) => game_state,
+            Ok(Err(error)) => {
+                eprintln!("Failed to load rescued game: {:?}", error);
+                return None;
+            }
+            Err(error) => {
+                eprintln!("Failed to load rescued game: {:?}", error);
+                return None;
+            }
+        };
+        if let Err(error) = file_storage.remove(RESCUE_FILE) {
+            eprintln!("Failed to remove rescued game: {:?}", error);
         }
+        Some(game_state)
     }
     fn load_game() -> Option<GameState> {
         let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
@@ -745,56 +2814,380 @@ impl AppData {
                 return None;
             }
         };
-        if !file_storage.exists(SAVE_FILE) {
+        // Look for a save in the current format first
+        let (file, other_file) = save_files(SAVE_FORMAT);
+        let (file, save_format) = if file_storage.exists(file) {
+            (file, SAVE_FORMAT)
+        } else if file_storage.exists(other_file) {
+            (other_file, save_format_of_file(other_file))
+        } else {
             return None;
-        }
-        println!("Loading from {:?}", file_storage.full_path(SAVE_FILE));
-        match file_storage.load(SAVE_FILE, SAVE_FORMAT) {
-            Ok(game_state) => Some(game_state),
+        };
+        println!("Loading from {:?}", file_storage.full_path(file));
+        let loaded = match save_format {
+            SaveFormat::Json => file_storage
+                .load(file, JSON_SAVE_FORMAT)
+                .map(save::from_json),
+            SaveFormat::Binary => file_storage
+                .load(file, BINARY_SAVE_FORMAT)
+                .map(save::from_binary),
+        };
+        match loaded {
+            Ok(Ok(game_state)) => Some(game_state),
+            Ok(Err(error)) => {
+                eprintln!("Failed to load game: {:?}", error);
+                None
+            }
             Err(error) => {
                 eprintln!("Failed to load game: {:?}", error);
                 None
             }
         }
     }
+    fn load_profile() -> Profile {
+        let file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create) {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                eprintln!("Failed to load profile: {:?}", error);
+                return Profile::default();
+            }
+        };
+        if !file_storage.exists(PROFILE_FILE) {
+            return Profile::default();
+        }
+        match file_storage.load(PROFILE_FILE, PROFILE_FORMAT) {
+            Ok(profile) => profile,
+            Err(error) => {
+                eprintln!("Failed to load profile: {:?}", error);
+                Profile::default()
+            }
+        }
+    }
+    fn store_profile(&self) {
+        let mut file_storage = match FileStorage::next_to_exe(SAVE_DIR, IfDirectoryMissing::Create)
+        {
+            Ok(file_storage) => file_storage,
+            Err(error) => {
+                eprintln!("Failed to store profile: {:?}", error);
+                return;
+            }
+        };
+        if let Err(error) = file_storage.store(PROFILE_FILE, &self.profile, PROFILE_FORMAT) {
+            eprintln!("Failed to store profile: {:?}", error);
+            return;
+        }
+        if let Some(save_sync) = self.save_sync.as_ref() {
+            save_sync.push(&file_storage, PROFILE_FILE);
+        }
+    }
+    // The stored options, with any given on the command line in their place
+    fn options(&self) -> Options {
+        self.option_overrides.apply(self.profile.options)
+    }
+    // Spectators are watching someone else play, so aren't shown hints
+    fn triggered_hint(&self) -> Option<Hint> {
+        if self.is_spectating() || !self.game_state.is_player_alive() {
+            return None;
+        }
+        self.profile.triggered_hint(&self.game_state)
+    }
+    fn dismiss_hint(&mut self, hint: Hint) {
+        self.profile.hints_shown.insert(hint);
+        self.store_profile();
+    }
+    // Called when the player dies, before the record is shown on the game over screen
+    fn record_endless_depth(&mut self) {
+        if self.is_spectating() || !self.game_state.is_endless() {
+            return;
+        }
+        let depth = self.game_state.dungeon_level();
+        if depth > self.profile.deepest_endless_level {
+            self.profile.deepest_endless_level = depth;
+            self.store_profile();
+        }
+    }
+    // Called when the player dies, before the high score table is shown on the game over screen
+    fn record_high_score(&mut self) {
+        self.high_score_rank = None;
+        if self.is_spectating() || self.game_state.is_arena() {
+            return;
+        }
+        self.high_score_rank = self
+            .profile
+            .high_scores
+            .insert(self.game_state.score_inputs());
+        if self.high_score_rank.is_some() {
+            self.store_profile();
+        }
+    }
+    // Tasks such as running, and macros, take a step each animation tick
+    fn task_step(&mut self) {
+        if self.game_state.is_macro_playing() {
+            self.game_state.continue_macro();
+            self.game_state.update_visibility(self.visibility_algorithm);
+        } else if self.game_state.is_task_in_progress() {
+            // Running and travelling pick up items along the way just like single steps do
+            let player_coord = self.game_state.player_coord();
+            self.game_state.continue_task();
+            self.auto_pickup(player_coord);
+            self.game_state.update_visibility(self.visibility_algorithm);
+        }
+    }
+    // Fills `shown` with the message log, leaving out messages hidden by the combat verbosity
+    // option
+    fn shown_messages(&self, shown: &mut ShownMessages) {
+        let combat_verbosity = self.options().combat_verbosity;
+        shown.entries.clear();
+        shown.entries.extend(
+            self.game_state
+                .message_log()
+                .iter()
+                .filter(|entry| combat_verbosity.shows(entry.message)),
+        );
+    }
+    fn num_shown_messages(&self) -> usize {
+        let combat_verbosity = self.options().combat_verbosity;
+        self.game_state
+            .message_log()
+            .iter()
+            .filter(|entry| combat_verbosity.shows(entry.message))
+            .count()
+    }
+    // Picks up the item the player has just stepped onto, if the auto-pickup option covers it
+    fn auto_pickup(&mut self, previous_player_coord: Coord) {
+        if self.game_state.player_coord() == previous_player_coord {
+            return;
+        }
+        if let Some(item_type) = self.game_state.item_under_player() {
+            if self
+                .profile
+                .options
+                .auto_pickup
+                .includes(item_type.category())
+            {
+                self.game_state.handle_action(PlayerAction::GetItem);
+            }
+        }
+    }
+    fn ui_button_at(&self, coord: Coord) -> Option<ui::Button> {
+        let ui_panel = self.layout.ui_panel?;
+        let coord = coord - ui_panel.offset;
+        if coord.y == ui_panel.button_bar_row() {
+            ui::button_at_x(coord.x)
+        } else {
+            None
+        }
+    }
+    fn move_cursor(&mut self, coord: Coord) {
+        if self.cursor != Some(coord) {
+            self.examine_index = 0;
+        }
+        self.cursor = Some(coord);
+    }
+    // Select the next or previous entity in the stack of entities under the cursor
+    fn cycle_examine_index(&mut self, step: i32) {
+        if let Some(cursor) = self.cursor {
+            let num_entities = self.game_state.examine_cell(cursor).len() as i32;
+            if num_entities > 0 {
+                self.examine_index =
+                    (self.examine_index as i32 + step).rem_euclid(num_entities) as usize;
+            }
+        }
+    }
     fn handle_input(&mut self, input: Input) -> Option<GameReturn> {
+        if self.is_spectating() {
+            return None;
+        }
+        // Pressing any key or mouse button interrupts a task or macro
+        if (self.game_state.is_task_in_progress() || self.game_state.is_macro_playing())
+            && matches!(
+                input,
+                Input::Keyboard(_) | Input::Mouse(MouseInput::MousePress { .. })
+            )
+        {
+            self.game_state.cancel_macro();
+            self.game_state.cancel_task();
+            return None;
+        }
         match input {
+            Input::Keyboard(key) if self.pending_direction_action.is_some() => {
+                let action = self.pending_direction_action.take().unwrap();
+                if let Some(direction) = self.profile.controls.direction(key) {
+                    self.game_state.handle_action(match action {
+                        DirectionAction::Charge => PlayerAction::Charge(direction),
+                        DirectionAction::Kick => PlayerAction::Kick(direction),
+                    });
+                }
+                self.cursor = None;
+            }
+            Input::Keyboard(key) if self.pending_macro_action.is_some() => {
+                let action = self.pending_macro_action.take().unwrap();
+                if let KeyboardInput::Char(name) = key {
+                    if name.is_ascii_alphanumeric() {
+                        match action {
+                            MacroAction::Record => {
+                                self.recording_macro_name = Some(name);
+                                self.game_state.start_recording_macro();
+                            }
+                            MacroAction::Play => {
+                                let steps =
+                                    self.profile.macros.get(&name).cloned().unwrap_or_default();
+                                self.game_state.start_macro(steps);
+                            }
+                        }
+                    }
+                }
+                self.cursor = None;
+            }
+            Input::Keyboard(key)
+                if !self
+                    .key_repeat
+                    .accept(key, self.profile.controls.direction(key).is_some()) => {}
             Input::Keyboard(key) => {
-                match key {
-                    KeyboardInput::Left => {
-                        self.game_state.maybe_move_player(CardinalDirection::West)
+                match self.profile.controls.get(key) {
+                    Some(ControlAction::Move(direction)) => {
+                        let player_coord = self.game_state.player_coord();
+                        self.game_state.handle_action(PlayerAction::Move(direction));
+                        self.auto_pickup(player_coord);
                     }
-                    KeyboardInput::Right => {
-                        self.game_state.maybe_move_player(CardinalDirection::East)
+                    Some(ControlAction::Run(direction)) => {
+                        self.game_state.start_task(Task::Run(direction));
                     }
-                    KeyboardInput::Up => {
-                        self.game_state.maybe_move_player(CardinalDirection::North)
+                    Some(ControlAction::Attack(direction)) => {
+                        self.game_state
+                            .handle_action(PlayerAction::Attack(direction));
                     }
-                    KeyboardInput::Down => {
-                        self.game_state.maybe_move_player(CardinalDirection::South)
+                    Some(ControlAction::Save) => self.save_game(),
+                    Some(ControlAction::Rest) => self.game_state.start_task(Task::Rest),
+                    Some(ControlAction::Explore) => self.game_state.start_task(Task::Explore),
+                    Some(ControlAction::Wait) => {
+                        self.game_state.handle_action(PlayerAction::Wait);
                     }
-                    KeyboardInput::Char('>') => {
-                        if self.game_state.is_player_on_stairs() {
-                            return Some(GameReturn::LevelUpAndDescend);
+                    Some(ControlAction::Kick) => {
+                        self.pending_direction_action = Some(DirectionAction::Kick)
+                    }
+                    Some(ControlAction::BugReport) => self.write_bug_report(),
+                    Some(ControlAction::CharacterSheet) => return Some(GameReturn::CharacterSheet),
+                    Some(ControlAction::CoordinateOverlay) => {
+                        if self.wizard {
+                            self.coordinate_overlay = self.coordinate_overlay.next();
                         }
                     }
-                    KeyboardInput::Char(' ') => self.game_state.wait_player(),
-                    KeyboardInput::Char('g') => self.game_state.maybe_player_get_item(),
-                    KeyboardInput::Char('i') => return Some(GameReturn::UseItem),
-                    KeyboardInput::Char('d') => return Some(GameReturn::DropItem),
-                    KeyboardInput::Char('x') => {
-                        if self.cursor.is_none() {
-                            self.cursor = Some(self.game_state.player_coord());
+                    Some(ControlAction::ToggleThreatOverlay) => {
+                        self.threat_overlay = !self.threat_overlay
+                    }
+                    Some(ControlAction::UseHotbarSlot(hotbar_slot)) => {
+                        if let Some(inventory_index) = self
+                            .game_state
+                            .player_hotbar_inventory_indices()
+                            .get(hotbar_slot)
+                            .copied()
+                            .flatten()
+                        {
+                            return Some(GameReturn::UseHotbarItem(inventory_index));
                         }
-                        return Some(GameReturn::Examine);
                     }
-                    keys::ESCAPE => return Some(GameReturn::Menu),
-                    _ => (),
+                    None => match key {
+                        KeyboardInput::Char('>') => {
+                            if self.game_state.is_player_on_stairs() {
+                                return Some(GameReturn::LevelUpAndDescend);
+                            }
+                        }
+                        KeyboardInput::Char('<') => {
+                            self.game_state.handle_action(PlayerAction::Ascend);
+                        }
+                        KeyboardInput::Char('s') => {
+                            self.game_state.handle_action(PlayerAction::Defend);
+                        }
+                        KeyboardInput::Char('t') => {
+                            self.game_state.handle_action(PlayerAction::Taunt);
+                        }
+                        KeyboardInput::Char('c') => {
+                            self.pending_direction_action = Some(DirectionAction::Charge)
+                        }
+                        KeyboardInput::Char('g') => {
+                            self.game_state.handle_action(PlayerAction::GetItem);
+                        }
+                        KeyboardInput::Char('p') => {
+                            self.game_state.handle_action(PlayerAction::Pray);
+                        }
+                        KeyboardInput::Char('q') => {
+                            self.game_state.handle_action(PlayerAction::Drink);
+                        }
+                        KeyboardInput::Char('i') => return Some(GameReturn::UseItem),
+                        KeyboardInput::Char('d') => return Some(GameReturn::DropItem),
+                        KeyboardInput::Char('f') => {
+                            match self
+                                .last_target
+                                .and_then(|entity| self.game_state.visible_npc_coord(entity))
+                            {
+                                Some(target) => {
+                                    self.game_state.handle_action(PlayerAction::Fire(target));
+                                }
+                                None => return Some(GameReturn::Fire),
+                            }
+                        }
+                        KeyboardInput::Char('F') => return Some(GameReturn::Fire),
+                        KeyboardInput::Char('x') => {
+                            if self.cursor.is_none() {
+                                self.cursor = Some(self.game_state.player_coord());
+                            }
+                            return Some(GameReturn::Examine);
+                        }
+                        keys::ESCAPE => return Some(GameReturn::Menu),
+                        keys::RETURN => return Some(GameReturn::ContextActionMenu),
+                        // Chargrid doesn't report key releases, but holding a key repeats its press,
+                        // so holding 'z' keeps skipping animations as they start
+                        KeyboardInput::Char('z') => self.game_state.resolve_animations(),
+                        KeyboardInput::Char('m') => return Some(GameReturn::MessageHistory),
+                        // The same key starts and stops recording
+                        KeyboardInput::Char('Q') => {
+                            if self.game_state.is_recording_macro() {
+                                let steps = self.game_state.stop_recording_macro();
+                                if let Some(name) = self.recording_macro_name.take() {
+                                    self.profile.macros.insert(name, steps);
+                                    self.store_profile();
+                                }
+                            } else {
+                                self.pending_macro_action = Some(MacroAction::Record);
+                            }
+                        }
+                        KeyboardInput::Char('P') => {
+                            self.pending_macro_action = Some(MacroAction::Play)
+                        }
+                        KeyboardInput::Char('D') if self.wizard => self.write_world_dump(),
+                        _ => (),
+                    },
                 }
                 self.cursor = None;
             }
             Input::Mouse(mouse_input) => match mouse_input {
-                MouseInput::MouseMove { coord, .. } => self.cursor = Some(coord),
+                MouseInput::MouseMove { coord, .. } => self.move_cursor(coord),
+                MouseInput::MousePress {
+                    button: MouseButton::Left,
+                    coord,
+                } => match self.ui_button_at(coord) {
+                    Some(ui::Button::Inventory) => return Some(GameReturn::UseItem),
+                    Some(ui::Button::Drop) => return Some(GameReturn::DropItem),
+                    Some(ui::Button::Examine) => {
+                        self.cursor = Some(self.game_state.player_coord());
+                        return Some(GameReturn::Examine);
+                    }
+                    Some(ui::Button::Wait) => {
+                        self.game_state.handle_action(PlayerAction::Wait);
+                    }
+                    Some(ui::Button::Descend) => {
+                        if self.game_state.is_player_on_stairs() {
+                            return Some(GameReturn::LevelUpAndDescend);
+                        }
+                    }
+                    // Clicking on the map travels to the clicked cell
+                    None => {
+                        if coord.is_valid(self.game_area_size) {
+                            self.game_state.start_task(Task::Travel(coord));
+                        }
+                    }
+                },
                 _ => (),
             },
         }
@@ -802,36 +3195,154 @@ impl AppData {
         if !self.game_state.is_player_alive() {
             return Some(GameReturn::GameOver);
         }
-        None
-    }
-    fn player_level_up_and_descend(&mut self, level_up: LevelUp) {
-        self.game_state.player_level_up_and_descend(level_up);
-        self.game_state.update_visibility(self.visibility_algorithm);
+        None
+    }
+    // Remember the npc at `target` (if any) so it can be fired at again later
+    fn lock_target(&mut self, target: Coord) {
+        self.last_target = self.game_state.visible_npc_at(target);
+    }
+    fn player_level_up_and_descend(&mut self, level_up: LevelUp) {
+        self.game_state
+            .handle_action(PlayerAction::LevelUpAndDescend(level_up));
+        self.game_state.update_visibility(self.visibility_algorithm);
+    }
+}
+
+// A debugging aid, showing the coordinates of cells in the game area
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CoordinateOverlay {
+    Off,
+    Rulers,
+    RulersAndGrid,
+}
+
+impl CoordinateOverlay {
+    fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Rulers,
+            Self::Rulers => Self::RulersAndGrid,
+            Self::RulersAndGrid => Self::Off,
+        }
+    }
+}
+
+// Rulers along the top and left edges of the game area label each column and row with the last
+// digit of its coordinate, with every tenth one highlighted. The grid lines are drawn every 10
+// cells, and the coordinate under the cursor is shown in the top right.
+fn render_coordinate_overlay<F: Frame, C: ColModify>(
+    coordinate_overlay: CoordinateOverlay,
+    game_area_size: Size,
+    cursor: Option<Coord>,
+    context: ViewContext<C>,
+    frame: &mut F,
+) {
+    // Drawn over everything in the game area
+    const DEPTH: i8 = 20;
+    const GRID_SPACING: i32 = 10;
+    if coordinate_overlay == CoordinateOverlay::Off {
+        return;
+    }
+    let ruler_cell = |i: i32| {
+        let foreground = if i % GRID_SPACING == 0 {
+            Rgb24::new(255, 255, 0)
+        } else {
+            Rgb24::new_grey(187)
+        };
+        ViewCell::new()
+            .with_character(std::char::from_digit((i % 10) as u32, 10).unwrap())
+            .with_foreground(foreground)
+            .with_background(Rgb24::new_grey(0))
+    };
+    for x in 0..game_area_size.width() as i32 {
+        frame.set_cell_relative(Coord::new(x, 0), DEPTH, ruler_cell(x), context);
+    }
+    for y in 1..game_area_size.height() as i32 {
+        frame.set_cell_relative(Coord::new(0, y), DEPTH, ruler_cell(y), context);
+    }
+    if coordinate_overlay == CoordinateOverlay::RulersAndGrid {
+        for coord in game_area_size.coord_iter_row_major() {
+            if coord.x % GRID_SPACING == 0 || coord.y % GRID_SPACING == 0 {
+                frame.blend_cell_background_relative(
+                    coord,
+                    DEPTH - 1,
+                    Rgb24::new(0, 255, 255),
+                    31,
+                    blend_mode::LinearInterpolate,
+                    context,
+                );
+            }
+        }
+    }
+    if let Some(cursor) = cursor {
+        let text = format!("({},{})", cursor.x, cursor.y);
+        let x = game_area_size.width() as i32 - text.len() as i32;
+        for (i, ch) in text.chars().enumerate() {
+            frame.set_cell_relative(
+                Coord::new(x + i as i32, 0),
+                DEPTH + 1,
+                ViewCell::new()
+                    .with_character(ch)
+                    .with_bold(true)
+                    .with_foreground(Rgb24::new_grey(255))
+                    .with_background(Rgb24::new_grey(0)),
+                context,
+            );
+        }
     }
 }
 
 struct AppView {
-    ui_y_offset: i32,
+    layout: Layout,
     game_view: GameView,
     ui_view: UiView,
+    enemy_list_view: EnemyListView,
     inventory_slot_menu_view: InventorySlotMenuView,
     main_menu_view: MainMenuView,
+    arena_spawn_menu_view: ArenaSpawnMenuView,
+    title_menu_view: TitleMenuView,
     level_up_menu_view: LevelUpMenuView,
+    item_detail_menu_view: ItemDetailMenuView,
+    context_action_menu_view: ContextActionMenuView,
+    message_history_view: MessageHistoryView,
+    shown_messages: ShownMessages,
+}
+
+// The messages which aren't hidden by the combat verbosity option. These are kept between frames
+// so that the buffers are only allocated when the log outgrows them.
+#[derive(Default)]
+struct ShownMessages {
+    entries: Vec<LogEntry>,
 }
 
 impl AppView {
-    fn new(screen_size: Size) -> Self {
-        const UI_Y_PADDING: u32 = 0;
-        let ui_y_offset = (screen_size.height() - UI_NUM_ROWS + UI_Y_PADDING) as i32;
+    fn new(layout: Layout, options: &Options, box_drawing_walls: bool) -> Self {
         Self {
-            ui_y_offset,
-            game_view: GameView::default(),
+            layout,
+            game_view: GameView {
+                show_damage_numbers: options.show_damage_numbers,
+                box_drawing_walls,
+                palette: options.palette,
+                cache: RenderCache::default(),
+            },
             ui_view: UiView::default(),
+            enemy_list_view: EnemyListView::default(),
             inventory_slot_menu_view: InventorySlotMenuView::default(),
             main_menu_view: MainMenuView::default(),
+            arena_spawn_menu_view: ArenaSpawnMenuView::default(),
+            title_menu_view: TitleMenuView::default(),
             level_up_menu_view: LevelUpMenuView::default(),
+            item_detail_menu_view: ItemDetailMenuView::default(),
+            context_action_menu_view: ContextActionMenuView::default(),
+            message_history_view: MessageHistoryView::default(),
+            shown_messages: ShownMessages::default(),
         }
     }
+    // Options which change how the game area is drawn, rather than what's in it
+    fn apply_options(&mut self, options: &Options) {
+        self.game_view.show_damage_numbers = options.show_damage_numbers;
+        self.game_view.palette = options.palette;
+        self.game_view.invalidate();
+    }
     fn render_ui<F: Frame, C: ColModify>(
         &mut self,
         name: Option<&'static str>,
@@ -839,9 +3350,116 @@ impl AppView {
         context: ViewContext<C>,
         frame: &mut F,
     ) {
+        render_coordinate_overlay(
+            data.coordinate_overlay,
+            self.layout.game_area_size,
+            data.cursor,
+            context,
+            frame,
+        );
+        if let Some(fov_comparison) = data.fov_comparison {
+            for coord in data
+                .game_state
+                .visibility_disagreements(data.visibility_algorithm, fov_comparison)
+            {
+                frame.blend_cell_background_relative(
+                    coord,
+                    1,
+                    Rgb24::new(255, 0, 255),
+                    127,
+                    blend_mode::LinearInterpolate,
+                    context,
+                );
+            }
+        }
+        if data.threat_overlay {
+            for coord in data.game_state.threatened_cells() {
+                frame.blend_cell_background_relative(
+                    coord,
+                    1,
+                    Rgb24::new(255, 0, 0),
+                    63,
+                    blend_mode::LinearInterpolate,
+                    context,
+                );
+            }
+        }
+        // While examining an npc, highlight the cells it can see
+        if let Some(cursor) = data.cursor {
+            if data
+                .game_state
+                .examine_cell(cursor)
+                .iter()
+                .any(|examine_cell| matches!(examine_cell, ExamineCell::Npc(_)))
+            {
+                for coord in data
+                    .game_state
+                    .cells_visible_from(cursor, data.visibility_algorithm)
+                {
+                    frame.blend_cell_background_relative(
+                        coord,
+                        1,
+                        Rgb24::new(255, 127, 0),
+                        63,
+                        blend_mode::LinearInterpolate,
+                        context,
+                    );
+                }
+            }
+        }
+        if let Some(enemy_list_panel) = self.layout.enemy_list_panel {
+            let enemy_list = data
+                .game_state
+                .visible_npcs()
+                .into_iter()
+                .map(|visible_npc| EnemyListEntry {
+                    view_cell: currently_visible_view_cell_of_tile(Tile::Npc(visible_npc.npc)),
+                    name: visible_npc.npc.name(),
+                    hit_points: visible_npc.hit_points,
+                    distance: visible_npc.distance,
+                })
+                .collect::<Vec<_>>();
+            BoundView {
+                size: enemy_list_panel.size,
+                view: &mut self.enemy_list_view,
+            }
+            .view(
+                enemy_list.as_slice(),
+                context.add_offset(enemy_list_panel.offset),
+                frame,
+            );
+        }
+        let ui_panel = match self.layout.ui_panel {
+            Some(ui_panel) => ui_panel,
+            None => return,
+        };
         let player_hit_points = data.game_state.player_hit_points();
-        let messages = data.game_state.message_log();
-        let examine_cell = if let Some(cursor) = data.cursor {
+        data.shown_messages(&mut self.shown_messages);
+        let player_inventory_slots = data.game_state.player_inventory().slots();
+        let hotbar = data
+            .game_state
+            .player_hotbar_inventory_indices()
+            .iter()
+            .map(|maybe_index| {
+                let item_entity = player_inventory_slots[(*maybe_index)?]?;
+                let item_type = data.game_state.item_type(item_entity)?;
+                // Ammunition is counted in shots, and everything else by how many of the same
+                // type of item are carried
+                let count = data.game_state.item_ammo(item_entity).unwrap_or_else(|| {
+                    player_inventory_slots
+                        .iter()
+                        .flatten()
+                        .filter(|&&entity| data.game_state.item_type(entity) == Some(item_type))
+                        .count() as u32
+                });
+                Some(HotbarSlot {
+                    view_cell: currently_visible_view_cell_of_tile(Tile::Item(item_type)),
+                    count,
+                })
+            })
+            .collect::<Vec<_>>();
+        let status_effects = data.game_state.player_status_effects();
+        let examine_cells = if let Some(cursor) = data.cursor {
             frame.blend_cell_background_relative(
                 cursor,
                 1,
@@ -852,22 +3470,41 @@ impl AppView {
             );
             data.game_state.examine_cell(cursor)
         } else {
-            None
+            Vec::new()
         };
-        self.ui_view.view(
+        BoundView {
+            size: ui_panel.size,
+            view: &mut self.ui_view,
+        }
+        .view(
             UiData {
                 player_hit_points,
-                messages,
+                messages: &self.shown_messages.entries,
+                language: data.profile.language,
+                time_of_day: data.game_state.time_of_day(),
+                item_under_player: data.game_state.item_under_player(),
                 name,
-                examine_cell,
+                examine_stack: ExamineStack {
+                    examine_cells: &examine_cells,
+                    selected_index: data.examine_index % examine_cells.len().max(1),
+                    sees_player: data.cursor.map_or(false, |cursor| {
+                        data.game_state.can_enemy_at_see_player(cursor)
+                    }),
+                },
                 stats_data: StatsData {
                     strength: data.game_state.player_strength(),
                     dexterity: data.game_state.player_dexterity(),
                     intelligence: data.game_state.player_intelligence(),
                 },
                 dungeon_level: data.game_state.dungeon_level(),
+                level_name: data.game_state.level_name(),
+                ranged_ammo: data.game_state.player_ranged_ammo(),
+                status_effects: &status_effects,
+                hotbar: &hotbar,
+                on_stairs: data.game_state.is_player_on_stairs(),
+                num_message_rows: ui_panel.num_message_rows(),
             },
-            context.add_offset(Coord::new(0, self.ui_y_offset)),
+            context.add_offset(ui_panel.offset),
             frame,
         );
     }
@@ -876,32 +3513,84 @@ impl AppView {
 pub mod colours {
     use super::*;
     pub const PLAYER: Rgb24 = Rgb24::new_grey(255);
+    pub const PLAYER_INVISIBLE: Rgb24 = Rgb24::new_grey(95);
     pub const ORC: Rgb24 = Rgb24::new(0, 187, 0);
     pub const TROLL: Rgb24 = Rgb24::new(187, 0, 0);
+    pub const ELITE_ORC: Rgb24 = Rgb24::new(127, 255, 255);
+    pub const ELITE_TROLL: Rgb24 = Rgb24::new(255, 127, 0);
+    pub const SPIRIT: Rgb24 = Rgb24::new(127, 187, 255);
+    pub const WARLORD: Rgb24 = Rgb24::new(255, 0, 63);
     pub const HEALTH_POTION: Rgb24 = Rgb24::new(255, 0, 255);
+    pub const INVISIBILITY_POTION: Rgb24 = Rgb24::new(187, 187, 255);
+    pub const HASTE_POTION: Rgb24 = Rgb24::new(255, 255, 0);
+    pub const REGENERATION_POTION: Rgb24 = Rgb24::new(0, 255, 127);
     pub const FIREBALL_SCROLL: Rgb24 = Rgb24::new(255, 127, 0);
     pub const CONFUSION_SCROLL: Rgb24 = Rgb24::new(187, 0, 255);
+    pub const SUMMON_SCROLL: Rgb24 = Rgb24::new(127, 187, 255);
+    pub const SLOW_SCROLL: Rgb24 = Rgb24::new(63, 127, 255);
     pub const SWORD: Rgb24 = Rgb24::new(187, 187, 187);
+    pub const FIRE_SWORD: Rgb24 = Rgb24::new(255, 63, 0);
+    pub const CURSED_BLADE: Rgb24 = Rgb24::new(127, 0, 63);
     pub const STAFF: Rgb24 = Rgb24::new(187, 127, 255);
     pub const ARMOUR: Rgb24 = Rgb24::new(127, 127, 127);
     pub const ROBE: Rgb24 = Rgb24::new(127, 127, 187);
+    pub const BOW: Rgb24 = Rgb24::new(187, 127, 63);
+    pub const ARROWS: Rgb24 = Rgb24::new(187, 187, 127);
+    pub const REPAIR_KIT: Rgb24 = Rgb24::new(187, 127, 63);
+    pub const ALTAR: Rgb24 = Rgb24::new(255, 215, 0);
+    pub const FOUNTAIN: Rgb24 = Rgb24::new(63, 187, 255);
 
-    pub fn npc_colour(npc_type: NpcType) -> Rgb24 {
-        match npc_type {
-            NpcType::Orc => ORC,
-            NpcType::Troll => TROLL,
+    pub fn npc_colour(npc: Npc) -> Rgb24 {
+        match (npc.npc_type, npc.elite) {
+            (NpcType::Orc, false) => ORC,
+            (NpcType::Orc, true) => ELITE_ORC,
+            (NpcType::Troll, false) => TROLL,
+            (NpcType::Troll, true) => ELITE_TROLL,
+            (NpcType::Spirit, _) => SPIRIT,
+            (NpcType::Warlord, _) => WARLORD,
         }
     }
 
     pub fn item_colour(item_type: ItemType) -> Rgb24 {
         match item_type {
             ItemType::HealthPotion => HEALTH_POTION,
+            ItemType::InvisibilityPotion => INVISIBILITY_POTION,
+            ItemType::HastePotion => HASTE_POTION,
+            ItemType::RegenerationPotion => REGENERATION_POTION,
             ItemType::FireballScroll => FIREBALL_SCROLL,
             ItemType::ConfusionScroll => CONFUSION_SCROLL,
+            ItemType::SummonScroll => SUMMON_SCROLL,
+            ItemType::SlowScroll => SLOW_SCROLL,
             ItemType::Sword => SWORD,
+            ItemType::FireSword => FIRE_SWORD,
+            ItemType::CursedBlade => CURSED_BLADE,
             ItemType::Staff => STAFF,
             ItemType::Armour => ARMOUR,
             ItemType::Robe => ROBE,
+            ItemType::Bow => BOW,
+            ItemType::Arrows => ARROWS,
+            ItemType::RepairKit => REPAIR_KIT,
+        }
+    }
+
+    // The whole map is tinted by the light of the time of day
+    pub fn time_of_day_tint(time_of_day: TimeOfDay) -> Rgb24 {
+        match time_of_day {
+            TimeOfDay::Day => Rgb24::new_grey(255),
+            TimeOfDay::Dusk => Rgb24::new(255, 187, 127),
+            TimeOfDay::Night => Rgb24::new(127, 127, 223),
+            TimeOfDay::Dawn => Rgb24::new(255, 223, 187),
+        }
+    }
+
+    // Level themes and the player's health also tint the map, on top of the time of day
+    pub fn tint_colour(tint: Tint) -> Rgb24 {
+        match tint {
+            Tint::TimeOfDay(time_of_day) => time_of_day_tint(time_of_day),
+            Tint::Cold => Rgb24::new(191, 223, 255),
+            Tint::Heat => Rgb24::new(255, 191, 159),
+            // Fades in from no tint at a quarter of the player's health
+            Tint::LowHealth { health_percent } => Rgb24::new_grey(155 + health_percent as u8 * 4),
         }
     }
 
@@ -909,6 +3598,8 @@ pub mod colours {
         match projcetile_type {
             ProjectileType::Fireball { .. } => FIREBALL_SCROLL,
             ProjectileType::Confusion { .. } => CONFUSION_SCROLL,
+            ProjectileType::Slow { .. } => SLOW_SCROLL,
+            ProjectileType::Arrow { .. } => ARROWS,
         }
     }
 }
@@ -930,39 +3621,82 @@ fn currently_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
             .with_bold(true)
             .with_foreground(Rgb24::new_grey(255))
             .with_background(Rgb24::new(0, 0, 63)),
+        Tile::UpStairs => ViewCell::new()
+            .with_character('<')
+            .with_bold(true)
+            .with_foreground(Rgb24::new_grey(255))
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::Trapdoor => ViewCell::new()
+            .with_character('^')
+            .with_foreground(Rgb24::new(187, 63, 0))
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::SlowingTrap => ViewCell::new()
+            .with_character('"')
+            .with_foreground(colours::SLOW_SCROLL)
+            .with_background(Rgb24::new(0, 0, 63)),
         Tile::Wall => ViewCell::new()
             .with_character('#')
             .with_foreground(Rgb24::new(0, 63, 63))
             .with_background(Rgb24::new(63, 127, 127)),
-        Tile::Npc(NpcType::Orc) => ViewCell::new()
-            .with_character('o')
+        Tile::Altar => ViewCell::new()
+            .with_character('_')
             .with_bold(true)
-            .with_foreground(colours::ORC),
-        Tile::Npc(NpcType::Troll) => ViewCell::new()
-            .with_character('T')
+            .with_foreground(colours::ALTAR)
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::Fountain => ViewCell::new()
+            .with_character('{')
             .with_bold(true)
-            .with_foreground(colours::TROLL),
-        Tile::NpcCorpse(NpcType::Orc) => ViewCell::new()
-            .with_character('%')
+            .with_foreground(colours::FOUNTAIN)
+            .with_background(Rgb24::new(0, 0, 63)),
+        Tile::Npc(npc) => ViewCell::new()
+            .with_character(match npc.npc_type {
+                NpcType::Orc => 'o',
+                NpcType::Troll => 'T',
+                NpcType::Spirit => 's',
+                NpcType::Warlord => 'W',
+            })
             .with_bold(true)
-            .with_foreground(colours::ORC),
-        Tile::NpcCorpse(NpcType::Troll) => ViewCell::new()
+            .with_foreground(colours::npc_colour(npc)),
+        Tile::NpcCorpse(npc) => ViewCell::new()
             .with_character('%')
             .with_bold(true)
-            .with_foreground(colours::TROLL),
+            .with_foreground(colours::npc_colour(npc)),
         Tile::Item(ItemType::HealthPotion) => ViewCell::new()
             .with_character('!')
             .with_foreground(colours::HEALTH_POTION),
+        Tile::Item(ItemType::InvisibilityPotion) => ViewCell::new()
+            .with_character('!')
+            .with_foreground(colours::INVISIBILITY_POTION),
+        Tile::Item(ItemType::HastePotion) => ViewCell::new()
+            .with_character('!')
+            .with_foreground(colours::HASTE_POTION),
+        Tile::Item(ItemType::RegenerationPotion) => ViewCell::new()
+            .with_character('!')
+            .with_foreground(colours::REGENERATION_POTION),
         Tile::Item(ItemType::FireballScroll) => ViewCell::new()
             .with_character('♫')
             .with_foreground(colours::FIREBALL_SCROLL),
         Tile::Item(ItemType::ConfusionScroll) => ViewCell::new()
             .with_character('♫')
             .with_foreground(colours::CONFUSION_SCROLL),
+        Tile::Item(ItemType::SummonScroll) => ViewCell::new()
+            .with_character('♫')
+            .with_foreground(colours::SUMMON_SCROLL),
+        Tile::Item(ItemType::SlowScroll) => ViewCell::new()
+            .with_character('♫')
+            .with_foreground(colours::SLOW_SCROLL),
         Tile::Item(ItemType::Sword) => ViewCell::new()
             .with_bold(true)
             .with_character('/')
             .with_foreground(colours::SWORD),
+        Tile::Item(ItemType::FireSword) => ViewCell::new()
+            .with_bold(true)
+            .with_character('/')
+            .with_foreground(colours::FIRE_SWORD),
+        Tile::Item(ItemType::CursedBlade) => ViewCell::new()
+            .with_bold(true)
+            .with_character('/')
+            .with_foreground(colours::CURSED_BLADE),
         Tile::Item(ItemType::Staff) => ViewCell::new()
             .with_bold(true)
             .with_character('\\')
@@ -975,50 +3709,300 @@ fn currently_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
             .with_bold(true)
             .with_character('}')
             .with_foreground(colours::ROBE),
+        Tile::Item(ItemType::Bow) => ViewCell::new()
+            .with_bold(true)
+            .with_character(')')
+            .with_foreground(colours::BOW),
+        Tile::Item(ItemType::Arrows) => ViewCell::new()
+            .with_character('|')
+            .with_foreground(colours::ARROWS),
+        Tile::Item(ItemType::RepairKit) => ViewCell::new()
+            .with_character('(')
+            .with_foreground(colours::REPAIR_KIT),
         Tile::Projectile(ProjectileType::Fireball { .. }) => ViewCell::new()
             .with_character('*')
             .with_foreground(colours::FIREBALL_SCROLL),
         Tile::Projectile(ProjectileType::Confusion { .. }) => ViewCell::new()
             .with_character('*')
             .with_foreground(colours::CONFUSION_SCROLL),
+        Tile::Projectile(ProjectileType::Slow { .. }) => ViewCell::new()
+            .with_character('*')
+            .with_foreground(colours::SLOW_SCROLL),
+        Tile::DashTrail => ViewCell::new()
+            .with_character('~')
+            .with_foreground(Rgb24::new_grey(187)),
+        // Damage numbers span several cells so are drawn separately
+        Tile::DamageNumber { .. } => ViewCell::new(),
+        Tile::Projectile(ProjectileType::Arrow { .. }) => ViewCell::new()
+            .with_character('-')
+            .with_foreground(colours::ARROWS),
     }
 }
 
-fn previously_visible_view_cell_of_tile(tile: Tile) -> ViewCell {
+// Remembered cells start out at the maximum brightness and fade to the minimum brightness over
+// the given number of turns
+const REMEMBERED_MAX_BRIGHTNESS: u8 = 95;
+const REMEMBERED_MIN_BRIGHTNESS: u8 = 31;
+const REMEMBERED_FADE_TURNS: u64 = 200;
+
+fn remembered_brightness(turns_ago: u64) -> u8 {
+    let brightness_range = (REMEMBERED_MAX_BRIGHTNESS - REMEMBERED_MIN_BRIGHTNESS) as u64;
+    let fade = turns_ago.min(REMEMBERED_FADE_TURNS) * brightness_range / REMEMBERED_FADE_TURNS;
+    REMEMBERED_MAX_BRIGHTNESS - fade as u8
+}
+
+fn previously_visible_view_cell_of_tile(tile: Tile, turns_ago: u64) -> ViewCell {
+    let foreground = Rgb24::new_grey(remembered_brightness(turns_ago));
     match tile {
         Tile::Floor => ViewCell::new()
             .with_character('.')
-            .with_foreground(Rgb24::new_grey(63))
+            .with_foreground(foreground)
             .with_background(Rgb24::new_grey(0)),
         Tile::Wall => ViewCell::new()
             .with_character('#')
-            .with_foreground(Rgb24::new_grey(63))
+            .with_foreground(foreground)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Trapdoor => ViewCell::new()
+            .with_character('^')
+            .with_foreground(foreground)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::SlowingTrap => ViewCell::new()
+            .with_character('"')
+            .with_foreground(foreground)
+            .with_background(Rgb24::new_grey(0)),
+        Tile::Altar => ViewCell::new()
+            .with_character('_')
+            .with_foreground(foreground)
             .with_background(Rgb24::new_grey(0)),
         _ => ViewCell::new(),
     }
 }
 
+// Subtle animations which play on visible tiles while nothing else is happening
+#[derive(Clone, Copy)]
+enum IdleAnimation {
+    Shimmer,
+    Glint,
+}
+
+fn idle_animation_of_tile(tile: Tile) -> Option<IdleAnimation> {
+    match tile {
+        Tile::Stairs | Tile::UpStairs | Tile::Fountain => Some(IdleAnimation::Shimmer),
+        Tile::Item(_) => Some(IdleAnimation::Glint),
+        _ => None,
+    }
+}
+
+impl IdleAnimation {
+    fn apply(self, view_cell: ViewCell, coord: Coord, animation_frame: u64) -> ViewCell {
+        // Offset each cell's animation so that nearby tiles don't animate in lockstep
+        let offset = (coord.x as u64).wrapping_mul(7) + (coord.y as u64).wrapping_mul(13);
+        match self {
+            Self::Shimmer => {
+                // The background brightens and dims smoothly
+                const PERIOD: u64 = 60;
+                let phase = (animation_frame + offset) % PERIOD;
+                let level = phase.min(PERIOD - phase) as u8;
+                view_cell.with_background(Rgb24::new(0, 0, 63 + level * 2))
+            }
+            Self::Glint => {
+                // Items flash briefly once in a while
+                const PERIOD: u64 = 150;
+                const DURATION: u64 = 3;
+                if (animation_frame + offset * 11) % PERIOD < DURATION {
+                    view_cell.with_foreground(Rgb24::new_grey(255))
+                } else {
+                    view_cell
+                }
+            }
+        }
+    }
+}
+
+fn weather_overlay(
+    view_cell: ViewCell,
+    weather: Weather,
+    coord: Coord,
+    animation_frame: u64,
+) -> ViewCell {
+    match weather {
+        Weather::Clear => view_cell,
+        Weather::Rain => {
+            // Each column has a drop falling down it, starting at a different height
+            const SPACING: u64 = 11;
+            const FRAMES_PER_CELL: u64 = 3;
+            let column_offset = (coord.x as u64).wrapping_mul(7) % SPACING;
+            let drop_y = (animation_frame / FRAMES_PER_CELL + column_offset) % SPACING;
+            if coord.y as u64 % SPACING == drop_y {
+                view_cell
+                    .with_character('|')
+                    .with_foreground(Rgb24::new(63, 127, 255))
+            } else {
+                view_cell
+            }
+        }
+        Weather::Fog => {
+            // Banks of fog drift slowly across the floor
+            const PERIOD: u64 = 32;
+            const FRAMES_PER_CELL: u64 = 8;
+            let phase =
+                (coord.x as u64 + coord.y as u64 * 3 + animation_frame / FRAMES_PER_CELL) % PERIOD;
+            let density = phase.min(PERIOD - phase) as u8 * 8;
+            let fog = Rgb24::new_grey(127);
+            let background = view_cell.style.background.unwrap_or(Rgb24::new_grey(0));
+            view_cell.with_background(background.linear_interpolate(fog, density))
+        }
+    }
+}
+
+// Injured npcs are drawn over a red background which deepens as they lose health
+fn injury_background(hit_points: HitPoints) -> Rgb24 {
+    const MIN_RED: u32 = 31;
+    const MAX_RED: u32 = 191;
+    let missing = hit_points.max.saturating_sub(hit_points.current);
+    let red = MIN_RED + (missing * (MAX_RED - MIN_RED)) / hit_points.max.max(1);
+    Rgb24::new(red as u8, 0, 0)
+}
+
+// Damage numbers fade out over their last few frames
+const DAMAGE_NUMBER_FADE_FRAMES: u32 = 8;
+
+struct GameView {
+    show_damage_numbers: bool,
+    box_drawing_walls: bool,
+    palette: Palette,
+    cache: RenderCache,
+}
+
+// Picks the box-drawing character which joins a wall up with the walls the player has seen in the
+// cells beside it, so that the outlines of rooms are drawn as continuous lines
+fn wall_character(game_state: &GameState, coord: Coord) -> char {
+    let is_wall =
+        |direction: CardinalDirection| game_state.is_known_wall_at(coord + direction.coord());
+    let north = is_wall(CardinalDirection::North);
+    let east = is_wall(CardinalDirection::East);
+    let south = is_wall(CardinalDirection::South);
+    let west = is_wall(CardinalDirection::West);
+    match (north, east, south, west) {
+        (false, false, false, false) => '■',
+        (_, false, _, false) => '║',
+        (false, _, false, _) => '═',
+        (false, true, true, false) => '╔',
+        (false, false, true, true) => '╗',
+        (true, true, false, false) => '╚',
+        (true, false, false, true) => '╝',
+        (true, true, true, false) => '╠',
+        (true, false, true, true) => '╣',
+        (false, true, true, true) => '╦',
+        (true, true, false, true) => '╩',
+        (true, true, true, true) => '╬',
+    }
+}
+
+// A cell of the map which looks different on each animation frame
+struct AnimatedCell {
+    coord: Coord,
+    depth: i8,
+    // How the cell looks before it's animated
+    view_cell: ViewCell,
+    idle_animation: Option<IdleAnimation>,
+    weather: bool,
+}
+
+// What was drawn in the game area on the last frame. Chargrid clears the frame between ticks, so
+// every cell is still set each frame, but cells are only worked out again from the game's
+// entities after something changes the game's render revision. Until then they're copied from
+// here, apart from idle animations and weather, which are reapplied to their cells each frame.
 #[derive(Default)]
-struct GameView {}
+struct RenderCache {
+    render_revision: Option<u64>,
+    cells: Vec<(Coord, i8, ViewCell)>,
+    animated_cells: Vec<AnimatedCell>,
+}
 
-impl<'a> View<&'a GameState> for GameView {
-    fn view<F: Frame, C: ColModify>(
-        &mut self,
-        game_state: &'a GameState,
-        context: ViewContext<C>,
-        frame: &mut F,
-    ) {
+impl GameView {
+    // Forget what was drawn, so the next frame is drawn from scratch
+    fn invalidate(&mut self) {
+        self.cache.render_revision = None;
+    }
+    fn cache_damage_number(&mut self, coord: Coord, damage: u32, frames_remaining: u32) {
+        // Drawn above everything else in the game area
+        const DEPTH: i8 = 10;
+        let brightness =
+            frames_remaining.min(DAMAGE_NUMBER_FADE_FRAMES) * 255 / DAMAGE_NUMBER_FADE_FRAMES;
+        let foreground = Rgb24::new(brightness as u8, brightness as u8 / 4, 0);
+        for (i, ch) in damage.to_string().chars().enumerate() {
+            self.cache.cells.push((
+                coord + Coord::new(i as i32, 0),
+                DEPTH,
+                ViewCell::new()
+                    .with_character(ch)
+                    .with_bold(true)
+                    .with_foreground(foreground),
+            ));
+        }
+    }
+    fn rebuild_cache(&mut self, game_state: &GameState) {
+        self.cache.cells.clear();
+        self.cache.animated_cells.clear();
+        let weather = game_state.weather() != Weather::Clear;
         for entity_to_render in game_state.entities_to_render() {
-            let view_cell = match entity_to_render.visibility {
-                CellVisibility::Currently => {
-                    currently_visible_view_cell_of_tile(entity_to_render.tile)
+            if let Tile::DamageNumber {
+                damage,
+                frames_remaining,
+            } = entity_to_render.tile
+            {
+                if self.show_damage_numbers {
+                    if let CellVisibility::Currently = entity_to_render.visibility {
+                        self.cache_damage_number(
+                            entity_to_render.location.coord,
+                            damage,
+                            frames_remaining,
+                        );
+                    }
                 }
-                CellVisibility::Previously => {
-                    previously_visible_view_cell_of_tile(entity_to_render.tile)
+                continue;
+            }
+            let mut idle_animation = None;
+            let view_cell = match entity_to_render.visibility {
+                CellVisibility::Currently => match entity_to_render.tile {
+                    // The player is drawn faintly while invisible
+                    Tile::Player if game_state.is_player_invisible() => ViewCell::new()
+                        .with_character('@')
+                        .with_foreground(colours::PLAYER_INVISIBLE),
+                    tile @ Tile::Npc(_) => {
+                        let view_cell = currently_visible_view_cell_of_tile(tile);
+                        match entity_to_render.hit_points {
+                            Some(hit_points) if hit_points.current < hit_points.max => {
+                                view_cell.with_background(injury_background(hit_points))
+                            }
+                            _ => view_cell,
+                        }
+                    }
+                    tile => {
+                        idle_animation = idle_animation_of_tile(tile);
+                        currently_visible_view_cell_of_tile(tile)
+                    }
+                },
+                CellVisibility::Previously { turns_ago } => {
+                    previously_visible_view_cell_of_tile(entity_to_render.tile, turns_ago)
                 }
                 CellVisibility::Never => ViewCell::new(),
             };
+            let view_cell = match entity_to_render.tile {
+                Tile::Wall if self.box_drawing_walls => view_cell
+                    .with_character(wall_character(game_state, entity_to_render.location.coord)),
+                _ => view_cell,
+            };
+            // Weather is drawn over the floor of visible cells, beneath anything standing there
+            let weather = weather
+                && matches!(
+                    (entity_to_render.visibility, entity_to_render.location.layer),
+                    (CellVisibility::Currently, Some(Layer::Floor))
+                );
             let depth = match entity_to_render.location.layer {
+                // Dash trails aren't on any layer, but are drawn over the cells they pass through
+                None if matches!(entity_to_render.tile, Tile::DashTrail) => 4,
                 None => -1,
                 Some(Layer::Floor) => 0,
                 Some(Layer::Feature) => 1,
@@ -1026,67 +4010,197 @@ impl<'a> View<&'a GameState> for GameView {
                 Some(Layer::Character) => 3,
                 Some(Layer::Projectile) => 4,
             };
-            frame.set_cell_relative(entity_to_render.location.coord, depth, view_cell, context);
+            // A character which just attacked is drawn over its target for a moment, so it's clear
+            // who hit whom
+            let (coord, depth) = match (entity_to_render.visibility, entity_to_render.lunge) {
+                (CellVisibility::Currently, Some(direction)) => {
+                    (entity_to_render.location.coord + direction.coord(), 5)
+                }
+                _ => (entity_to_render.location.coord, depth),
+            };
+            if idle_animation.is_some() || weather {
+                self.cache.animated_cells.push(AnimatedCell {
+                    coord,
+                    depth,
+                    view_cell,
+                    idle_animation,
+                    weather,
+                });
+            } else {
+                self.cache.cells.push((coord, depth, view_cell));
+            }
+        }
+        // Drawn on the character layer, as only cells without a character have trails
+        for motion_trail in game_state.motion_trails() {
+            let view_cell = currently_visible_view_cell_of_tile(motion_trail.tile);
+            let foreground = view_cell.style.foreground.unwrap_or(Rgb24::new_grey(255));
+            // Starts at half brightness and fades out
+            let foreground = foreground
+                .saturating_scalar_mul_div(motion_trail.frames_remaining, 2 * MOTION_TRAIL_FRAMES);
+            self.cache.cells.push((
+                motion_trail.coord,
+                3,
+                view_cell.with_foreground(foreground).with_bold(false),
+            ));
+        }
+        self.cache.render_revision = Some(game_state.render_revision());
+    }
+}
+
+impl<'a> View<&'a GameState> for GameView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        game_state: &'a GameState,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        let tint = game_state
+            .tints()
+            .map(colours::tint_colour)
+            .fold(Rgb24::new_grey(255), Rgb24::normalised_mul);
+        let palette = self.palette;
+        let context = context.compose_col_modify(ColModifyMap(move |c: Rgb24| {
+            palette.apply(c.normalised_mul(tint))
+        }));
+        if self.cache.render_revision != Some(game_state.render_revision()) {
+            self.rebuild_cache(game_state);
+        }
+        for &(coord, depth, view_cell) in self.cache.cells.iter() {
+            frame.set_cell_relative(coord, depth, view_cell, context);
+        }
+        let animation_frame = game_state.animation_frame();
+        for animated_cell in self.cache.animated_cells.iter() {
+            let view_cell = match animated_cell.idle_animation {
+                Some(idle_animation) => idle_animation.apply(
+                    animated_cell.view_cell,
+                    animated_cell.coord,
+                    animation_frame,
+                ),
+                None => animated_cell.view_cell,
+            };
+            let view_cell = if animated_cell.weather {
+                weather_overlay(
+                    view_cell,
+                    game_state.weather(),
+                    animated_cell.coord,
+                    animation_frame,
+                )
+            } else {
+                view_cell
+            };
+            frame.set_cell_relative(animated_cell.coord, animated_cell.depth, view_cell, context);
         }
     }
 }
 
+// Use the item in an inventory slot, aiming it first if necessary. Returns `Some(())` if the item
+// was used.
+fn use_item_in_slot(
+    index: usize,
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    SideEffectThen::new_with_view(move |data: &mut AppData, _: &_| {
+        make_either!(Ei = A | B | C);
+        match data.game_state.handle_action(PlayerAction::UseItem(index)) {
+            ActionResult::Done => Ei::A(Value::new(Some(()))),
+            ActionResult::NeedsTarget => Ei::B(TargetEventRoutine { name: "AIM" }.and_then(
+                move |maybe_coord| {
+                    SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                        let target = maybe_coord?;
+                        data.lock_target(target);
+                        match data.game_state.handle_action(PlayerAction::UseItemAim {
+                            inventory_index: index,
+                            target,
+                        }) {
+                            ActionResult::Done => Some(()),
+                            _ => None,
+                        }
+                    })
+                },
+            )),
+            ActionResult::Failed => Ei::C(Value::new(None)),
+        }
+    })
+}
+
+// Drop the item in an inventory slot. Returns `Some(())` if the item was dropped.
+fn drop_item_in_slot(
+    index: usize,
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+        match data.game_state.handle_action(PlayerAction::DropItem(index)) {
+            ActionResult::Done => Some(()),
+            _ => None,
+        }
+    })
+}
+
+// Show the details of the item in an inventory slot, then perform the action chosen from the
+// detail screen. Returns `Some(())` if an action was performed.
+fn inspect_item(
+    index: usize,
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    SideEffectThen::new_with_view(move |data: &mut AppData, _: &_| {
+        make_either!(Ei = A | B | C);
+        data.inspected_inventory_index = index;
+        data.item_action_menu = item_action_menu_instance();
+        item_detail_menu().and_then(move |result| match result {
+            Err(menu::Escape) => Ei::A(Value::new(None)),
+            Ok(ItemAction::Use) => Ei::B(use_item_in_slot(index)),
+            Ok(ItemAction::Drop) => Ei::C(drop_item_in_slot(index)),
+        })
+    })
+}
+
+fn sort_inventory(
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+        data.game_state.handle_action(PlayerAction::SortInventory);
+        None
+    })
+}
+
+fn assign_hotbar_slot(
+    inventory_index: usize,
+    hotbar_slot: usize,
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+        data.game_state
+            .handle_action(PlayerAction::AssignHotbarSlot {
+                inventory_index,
+                hotbar_slot,
+            });
+        None
+    })
+}
+
 fn use_item() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
 {
-    make_either!(Ei = A | B);
+    make_either!(Ei = A | B | C | D | E);
     Loop::new(|| {
         inventory_slot_menu("Use Item").and_then(|result| match result {
             Err(menu::Escape) => Ei::A(Value::new(Some(()))),
-            Ok(entry) => Ei::B(SideEffectThen::new_with_view(
-                move |data: &mut AppData, _: &_| {
-                    make_either!(Ei = A | B | C);
-                    if let Ok(usage) = data.game_state.maybe_player_use_item(entry.index) {
-                        match usage {
-                            ItemUsage::Immediate => Ei::A(Value::new(Some(()))),
-                            ItemUsage::Aim => Ei::B(TargetEventRoutine { name: "AIM" }.and_then(
-                                move |maybe_coord| {
-                                    SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
-                                        if let Some(coord) = maybe_coord {
-                                            if data
-                                                .game_state
-                                                .maybe_player_use_item_aim(entry.index, coord)
-                                                .is_ok()
-                                            {
-                                                Some(())
-                                            } else {
-                                                None
-                                            }
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                },
-                            )),
-                        }
-                    } else {
-                        Ei::C(Value::new(None))
-                    }
-                },
-            )),
+            Ok(InventorySlotChoice::Select(entry)) => Ei::B(use_item_in_slot(entry.index)),
+            Ok(InventorySlotChoice::Inspect(entry)) => Ei::C(inspect_item(entry.index)),
+            Ok(InventorySlotChoice::Sort) => Ei::D(sort_inventory()),
+            Ok(InventorySlotChoice::AssignHotbarSlot(entry, hotbar_slot)) => {
+                Ei::E(assign_hotbar_slot(entry.index, hotbar_slot))
+            }
         })
     })
 }
 
 fn drop_item() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
 {
-    make_either!(Ei = A | B);
+    make_either!(Ei = A | B | C | D | E);
     Loop::new(|| {
         inventory_slot_menu("Drop Item").and_then(|result| match result {
             Err(menu::Escape) => Ei::A(Value::new(Some(()))),
-            Ok(entry) => Ei::B(SideEffect::new_with_view(
-                move |data: &mut AppData, _: &_| {
-                    if data.game_state.maybe_player_drop_item(entry.index).is_ok() {
-                        Some(())
-                    } else {
-                        None
-                    }
-                },
-            )),
+            Ok(InventorySlotChoice::Select(entry)) => Ei::B(drop_item_in_slot(entry.index)),
+            Ok(InventorySlotChoice::Inspect(entry)) => Ei::C(inspect_item(entry.index)),
+            Ok(InventorySlotChoice::Sort) => Ei::D(sort_inventory()),
+            Ok(InventorySlotChoice::AssignHotbarSlot(entry, hotbar_slot)) => {
+                Ei::E(assign_hotbar_slot(entry.index, hotbar_slot))
+            }
         })
     })
 }
@@ -1117,6 +4231,123 @@ fn game_over() -> impl EventRoutine<Return = (), Data = AppData, View = AppView,
                 ),
             }
             .view("YOU DIED", context.add_depth(10), frame);
+            AlignView {
+                alignment: Alignment::centre(),
+                view: StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))),
+            }
+            .view(
+                format!("Score: {}", data.game_state.score_inputs().score()),
+                context.add_offset(Coord::new(0, 2)).add_depth(10),
+                frame,
+            );
+            AlignView {
+                alignment: Alignment::centre(),
+                view: StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187))),
+            }
+            .view(
+                format!(
+                    "Died on level {}: {}{}",
+                    data.game_state.dungeon_level(),
+                    data.game_state.level_name(),
+                    if data.game_state.is_peaceful() {
+                        " (peaceful)"
+                    } else {
+                        ""
+                    }
+                ),
+                context.add_offset(Coord::new(0, 4)).add_depth(10),
+                frame,
+            );
+            let challenge_names = data.game_state.challenges().names();
+            if !challenge_names.is_empty() {
+                AlignView {
+                    alignment: Alignment::centre(),
+                    view: StringViewSingleLine::new(
+                        Style::new().with_foreground(Rgb24::new_grey(187)),
+                    ),
+                }
+                .view(
+                    format!("Challenges: {}", challenge_names.join(", ")),
+                    context.add_offset(Coord::new(0, 5)).add_depth(10),
+                    frame,
+                );
+            }
+            let stats = data.game_state.stats();
+            AlignView {
+                alignment: Alignment::centre(),
+                view: StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187))),
+            }
+            .view(
+                format!(
+                    "Steps: {}  Dealt: {}  Taken: {}  Items: {}",
+                    stats.steps_taken,
+                    stats.total_damage_dealt(),
+                    stats.total_damage_taken(),
+                    stats.total_items_used()
+                ),
+                context.add_offset(Coord::new(0, 8)).add_depth(10),
+                frame,
+            );
+            // Endless runs are about depth, so are compared against the deepest one so far
+            if data.game_state.is_endless() {
+                let deepest = data.profile.deepest_endless_level;
+                let text = if data.game_state.dungeon_level() >= deepest {
+                    "New record depth!".to_string()
+                } else {
+                    format!("Record depth: {}", deepest)
+                };
+                AlignView {
+                    alignment: Alignment::centre(),
+                    view: StringViewSingleLine::new(
+                        Style::new().with_foreground(Rgb24::new(255, 187, 63)),
+                    ),
+                }
+                .view(
+                    text,
+                    context.add_offset(Coord::new(0, 6)).add_depth(10),
+                    frame,
+                );
+            }
+            let high_scores = data.profile.high_scores.entries();
+            if !high_scores.is_empty() {
+                AlignView {
+                    alignment: Alignment::centre(),
+                    view: StringViewSingleLine::new(
+                        Style::new().with_foreground(Rgb24::new_grey(255)),
+                    ),
+                }
+                .view(
+                    "High scores",
+                    context.add_offset(Coord::new(0, -8)).add_depth(10),
+                    frame,
+                );
+            }
+            for (rank, high_score) in high_scores.iter().enumerate() {
+                // The score of the game which just ended stands out from the others
+                let rgb24 = if data.high_score_rank == Some(rank) {
+                    Rgb24::new(255, 187, 63)
+                } else {
+                    Rgb24::new_grey(187)
+                };
+                AlignView {
+                    alignment: Alignment::centre(),
+                    view: StringViewSingleLine::new(Style::new().with_foreground(rgb24)),
+                }
+                .view(
+                    format!(
+                        "{}. {}  level {}{}  {} turns",
+                        rank + 1,
+                        high_score.score,
+                        high_score.depth,
+                        if high_score.endless { " (endless)" } else { "" },
+                        high_score.turns
+                    ),
+                    context
+                        .add_offset(Coord::new(0, rank as i32 - 7))
+                        .add_depth(10),
+                    frame,
+                );
+            }
             FillBackgroundView {
                 rgb24: Rgb24::new(31, 0, 0),
                 view: &mut event_routine_view.view.game_view,
@@ -1134,19 +4365,37 @@ fn game_over() -> impl EventRoutine<Return = (), Data = AppData, View = AppView,
                 .render_ui(None, &data, context, frame);
         }
     }
-    Delay::new(Duration::from_millis(2000)).decorated(GameOverDecorate)
+    SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+        data.record_endless_depth();
+        data.record_high_score();
+    })
+    .and_then(|()| Delay::new(Duration::from_millis(2000)).decorated(GameOverDecorate))
 }
 
 fn game_loop() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
 {
-    make_either!(Ei = A | B | C | D | E | F);
+    make_either!(Ei = A | B | C | D | E | F | G | H | I | J | K | L);
     Loop::new(|| {
         GameEventRoutine.and_then(|game_return| match game_return {
             GameReturn::Menu => Ei::A(main_menu().and_then(|choice| {
-                make_either!(Ei = A | B | C);
+                make_either!(Ei = A | B | C | D | E);
                 match choice {
                     Err(menu::Escape) => Ei::A(Value::new(None)),
                     Ok(MainMenuEntry::Resume) => Ei::A(Value::new(None)),
+                    Ok(MainMenuEntry::SpawnNpc) => Ei::D(
+                        arena_spawn_menu("Spawn Monster", ArenaSpawn::all_npcs()).map(|()| None),
+                    ),
+                    Ok(MainMenuEntry::SpawnItem) => Ei::D(
+                        arena_spawn_menu("Spawn Item", ArenaSpawn::all_items()).map(|()| None),
+                    ),
+                    Ok(MainMenuEntry::Options) => Ei::E(OptionsEventRoutine.map(|()| None)),
+                    // Saving without quitting leaves a checkpoint and goes straight back to the game
+                    Ok(MainMenuEntry::Save) => {
+                        Ei::B(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                            data.save_game();
+                            None
+                        }))
+                    }
                     Ok(MainMenuEntry::SaveAndQuit) => {
                         Ei::C(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
                             data.save_game();
@@ -1155,7 +4404,15 @@ fn game_loop() -> impl EventRoutine<Return = (), Data = AppData, View = AppView,
                     }
                     Ok(MainMenuEntry::NewGame) => {
                         Ei::B(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
-                            data.new_game();
+                            data.finish_daily_challenge();
+                            data.new_game(false, false);
+                            None
+                        }))
+                    }
+                    Ok(MainMenuEntry::NewPeacefulGame) => {
+                        Ei::B(SideEffect::new_with_view(|data: &mut AppData, _: &_| {
+                            data.finish_daily_challenge();
+                            data.new_game(true, false);
                             None
                         }))
                     }
@@ -1163,33 +4420,143 @@ fn game_loop() -> impl EventRoutine<Return = (), Data = AppData, View = AppView,
             })),
             GameReturn::GameOver => Ei::B(game_over().and_then(|()| {
                 SideEffect::new_with_view(|data: &mut AppData, _: &_| {
-                    data.new_game();
+                    if !data.game_state.is_arena() {
+                        data.write_morgue_file();
+                    }
+                    data.finish_daily_challenge();
+                    // Keep playing in the same mode after dying
+                    if data.game_state.is_arena() {
+                        data.new_arena();
+                    } else {
+                        data.new_game(data.game_state.is_peaceful(), data.game_state.is_endless());
+                    }
                     None
                 })
             })),
             GameReturn::UseItem => Ei::C(use_item().map(|_| None)),
             GameReturn::DropItem => Ei::D(drop_item().map(|_| None)),
+            GameReturn::UseHotbarItem(inventory_index) => {
+                Ei::H(use_item_in_slot(inventory_index).map(|_| None))
+            }
             GameReturn::Examine => Ei::E(TargetEventRoutine { name: "EXAMINE" }.map(|_| None)),
-            GameReturn::LevelUpAndDescend => Ei::F(level_up_menu().and_then(|maybe_level_up| {
-                SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
-                    match maybe_level_up {
-                        Err(menu::Escape) => (),
-                        Ok(level_up) => data.player_level_up_and_descend(level_up),
-                    }
-                    None
-                })
+            GameReturn::Fire => {
+                Ei::G(TargetEventRoutine { name: "FIRE" }.and_then(|maybe_coord| {
+                    SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+                        if let Some(target) = maybe_coord {
+                            data.lock_target(target);
+                            data.game_state.handle_action(PlayerAction::Fire(target));
+                            data.game_state.update_visibility(data.visibility_algorithm);
+                        }
+                        None
+                    })
+                }))
+            }
+            GameReturn::LevelUpAndDescend => Ei::F(level_up_and_descend()),
+            GameReturn::Hint(hint) => Ei::J(HintEventRoutine { hint }.map(|()| None)),
+            GameReturn::MessageHistory => {
+                Ei::K(MessageHistoryEventRoutine { scroll: 0 }.map(|()| None))
+            }
+            GameReturn::CharacterSheet => Ei::L(CharacterSheetEventRoutine.map(|()| None)),
+            GameReturn::ContextActionMenu => Ei::I(context_action_menu().and_then(|result| {
+                make_either!(Ei = A | B);
+                match result {
+                    Ok(ContextAction::Descend) => Ei::A(level_up_and_descend()),
+                    other => Ei::B(SideEffect::new_with_view(
+                        move |data: &mut AppData, _: &_| {
+                            if let Some(action) = other.ok().and_then(ContextAction::player_action)
+                            {
+                                data.game_state.handle_action(action);
+                                data.game_state.update_visibility(data.visibility_algorithm);
+                            }
+                            None
+                        },
+                    )),
+                }
             })),
         })
     })
     .return_on_exit(|data| data.save_game())
 }
 
+// Spectators go straight to the game being watched, as they have no game of their own to start
+fn top_level() -> impl EventRoutine<Return = (), Data = AppData, View = AppView, Event = CommonEvent>
+{
+    make_either!(Ei = A | B);
+    SideEffectThen::new_with_view(|data: &mut AppData, _: &_| {
+        if data.is_spectating() {
+            Ei::A(game_loop())
+        } else {
+            Ei::B(title_screen())
+        }
+    })
+}
+
+fn level_up_and_descend(
+) -> impl EventRoutine<Return = Option<()>, Data = AppData, View = AppView, Event = CommonEvent> {
+    level_up_menu().and_then(|maybe_level_up| {
+        SideEffect::new_with_view(move |data: &mut AppData, _: &_| {
+            match maybe_level_up {
+                Err(menu::Escape) => (),
+                Ok(level_up) => data.player_level_up_and_descend(level_up),
+            }
+            None
+        })
+    })
+}
+
 pub fn app(
-    screen_size: Size,
+    layout: Layout,
     rng_seed: u64,
     visibility_algorithm: VisibilityAlgorithm,
+    fov_comparison: Option<VisibilityAlgorithm>,
+    wizard: bool,
+    hide_damage_numbers: bool,
+    box_drawing_walls: bool,
+    animation_speed: Option<AnimationSpeed>,
+    npc_respawning: bool,
+    dungeon_config: DungeonConfig,
+    key_repeat_delay: Duration,
+    key_repeat_interval: Duration,
+    daily_challenge: Option<DailyChallenge>,
+    trace: Option<Trace>,
+    save_sync: Option<SaveSync>,
+    #[cfg(feature = "spectate")] spectate: Option<Spectate>,
 ) -> impl ChargridApp {
-    let data = AppData::new(screen_size, rng_seed, visibility_algorithm);
-    let view = AppView::new(screen_size);
-    game_loop().app_one_shot_ignore_return(data, view)
+    #[cfg(feature = "spectate")]
+    let data = AppData::new(
+        layout,
+        rng_seed,
+        visibility_algorithm,
+        fov_comparison,
+        wizard,
+        hide_damage_numbers,
+        animation_speed,
+        npc_respawning,
+        dungeon_config,
+        key_repeat_delay,
+        key_repeat_interval,
+        daily_challenge,
+        trace,
+        save_sync,
+        spectate,
+    );
+    #[cfg(not(feature = "spectate"))]
+    let data = AppData::new(
+        layout,
+        rng_seed,
+        visibility_algorithm,
+        fov_comparison,
+        wizard,
+        hide_damage_numbers,
+        animation_speed,
+        npc_respawning,
+        dungeon_config,
+        key_repeat_delay,
+        key_repeat_interval,
+        daily_challenge,
+        trace,
+        save_sync,
+    );
+    let view = AppView::new(layout, &data.options(), box_drawing_walls);
+    top_level().app_one_shot_ignore_return(data, view)
 }
@@ -1,71 +1,244 @@
-use crate::world::World;
+use crate::task;
+use crate::world::{Faction, HitPoints, NpcType, World};
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::Entity;
-use grid_search_cardinal::{
-    distance_map::{
-        DistanceMap, PopulateContext as DistanceMapPopulateContext,
-        SearchContext as DistanceMapSearchContext,
-    },
-    CanEnter,
-};
+use grid_2d::Grid;
 use line_2d::LineSegment;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use shadowcast::{vision_distance, VisionDistance};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 
+// The cost for an npc to reach the player from each cell, where each step costs the movement cost
+// of the cell being entered. Npcs approach the player along the cheapest route, so they go around
+// hazards when there's a reasonable way around.
+struct WeightedDistanceMap {
+    costs: Grid<u32>,
+}
+
+impl WeightedDistanceMap {
+    const UNREACHABLE: u32 = u32::MAX;
+
+    fn new(size: Size) -> Self {
+        Self {
+            costs: Grid::new_copy(size, Self::UNREACHABLE),
+        }
+    }
+
+    // Dijkstra's algorithm outwards from the destination, stopping once paths cost more than
+    // `max_cost`
+    fn populate(&mut self, destination: Coord, max_cost: u32, world: &World) {
+        if self.costs.size() != world.size() {
+            *self = Self::new(world.size());
+        }
+        for cost in self.costs.iter_mut() {
+            *cost = Self::UNREACHABLE;
+        }
+        let mut queue = BinaryHeap::new();
+        *self.costs.get_checked_mut(destination) = 0;
+        queue.push(Reverse((0, destination)));
+        while let Some(Reverse((cost, coord))) = queue.pop() {
+            if cost > *self.costs.get_checked(coord) {
+                continue;
+            }
+            // The cost of a step from a neighbour is the cost of entering this cell
+            let step_cost = world.npc_move_cost_ignoring_other_npcs(coord).unwrap_or(1);
+            let next_cost = cost + step_cost;
+            if next_cost > max_cost {
+                continue;
+            }
+            for direction in CardinalDirection::all() {
+                let next = coord + direction.coord();
+                if world.npc_move_cost_ignoring_other_npcs(next).is_none() {
+                    continue;
+                }
+                if let Some(next_best_cost) = self.costs.get_mut(next) {
+                    if next_cost < *next_best_cost {
+                        *next_best_cost = next_cost;
+                        queue.push(Reverse((next_cost, next)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn cost(&self, coord: Coord) -> u32 {
+        self.costs.get(coord).cloned().unwrap_or(Self::UNREACHABLE)
+    }
+
+    // The first step of the cheapest route to the player. The costs ignore other npcs, so routes
+    // start with a short search through cells the npc can enter right now, which lets npcs find
+    // their way around allies in their way rather than waiting behind them.
+    fn search_first(&self, npc_coord: Coord, world: &World) -> Option<CardinalDirection> {
+        CardinalDirection::all()
+            .filter_map(|direction| {
+                let route_cost = self.cheapest_route_starting(npc_coord, direction, world)?;
+                Some((direction, route_cost))
+            })
+            .min_by_key(|&(_, route_cost)| route_cost)
+            .map(|(direction, _)| direction)
+    }
+
+    // The cost of the cheapest route to the player which starts with a step in `direction`. Routes
+    // take up to `SEARCH_DISTANCE` steps through cells the npc can enter, and must reach a cell
+    // which is cheaper than `npc_coord`, from where the rest of the route costs what the map says.
+    fn cheapest_route_starting(
+        &self,
+        npc_coord: Coord,
+        direction: CardinalDirection,
+        world: &World,
+    ) -> Option<u32> {
+        const SEARCH_DISTANCE: u32 = 5;
+        let current_cost = self.cost(npc_coord);
+        let first_coord = npc_coord + direction.coord();
+        if !world.can_npc_enter(first_coord) {
+            return None;
+        }
+        let mut best = None;
+        let mut visited = vec![npc_coord];
+        let mut queue = BinaryHeap::new();
+        let first_step_cost = world.npc_move_cost_ignoring_other_npcs(first_coord)?;
+        queue.push(Reverse((first_step_cost, 1, first_coord)));
+        while let Some(Reverse((path_cost, steps, coord))) = queue.pop() {
+            if visited.contains(&coord) {
+                continue;
+            }
+            visited.push(coord);
+            let remaining_cost = self.cost(coord);
+            if remaining_cost < current_cost {
+                let route_cost = path_cost.saturating_add(remaining_cost);
+                best = Some(best.map_or(route_cost, |best: u32| best.min(route_cost)));
+            }
+            if steps == SEARCH_DISTANCE {
+                continue;
+            }
+            for direction in CardinalDirection::all() {
+                let next = coord + direction.coord();
+                if visited.contains(&next) || !world.can_npc_enter(next) {
+                    continue;
+                }
+                if let Some(step_cost) = world.npc_move_cost_ignoring_other_npcs(next) {
+                    queue.push(Reverse((path_cost + step_cost, steps + 1, next)));
+                }
+            }
+        }
+        best
+    }
+}
+
+impl Default for WeightedDistanceMap {
+    fn default() -> Self {
+        Self::new(Size::new(0, 0))
+    }
+}
+
+// Recomputed at the start of each npc turn, so none of it needs saving
 #[derive(Serialize, Deserialize)]
 pub struct BehaviourContext {
-    distance_map_to_player: DistanceMap,
-    distance_map_populate_context: DistanceMapPopulateContext,
-    distance_map_search_context: DistanceMapSearchContext,
+    #[serde(skip)]
+    distance_map_to_player: WeightedDistanceMap,
 }
 
 impl BehaviourContext {
     pub fn new(size: Size) -> Self {
         Self {
-            distance_map_to_player: DistanceMap::new(size),
-            distance_map_populate_context: DistanceMapPopulateContext::default(),
-            distance_map_search_context: DistanceMapSearchContext::new(size),
+            distance_map_to_player: WeightedDistanceMap::new(size),
         }
     }
 
     pub fn update(&mut self, player: Entity, world: &World) {
-        struct NpcCanEnterIgnoringOtherNpcs<'a> {
-            world: &'a World,
-        }
-        impl<'a> CanEnter for NpcCanEnterIgnoringOtherNpcs<'a> {
-            fn can_enter(&self, coord: Coord) -> bool {
-                self.world.can_npc_enter_ignoring_other_npcs(coord)
-            }
-        }
         let player_coord = world.entity_coord(player).expect("player has no coord");
-        const MAX_APPROACH_DISTANCE: u32 = 20;
-        self.distance_map_populate_context.add(player_coord);
-        self.distance_map_populate_context.populate_approach(
-            &NpcCanEnterIgnoringOtherNpcs { world },
-            MAX_APPROACH_DISTANCE,
-            &mut self.distance_map_to_player,
-        );
+        const MAX_APPROACH_COST: u32 = 20;
+        self.distance_map_to_player
+            .populate(player_coord, MAX_APPROACH_COST, world);
+    }
+
+    fn approach_player(&self, npc_coord: Coord, world: &World) -> NpcAction {
+        match self.distance_map_to_player.search_first(npc_coord, world) {
+            None => NpcAction::Wait,
+            Some(direction) => NpcAction::Move(direction),
+        }
     }
 }
 
 pub enum NpcAction {
     Wait,
     Move(CardinalDirection),
+    // Attack the character in the given direction, even if they're on the same side
+    Attack(CardinalDirection),
+    // A boss spends a turn moving on to the next phase of its fight
+    EnterPhase(BossPhase),
+}
+
+// The stages of a boss fight, which a boss moves through in order as it loses hit points
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum BossPhase {
+    Calm,
+    // Below two thirds of its hit points, a boss summons minions
+    Summoning,
+    // Below a third of its hit points, a boss attacks twice whenever it attacks
+    Enraged,
+}
+
+impl BossPhase {
+    fn for_hit_points(hit_points: HitPoints) -> Self {
+        if hit_points.current * 3 < hit_points.max {
+            Self::Enraged
+        } else if hit_points.current * 3 < hit_points.max * 2 {
+            Self::Summoning
+        } else {
+            Self::Calm
+        }
+    }
+    fn next(self) -> Self {
+        match self {
+            Self::Calm => Self::Summoning,
+            Self::Summoning | Self::Enraged => Self::Enraged,
+        }
+    }
+}
+
+// An npc hurt by another npc on its own side fights back for a while
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Grudge {
+    target: Entity,
+    turns_remaining: u32,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Agent {
     turns_since_last_saw_player: u32,
+    // Npcs which hear the player keep approaching them for a while, even without seeing them
+    turns_to_follow_noise: u32,
+    // Where the npc is strolling to while it doesn't know where the player is
+    #[serde(default)]
+    wander_destination: Option<Coord>,
+    // Npcs with a patrol route walk it in a loop instead of wandering, until they see the player
+    #[serde(default)]
+    patrol_route: Vec<Coord>,
+    // The waypoint the npc is currently walking to
+    #[serde(default)]
+    patrol_index: usize,
+    #[serde(default)]
+    grudge: Option<Grudge>,
+    // Only bosses have a phase
+    #[serde(default)]
+    boss_phase: Option<BossPhase>,
 }
 
-fn npc_has_line_of_sight(src: Coord, dst: Coord, world: &World) -> bool {
+pub(crate) fn npc_has_line_of_sight(src: Coord, dst: Coord, world: &World) -> bool {
     const NPC_VISION_DISTANCE_SQUARED: u32 = 100;
     const NPC_VISION_DISTANCE: vision_distance::Circle =
         vision_distance::Circle::new_squared(NPC_VISION_DISTANCE_SQUARED);
     if src == dst {
         return true;
     }
+    // Invisible characters can only be noticed from adjacent cells
+    if src.manhattan_distance(dst) > 1 && world.is_invisible_character_at(dst) {
+        return false;
+    }
     for coord in LineSegment::new(src, dst).iter() {
         let src_to_coord = coord - src;
         if !NPC_VISION_DISTANCE.in_range(src_to_coord) {
@@ -82,44 +255,271 @@ impl Agent {
     pub fn new() -> Self {
         Self {
             turns_since_last_saw_player: u32::MAX,
+            turns_to_follow_noise: 0,
+            wander_destination: None,
+            patrol_route: Vec::new(),
+            patrol_index: 0,
+            grudge: None,
+            boss_phase: None,
+        }
+    }
+
+    pub fn new_boss() -> Self {
+        Self {
+            boss_phase: Some(BossPhase::Calm),
+            ..Self::new()
+        }
+    }
+
+    pub fn for_npc_type(npc_type: NpcType) -> Self {
+        if npc_type.is_boss() {
+            Self::new_boss()
+        } else {
+            Self::new()
+        }
+    }
+
+    pub fn attacks_per_action(&self) -> u32 {
+        if self.boss_phase == Some(BossPhase::Enraged) {
+            2
+        } else {
+            1
         }
     }
 
-    pub fn act(
+    pub fn new_patrolling(patrol_route: Vec<Coord>) -> Self {
+        Self {
+            patrol_route,
+            ..Self::new()
+        }
+    }
+
+    pub fn hear_noise(&mut self) {
+        const TURNS_TO_FOLLOW_NOISE: u32 = 10;
+        self.turns_to_follow_noise = TURNS_TO_FOLLOW_NOISE;
+    }
+
+    pub fn provoke(&mut self, attacker: Entity) {
+        const GRUDGE_TURNS: u32 = 8;
+        self.grudge = Some(Grudge {
+            target: attacker,
+            turns_remaining: GRUDGE_TURNS,
+        });
+    }
+
+    pub fn act<R: Rng>(
         &mut self,
         entity: Entity,
         player: Entity,
         world: &World,
-        behaviour_context: &mut BehaviourContext,
+        behaviour_context: &BehaviourContext,
+        rng: &mut R,
     ) -> NpcAction {
-        struct NpcCanEnter<'a> {
-            world: &'a World,
+        let npc_coord = world.entity_coord(entity).expect("npc has no coord");
+        let player_coord = world.entity_coord(player).expect("player has no coord");
+        if let Some(npc_action) = self.boss_act(entity, npc_coord, player_coord, world) {
+            return npc_action;
+        }
+        if let Some(npc_action) = self.pursue_grudge(npc_coord, world) {
+            return npc_action;
+        }
+        if world.faction(entity) == Some(Faction::Player) {
+            return act_ally(npc_coord, player_coord, world, behaviour_context);
         }
-        impl<'a> CanEnter for NpcCanEnter<'a> {
-            fn can_enter(&self, coord: Coord) -> bool {
-                self.world.can_npc_enter(coord)
+        // Allies in the way are attacked, but the player is always the priority
+        if npc_coord.manhattan_distance(player_coord) > 1 {
+            if let Some(direction) = adjacent_hostile_direction(npc_coord, Faction::Monster, world)
+            {
+                return NpcAction::Move(direction);
             }
         }
-        let npc_coord = world.entity_coord(entity).expect("npc has no coord");
-        let player_coord = world.entity_coord(player).expect("player has no coord");
         if npc_has_line_of_sight(npc_coord, player_coord, world) {
             self.turns_since_last_saw_player = 0;
         } else {
             self.turns_since_last_saw_player = self.turns_since_last_saw_player.saturating_add(1);
         }
+        let following_noise = self.turns_to_follow_noise > 0;
+        self.turns_to_follow_noise = self.turns_to_follow_noise.saturating_sub(1);
         const MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT: u32 = 3;
-        if self.turns_since_last_saw_player > MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT {
+        if self.turns_since_last_saw_player > MAX_TURNS_TO_CHASE_PLAYER_AFTER_LOSING_SIGHT
+            && !following_noise
+        {
+            return if self.patrol_route.is_empty() {
+                self.wander(npc_coord, world, rng)
+            } else {
+                self.patrol(npc_coord, world)
+            };
+        }
+        self.wander_destination = None;
+        behaviour_context.approach_player(npc_coord, world)
+    }
+
+    // A boss moves on to its next phase as soon as it has lost enough hit points, one phase at a
+    // time. Once enraged, it attacks the player directly so that each attack lands twice.
+    fn boss_act(
+        &mut self,
+        entity: Entity,
+        npc_coord: Coord,
+        player_coord: Coord,
+        world: &World,
+    ) -> Option<NpcAction> {
+        let phase = self.boss_phase.as_mut()?;
+        let hit_points = world.hit_points(entity)?;
+        if BossPhase::for_hit_points(hit_points) > *phase {
+            *phase = phase.next();
+            return Some(NpcAction::EnterPhase(*phase));
+        }
+        if *phase == BossPhase::Enraged {
+            return CardinalDirection::all()
+                .find(|direction| npc_coord + direction.coord() == player_coord)
+                .map(NpcAction::Attack);
+        }
+        None
+    }
+
+    // Takes priority over everything else until the grudge runs out, or the npc loses sight of the
+    // one it holds the grudge against
+    fn pursue_grudge(&mut self, npc_coord: Coord, world: &World) -> Option<NpcAction> {
+        let grudge = self.grudge.as_mut()?;
+        grudge.turns_remaining = grudge.turns_remaining.saturating_sub(1);
+        let target_coord = world
+            .entity_coord(grudge.target)
+            .filter(|_| grudge.turns_remaining > 0 && world.is_living_character(grudge.target))
+            .filter(|&coord| npc_has_line_of_sight(npc_coord, coord, world));
+        let target_coord = match target_coord {
+            Some(target_coord) => target_coord,
+            None => {
+                self.grudge = None;
+                return None;
+            }
+        };
+        let npc_action = match CardinalDirection::all()
+            .find(|direction| npc_coord + direction.coord() == target_coord)
+        {
+            Some(direction) => NpcAction::Attack(direction),
+            None => step_closer(npc_coord, target_coord, world),
+        };
+        Some(npc_action)
+    }
+
+    // Stroll towards a random nearby destination, choosing a new one after arriving or finding
+    // that the destination can't be reached
+    fn wander<R: Rng>(&mut self, npc_coord: Coord, world: &World, rng: &mut R) -> NpcAction {
+        const WANDER_DISTANCE: i32 = 6;
+        // Npcs stop to look around as they wander, so they move slower than when giving chase
+        const CHANCE_TO_PAUSE: f64 = 0.5;
+        if self.wander_destination == Some(npc_coord) {
+            self.wander_destination = None;
+        }
+        if rng.gen_bool(CHANCE_TO_PAUSE) {
             return NpcAction::Wait;
         }
-        const SEARCH_DISTANCE: u32 = 5;
-        match behaviour_context.distance_map_search_context.search_first(
-            &NpcCanEnter { world },
-            npc_coord,
-            SEARCH_DISTANCE,
-            &behaviour_context.distance_map_to_player,
-        ) {
-            None => NpcAction::Wait,
-            Some(direction) => NpcAction::Move(direction),
+        let destination = match self.wander_destination {
+            Some(destination) => destination,
+            None => {
+                let offset = Coord::new(
+                    rng.gen_range(-WANDER_DISTANCE..=WANDER_DISTANCE),
+                    rng.gen_range(-WANDER_DISTANCE..=WANDER_DISTANCE),
+                );
+                let destination = npc_coord + offset;
+                // Rather than retrying, the npc pauses and picks again next turn
+                if destination == npc_coord
+                    || !world.can_npc_enter_ignoring_other_npcs(destination)
+                    || world.hazard_at(destination).is_some()
+                {
+                    return NpcAction::Wait;
+                }
+                self.wander_destination = Some(destination);
+                destination
+            }
+        };
+        unaware_step_towards(npc_coord, destination, world).unwrap_or_else(|| {
+            self.wander_destination = None;
+            NpcAction::Wait
+        })
+    }
+
+    // Walk towards the current waypoint, moving on to the next one after arriving or finding that
+    // the waypoint can't be reached
+    fn patrol(&mut self, npc_coord: Coord, world: &World) -> NpcAction {
+        let waypoint = self.patrol_route[self.patrol_index];
+        match unaware_step_towards(npc_coord, waypoint, world) {
+            Some(npc_action) if npc_coord != waypoint => npc_action,
+            _ => {
+                self.patrol_index = (self.patrol_index + 1) % self.patrol_route.len();
+                NpcAction::Wait
+            }
         }
     }
 }
+
+// The first step along a path to the destination, for an npc which doesn't know where the player
+// is. Returns `None` if the destination can't be reached.
+fn unaware_step_towards(npc_coord: Coord, destination: Coord, world: &World) -> Option<NpcAction> {
+    let direction = task::first_step_towards(
+        npc_coord,
+        world.size(),
+        |coord| world.npc_move_cost_ignoring_other_npcs(coord),
+        |coord| coord == destination,
+    )?;
+    let next_coord = npc_coord + direction.coord();
+    // An npc which doesn't know where the player is never attacks by walking into them
+    if world.can_npc_enter(next_coord)
+        && !world.is_hostile_character_at(next_coord, Faction::Monster)
+    {
+        Some(NpcAction::Move(direction))
+    } else {
+        Some(NpcAction::Wait)
+    }
+}
+
+fn adjacent_hostile_direction(
+    coord: Coord,
+    faction: Faction,
+    world: &World,
+) -> Option<CardinalDirection> {
+    CardinalDirection::all()
+        .find(|direction| world.is_hostile_character_at(coord + direction.coord(), faction))
+}
+
+// Take whichever step gets closest to a target in sight, without planning a path around obstacles.
+// Hazards are never stepped on, as the target is close enough to wait for.
+fn step_closer(npc_coord: Coord, target_coord: Coord, world: &World) -> NpcAction {
+    let current_distance = npc_coord.distance2(target_coord);
+    CardinalDirection::all()
+        .filter(|direction| {
+            let coord = npc_coord + direction.coord();
+            world.can_npc_enter(coord) && world.hazard_at(coord).is_none()
+        })
+        .map(|direction| {
+            let distance = (npc_coord + direction.coord()).distance2(target_coord);
+            (direction, distance)
+        })
+        .filter(|&(_, distance)| distance < current_distance)
+        .min_by_key(|&(_, distance)| distance)
+        .map_or(NpcAction::Wait, |(direction, _)| NpcAction::Move(direction))
+}
+
+// Allies attack the nearest monster they can see, and otherwise stay close to the player
+fn act_ally(
+    npc_coord: Coord,
+    player_coord: Coord,
+    world: &World,
+    behaviour_context: &BehaviourContext,
+) -> NpcAction {
+    if let Some(direction) = adjacent_hostile_direction(npc_coord, Faction::Player, world) {
+        return NpcAction::Move(direction);
+    }
+    let nearest_visible_hostile_coord = world
+        .hostile_character_coords(Faction::Player)
+        .filter(|&coord| npc_has_line_of_sight(npc_coord, coord, world))
+        .min_by_key(|&coord| npc_coord.distance2(coord));
+    if let Some(target_coord) = nearest_visible_hostile_coord {
+        return step_closer(npc_coord, target_coord, world);
+    }
+    const MAX_DISTANCE_FROM_PLAYER: u32 = 2;
+    if npc_coord.manhattan_distance(player_coord) <= MAX_DISTANCE_FROM_PLAYER {
+        return NpcAction::Wait;
+    }
+    behaviour_context.approach_player(npc_coord, world)
+}
@@ -0,0 +1,130 @@
+use chargrid_roguelike_tutorial_2020::{
+    generate_dungeon, DungeonConfig, DungeonStats, LevelName, RoomPlacement, TerrainTile,
+};
+use coord_2d::Size;
+use meap;
+use rand::{Rng, SeedableRng};
+use rand_isaac::Isaac64Rng;
+
+// Matches the size of the game area in the main binary
+const DEFAULT_WIDTH: u32 = 40;
+const DEFAULT_HEIGHT: u32 = 25;
+
+struct Args {
+    first_rng_seed: u64,
+    num_dungeons: u64,
+    level: u32,
+    size: Size,
+    dungeon_config: DungeonConfig,
+    stats_only: bool,
+}
+
+impl Args {
+    fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                first_rng_seed = opt_opt::<u64, _>("INT", 'r').name("rng-seed").desc("seed for the first dungeon")
+                    .with_default_lazy("randomly chosen seed", || rand::thread_rng().gen());
+                num_dungeons = opt_opt::<u64, _>("INT", 'n').name("num-dungeons").desc("number of dungeons to generate, with consecutive seeds")
+                    .with_default(1);
+                level = opt_opt::<u32, _>("INT", 'l').name("level").desc("dungeon level to generate")
+                    .with_default(1);
+                width = opt_opt::<u32, _>("INT", 'x').name("width").desc("width of dungeon")
+                    .with_default(DEFAULT_WIDTH);
+                height = opt_opt::<u32, _>("INT", 'y').name("height").desc("height of dungeon")
+                    .with_default(DEFAULT_HEIGHT);
+                bsp = flag("bsp").desc("place rooms by binary space partitioning");
+                stats_only = flag("stats-only").desc("print the statistics of each dungeon without drawing it");
+            } in {
+                let room_placement = if bsp { RoomPlacement::Bsp } else { RoomPlacement::Random };
+                Self {
+                    first_rng_seed,
+                    num_dungeons,
+                    level,
+                    size: Size::new(width, height),
+                    dungeon_config: DungeonConfig {
+                        room_placement,
+                        ..DungeonConfig::default()
+                    },
+                    stats_only,
+                }
+            }
+        }
+    }
+}
+
+fn terrain_tile_char(terrain_tile: TerrainTile) -> char {
+    match terrain_tile {
+        TerrainTile::Player => '@',
+        TerrainTile::Floor => '.',
+        TerrainTile::Wall => '#',
+        TerrainTile::Stairs => '>',
+        TerrainTile::Altar => '_',
+        TerrainTile::Fountain => '{',
+        TerrainTile::Trapdoor => '^',
+        TerrainTile::SlowingTrap => '"',
+        TerrainTile::Npc(npc) => npc.ascii_char(),
+        TerrainTile::Item(item_type) => item_type.ascii_char(),
+    }
+}
+
+fn print_stats(stats: &DungeonStats) {
+    let stairs_distance = stats.stairs_distance.map_or_else(
+        || "unreachable".to_string(),
+        |distance| distance.to_string(),
+    );
+    println!(
+        "Rooms: {}, Floor: {}, Corridors: {}, Npcs: {}, Items: {} ({} healing), Unreachable: {}, Stairs distance: {}",
+        stats.num_rooms,
+        stats.floor_area,
+        stats.corridor_length,
+        stats.num_npcs,
+        stats.num_items,
+        stats.num_healing_items,
+        stats.unreachable_area,
+        stairs_distance
+    );
+}
+
+fn main() {
+    use meap::Parser;
+    let Args {
+        first_rng_seed,
+        num_dungeons,
+        level,
+        size,
+        dungeon_config,
+        stats_only,
+    } = Args::parser().with_help_default().parse_env_or_exit();
+    let mut num_failing = 0;
+    for i in 0..num_dungeons {
+        let rng_seed = first_rng_seed.wrapping_add(i);
+        let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
+        let level_name = LevelName::choose(&mut rng);
+        let dungeon = generate_dungeon(size, level, level_name.theme, dungeon_config, &mut rng);
+        println!("RNG Seed: {}, Level: {} ({})", rng_seed, level, level_name);
+        if !stats_only {
+            for row in dungeon.grid.rows() {
+                let line = row
+                    .iter()
+                    .map(|&terrain_tile| terrain_tile_char(terrain_tile))
+                    .collect::<String>();
+                println!("{}", line);
+            }
+        }
+        let stats = dungeon.stats();
+        print_stats(&stats);
+        let unmet_constraints = stats.unmet_constraints();
+        if !unmet_constraints.is_empty() {
+            num_failing += 1;
+        }
+        for constraint in unmet_constraints {
+            println!("Unmet constraint: {}", constraint.description());
+        }
+        println!();
+    }
+    println!(
+        "{} of {} dungeons failed to meet a constraint",
+        num_failing, num_dungeons
+    );
+}
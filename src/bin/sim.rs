@@ -0,0 +1,191 @@
+use chargrid_roguelike_tutorial_2020::{
+    bot::{self, BotOutcome, SimpleBot},
+    Challenges, DungeonConfig, GameState, RoomPlacement, VisibilityAlgorithm,
+};
+use coord_2d::Size;
+use meap;
+use rand::Rng;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Matches the size of the game area in the main binary
+const WIDTH: u32 = 40;
+const HEIGHT: u32 = 24;
+
+struct Args {
+    first_rng_seed: u64,
+    num_games: u64,
+    max_actions: u64,
+    output_path: String,
+}
+
+impl Args {
+    fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                first_rng_seed = opt_opt::<u64, _>("INT", 'r').name("rng-seed").desc("seed for the first game of each variation")
+                    .with_default_lazy("randomly chosen seed", || rand::thread_rng().gen());
+                num_games = opt_opt::<u64, _>("INT", 'n').name("num-games").desc("number of games to play for each variation, with consecutive seeds")
+                    .with_default(1000);
+                max_actions = opt_opt::<u64, _>("INT", 'm').name("max-actions").desc("abandon a game after the bot has taken this many actions")
+                    .with_default(20000);
+                output_path = opt_req::<String, _>("PATH", 'o').name("output").desc("file to write the outcome of each game to, as CSV");
+            } in {
+                Self {
+                    first_rng_seed,
+                    num_games,
+                    max_actions,
+                    output_path,
+                }
+            }
+        }
+    }
+}
+
+// A difficulty or dungeon setting to compare against the others
+struct Variation {
+    name: &'static str,
+    dungeon_config: DungeonConfig,
+    challenges: Challenges,
+}
+
+fn variations() -> Vec<Variation> {
+    let dungeon_config = DungeonConfig::default();
+    let challenges = Challenges::default();
+    vec![
+        Variation {
+            name: "default",
+            dungeon_config,
+            challenges,
+        },
+        Variation {
+            name: "bsp",
+            dungeon_config: DungeonConfig {
+                room_placement: RoomPlacement::Bsp,
+                ..dungeon_config
+            },
+            challenges,
+        },
+        Variation {
+            name: "sparse-npcs",
+            dungeon_config: DungeonConfig {
+                npcs_per_thousand_cells: dungeon_config.npcs_per_thousand_cells / 2,
+                ..dungeon_config
+            },
+            challenges,
+        },
+        Variation {
+            name: "sparse-items",
+            dungeon_config: DungeonConfig {
+                items_per_thousand_cells: dungeon_config.items_per_thousand_cells / 2,
+                ..dungeon_config
+            },
+            challenges,
+        },
+        Variation {
+            name: "endless",
+            dungeon_config: DungeonConfig {
+                endless: true,
+                ..dungeon_config
+            },
+            challenges,
+        },
+        Variation {
+            name: "double-monsters",
+            dungeon_config,
+            challenges: Challenges {
+                double_monsters: true,
+                ..challenges
+            },
+        },
+        Variation {
+            name: "no-items",
+            dungeon_config,
+            challenges: Challenges {
+                no_items: true,
+                ..challenges
+            },
+        },
+        Variation {
+            name: "fragile",
+            dungeon_config,
+            challenges: Challenges {
+                fragile: true,
+                ..challenges
+            },
+        },
+    ]
+}
+
+fn cause_of_death(outcome: &BotOutcome) -> &'static str {
+    match (outcome.died, outcome.killed_by) {
+        (false, _) => "",
+        (true, Some(npc)) => npc.name(),
+        (true, None) => "other",
+    }
+}
+
+fn write_outcome<W: Write>(
+    writer: &mut W,
+    variation: &Variation,
+    rng_seed: u64,
+    outcome: &BotOutcome,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{},{},{},{},{},{},{}",
+        variation.name,
+        rng_seed,
+        outcome.depth,
+        outcome.turns,
+        outcome.kills,
+        outcome.died,
+        cause_of_death(outcome)
+    )
+}
+
+fn run(args: Args) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(&args.output_path)?);
+    writeln!(
+        writer,
+        "variation,seed,depth,turns,kills,died,cause_of_death"
+    )?;
+    for variation in variations() {
+        let mut deaths = 0;
+        let mut total_depth = 0;
+        for rng_seed in args.first_rng_seed..args.first_rng_seed.wrapping_add(args.num_games) {
+            let mut game_state = GameState::new(
+                Size::new(WIDTH, HEIGHT),
+                rng_seed,
+                VisibilityAlgorithm::Shadowcast,
+                false,
+                variation.dungeon_config,
+                variation.challenges,
+            );
+            let outcome = bot::play(&mut SimpleBot, &mut game_state, args.max_actions);
+            write_outcome(&mut writer, &variation, rng_seed, &outcome)?;
+            total_depth += outcome.depth as u64;
+            if outcome.died {
+                deaths += 1;
+            }
+        }
+        // The game prints each seed to stdout as it starts, so progress goes to stderr
+        eprintln!(
+            "{}: {} deaths in {} games, mean depth {:.2}",
+            variation.name,
+            deaths,
+            args.num_games,
+            total_depth as f64 / args.num_games.max(1) as f64
+        );
+    }
+    writer.flush()
+}
+
+fn main() {
+    use meap::Parser;
+    let args = Args::parser().with_help_default().parse_env_or_exit();
+    if let Err(error) = run(args) {
+        eprintln!("Failed to write simulation results: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::game::{ActionResult, GameState, LevelUp, LogEntry, LogMessage, PlayerAction};
+use crate::task;
+use crate::visibility::{CellVisibility, VisibilityAlgorithm};
+use crate::world::{HitPoints, ItemType, Npc, Tile};
+use coord_2d::{Coord, Size};
+use direction::CardinalDirection;
+use grid_2d::Grid;
+
+// What the player knows about a cell of the current level
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObservedCell {
+    Unknown,
+    Floor,
+    Wall,
+    Stairs,
+}
+
+impl ObservedCell {
+    fn is_walkable(self) -> bool {
+        matches!(self, Self::Floor | Self::Stairs)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ObservedNpc {
+    pub coord: Coord,
+    pub npc: Npc,
+    pub hit_points: HitPoints,
+}
+
+// Everything a bot is told before choosing an action. This is no more than a human player could
+// see on the screen: npcs and items out of sight are left out, and only remembered cells are known.
+pub struct Observation<'a> {
+    pub cells: Grid<ObservedCell>,
+    pub player_coord: Coord,
+    pub hit_points: HitPoints,
+    pub dungeon_level: u32,
+    // Enemies the player can currently see, nearest first
+    pub npcs: Vec<ObservedNpc>,
+    // Items on the floor which the player can currently see
+    pub items: Vec<(Coord, ItemType)>,
+    // The type of the item in each inventory slot
+    pub inventory: Vec<Option<ItemType>>,
+    pub item_under_player: Option<ItemType>,
+    pub messages: &'a [LogEntry],
+}
+
+impl<'a> Observation<'a> {
+    pub fn new(game_state: &'a GameState) -> Self {
+        let mut cells = Grid::new_copy(game_state.size(), ObservedCell::Unknown);
+        let mut items = Vec::new();
+        for entity in game_state.entities_to_render() {
+            if let CellVisibility::Never = entity.visibility {
+                continue;
+            }
+            let coord = entity.location.coord;
+            let observed = match entity.tile {
+                Tile::Wall => Some(ObservedCell::Wall),
+                Tile::Stairs => Some(ObservedCell::Stairs),
+                Tile::Floor | Tile::UpStairs | Tile::Trapdoor | Tile::SlowingTrap => {
+                    Some(ObservedCell::Floor)
+                }
+                Tile::Item(item_type) => {
+                    if let CellVisibility::Currently = entity.visibility {
+                        items.push((coord, item_type));
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let (Some(observed), Some(cell)) = (observed, cells.get_mut(coord)) {
+                // The floor under the stairs mustn't hide them
+                if *cell != ObservedCell::Stairs {
+                    *cell = observed;
+                }
+            }
+        }
+        let npcs = game_state
+            .visible_npcs()
+            .into_iter()
+            .filter_map(|visible_npc| {
+                Some(ObservedNpc {
+                    coord: game_state.visible_npc_coord(visible_npc.entity)?,
+                    npc: visible_npc.npc,
+                    hit_points: visible_npc.hit_points,
+                })
+            })
+            .collect();
+        let inventory = game_state
+            .player_inventory()
+            .slots()
+            .iter()
+            .map(|slot| slot.and_then(|entity| game_state.item_type(entity)))
+            .collect();
+        Self {
+            cells,
+            player_coord: game_state.player_coord(),
+            hit_points: game_state.player_hit_points(),
+            dungeon_level: game_state.dungeon_level(),
+            npcs,
+            items,
+            inventory,
+            item_under_player: game_state.item_under_player(),
+            messages: game_state.message_log(),
+        }
+    }
+    pub fn size(&self) -> Size {
+        self.cells.size()
+    }
+    pub fn cell(&self, coord: Coord) -> ObservedCell {
+        self.cells
+            .get(coord)
+            .copied()
+            .unwrap_or(ObservedCell::Unknown)
+    }
+    pub fn is_inventory_full(&self) -> bool {
+        self.inventory.iter().all(Option::is_some)
+    }
+    // The first step along the shortest known path to the nearest cell satisfying `is_goal`
+    pub fn first_step_towards<G: Fn(Coord) -> bool>(
+        &self,
+        is_goal: G,
+    ) -> Option<CardinalDirection> {
+        task::first_step_towards(
+            self.player_coord,
+            self.size(),
+            |coord| {
+                if self.cell(coord).is_walkable() {
+                    Some(1)
+                } else {
+                    None
+                }
+            },
+            is_goal,
+        )
+    }
+}
+
+// Something which plays the game by choosing an action on each of the player's turns
+pub trait Bot {
+    fn act(&mut self, observation: &Observation) -> PlayerAction;
+}
+
+// A bot which fights anything it sees, drinks a health potion when badly hurt, picks up
+// everything, and explores each level until it finds the stairs
+pub struct SimpleBot;
+
+impl SimpleBot {
+    const DRINK_HEALTH_PERCENT: u32 = 40;
+}
+
+impl Bot for SimpleBot {
+    fn act(&mut self, observation: &Observation) -> PlayerAction {
+        let hit_points = observation.hit_points;
+        if hit_points.current * 100 <= hit_points.max * Self::DRINK_HEALTH_PERCENT {
+            if let Some(index) = observation
+                .inventory
+                .iter()
+                .position(|&slot| slot == Some(ItemType::HealthPotion))
+            {
+                return PlayerAction::UseItem(index);
+            }
+        }
+        let player_coord = observation.player_coord;
+        for direction in CardinalDirection::all() {
+            let coord = player_coord + direction.coord();
+            if observation.npcs.iter().any(|npc| npc.coord == coord) {
+                return PlayerAction::Attack(direction);
+            }
+        }
+        if let Some(direction) = observation
+            .first_step_towards(|coord| observation.npcs.iter().any(|npc| npc.coord == coord))
+        {
+            return PlayerAction::Move(direction);
+        }
+        if !observation.is_inventory_full() {
+            if observation.item_under_player.is_some() {
+                return PlayerAction::GetItem;
+            }
+            if let Some(direction) = observation.first_step_towards(|coord| {
+                observation
+                    .items
+                    .iter()
+                    .any(|&(item_coord, _)| item_coord == coord)
+            }) {
+                return PlayerAction::Move(direction);
+            }
+        }
+        // Explore towards the nearest cell next to one which hasn't been seen yet
+        if let Some(direction) = observation.first_step_towards(|coord| {
+            CardinalDirection::all().any(|direction| {
+                let neighbour = coord + direction.coord();
+                neighbour.is_valid(observation.size())
+                    && observation.cell(neighbour) == ObservedCell::Unknown
+            })
+        }) {
+            return PlayerAction::Move(direction);
+        }
+        if observation.cell(player_coord) == ObservedCell::Stairs {
+            return PlayerAction::LevelUpAndDescend(LevelUp::Health);
+        }
+        if let Some(direction) =
+            observation.first_step_towards(|coord| observation.cell(coord) == ObservedCell::Stairs)
+        {
+            return PlayerAction::Move(direction);
+        }
+        PlayerAction::Wait
+    }
+}
+
+// How a game played by a bot ended
+#[derive(Clone, Copy, Debug)]
+pub struct BotOutcome {
+    pub depth: u32,
+    pub turns: u64,
+    pub kills: u32,
+    pub died: bool,
+    // The npc which landed the killing blow. This is `None` if the player survived, or died to
+    // something other than an npc, such as a fall through a trapdoor.
+    pub killed_by: Option<Npc>,
+}
+
+// Lets `bot` play `game_state` without a frontend until the player dies or the bot has taken
+// `max_actions` actions. Animations are resolved immediately after each action.
+pub fn play<B: Bot>(bot: &mut B, game_state: &mut GameState, max_actions: u64) -> BotOutcome {
+    for _ in 0..max_actions {
+        if !game_state.is_player_alive() {
+            break;
+        }
+        let action = bot.act(&Observation::new(game_state));
+        if game_state.handle_action(action) != ActionResult::Done {
+            // The bot asked for something impossible. It loses its turn so that the game still
+            // progresses if it keeps asking.
+            game_state.handle_action(PlayerAction::Wait);
+        }
+        game_state.resolve_animations();
+        game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+    }
+    let score_inputs = game_state.score_inputs();
+    let died = !game_state.is_player_alive();
+    let killed_by = if died {
+        game_state
+            .message_log()
+            .iter()
+            .rev()
+            .find_map(|entry| match entry.message {
+                LogMessage::NpcKillsPlayer(npc) => Some(npc),
+                _ => None,
+            })
+    } else {
+        None
+    };
+    BotOutcome {
+        depth: score_inputs.depth,
+        turns: game_state.turn_count(),
+        kills: score_inputs.kills,
+        died,
+        killed_by,
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::{write::FileOptions, ZipWriter};
+
+// Everything needed to reproduce a problem with the game in progress, bundled into a single zip
+// file so that it can be attached to an issue
+pub struct BugReport {
+    // The seed, settings and version of the game, as text
+    pub info: String,
+    // The game in progress, in the same format as a json save
+    pub save: String,
+    pub profile: String,
+    // The most recent lines of the trace, if the game is being traced
+    pub trace: Option<String>,
+    pub messages: String,
+}
+
+impl BugReport {
+    // How many of the most recent messages from the message log are included
+    pub const NUM_MESSAGES: usize = 50;
+
+    // Writes the bundle next to the executable, returning its path
+    pub fn write(&self) -> io::Result<PathBuf> {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let exe = std::env::current_exe()?;
+        let path = exe
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(format!("bug-report-{}.zip", since_epoch.as_secs()));
+        let mut zip = ZipWriter::new(File::create(&path)?);
+        let mut entries = vec![
+            ("info.txt", &self.info),
+            ("save.json", &self.save),
+            ("profile.json", &self.profile),
+            ("messages.txt", &self.messages),
+        ];
+        if let Some(trace) = self.trace.as_ref() {
+            entries.push(("trace.txt", trace));
+        }
+        for (name, contents) in entries {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(contents.as_bytes())?;
+        }
+        zip.finish()?;
+        Ok(path)
+    }
+}
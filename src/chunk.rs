@@ -0,0 +1,130 @@
+use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// The width and height of each chunk of a `ChunkedGrid`, in cells
+pub const CHUNK_SIZE: u32 = 32;
+
+// A grid which is split into square chunks, each of which is only allocated once one of its cells
+// is written to. Cells of unallocated chunks read as the default value. On a large level most of
+// the map is never seen, so this keeps per-cell state proportional to the explored area rather
+// than the size of the map.
+//
+// Only the player's visibility memory is stored this way. The world's spatial table is still a
+// dense grid covering the whole level, and its components are stored per entity, so the memory
+// used by a level still grows with its size.
+pub struct ChunkedGrid<T> {
+    size: Size,
+    size_in_chunks: Size,
+    chunks: Vec<Option<Box<[T]>>>,
+    default: T,
+}
+
+impl<T: Clone + Default> ChunkedGrid<T> {
+    pub fn new(size: Size) -> Self {
+        let size_in_chunks = Size::new(
+            (size.width() + CHUNK_SIZE - 1) / CHUNK_SIZE,
+            (size.height() + CHUNK_SIZE - 1) / CHUNK_SIZE,
+        );
+        let chunks = (0..size_in_chunks.count()).map(|_| None).collect();
+        Self {
+            size,
+            size_in_chunks,
+            chunks,
+            default: T::default(),
+        }
+    }
+    pub fn size(&self) -> Size {
+        self.size
+    }
+    // The index of the chunk containing `coord` and the index of `coord` within that chunk
+    fn indices(&self, coord: Coord) -> Option<(usize, usize)> {
+        if !coord.is_valid(self.size) {
+            return None;
+        }
+        let chunk_size = CHUNK_SIZE as i32;
+        let chunk_coord = Coord::new(coord.x / chunk_size, coord.y / chunk_size);
+        let chunk_index =
+            chunk_coord.y as usize * self.size_in_chunks.width() as usize + chunk_coord.x as usize;
+        let offset = coord - chunk_coord * chunk_size;
+        let cell_index = offset.y as usize * CHUNK_SIZE as usize + offset.x as usize;
+        Some((chunk_index, cell_index))
+    }
+    pub fn get(&self, coord: Coord) -> Option<&T> {
+        let (chunk_index, cell_index) = self.indices(coord)?;
+        Some(match &self.chunks[chunk_index] {
+            Some(chunk) => &chunk[cell_index],
+            None => &self.default,
+        })
+    }
+    // Allocates the chunk containing `coord` if it hasn't been already
+    pub fn get_mut(&mut self, coord: Coord) -> Option<&mut T> {
+        let (chunk_index, cell_index) = self.indices(coord)?;
+        let chunk = self.chunks[chunk_index].get_or_insert_with(|| {
+            vec![T::default(); (CHUNK_SIZE * CHUNK_SIZE) as usize].into_boxed_slice()
+        });
+        Some(&mut chunk[cell_index])
+    }
+    pub fn get_checked_mut(&mut self, coord: Coord) -> &mut T {
+        self.get_mut(coord).expect("coord out of bounds")
+    }
+}
+
+// Chunked grids are saved in the same format as dense grids, so saves from before grids were
+// chunked still load. Only chunks containing a cell other than the default are allocated when
+// loading.
+impl<T: Clone + Default + Serialize> Serialize for ChunkedGrid<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Grid::new_fn(self.size, |coord| self.get(coord).unwrap().clone()).serialize(serializer)
+    }
+}
+
+impl<'de, T: Clone + Default + PartialEq + Deserialize<'de>> Deserialize<'de> for ChunkedGrid<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let grid = Grid::<T>::deserialize(deserializer)?;
+        let mut chunked_grid = Self::new(grid.size());
+        let default = T::default();
+        for (coord, cell) in grid.enumerate() {
+            if *cell != default {
+                *chunked_grid.get_checked_mut(coord) = cell.clone();
+            }
+        }
+        Ok(chunked_grid)
+    }
+}
+
+// The part of the level around the player which is simulated and drawn. Npcs outside it don't
+// act, and entities outside it aren't rendered, though finding them still visits every entity on
+// the level. Levels which fit within the window are always entirely active, wherever the player
+// stands.
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveWindow {
+    top_left: Coord,
+    size: Size,
+}
+
+impl ActiveWindow {
+    // The window reaches `radius` cells from the centre in each direction, clipped to the level
+    pub fn around(centre: Coord, radius: u32, level_size: Size) -> Self {
+        let radius = radius as i32;
+        let top_left = Coord::new((centre.x - radius).max(0), (centre.y - radius).max(0));
+        let bottom_right = Coord::new(
+            (centre.x + radius).min(level_size.width() as i32 - 1),
+            (centre.y + radius).min(level_size.height() as i32 - 1),
+        );
+        let size = Size::new(
+            (bottom_right.x - top_left.x + 1).max(0) as u32,
+            (bottom_right.y - top_left.y + 1).max(0) as u32,
+        );
+        Self { top_left, size }
+    }
+    pub fn contains(&self, coord: Coord) -> bool {
+        (coord - self.top_left).is_valid(self.size)
+    }
+    pub fn top_left(&self) -> Coord {
+        self.top_left
+    }
+    pub fn size(&self) -> Size {
+        self.size
+    }
+}
@@ -0,0 +1,205 @@
+use chargrid::input::KeyboardInput;
+use chargrid_roguelike_tutorial_2020::NUM_HOTBAR_SLOTS;
+use direction::CardinalDirection;
+use serde::{Deserialize, Serialize};
+
+// Actions which can be bound to keys in the player's profile
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ControlAction {
+    Move(CardinalDirection),
+    Run(CardinalDirection),
+    Attack(CardinalDirection),
+    Wait,
+    Rest,
+    Explore,
+    Save,
+    // Asks for a direction to kick in
+    Kick,
+    // Uses the item in a hotbar slot, counting from 0. In the inventory menu, assigns the
+    // selected item to that slot instead.
+    UseHotbarSlot(usize),
+    // Writes a bug report bundle next to the executable
+    BugReport,
+    // Shows or hides the tint on cells next to visible enemies
+    ToggleThreatOverlay,
+    // Opens the character sheet, or closes it if it's open
+    CharacterSheet,
+    // Cycles through the coordinate overlays. Only available in wizard mode.
+    CoordinateOverlay,
+}
+
+// A key held down while pressing another. Chargrid doesn't report modifiers, but holding shift
+// turns a letter into a capital, and holding ctrl turns it into an ascii control character. Other
+// keys, such as the arrow keys, can't be told apart from their unmodified versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+}
+
+// Splits a key press into the modifier held while pressing it and the unmodified key. Tab and
+// enter send the same characters as ctrl+i and ctrl+m, so are left alone, but backspace can't be
+// told apart from ctrl+h.
+fn split_modifier(key: KeyboardInput) -> Option<(Modifier, KeyboardInput)> {
+    match key {
+        KeyboardInput::Char(ch) if ch.is_ascii_uppercase() => Some((
+            Modifier::Shift,
+            KeyboardInput::Char(ch.to_ascii_lowercase()),
+        )),
+        KeyboardInput::Char(ch)
+            if ('\u{1}'..='\u{1a}').contains(&ch) && ch != '\t' && ch != '\r' =>
+        {
+            Some((
+                Modifier::Ctrl,
+                KeyboardInput::Char((b'a' + ch as u8 - 1) as char),
+            ))
+        }
+        _ => None,
+    }
+}
+
+// Stored as a list rather than a map, since keys of json objects must be strings
+#[derive(Serialize, Deserialize)]
+pub struct Controls {
+    bindings: Vec<(KeyboardInput, ControlAction)>,
+    // Keys for actions other than moving, waiting and the tasks. Profiles written before these
+    // could be bound get the default command bindings.
+    #[serde(default = "default_command_bindings")]
+    command_bindings: Vec<(KeyboardInput, ControlAction)>,
+    // Profiles written before modifiers could be bound get the default modifier bindings
+    #[serde(default = "default_modifier_bindings")]
+    modifier_bindings: Vec<(Modifier, KeyboardInput, ControlAction)>,
+}
+
+// Shift and a vi-key runs, and ctrl and a vi-key attacks without moving
+fn default_modifier_bindings() -> Vec<(Modifier, KeyboardInput, ControlAction)> {
+    use CardinalDirection::*;
+    use ControlAction::*;
+    use Modifier::*;
+    vec![
+        (Shift, KeyboardInput::Char('h'), Run(West)),
+        (Shift, KeyboardInput::Char('j'), Run(South)),
+        (Shift, KeyboardInput::Char('k'), Run(North)),
+        (Shift, KeyboardInput::Char('l'), Run(East)),
+        (Ctrl, KeyboardInput::Char('h'), Attack(West)),
+        (Ctrl, KeyboardInput::Char('j'), Attack(South)),
+        (Ctrl, KeyboardInput::Char('k'), Attack(North)),
+        (Ctrl, KeyboardInput::Char('l'), Attack(East)),
+        (Ctrl, KeyboardInput::Char('s'), Save),
+    ]
+}
+
+// The function keys use the hotbar, counting from F1. A slot's number key uses it too, unless the
+// numpad needs that key for moving or waiting, which leaves the diagonals.
+fn default_command_bindings() -> Vec<(KeyboardInput, ControlAction)> {
+    const FREE_NUMBER_KEYS: &[char] = &['1', '3', '7', '9'];
+    let mut bindings = vec![
+        (KeyboardInput::Char('b'), ControlAction::Kick),
+        (KeyboardInput::Char('B'), ControlAction::BugReport),
+        (KeyboardInput::Char('T'), ControlAction::ToggleThreatOverlay),
+        (KeyboardInput::Char('C'), ControlAction::CharacterSheet),
+        (KeyboardInput::Char('`'), ControlAction::CoordinateOverlay),
+    ];
+    for slot in 0..NUM_HOTBAR_SLOTS {
+        bindings.push((
+            KeyboardInput::Function(slot as u8 + 1),
+            ControlAction::UseHotbarSlot(slot),
+        ));
+        let number_key = std::char::from_digit(slot as u32 + 1, 10).unwrap();
+        if FREE_NUMBER_KEYS.contains(&number_key) {
+            bindings.push((
+                KeyboardInput::Char(number_key),
+                ControlAction::UseHotbarSlot(slot),
+            ));
+        }
+    }
+    bindings
+}
+
+impl Default for Controls {
+    // Arrow keys, vi-keys and the numpad all move the player. The numpad sends the same characters
+    // as the number keys above the letters.
+    fn default() -> Self {
+        use CardinalDirection::*;
+        use ControlAction::*;
+        let bindings = vec![
+            (KeyboardInput::Left, Move(West)),
+            (KeyboardInput::Right, Move(East)),
+            (KeyboardInput::Up, Move(North)),
+            (KeyboardInput::Down, Move(South)),
+            (KeyboardInput::Char('h'), Move(West)),
+            (KeyboardInput::Char('j'), Move(South)),
+            (KeyboardInput::Char('k'), Move(North)),
+            (KeyboardInput::Char('l'), Move(East)),
+            (KeyboardInput::Char('4'), Move(West)),
+            (KeyboardInput::Char('2'), Move(South)),
+            (KeyboardInput::Char('8'), Move(North)),
+            (KeyboardInput::Char('6'), Move(East)),
+            (KeyboardInput::Char(' '), Wait),
+            (KeyboardInput::Char('5'), Wait),
+            (KeyboardInput::Char('R'), Rest),
+            (KeyboardInput::Char('X'), Explore),
+        ];
+        Self {
+            bindings,
+            command_bindings: default_command_bindings(),
+            modifier_bindings: default_modifier_bindings(),
+        }
+    }
+}
+
+impl Controls {
+    // Keys bound without a modifier take priority, so binding a capital letter on its own
+    // overrides shift and that letter
+    pub fn get(&self, key: KeyboardInput) -> Option<ControlAction> {
+        self.bindings
+            .iter()
+            .chain(&self.command_bindings)
+            .find(|&&(bound_key, _)| bound_key == key)
+            .map(|&(_, action)| action)
+            .or_else(|| {
+                let (modifier, key) = split_modifier(key)?;
+                self.modifier_bindings
+                    .iter()
+                    .find(|&&(bound_modifier, bound_key, _)| {
+                        bound_modifier == modifier && bound_key == key
+                    })
+                    .map(|&(_, _, action)| action)
+            })
+    }
+    fn hotbar_slot(&self, key: KeyboardInput) -> Option<usize> {
+        match self.get(key) {
+            Some(ControlAction::UseHotbarSlot(slot)) if slot < NUM_HOTBAR_SLOTS => Some(slot),
+            _ => None,
+        }
+    }
+    // Every key bound to a hotbar slot, along with its slot
+    pub fn hotbar_bindings(&self) -> Vec<(KeyboardInput, usize)> {
+        self.bindings
+            .iter()
+            .chain(&self.command_bindings)
+            .filter_map(|&(key, _)| self.hotbar_slot(key).map(|slot| (key, slot)))
+            .collect()
+    }
+    // The direction to use for actions which need one, such as kicking
+    pub fn direction(&self, key: KeyboardInput) -> Option<CardinalDirection> {
+        match self.get(key) {
+            Some(ControlAction::Move(direction)) => Some(direction),
+            _ => None,
+        }
+    }
+}
+
+// How a key is written in help text
+pub fn key_name(key: KeyboardInput) -> String {
+    match key {
+        KeyboardInput::Char(' ') => "Space".to_string(),
+        KeyboardInput::Char(ch) => ch.to_string(),
+        KeyboardInput::Function(n) => format!("F{}", n),
+        KeyboardInput::Left => "Left".to_string(),
+        KeyboardInput::Right => "Right".to_string(),
+        KeyboardInput::Up => "Up".to_string(),
+        KeyboardInput::Down => "Down".to_string(),
+        other => format!("{:?}", other),
+    }
+}
@@ -0,0 +1,81 @@
+use crate::http;
+use chargrid_roguelike_tutorial_2020::ScoreInputs;
+use serde::Serialize;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The daily challenge server is expected to respond to:
+//  - GET <url>/seed with today's seed as a decimal integer
+//  - POST <url>/scores with a json summary of a finished run
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+// The json summary of a finished run
+#[derive(Serialize)]
+struct ScoreUpload {
+    seed: u64,
+    score: u64,
+    depth: u32,
+    kills: u32,
+    turns: u64,
+    endless: bool,
+    challenges: u32,
+}
+
+pub struct DailyChallenge {
+    url: String,
+    seed: u64,
+}
+
+impl DailyChallenge {
+    // Never fails. If the server can't be reached, the seed is derived from the date instead, so
+    // players without a connection still play the same dungeon as each other. This is called
+    // before the game starts, since the dungeon can't be generated without the seed.
+    pub fn fetch(url: String) -> Self {
+        let seed = match http::get_json::<u64>(&url, "/seed") {
+            Ok(seed) => seed,
+            Err(error) => {
+                eprintln!("Failed to fetch daily challenge seed: {}", error);
+                let offline_seed = offline_seed();
+                println!("Using offline daily challenge seed: {}", offline_seed);
+                offline_seed
+            }
+        };
+        Self { url, seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // The score is uploaded on a background thread so the game over screen appears immediately
+    pub fn upload_score(self, score_inputs: ScoreInputs) {
+        let ScoreInputs {
+            depth,
+            kills,
+            turns,
+            endless,
+            challenges,
+        } = score_inputs;
+        let body = ScoreUpload {
+            seed: self.seed,
+            score: score_inputs.score(),
+            depth,
+            kills,
+            turns,
+            endless,
+            challenges,
+        };
+        thread::spawn(move || match http::post_json(&self.url, "/scores", &body) {
+            Ok(()) => println!("Uploaded daily challenge score"),
+            Err(error) => eprintln!("Failed to upload daily challenge score: {}", error),
+        });
+    }
+}
+
+fn offline_seed() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_secs() / SECONDS_PER_DAY
+}
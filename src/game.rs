@@ -1,29 +1,56 @@
-use crate::behaviour::{Agent, BehaviourContext, NpcAction};
-use crate::visibility::{CellVisibility, VisibilityAlgorithm, VisibilityGrid};
+use crate::ambient::{AmbientEvent, AmbientEventScheduler};
+use crate::behaviour::{self, Agent, BehaviourContext, BossPhase, NpcAction};
+use crate::chunk::ActiveWindow;
+use crate::score::ScoreInputs;
+use crate::stats::Stats;
+use crate::task::{
+    self, InterruptReason, MacroPlayback, MacroStep, Task, TaskState, MAX_REST_TURNS,
+};
+use crate::terrain::{DungeonConfig, LevelName, LevelTheme, Weather};
+use crate::trace::{CountingRng, Trace};
+use crate::visibility::{self, CellVisibility, VisibilityAlgorithm, VisibilityGrid};
 use crate::world::{
-    EquippedInventoryIndices, HitPoints, Inventory, ItemType, ItemUsage, Location, NpcType,
-    Populate, ProjectileType, Tile, World,
+    ActiveStatusEffect, Buc, BumpAttackOutcome, CharacterData, Combatants, Durability,
+    EquippedInventoryIndices, Faction, FountainOutcome, HitPoints, Inventory, ItemCategory,
+    ItemType, ItemUsage, Location, MotionTrail, Npc, NpcType, Populate, PrayerOutcome,
+    ProjectileType, SpeedEffect, Tile, World, HAZARD_MOVE_COST, NUM_HOTBAR_SLOTS,
 };
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::ComponentTable;
 use entity_table::Entity;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rand_isaac::Isaac64Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub struct EntityToRender {
     pub tile: Tile,
     pub location: Location,
     pub visibility: CellVisibility,
+    pub hit_points: Option<HitPoints>,
+    // Set while a character is lunging at something it just attacked
+    pub lunge: Option<CardinalDirection>,
+}
+
+// An npc which the player can currently see
+#[derive(Clone, Copy, Debug)]
+pub struct VisibleNpc {
+    pub entity: Entity,
+    pub npc: Npc,
+    pub hit_points: HitPoints,
+    // The number of cardinal steps between the npc and the player
+    pub distance: u32,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum LogMessage {
-    PlayerAttacksNpc(NpcType),
-    NpcAttacksPlayer(NpcType),
-    PlayerKillsNpc(NpcType),
-    NpcKillsPlayer(NpcType),
+    PlayerAttacksNpc(Npc),
+    NpcAttacksPlayer(Npc),
+    PlayerKillsNpc(Npc),
+    NpcKillsPlayer(Npc),
     PlayerGets(ItemType),
     PlayerInventoryIsFull,
     NoItemUnderPlayer,
@@ -32,23 +59,339 @@ pub enum LogMessage {
     PlayerDrops(ItemType),
     NoSpaceToDropItem,
     PlayerLaunchesProjectile(ProjectileType),
-    NpcDies(NpcType),
-    NpcBecomesConfused(NpcType),
-    NpcIsNoLongerConfused(NpcType),
-    PlayerDodges(NpcType),
-    NpcDodges(NpcType),
+    NpcDies(Npc),
+    NpcBecomesConfused(Npc),
+    NpcIsNoLongerConfused(Npc),
+    PlayerDodges(Npc),
+    NpcDodges(Npc),
     PlayerEquips(ItemType),
+    Tremor,
+    MonstersMigrate,
+    ItemsTeleport,
+    NoAltarUnderPlayer,
+    AltarIsSilent,
+    AltarHeals,
+    AltarEnchants(ItemType),
+    AltarRevealsMap,
+    AltarSmites,
+    AltarSummons,
+    AltarWeakens,
+    NoFountainUnderPlayer,
+    FountainHeals,
+    FountainPoisons,
+    FountainRevealsMap,
+    FountainDriesUp,
+    FountainEvaporates,
+    PlayerRecoversFromPoison,
+    AmmoMustBeFired,
+    NoRangedWeaponHeld,
+    NoAmmo,
+    PlayerFires(ProjectileType),
+    ArrowGlancesOff(Npc),
+    NpcBurns(Npc),
+    NpcIsKnockedBack(Npc),
+    AllyAttacksNpc(Npc),
+    AllyKillsNpc(Npc),
+    NpcDodgesAlly(Npc),
+    NpcAttacksAlly(Npc),
+    NpcKillsAlly(Npc),
+    AllyDodges(Npc),
+    // Fights between npcs on the same side, with the attacker first and the victim second
+    NpcAttacksNpc(Npc, Npc),
+    NpcKillsNpc(Npc, Npc),
+    NpcDodgesNpc(Npc, Npc),
+    BossSummonsMinions(Npc),
+    BossBecomesEnraged(Npc),
+    AllyAppears(Npc),
+    AllyVanishes(Npc),
+    NoSpaceToSummon,
+    PlayerBecomesInvisible,
+    PlayerInvisibilityFading,
+    PlayerIsVisible,
+    PlayerSpeedChanges(SpeedEffect),
+    NpcSpeedChanges(Npc, SpeedEffect),
+    PlayerSpeedReturnsToNormal,
+    NpcSpeedReturnsToNormal(Npc),
+    PlayerStealsLife(u32),
+    NpcStealsLife(Npc, u32),
+    PlayerStartsRegenerating,
+    PlayerStopsRegenerating,
+    PlayerDefends,
+    PlayerCharges,
+    ChargeNotReady(u32),
+    NoRoomToCharge,
+    PlayerKicksNpc(Npc),
+    NpcDoesNotBudge(Npc),
+    PlayerKicksWall,
+    PlayerKicksNothing,
+    PlayerAttacksNothing,
+    PlayerTaunts,
+    ItemBreaks(ItemType),
+    PlayerRepairs,
+    NothingToRepair,
+    HostileSummonAppears(Npc),
+    ScrollBackfires,
+    ItemIsCursed(ItemType),
+    CursedItemIsStuck(ItemType),
+    ItemBucRevealed(ItemType, Buc),
+    PlayerFallsThroughTrapdoor(u32),
+    PlayerSpringsSlowingTrap,
+    PlayerSearchesCorpse(ItemType),
+    PlayerEntersLevel(LevelName),
+    ChallengeForbidsItems,
+    TaskInterrupted(InterruptReason),
+    MacroRecordingStarted,
+    MacroRecorded(u32),
+    NoMacroRecorded,
+    NpcAppears(Npc),
+    ItemAppears(ItemType),
+}
+
+impl LogMessage {
+    // Dodges, and blows exchanged in fights which the player isn't part of. Kills are never minor.
+    pub fn is_minor_combat(self) -> bool {
+        matches!(
+            self,
+            Self::PlayerDodges(_)
+                | Self::NpcDodges(_)
+                | Self::ArrowGlancesOff(_)
+                | Self::AllyAttacksNpc(_)
+                | Self::NpcDodgesAlly(_)
+                | Self::NpcAttacksAlly(_)
+                | Self::AllyDodges(_)
+                | Self::NpcAttacksNpc(_, _)
+                | Self::NpcDodgesNpc(_, _)
+        )
+    }
+}
+
+// A message in the message log, along with the turn on which it was logged
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub message: LogMessage,
+    pub turn: u64,
+}
+
+// Something that happened in the world. The world reports what it does through events rather than
+// writing to the message log or statistics itself, and the game hands each event on to whatever
+// reacts to it once control returns from the world.
+#[derive(Clone, Copy, Debug)]
+pub enum GameEvent {
+    Message(LogMessage),
+    Attack {
+        attacker: Entity,
+        victim: Entity,
+    },
+    // The result of a bump attack, which is reported after any damage it did
+    BumpAttack {
+        combatants: Combatants,
+        outcome: BumpAttackOutcome,
+    },
+    // An npc died of something other than a bump attack, such as a fireball or burning
+    NpcDies(Npc),
+    // The player picked up an item, either from the floor or by searching a corpse
+    ItemPickedUp {
+        item_type: ItemType,
+        from_corpse: bool,
+    },
+    // Damage done to an npc by the player or one of their allies
+    DamageDealt {
+        npc_type: NpcType,
+        damage: u32,
+    },
+    // Damage done to the player. The npc type is `None` for damage which didn't come from an npc.
+    DamageTaken {
+        npc_type: Option<NpcType>,
+        damage: u32,
+    },
+    // An npc was hurt by another npc on its own side, and will fight back
+    Provoked {
+        victim: Entity,
+        attacker: Entity,
+    },
+}
+
+fn bump_attack_message(combatants: Combatants, outcome: BumpAttackOutcome) -> LogMessage {
+    use BumpAttackOutcome::{Dodge, Hit, Kill};
+    match (combatants, outcome) {
+        (Combatants::PlayerVsNpc(npc), Kill) => LogMessage::PlayerKillsNpc(npc),
+        (Combatants::PlayerVsNpc(npc), Hit) => LogMessage::PlayerAttacksNpc(npc),
+        (Combatants::PlayerVsNpc(npc), Dodge) => LogMessage::NpcDodges(npc),
+        (Combatants::NpcVsPlayer(npc), Kill) => LogMessage::NpcKillsPlayer(npc),
+        (Combatants::NpcVsPlayer(npc), Hit) => LogMessage::NpcAttacksPlayer(npc),
+        (Combatants::NpcVsPlayer(npc), Dodge) => LogMessage::PlayerDodges(npc),
+        (Combatants::AllyVsNpc(npc), Kill) => LogMessage::AllyKillsNpc(npc),
+        (Combatants::AllyVsNpc(npc), Hit) => LogMessage::AllyAttacksNpc(npc),
+        (Combatants::AllyVsNpc(npc), Dodge) => LogMessage::NpcDodgesAlly(npc),
+        (Combatants::NpcVsAlly(npc), Kill) => LogMessage::NpcKillsAlly(npc),
+        (Combatants::NpcVsAlly(npc), Hit) => LogMessage::NpcAttacksAlly(npc),
+        (Combatants::NpcVsAlly(npc), Dodge) => LogMessage::AllyDodges(npc),
+        (Combatants::NpcVsNpc { attacker, victim }, Kill) => {
+            LogMessage::NpcKillsNpc(attacker, victim)
+        }
+        (Combatants::NpcVsNpc { attacker, victim }, Hit) => {
+            LogMessage::NpcAttacksNpc(attacker, victim)
+        }
+        (Combatants::NpcVsNpc { attacker, victim }, Dodge) => {
+            LogMessage::NpcDodgesNpc(attacker, victim)
+        }
+    }
+}
+
+// Optional rules chosen when starting a game, each of which makes it harder
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Challenges {
+    // The player can't pick up items
+    pub no_items: bool,
+    // The player has a single hit point, which levelling up doesn't increase
+    pub fragile: bool,
+    // The player can only see a short distance
+    pub blind: bool,
+    // Twice as many npcs are generated on each level
+    pub double_monsters: bool,
+}
+
+impl Challenges {
+    const BLIND_VISION_DISTANCE_SQUARED: u32 = 9;
+    // The names of the challenges which are enabled
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (self.no_items, "No Items"),
+            (self.fragile, "Fragile"),
+            (self.blind, "Blind"),
+            (self.double_monsters, "Double Monsters"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|&(_, name)| name)
+        .collect()
+    }
+    pub fn count(self) -> u32 {
+        self.names().len() as u32
+    }
+    fn dungeon_config(self, dungeon_config: DungeonConfig) -> DungeonConfig {
+        if self.double_monsters {
+            DungeonConfig {
+                npcs_per_thousand_cells: dungeon_config.npcs_per_thousand_cells * 2,
+                ..dungeon_config
+            }
+        } else {
+            dungeon_config
+        }
+    }
+}
+
+// The time of day cycles as turns pass, and is the same on every level of the dungeon
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeOfDay {
+    Day,
+    Dusk,
+    Night,
+    Dawn,
+}
+
+impl TimeOfDay {
+    const DAY_LENGTH_TURNS: u64 = 600;
+    fn from_turn(turn: u64) -> Self {
+        match turn % Self::DAY_LENGTH_TURNS {
+            0..=249 => Self::Day,
+            250..=299 => Self::Dusk,
+            300..=549 => Self::Night,
+            _ => Self::Dawn,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Day => "Day",
+            Self::Dusk => "Dusk",
+            Self::Night => "Night",
+            Self::Dawn => "Dawn",
+        }
+    }
+    pub fn is_night(self) -> bool {
+        self == Self::Night
+    }
+    // The player sees less far in the dark
+    fn vision_distance_squared(self) -> u32 {
+        match self {
+            Self::Day => 100,
+            Self::Dusk | Self::Dawn => 49,
+            Self::Night => 25,
+        }
+    }
+}
+
+// A colour modifier applied to the whole map. `GameState::tints` lists the tints currently in
+// effect, and the frontend composes them in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tint {
+    TimeOfDay(TimeOfDay),
+    Cold,
+    Heat,
+    // The map darkens as the player nears death
+    LowHealth { health_percent: u32 },
+}
+
+impl Tint {
+    const LOW_HEALTH_PERCENT: u32 = 25;
+    fn of_theme(theme: LevelTheme) -> Option<Self> {
+        match theme {
+            LevelTheme::FrozenCaverns => Some(Self::Cold),
+            LevelTheme::MagmaForge => Some(Self::Heat),
+            _ => None,
+        }
+    }
+    fn of_hit_points(hit_points: HitPoints) -> Option<Self> {
+        let health_percent = (hit_points.current * 100) / hit_points.max.max(1);
+        if health_percent <= Self::LOW_HEALTH_PERCENT {
+            Some(Self::LowHealth { health_percent })
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum ExamineCell {
-    Npc(NpcType),
-    NpcCorpse(NpcType),
+    Npc(Npc),
+    NpcCorpse(Npc),
     Item(ItemType),
     Player,
+    Altar,
+    Fountain,
 }
 
+// An action the player can currently take at or next to their location
 #[derive(Clone, Copy, Debug)]
+pub enum ContextAction {
+    GetItem(ItemType),
+    Descend,
+    Ascend,
+    Pray,
+    Drink,
+    Attack(Npc, CardinalDirection),
+    Kick(Npc, CardinalDirection),
+    Wait,
+}
+
+impl ContextAction {
+    // The action to take to carry out this context action. Descending has no equivalent since the
+    // player must first choose how to level up.
+    pub fn player_action(self) -> Option<PlayerAction> {
+        match self {
+            Self::GetItem(_) => Some(PlayerAction::GetItem),
+            Self::Descend => None,
+            Self::Ascend => Some(PlayerAction::Ascend),
+            Self::Pray => Some(PlayerAction::Pray),
+            Self::Drink => Some(PlayerAction::Drink),
+            Self::Attack(_, direction) => Some(PlayerAction::Move(direction)),
+            Self::Kick(_, direction) => Some(PlayerAction::Kick(direction)),
+            Self::Wait => Some(PlayerAction::Wait),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum LevelUp {
     Strength,
     Dexterity,
@@ -56,6 +399,67 @@ pub enum LevelUp {
     Health,
 }
 
+// Everything the player can do which changes the state of the game
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PlayerAction {
+    Move(CardinalDirection),
+    // Attack an enemy without moving, even if it isn't where the player expected
+    Attack(CardinalDirection),
+    Wait,
+    Defend,
+    Charge(CardinalDirection),
+    Kick(CardinalDirection),
+    Taunt,
+    GetItem,
+    Pray,
+    Drink,
+    UseItem(usize),
+    UseItemAim {
+        inventory_index: usize,
+        target: Coord,
+    },
+    DropItem(usize),
+    SortInventory,
+    AssignHotbarSlot {
+        inventory_index: usize,
+        hotbar_slot: usize,
+    },
+    Fire(Coord),
+    LevelUpAndDescend(LevelUp),
+    Ascend,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionResult {
+    Done,
+    // The item must be aimed by following up with `PlayerAction::UseItemAim`
+    NeedsTarget,
+    // Nothing happened, e.g. because there was no item to pick up or an animation is playing
+    Failed,
+}
+
+impl From<Result<(), ()>> for ActionResult {
+    fn from(result: Result<(), ()>) -> Self {
+        match result {
+            Ok(()) => Self::Done,
+            Err(()) => Self::Failed,
+        }
+    }
+}
+
+// A level the player has left, which is restored as it was when they return to it
+#[derive(Serialize, Deserialize)]
+struct Level {
+    world: World,
+    ai_state: ComponentTable<Agent>,
+    visibility_grid: VisibilityGrid,
+    level_name: LevelName,
+    weather: Weather,
+    turns_on_level: u64,
+    // Where the player was when they left, which is where they reappear
+    player_coord: Coord,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GameState {
     world: World,
@@ -64,10 +468,64 @@ pub struct GameState {
     visibility_grid: VisibilityGrid,
     ai_state: ComponentTable<Agent>,
     behaviour_context: BehaviourContext,
-    message_log: Vec<LogMessage>,
-    rng: Isaac64Rng,
+    // Tasks are cancelled by any input, so one in progress when the game is saved is dropped
+    #[serde(skip)]
+    task: Option<TaskState>,
+    // The steps taken since the player started recording a macro
+    #[serde(skip)]
+    macro_recording: Option<Vec<MacroStep>>,
+    #[serde(skip)]
+    macro_playback: Option<MacroPlayback>,
+    // Events are dispatched at the end of each action, so never need saving
+    #[serde(skip)]
+    events: Vec<GameEvent>,
+    message_log: Vec<LogEntry>,
+    rng: CountingRng,
     screen_size: Size,
     dungeon_level: u32,
+    // Every level the player has been to other than the current one, by depth. Older saves only
+    // have the current level.
+    #[serde(default)]
+    previous_levels: BTreeMap<u32, Level>,
+    level_name: LevelName,
+    // Chosen along with each level, based on its theme
+    weather: Weather,
+    turn_count: u64,
+    // Turns taken by npcs, which differs from the player's turn count while the player is hasted
+    // or slowed. Older saves start counting from zero.
+    #[serde(default)]
+    npc_turn_count: u64,
+    // Turns spent on the current level, which makes new npcs increasingly likely to appear
+    turns_on_level: u64,
+    // Whether new npcs appear over time. This is a setting rather than part of the game, so it
+    // isn't saved.
+    #[serde(skip)]
+    npc_respawning: bool,
+    // In peaceful mode monsters never act, so the dungeon can be explored without combat
+    peaceful: bool,
+    // Chosen when the game starts, so that every level of a loaded game is generated the same way
+    dungeon_config: DungeonConfig,
+    challenges: Challenges,
+    // Older saves don't have statistics, so they start from zero when loaded
+    #[serde(default)]
+    stats: Stats,
+    // Counts animation ticks for purely cosmetic idle animations, so isn't saved
+    #[serde(skip)]
+    animation_frame: u64,
+    ambient_event_scheduler: AmbientEventScheduler,
+    // Debugging output which belongs to this run of the program rather than to the game
+    #[serde(skip)]
+    trace: Option<Trace>,
+    // Changes whenever the map might be drawn differently, other than by idle animations, so that
+    // a frontend can reuse what it drew on the last frame while this stays the same. Every game
+    // state, including each one loaded, gets a revision no other has had.
+    #[serde(skip, default = "next_render_revision")]
+    render_revision: u64,
+}
+
+fn next_render_revision() -> u64 {
+    static NEXT_RENDER_REVISION: AtomicU64 = AtomicU64::new(0);
+    NEXT_RENDER_REVISION.fetch_add(1, Ordering::Relaxed)
 }
 
 impl GameState {
@@ -75,18 +533,71 @@ impl GameState {
         screen_size: Size,
         rng_seed: u64,
         initial_visibility_algorithm: VisibilityAlgorithm,
+        peaceful: bool,
+        dungeon_config: DungeonConfig,
+        challenges: Challenges,
     ) -> Self {
+        let dungeon_config = challenges.dungeon_config(dungeon_config);
+        Self::new_with_first_level(
+            screen_size,
+            rng_seed,
+            initial_visibility_algorithm,
+            peaceful,
+            dungeon_config,
+            challenges,
+            |world, rng| {
+                let level_name = LevelName::choose(rng);
+                let weather = level_name.theme.choose_weather(rng);
+                let populate = world.populate(1, level_name.theme, dungeon_config, rng);
+                (level_name, weather, populate)
+            },
+        )
+    }
+    // A small handcrafted level with no stairs, where the player can spawn npcs and items to try
+    // out combat
+    pub fn new_arena(
+        screen_size: Size,
+        rng_seed: u64,
+        initial_visibility_algorithm: VisibilityAlgorithm,
+    ) -> Self {
+        Self::new_with_first_level(
+            screen_size,
+            rng_seed,
+            initial_visibility_algorithm,
+            false,
+            DungeonConfig::default(),
+            Challenges::default(),
+            |world, _| (LevelName::arena(), Weather::Clear, world.populate_arena()),
+        )
+    }
+    fn new_with_first_level<F>(
+        screen_size: Size,
+        rng_seed: u64,
+        initial_visibility_algorithm: VisibilityAlgorithm,
+        peaceful: bool,
+        dungeon_config: DungeonConfig,
+        challenges: Challenges,
+        make_first_level: F,
+    ) -> Self
+    where
+        F: FnOnce(&mut World, &mut CountingRng) -> (LevelName, Weather, Populate),
+    {
         println!("RNG Seed: {}", rng_seed);
         let mut world = World::new(screen_size);
-        let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
+        let mut rng = CountingRng::new(Isaac64Rng::seed_from_u64(rng_seed));
         let dungeon_level = 1;
-        let Populate {
-            player_entity,
-            ai_state,
-        } = world.populate(dungeon_level, &mut rng);
+        let (
+            level_name,
+            weather,
+            Populate {
+                player_entity,
+                ai_state,
+            },
+        ) = make_first_level(&mut world, &mut rng);
         let shadowcast_context = shadowcast::Context::default();
         let visibility_grid = VisibilityGrid::new(screen_size);
         let behaviour_context = BehaviourContext::new(screen_size);
+        let ambient_event_scheduler = AmbientEventScheduler::new(&mut rng);
         let mut game_state = Self {
             world,
             player_entity,
@@ -94,119 +605,905 @@ impl GameState {
             visibility_grid,
             ai_state,
             behaviour_context,
+            task: None,
+            macro_recording: None,
+            macro_playback: None,
+            events: Vec::new(),
             message_log: Vec::new(),
             rng,
             screen_size,
             dungeon_level,
+            previous_levels: BTreeMap::new(),
+            level_name,
+            weather,
+            turn_count: 0,
+            npc_turn_count: 0,
+            turns_on_level: 0,
+            npc_respawning: false,
+            peaceful,
+            dungeon_config,
+            challenges,
+            stats: Stats::default(),
+            animation_frame: 0,
+            ambient_event_scheduler,
+            trace: None,
+            render_revision: next_render_revision(),
         };
+        game_state.apply_fragile_challenge();
+        game_state
+            .events
+            .push(GameEvent::Message(LogMessage::PlayerEntersLevel(
+                level_name,
+            )));
+        game_state.dispatch_events();
         game_state.update_visibility(initial_visibility_algorithm);
         game_state
     }
-    pub fn player_level_up_and_descend(&mut self, level_up: LevelUp) {
-        assert!(self.is_player_on_stairs());
+    // Messages are stamped with the turn on which they're dispatched rather than passing the turn
+    // down into the world
+    fn log_message(&mut self, message: LogMessage) {
+        self.message_log.push(LogEntry {
+            message,
+            turn: self.turn_count,
+        });
+    }
+    fn dispatch_events(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            match event {
+                GameEvent::Message(message) => self.log_message(message),
+                GameEvent::BumpAttack {
+                    combatants,
+                    outcome,
+                } => {
+                    if let (Combatants::PlayerVsNpc(_), BumpAttackOutcome::Kill) =
+                        (combatants, outcome)
+                    {
+                        self.stats.record_kill();
+                    }
+                    self.log_message(bump_attack_message(combatants, outcome));
+                }
+                GameEvent::NpcDies(npc) => {
+                    self.stats.record_kill();
+                    self.log_message(LogMessage::NpcDies(npc));
+                }
+                GameEvent::ItemPickedUp {
+                    item_type,
+                    from_corpse,
+                } => self.log_message(if from_corpse {
+                    LogMessage::PlayerSearchesCorpse(item_type)
+                } else {
+                    LogMessage::PlayerGets(item_type)
+                }),
+                GameEvent::Attack { attacker, victim } => self.world.start_lunge(attacker, victim),
+                GameEvent::DamageDealt { npc_type, damage } => {
+                    self.stats.record_damage_dealt(npc_type, damage)
+                }
+                GameEvent::DamageTaken { npc_type, damage } => {
+                    self.stats.record_damage_taken(npc_type, damage)
+                }
+                GameEvent::Provoked { victim, attacker } => {
+                    if let Some(agent) = self.ai_state.get_mut(victim) {
+                        agent.provoke(attacker);
+                    }
+                }
+            }
+        }
+    }
+    fn apply_fragile_challenge(&mut self) {
+        if self.challenges.fragile {
+            self.world
+                .set_hit_points(self.player_entity, HitPoints { current: 1, max: 1 });
+        }
+    }
+    pub fn handle_action(&mut self, action: PlayerAction) -> ActionResult {
+        let result = self.perform_action(action);
+        if result == ActionResult::Done {
+            self.record_macro_step(MacroStep::Action(action));
+        }
+        result
+    }
+    // Like `handle_action`, but for actions taken on the player's behalf by tasks and macros,
+    // which aren't recorded
+    fn perform_action(&mut self, action: PlayerAction) -> ActionResult {
+        self.mark_render_dirty();
+        let result = match action {
+            PlayerAction::Move(direction) => self.maybe_move_player(direction).into(),
+            PlayerAction::Attack(direction) => self.maybe_player_attack(direction).into(),
+            PlayerAction::Wait => self.wait_player().into(),
+            PlayerAction::Defend => self.player_defend().into(),
+            PlayerAction::Charge(direction) => self.maybe_player_charge(direction).into(),
+            PlayerAction::Kick(direction) => self.maybe_player_kick(direction).into(),
+            PlayerAction::Taunt => self.maybe_player_taunt().into(),
+            PlayerAction::GetItem => self.maybe_player_get_item().into(),
+            PlayerAction::Pray => self.maybe_player_pray().into(),
+            PlayerAction::Drink => self.maybe_player_drink().into(),
+            PlayerAction::UseItem(inventory_index) => {
+                match self.maybe_player_use_item(inventory_index) {
+                    Ok(ItemUsage::Immediate) | Ok(ItemUsage::Summon(_)) => ActionResult::Done,
+                    Ok(ItemUsage::Aim) => ActionResult::NeedsTarget,
+                    Err(()) => ActionResult::Failed,
+                }
+            }
+            PlayerAction::UseItemAim {
+                inventory_index,
+                target,
+            } => self
+                .maybe_player_use_item_aim(inventory_index, target)
+                .into(),
+            PlayerAction::DropItem(inventory_index) => {
+                self.maybe_player_drop_item(inventory_index).into()
+            }
+            PlayerAction::SortInventory => self.player_sort_inventory().into(),
+            PlayerAction::AssignHotbarSlot {
+                inventory_index,
+                hotbar_slot,
+            } => self
+                .world
+                .maybe_assign_hotbar_slot(
+                    self.player_entity,
+                    inventory_index,
+                    hotbar_slot,
+                    &mut self.events,
+                )
+                .into(),
+            PlayerAction::Fire(target) => self.maybe_player_fire(target).into(),
+            PlayerAction::LevelUpAndDescend(level_up) => {
+                self.maybe_player_level_up_and_descend(level_up).into()
+            }
+            PlayerAction::Ascend => self.maybe_player_ascend().into(),
+        };
+        self.dispatch_events();
+        self.trace_action(action, result);
+        result
+    }
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+    pub fn turn_count(&self) -> u64 {
+        self.turn_count
+    }
+    fn trace_action(&mut self, action: PlayerAction, result: ActionResult) {
+        if self.trace.is_none() {
+            return;
+        }
+        let world_hash = self.world_hash();
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record_action(
+                self.turn_count,
+                action,
+                result,
+                self.rng.draws(),
+                world_hash,
+            );
+        }
+    }
+    pub fn world_hash(&self) -> u64 {
+        self.world.stable_hash()
+    }
+    pub fn debug_dump(&self) -> String {
+        self.world.debug_dump()
+    }
+    fn maybe_player_level_up_and_descend(&mut self, level_up: LevelUp) -> Result<(), ()> {
+        if !self.is_player_on_stairs() {
+            return Err(());
+        }
         self.world.level_up_character(self.player_entity, level_up);
+        self.apply_fragile_challenge();
+        self.descend();
+        Ok(())
+    }
+    fn maybe_player_ascend(&mut self) -> Result<(), ()> {
+        if !self.is_player_on_up_stairs() {
+            return Err(());
+        }
+        let player_data = self.leave_level();
+        self.enter_level(self.dungeon_level - 1, player_data);
+        Ok(())
+    }
+    fn descend(&mut self) {
+        let player_data = self.leave_level();
+        self.enter_level(self.dungeon_level + 1, player_data);
+    }
+    // Set the current level aside so that it can be returned to, taking the player out of it
+    fn leave_level(&mut self) -> CharacterData {
+        let player_coord = self.player_coord();
         let player_data = self.world.remove_character(self.player_entity);
-        self.world.clear();
-        self.visibility_grid.clear();
-        self.dungeon_level += 1;
-        let Populate {
-            player_entity,
-            ai_state,
-        } = self.world.populate(self.dungeon_level, &mut self.rng);
-        self.world.replace_character(player_entity, player_data);
-        self.player_entity = player_entity;
-        self.ai_state = ai_state;
+        let level = Level {
+            world: mem::replace(&mut self.world, World::new(self.screen_size)),
+            ai_state: mem::take(&mut self.ai_state),
+            visibility_grid: mem::replace(
+                &mut self.visibility_grid,
+                VisibilityGrid::new(self.screen_size),
+            ),
+            level_name: self.level_name,
+            weather: self.weather,
+            turns_on_level: self.turns_on_level,
+            player_coord,
+        };
+        self.previous_levels.insert(self.dungeon_level, level);
+        player_data
+    }
+    // Make the level at `dungeon_level` the current one, bringing the player along. Levels which
+    // the player has been to before are restored as they were left, and others are generated.
+    fn enter_level(&mut self, dungeon_level: u32, player_data: CharacterData) {
+        self.dungeon_level = dungeon_level;
+        match self.previous_levels.remove(&dungeon_level) {
+            Some(level) => {
+                self.world = level.world;
+                self.ai_state = level.ai_state;
+                self.visibility_grid = level.visibility_grid;
+                self.level_name = level.level_name;
+                self.weather = level.weather;
+                self.turns_on_level = level.turns_on_level;
+                self.player_entity = self.world.insert_character(level.player_coord, player_data);
+            }
+            None => {
+                self.turns_on_level = 0;
+                self.level_name = LevelName::choose(&mut self.rng);
+                self.weather = self.level_name.theme.choose_weather(&mut self.rng);
+                let Populate {
+                    player_entity,
+                    ai_state,
+                } = self.world.populate(
+                    self.dungeon_level,
+                    self.level_name.theme,
+                    self.dungeon_config,
+                    &mut self.rng,
+                );
+                self.world.replace_character(player_entity, player_data);
+                self.player_entity = player_entity;
+                self.ai_state = ai_state;
+            }
+        }
+        self.events
+            .push(GameEvent::Message(LogMessage::PlayerEntersLevel(
+                self.level_name,
+            )));
     }
     pub fn is_player_on_stairs(&self) -> bool {
         self.world.coord_contains_stairs(self.player_coord())
     }
-    pub fn wait_player(&mut self) {
+    pub fn is_player_on_up_stairs(&self) -> bool {
+        self.world.coord_contains_up_stairs(self.player_coord())
+    }
+    fn wait_player(&mut self) -> Result<(), ()> {
         if self.has_animations() {
-            return;
+            return Err(());
         }
-        self.ai_turn();
+        self.end_turn();
+        Ok(())
     }
-    pub fn maybe_move_player(&mut self, direction: CardinalDirection) {
+    fn player_defend(&mut self) -> Result<(), ()> {
         if self.has_animations() {
-            return;
+            return Err(());
+        }
+        self.world.defend(self.player_entity);
+        self.events
+            .push(GameEvent::Message(LogMessage::PlayerDefends));
+        self.end_turn();
+        Ok(())
+    }
+    fn maybe_player_charge(&mut self, direction: CardinalDirection) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
         }
+        self.world.maybe_charge(
+            self.player_entity,
+            direction,
+            &mut self.events,
+            &mut self.rng,
+        )?;
+        self.end_turn();
+        Ok(())
+    }
+    fn maybe_player_kick(&mut self, direction: CardinalDirection) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        self.world
+            .kick(self.player_entity, direction, &mut self.events);
+        self.end_turn();
+        Ok(())
+    }
+    // Make a noise which draws nearby npcs towards the player, even if they can't see them
+    fn maybe_player_taunt(&mut self) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        let noise_distance_squared = self.weather.noise_distance_squared();
+        let player_coord = self.player_coord();
+        for (entity, agent) in self.ai_state.iter_mut() {
+            let within_earshot = self.world.entity_coord(entity).map_or(false, |coord| {
+                coord.distance2(player_coord) <= noise_distance_squared
+            });
+            if within_earshot && self.world.faction(entity) == Some(Faction::Monster) {
+                agent.hear_noise();
+            }
+        }
+        self.events
+            .push(GameEvent::Message(LogMessage::PlayerTaunts));
+        self.end_turn();
+        Ok(())
+    }
+    fn maybe_move_player(&mut self, direction: CardinalDirection) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        let coord = self.player_coord();
         self.world.maybe_move_character(
             self.player_entity,
             direction,
-            &mut self.message_log,
+            &mut self.events,
             &mut self.rng,
         );
-        self.ai_turn();
+        // Bumping into something doesn't count as a step
+        if self.player_coord() != coord {
+            self.stats.steps_taken += 1;
+            // Unlike trapdoors, slowing traps can be stood on, so only spring when stepped onto
+            if self.world.coord_contains_slowing_trap(self.player_coord()) {
+                self.player_springs_slowing_trap();
+            }
+        }
+        if self.world.coord_contains_trapdoor(self.player_coord()) {
+            self.player_falls_through_trapdoor();
+        }
+        self.end_turn();
+        Ok(())
     }
-    pub fn maybe_player_get_item(&mut self) {
+    // Unlike moving into an enemy, this never steps into the cell if the enemy has gone
+    fn maybe_player_attack(&mut self, direction: CardinalDirection) -> Result<(), ()> {
         if self.has_animations() {
-            return;
+            return Err(());
+        }
+        let coord = self.player_coord() + direction.coord();
+        if !self.world.is_hostile_character_at(coord, Faction::Player) {
+            self.events
+                .push(GameEvent::Message(LogMessage::PlayerAttacksNothing));
+            return Err(());
         }
-        if self
+        self.world.character_attack(
+            self.player_entity,
+            direction,
+            &mut self.events,
+            &mut self.rng,
+        );
+        self.end_turn();
+        Ok(())
+    }
+    // Falling skips the level up which comes with taking the stairs. The level fallen from is kept,
+    // and the player reappears on the trapdoor if they climb back up to it.
+    fn player_falls_through_trapdoor(&mut self) {
+        self.world.reveal_trap(self.player_coord());
+        let damage = self
             .world
-            .maybe_get_item(self.player_entity, &mut self.message_log)
-            .is_ok()
+            .fall(self.player_entity, &mut self.events, &mut self.rng);
+        self.events
+            .push(GameEvent::Message(LogMessage::PlayerFallsThroughTrapdoor(
+                damage,
+            )));
+        if self.is_player_alive() {
+            self.descend();
+        }
+    }
+    fn player_springs_slowing_trap(&mut self) {
+        self.world.reveal_trap(self.player_coord());
+        self.events
+            .push(GameEvent::Message(LogMessage::PlayerSpringsSlowingTrap));
+        self.world
+            .spring_slowing_trap(self.player_entity, &mut self.events);
+    }
+    fn player_side_openings(&self, direction: CardinalDirection) -> (bool, bool) {
+        let coord = self.player_coord();
+        (
+            self.world.is_open_floor(coord + direction.left90().coord()),
+            self.world
+                .is_open_floor(coord + direction.right90().coord()),
+        )
+    }
+    fn is_hostile_npc_visible(&self) -> bool {
+        self.world
+            .hostile_character_coords(Faction::Player)
+            .any(|coord| {
+                matches!(
+                    self.visibility_grid.cell_visibility(coord),
+                    CellVisibility::Currently
+                )
+            })
+    }
+    // The task is checked before each step, so one which can't be started stops on its first step
+    pub fn start_task(&mut self, task: Task) {
+        self.record_macro_step(MacroStep::Task(task));
+        self.begin_task(task);
+    }
+    fn begin_task(&mut self, task: Task) {
+        self.task = Some(match task {
+            Task::Run(direction) => TaskState::Run {
+                direction,
+                side_openings: self.player_side_openings(direction),
+            },
+            Task::Rest => TaskState::Rest {
+                turns_remaining: MAX_REST_TURNS,
+            },
+            Task::Explore => TaskState::Explore,
+            Task::Travel(destination) => TaskState::Travel { destination },
+        });
+    }
+    pub fn is_task_in_progress(&self) -> bool {
+        self.task.is_some()
+    }
+    pub fn cancel_task(&mut self) {
+        if self.task.is_some() {
+            self.stop_task(InterruptReason::Cancelled);
+        }
+    }
+    // Take the next step of the current task, returning the reason it stopped if this was its last
+    // step. Visibility must be updated between steps so that the task stops when an enemy comes
+    // into view.
+    pub fn continue_task(&mut self) -> Option<InterruptReason> {
+        let task = self.task?;
+        if self.has_animations() {
+            // Wait for animations to finish before acting again
+            return None;
+        }
+        match self.task_step(task) {
+            Ok(task) => {
+                self.task = Some(task);
+                None
+            }
+            Err(reason) => {
+                self.stop_task(reason);
+                Some(reason)
+            }
+        }
+    }
+    fn stop_task(&mut self, reason: InterruptReason) {
+        self.task = None;
+        if reason.is_logged() {
+            self.events
+                .push(GameEvent::Message(LogMessage::TaskInterrupted(reason)));
+            self.dispatch_events();
+        }
+    }
+    fn task_step(&mut self, task: TaskState) -> Result<TaskState, InterruptReason> {
+        if self.is_hostile_npc_visible() {
+            return Err(InterruptReason::EnemySeen);
+        }
+        let coord = self.player_coord();
+        let action = match task {
+            TaskState::Run { direction, .. } => {
+                let next_coord = coord + direction.coord();
+                if !self.can_player_walk_through(next_coord) || self.is_known_hazard_at(next_coord)
+                {
+                    return Err(InterruptReason::Blocked);
+                }
+                PlayerAction::Move(direction)
+            }
+            TaskState::Rest { turns_remaining } => {
+                let hit_points = self.player_hit_points();
+                let recovering = self
+                    .player_status_effects()
+                    .iter()
+                    .any(|status_effect| status_effect.effect.is_harmful());
+                if hit_points.current >= hit_points.max && !recovering {
+                    return Err(InterruptReason::FullyRested);
+                }
+                if turns_remaining == 0 {
+                    return Err(InterruptReason::RestedTooLong);
+                }
+                PlayerAction::Wait
+            }
+            TaskState::Explore => {
+                let is_unexplored_edge = |coord: Coord| {
+                    CardinalDirection::all().any(|direction| {
+                        matches!(
+                            self.visibility_grid
+                                .cell_visibility(coord + direction.coord()),
+                            CellVisibility::Never
+                        )
+                    })
+                };
+                match self.first_step_towards(is_unexplored_edge) {
+                    Some(direction) => PlayerAction::Move(direction),
+                    None => return Err(InterruptReason::NothingToExplore),
+                }
+            }
+            TaskState::Travel { destination } => {
+                if coord == destination {
+                    return Err(InterruptReason::Arrived);
+                }
+                match self.first_step_towards(|coord| coord == destination) {
+                    Some(direction) => PlayerAction::Move(direction),
+                    None => return Err(InterruptReason::Blocked),
+                }
+            }
+        };
+        let hit_points = self.player_hit_points().current;
+        let num_messages = self.message_log.len();
+        if self.perform_action(action) == ActionResult::Failed {
+            return Err(InterruptReason::Blocked);
+        }
+        if !self.is_player_alive() || self.player_hit_points().current < hit_points {
+            return Err(InterruptReason::Damaged);
+        }
+        // This includes falling through a trapdoor, which logs a message about it
+        if self.message_log.len() > num_messages {
+            return Err(InterruptReason::Message);
+        }
+        if let TaskState::Rest { turns_remaining } = task {
+            return Ok(TaskState::Rest {
+                turns_remaining: turns_remaining - 1,
+            });
+        }
+        if self.player_coord() == coord {
+            return Err(InterruptReason::Blocked);
+        }
+        let coord = self.player_coord();
+        if self.item_under_player().is_some()
+            || self.is_player_on_stairs()
+            || self.is_player_on_up_stairs()
+            || self.world.coord_contains_unused_altar(coord)
+            || self.world.coord_contains_fountain(coord)
         {
-            self.ai_turn();
+            return Err(InterruptReason::FoundSomething);
+        }
+        match task {
+            TaskState::Run {
+                direction,
+                side_openings,
+            } if self.player_side_openings(direction) != side_openings => {
+                Err(InterruptReason::Junction)
+            }
+            TaskState::Travel { destination } if coord == destination => {
+                Err(InterruptReason::Arrived)
+            }
+            _ => Ok(task),
+        }
+    }
+    fn record_macro_step(&mut self, step: MacroStep) {
+        if let Some(steps) = self.macro_recording.as_mut() {
+            steps.push(step);
+        }
+    }
+    pub fn start_recording_macro(&mut self) {
+        self.macro_recording = Some(Vec::new());
+        self.events
+            .push(GameEvent::Message(LogMessage::MacroRecordingStarted));
+        self.dispatch_events();
+    }
+    pub fn is_recording_macro(&self) -> bool {
+        self.macro_recording.is_some()
+    }
+    // Returns the steps taken since recording started
+    pub fn stop_recording_macro(&mut self) -> Vec<MacroStep> {
+        let steps = self.macro_recording.take().unwrap_or_default();
+        self.events
+            .push(GameEvent::Message(LogMessage::MacroRecorded(
+                steps.len() as u32
+            )));
+        self.dispatch_events();
+        steps
+    }
+    pub fn start_macro(&mut self, steps: Vec<MacroStep>) {
+        if steps.is_empty() {
+            self.events
+                .push(GameEvent::Message(LogMessage::NoMacroRecorded));
+            self.dispatch_events();
+            return;
+        }
+        self.macro_playback = Some(MacroPlayback {
+            steps,
+            next_step: 0,
+        });
+    }
+    pub fn is_macro_playing(&self) -> bool {
+        self.macro_playback.is_some()
+    }
+    // Stops the macro, leaving any task it started to be cancelled separately
+    pub fn cancel_macro(&mut self) {
+        self.macro_playback = None;
+    }
+    // Take the next step of the macro being played, returning the reason it stopped if it did. A
+    // task started by the macro is carried out one step at a time, like any other task, before
+    // the macro moves on.
+    pub fn continue_macro(&mut self) -> Option<InterruptReason> {
+        self.macro_playback.as_ref()?;
+        if self.task.is_some() {
+            let reason = self.continue_task()?;
+            if reason.stops_macro() {
+                // The task has already reported why it stopped
+                self.macro_playback = None;
+                return Some(reason);
+            }
+            return None;
+        }
+        if self.has_animations() {
+            return None;
+        }
+        let playback = self.macro_playback.as_mut()?;
+        let step = match playback.steps.get(playback.next_step) {
+            Some(&step) => step,
+            None => return self.stop_macro(InterruptReason::Finished),
+        };
+        playback.next_step += 1;
+        match step {
+            MacroStep::Action(action) => {
+                let hit_points = self.player_hit_points().current;
+                if self.perform_action(action) == ActionResult::Failed {
+                    return self.stop_macro(InterruptReason::Blocked);
+                }
+                if !self.is_player_alive() || self.player_hit_points().current < hit_points {
+                    return self.stop_macro(InterruptReason::Damaged);
+                }
+            }
+            MacroStep::Task(task) => self.begin_task(task),
+        }
+        None
+    }
+    fn stop_macro(&mut self, reason: InterruptReason) -> Option<InterruptReason> {
+        self.macro_playback = None;
+        if reason.is_logged() {
+            self.events
+                .push(GameEvent::Message(LogMessage::TaskInterrupted(reason)));
+            self.dispatch_events();
+        }
+        Some(reason)
+    }
+    // Tasks only walk through cells the player has seen, and never into another character
+    fn can_player_walk_through(&self, coord: Coord) -> bool {
+        !matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Never
+        ) && self.world.is_open_floor(coord)
+            && self
+                .world
+                .spatial_table
+                .layers_at_checked(coord)
+                .character
+                .is_none()
+    }
+    // Hazards are walked around where there's another way, but only if the player knows about them
+    fn player_move_cost(&self, coord: Coord) -> Option<u32> {
+        if !self.can_player_walk_through(coord) {
+            return None;
+        }
+        if self.is_known_hazard_at(coord) {
+            Some(HAZARD_MOVE_COST)
+        } else {
+            Some(1)
+        }
+    }
+    pub fn is_known_hazard_at(&self, coord: Coord) -> bool {
+        let seen = !matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Never
+        );
+        seen && self
+            .world
+            .hazard_at(coord)
+            .map_or(false, |hazard| !hazard.is_hidden())
+    }
+    fn first_step_towards<G: Fn(Coord) -> bool>(&self, is_goal: G) -> Option<CardinalDirection> {
+        task::first_step_towards(
+            self.player_coord(),
+            self.world.size(),
+            |coord| self.player_move_cost(coord),
+            is_goal,
+        )
+    }
+    fn maybe_player_get_item(&mut self) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        if self.challenges.no_items {
+            self.events
+                .push(GameEvent::Message(LogMessage::ChallengeForbidsItems));
+            return Err(());
+        }
+        self.world
+            .maybe_get_item(self.player_entity, &mut self.events)?;
+        self.end_turn();
+        Ok(())
+    }
+    fn maybe_player_pray(&mut self) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        let outcome = self
+            .world
+            .maybe_pray(self.player_entity, &mut self.events, &mut self.rng)?;
+        match outcome {
+            PrayerOutcome::RevealMap => self.visibility_grid.reveal_all(),
+            PrayerOutcome::Summon => {
+                const SUMMON_RADIUS: i32 = 2;
+                let player_coord = self.player_coord();
+                for entity in self.world.spawn_npcs_near(
+                    player_coord,
+                    SUMMON_RADIUS,
+                    self.dungeon_level,
+                    self.level_name.theme,
+                    self.dungeon_config,
+                    &mut self.rng,
+                ) {
+                    self.ai_state.insert(entity, Agent::new());
+                }
+            }
+            _ => (),
         }
+        self.end_turn();
+        Ok(())
     }
-    pub fn maybe_player_use_item(&mut self, inventory_index: usize) -> Result<ItemUsage, ()> {
+    fn maybe_player_drink(&mut self) -> Result<(), ()> {
         if self.has_animations() {
             return Err(());
         }
+        let outcome =
+            self.world
+                .maybe_drink(self.player_entity, &mut self.events, &mut self.rng)?;
+        if let FountainOutcome::RevealMap = outcome {
+            self.visibility_grid.reveal_all();
+        }
+        self.end_turn();
+        Ok(())
+    }
+    fn maybe_player_use_item(&mut self, inventory_index: usize) -> Result<ItemUsage, ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        let item_type = self.player_item_type(inventory_index);
         let result =
             self.world
-                .maybe_use_item(self.player_entity, inventory_index, &mut self.message_log);
+                .maybe_use_item(self.player_entity, inventory_index, &mut self.events);
         if let Ok(usage) = result {
             match usage {
-                ItemUsage::Immediate => self.ai_turn(),
+                ItemUsage::Immediate => self.end_turn(),
                 ItemUsage::Aim => (),
+                ItemUsage::Summon(entity) => {
+                    self.ai_state.insert(entity, Agent::new());
+                    self.end_turn();
+                }
+            }
+            // Aimed items are counted once they've been aimed
+            if !matches!(usage, ItemUsage::Aim) {
+                self.record_item_use(item_type);
             }
         }
         result
     }
-    pub fn maybe_player_use_item_aim(
+    fn maybe_player_use_item_aim(
         &mut self,
         inventory_index: usize,
         target: Coord,
     ) -> Result<(), ()> {
+        let item_type = self.player_item_type(inventory_index);
         self.world.maybe_use_item_aim(
             self.player_entity,
             inventory_index,
             target,
-            &mut self.message_log,
-        )
+            &mut self.events,
+        )?;
+        self.record_item_use(item_type);
+        Ok(())
+    }
+    fn player_item_type(&self, inventory_index: usize) -> Option<ItemType> {
+        let entity = self.player_inventory().get(inventory_index).ok()?;
+        self.world.item_type(entity)
+    }
+    fn record_item_use(&mut self, item_type: Option<ItemType>) {
+        if let Some(item_type) = item_type {
+            self.stats.record_item_use(item_type);
+            if item_type.category() == ItemCategory::Scroll && !self.is_hostile_npc_visible() {
+                self.stats.scrolls_wasted += 1;
+            }
+        }
     }
-    pub fn maybe_player_drop_item(&mut self, inventory_index: usize) -> Result<(), ()> {
+    fn maybe_player_drop_item(&mut self, inventory_index: usize) -> Result<(), ()> {
         let result =
             self.world
-                .maybe_drop_item(self.player_entity, inventory_index, &mut self.message_log);
+                .maybe_drop_item(self.player_entity, inventory_index, &mut self.events);
         if result.is_ok() {
-            self.ai_turn();
+            self.end_turn();
         }
         result
     }
+    // Rearranging the inventory doesn't take a turn
+    fn player_sort_inventory(&mut self) -> Result<(), ()> {
+        self.world.sort_inventory(self.player_entity);
+        Ok(())
+    }
+    fn maybe_player_fire(&mut self, target: Coord) -> Result<(), ()> {
+        if self.has_animations() {
+            return Err(());
+        }
+        self.world
+            .maybe_fire(self.player_entity, target, &mut self.events)?;
+        self.end_turn();
+        Ok(())
+    }
     pub fn tick_animations(&mut self) {
-        self.world.move_projectiles(&mut self.message_log)
+        if self.has_animations() || self.world.has_cosmetic_animations() {
+            self.mark_render_dirty();
+        }
+        self.world.move_projectiles(&mut self.events, &mut self.rng);
+        self.world.fade_effects();
+        self.world.float_damage_numbers();
+        self.world.advance_lunges();
+        self.world.advance_motion_trails();
+        self.animation_frame = self.animation_frame.wrapping_add(1);
+        self.dispatch_events();
+    }
+    pub fn animation_frame(&self) -> u64 {
+        self.animation_frame
+    }
+    fn mark_render_dirty(&mut self) {
+        self.render_revision = next_render_revision();
     }
+    pub fn render_revision(&self) -> u64 {
+        self.render_revision
+    }
+    // Only projectiles hold up the game. Everything else which animates is cosmetic.
     fn has_animations(&self) -> bool {
         self.world.has_projectiles()
     }
+    // Run animation ticks until no projectiles remain, so the player can act
+    // without waiting for them to play out
+    pub fn resolve_animations(&mut self) {
+        // Guards against an animation which never finishes locking up the game
+        const MAX_TICKS: usize = 1000;
+        for _ in 0..MAX_TICKS {
+            if !self.has_animations() {
+                break;
+            }
+            self.tick_animations();
+        }
+    }
     pub fn entities_to_render<'a>(&'a self) -> impl 'a + Iterator<Item = EntityToRender> {
         let tile_component = &self.world.components.tile;
         let spatial_table = &self.world.spatial_table;
         let visibility_grid = &self.visibility_grid;
+        let hit_points_component = &self.world.components.hit_points;
+        let world = &self.world;
+        let active_window = self.active_window();
         tile_component.iter().filter_map(move |(entity, &tile)| {
             let &location = spatial_table.location_of(entity)?;
+            if !active_window.contains(location.coord) {
+                return None;
+            }
             let visibility = visibility_grid.cell_visibility(location.coord);
+            let hit_points = hit_points_component.get(entity).cloned();
             Some(EntityToRender {
                 tile,
                 location,
                 visibility,
+                hit_points,
+                lunge: world.lunge_direction(entity),
             })
         })
     }
+    // Trails left by npcs which just moved, in cells the player can see which nothing else has
+    // moved into since
+    pub fn motion_trails<'a>(&'a self) -> impl 'a + Iterator<Item = MotionTrail> {
+        let visibility_grid = &self.visibility_grid;
+        let spatial_table = &self.world.spatial_table;
+        self.world
+            .motion_trails()
+            .filter(move |motion_trail| {
+                matches!(
+                    visibility_grid.cell_visibility(motion_trail.coord),
+                    CellVisibility::Currently
+                ) && spatial_table
+                    .layers_at(motion_trail.coord)
+                    .map_or(false, |layers| layers.character.is_none())
+            })
+            .cloned()
+    }
     pub fn update_visibility(&mut self, visibility_algorithm: VisibilityAlgorithm) {
+        self.mark_render_dirty();
         let player_coord = self
             .world
             .spatial_table
@@ -214,11 +1511,221 @@ impl GameState {
             .unwrap();
         self.visibility_grid.update(
             player_coord,
+            self.turn_count,
+            self.vision_distance_squared(),
             &self.world,
             &mut self.shadowcast_context,
             visibility_algorithm,
         );
     }
+    fn vision_distance_squared(&self) -> u32 {
+        let distance_squared = self
+            .time_of_day()
+            .vision_distance_squared()
+            .min(self.weather.vision_distance_squared());
+        if self.challenges.blind {
+            distance_squared.min(Challenges::BLIND_VISION_DISTANCE_SQUARED)
+        } else {
+            distance_squared
+        }
+    }
+    pub fn visibility_disagreements(
+        &self,
+        a: VisibilityAlgorithm,
+        b: VisibilityAlgorithm,
+    ) -> Vec<Coord> {
+        visibility::visibility_disagreements(self.player_coord(), &self.world, a, b)
+    }
+    // The cells visible from an arbitrary coordinate, such as an npc's. Only cells the player can
+    // currently see are included, so this can't be used to reveal unexplored parts of the map.
+    pub fn cells_visible_from(&self, coord: Coord, algorithm: VisibilityAlgorithm) -> Vec<Coord> {
+        let visibility_grid = &self.visibility_grid;
+        visibility::cells_visible_from(
+            coord,
+            visibility::DEFAULT_VISION_DISTANCE_SQUARED,
+            &self.world,
+            algorithm,
+        )
+        .into_iter()
+        .filter(|&coord| {
+            matches!(
+                visibility_grid.cell_visibility(coord),
+                CellVisibility::Currently
+            )
+        })
+        .collect()
+    }
+    // Whether there's an enemy at a cell the player can see which can see the player. This is the
+    // same check npcs make when deciding whether to chase the player.
+    pub fn can_enemy_at_see_player(&self, coord: Coord) -> bool {
+        matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Currently
+        ) && self.world.is_hostile_character_at(coord, Faction::Player)
+            && behaviour::npc_has_line_of_sight(coord, self.player_coord(), &self.world)
+    }
+    fn end_turn(&mut self) {
+        // A hasted player acts twice for each turn taken by npcs, and a slowed player gives npcs two
+        // turns for each of their own
+        let num_ai_turns = match self
+            .world
+            .speed_status(self.player_entity)
+            .map(|status| status.effect)
+        {
+            Some(SpeedEffect::Haste) => self.turn_count % 2,
+            Some(SpeedEffect::Slow) => 2,
+            None => 1,
+        };
+        self.world
+            .set_night_for_nocturnal_npcs(self.time_of_day().is_night());
+        // Status effects last for a number of npc turns, so they run out at the same pace whatever
+        // the player's speed
+        for _ in 0..num_ai_turns {
+            self.ai_turn();
+            self.npc_turn_count += 1;
+            self.world.tick_status_effects(&mut self.events);
+        }
+        // Defending lasts until npcs have had their turn, which for a hasted player may only come
+        // after their next action
+        if num_ai_turns > 0 {
+            self.world.stop_defending(self.player_entity);
+        }
+        self.world.tick_summons(&mut self.events);
+        self.ambient_event_turn();
+        self.npc_respawn_turn();
+        self.turn_count += 1;
+        self.turns_on_level += 1;
+        #[cfg(debug_assertions)]
+        self.world.validate();
+    }
+    fn ambient_event_turn(&mut self) {
+        // The arena is only for testing combat, so nothing happens there which the player didn't
+        // ask for
+        if !self.is_player_alive() || self.is_arena() {
+            return;
+        }
+        let ambient_event = match self.ambient_event_scheduler.tick(&mut self.rng) {
+            Some(ambient_event) => ambient_event,
+            None => return,
+        };
+        match ambient_event {
+            AmbientEvent::Tremor => {
+                self.world.tremor(&mut self.rng);
+                self.events.push(GameEvent::Message(LogMessage::Tremor));
+            }
+            AmbientEvent::MonsterMigration => {
+                for entity in self.world.spawn_npcs_near_stairs(
+                    self.dungeon_level,
+                    self.level_name.theme,
+                    self.dungeon_config,
+                    &mut self.rng,
+                ) {
+                    self.ai_state.insert(entity, Agent::new());
+                }
+                self.events
+                    .push(GameEvent::Message(LogMessage::MonstersMigrate));
+            }
+            AmbientEvent::ItemsTeleport => {
+                self.world.teleport_items(&mut self.rng);
+                self.events
+                    .push(GameEvent::Message(LogMessage::ItemsTeleport));
+            }
+        }
+    }
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+    pub fn challenges(&self) -> Challenges {
+        self.challenges
+    }
+    pub fn is_endless(&self) -> bool {
+        self.dungeon_config.endless
+    }
+    pub fn is_peaceful(&self) -> bool {
+        self.peaceful
+    }
+    pub fn is_arena(&self) -> bool {
+        self.level_name.theme == LevelTheme::Arena
+    }
+    // Spawn an npc close to the player. Only possible in the arena.
+    pub fn arena_spawn_npc(&mut self, npc: Npc) {
+        if !self.is_arena() {
+            return;
+        }
+        self.mark_render_dirty();
+        let player_coord = self.player_coord();
+        match self.world.spawn_npc_near(player_coord, npc, &mut self.rng) {
+            Some(entity) => {
+                self.ai_state
+                    .insert(entity, Agent::for_npc_type(npc.npc_type));
+                self.events
+                    .push(GameEvent::Message(LogMessage::NpcAppears(npc)));
+            }
+            None => self
+                .events
+                .push(GameEvent::Message(LogMessage::NoSpaceToSummon)),
+        }
+        self.dispatch_events();
+    }
+    // Spawn an item close to the player. Only possible in the arena.
+    pub fn arena_spawn_item(&mut self, item_type: ItemType) {
+        if !self.is_arena() {
+            return;
+        }
+        self.mark_render_dirty();
+        let player_coord = self.player_coord();
+        match self
+            .world
+            .spawn_item_near(player_coord, item_type, &mut self.rng)
+        {
+            Some(_) => self
+                .events
+                .push(GameEvent::Message(LogMessage::ItemAppears(item_type))),
+            None => self
+                .events
+                .push(GameEvent::Message(LogMessage::NoSpaceToSummon)),
+        }
+        self.dispatch_events();
+    }
+    pub fn set_npc_respawning(&mut self, npc_respawning: bool) {
+        self.npc_respawning = npc_respawning;
+    }
+    // Occasionally spawn an npc out of the player's sight, more often the longer they linger on a
+    // level, so resting and grinding can't go on forever
+    fn npc_respawn_turn(&mut self) {
+        // No npcs appear during this many turns after arriving on a level
+        const GRACE_TURNS: u64 = 100;
+        // After the grace period, the chance of an npc appearing each turn goes up by one in a
+        // thousand every this many turns...
+        const TURNS_PER_PER_MILLE: u64 = 50;
+        // ...up to this many in a thousand
+        const MAX_PER_MILLE: u64 = 20;
+        if !self.npc_respawning || !self.is_player_alive() || self.is_arena() {
+            return;
+        }
+        let per_mille = match self.turns_on_level.checked_sub(GRACE_TURNS) {
+            Some(turns) => (turns / TURNS_PER_PER_MILLE).min(MAX_PER_MILLE),
+            None => return,
+        };
+        if self.rng.gen_range(0..1000) >= per_mille {
+            return;
+        }
+        let visibility_grid = &self.visibility_grid;
+        if let Some(entity) = self.world.spawn_npc_where_hidden(
+            self.dungeon_level,
+            self.level_name.theme,
+            self.dungeon_config,
+            |coord| {
+                !matches!(
+                    visibility_grid.cell_visibility(coord),
+                    CellVisibility::Currently
+                )
+            },
+            &mut self.rng,
+        ) {
+            self.ai_state.insert(entity, Agent::new());
+        }
+    }
     fn ai_turn(&mut self) {
         self.behaviour_context
             .update(self.player_entity, &self.world);
@@ -230,23 +1737,113 @@ impl GameState {
         for dead_entity in dead_entities {
             self.ai_state.remove(dead_entity);
         }
-        for (entity, agent) in self.ai_state.iter_mut() {
-            let npc_action = agent.act(
+        let active_window = self.active_window();
+        let world = &self.world;
+        let peaceful = self.peaceful;
+        let npc_turn_count = self.npc_turn_count;
+        let rng = &mut self.rng;
+        // Each npc gets its own random number generator for the turn, seeded in a fixed order, so
+        // that the npcs' choices don't depend on the order they're made in
+        let mut acting_agents = self
+            .ai_state
+            .iter_mut()
+            .filter(|&(entity, _)| {
+                !(peaceful && world.faction(entity) == Some(Faction::Monster))
+                    // Npcs far from the player wait until the player comes closer
+                    && world
+                        .entity_coord(entity)
+                        .map_or(false, |coord| active_window.contains(coord))
+            })
+            .map(|(entity, agent)| ActingAgent {
                 entity,
+                agent,
+                num_actions: world.num_actions(entity, npc_turn_count),
+                rng: Isaac64Rng::seed_from_u64(rng.gen()),
+            })
+            .collect::<Vec<_>>();
+        // Agents can't be added while iterating over them
+        let mut minions = Vec::new();
+        // Hasted npcs act twice per turn, so their second actions are planned in a second round,
+        // after everyone's first actions have been applied
+        let num_rounds = acting_agents
+            .iter()
+            .map(|acting_agent| acting_agent.num_actions)
+            .max()
+            .unwrap_or(0);
+        for round in 0..num_rounds {
+            acting_agents.retain(|acting_agent| acting_agent.num_actions > round);
+            let planned_actions = plan_npc_actions(
+                &mut acting_agents,
                 self.player_entity,
                 &self.world,
-                &mut self.behaviour_context,
+                &self.behaviour_context,
             );
-            match npc_action {
-                NpcAction::Wait => (),
-                NpcAction::Move(direction) => self.world.maybe_move_character(
-                    entity,
-                    direction,
-                    &mut self.message_log,
-                    &mut self.rng,
-                ),
+            for planned_action in planned_actions {
+                // An earlier action this round may have killed the npc, in which case it loses its
+                // action. If the npc was moved, or what's in the cell it was going to move into or
+                // attack has changed, it chooses again as though the npcs acted one at a time.
+                let planned_action = if is_planned_action_stale(&self.world, &planned_action) {
+                    match plan_npc_action(
+                        planned_action.index,
+                        &mut acting_agents[planned_action.index],
+                        self.player_entity,
+                        &self.world,
+                        &self.behaviour_context,
+                    ) {
+                        Some(planned_action) => planned_action,
+                        None => continue,
+                    }
+                } else if self.world.is_living_character(planned_action.entity) {
+                    planned_action
+                } else {
+                    continue;
+                };
+                let entity = planned_action.entity;
+                match planned_action.npc_action {
+                    NpcAction::Wait => (),
+                    NpcAction::Move(direction) => {
+                        self.world.maybe_move_character(
+                            entity,
+                            direction,
+                            &mut self.events,
+                            &mut self.rng,
+                        );
+                        self.world.leave_motion_trail(entity, planned_action.coord);
+                    }
+                    NpcAction::Attack(direction) => {
+                        for _ in 0..planned_action.attacks {
+                            self.world.character_attack(
+                                entity,
+                                direction,
+                                &mut self.events,
+                                &mut self.rng,
+                            );
+                        }
+                    }
+                    NpcAction::EnterPhase(BossPhase::Calm) => (),
+                    NpcAction::EnterPhase(BossPhase::Summoning) => minions.extend(
+                        self.world
+                            .summon_minions(entity, &mut self.events, &mut self.rng),
+                    ),
+                    NpcAction::EnterPhase(BossPhase::Enraged) => {
+                        if let Some(npc) = self.world.npc(entity) {
+                            self.events
+                                .push(GameEvent::Message(LogMessage::BossBecomesEnraged(npc)));
+                        }
+                    }
+                }
             }
         }
+        for entity in minions {
+            self.ai_state.insert(entity, Agent::new());
+        }
+    }
+    // The window reaches as far as the screen is wide or tall in every direction, so nothing which
+    // could be drawn is left out. Levels are generated at the size of the screen, so for now every
+    // level is entirely active; the window only limits anything on levels larger than that.
+    pub fn active_window(&self) -> ActiveWindow {
+        let radius = self.screen_size.width().max(self.screen_size.height());
+        ActiveWindow::around(self.player_coord(), radius, self.world.size())
     }
     pub fn is_player_alive(&self) -> bool {
         self.world.is_living_character(self.player_entity)
@@ -256,12 +1853,18 @@ impl GameState {
             .entity_coord(self.player_entity)
             .expect("player has no coord")
     }
+    pub fn is_player_invisible(&self) -> bool {
+        self.world.is_invisible(self.player_entity)
+    }
+    pub fn player_status_effects(&self) -> Vec<ActiveStatusEffect> {
+        self.world.status_effects(self.player_entity)
+    }
     pub fn player_hit_points(&self) -> HitPoints {
         self.world
             .hit_points(self.player_entity)
             .expect("player has no hit points")
     }
-    pub fn message_log(&self) -> &[LogMessage] {
+    pub fn message_log(&self) -> &[LogEntry] {
         &self.message_log
     }
     pub fn player_inventory(&self) -> &Inventory {
@@ -272,13 +1875,130 @@ impl GameState {
     pub fn item_type(&self, entity: Entity) -> Option<ItemType> {
         self.world.item_type(entity)
     }
+    pub fn item_known_buc(&self, entity: Entity) -> Option<Buc> {
+        self.world.known_item_buc(entity)
+    }
+    pub fn item_durability(&self, entity: Entity) -> Option<Durability> {
+        self.world.item_durability(entity)
+    }
+    pub fn item_enchantment(&self, entity: Entity) -> i32 {
+        self.world.item_enchantment(entity)
+    }
+    pub fn item_ammo(&self, entity: Entity) -> Option<u32> {
+        self.world.components.ammo.get(entity).cloned()
+    }
+    pub fn player_ranged_ammo(&self) -> Option<u32> {
+        self.world.ranged_ammo(self.player_entity)
+    }
+    pub fn item_under_player(&self) -> Option<ItemType> {
+        self.world.item_at(self.player_coord())
+    }
     pub fn size(&self) -> Size {
         self.world.size()
     }
-    pub fn examine_cell(&self, coord: Coord) -> Option<ExamineCell> {
+    // Returns the npc at `coord` if the player can currently see it
+    pub fn visible_npc_at(&self, coord: Coord) -> Option<Entity> {
+        if let CellVisibility::Currently = self.visibility_grid.cell_visibility(coord) {
+            let entity = self.world.spatial_table.layers_at(coord)?.character?;
+            self.world.npc(entity).map(|_| entity)
+        } else {
+            None
+        }
+    }
+    // Whether there's a wall at `coord` which the player has seen
+    pub fn is_known_wall_at(&self, coord: Coord) -> bool {
+        !matches!(
+            self.visibility_grid.cell_visibility(coord),
+            CellVisibility::Never
+        ) && self.world.is_wall_at(coord)
+    }
+    // Every living npc which the player can currently see, nearest first
+    pub fn visible_npcs(&self) -> Vec<VisibleNpc> {
+        let player_coord = self.player_coord();
+        let mut visible_npcs = self
+            .world
+            .hostile_character_coords(Faction::Player)
+            .filter_map(|coord| {
+                let entity = self.visible_npc_at(coord)?;
+                let delta = coord - player_coord;
+                Some(VisibleNpc {
+                    entity,
+                    npc: self.world.npc(entity)?,
+                    hit_points: self.world.hit_points(entity)?,
+                    distance: (delta.x.abs() + delta.y.abs()) as u32,
+                })
+            })
+            .collect::<Vec<_>>();
+        visible_npcs.sort_by_key(|visible_npc| visible_npc.distance);
+        visible_npcs
+    }
+    // The cells next to an enemy the player can currently see, which the enemy could attack on its
+    // next turn. Known walls are left out, since the player can't end their move in one.
+    pub fn threatened_cells(&self) -> Vec<Coord> {
+        let mut threatened_cells = self
+            .visible_npcs()
+            .into_iter()
+            .filter_map(|visible_npc| self.world.entity_coord(visible_npc.entity))
+            .flat_map(|coord| {
+                CardinalDirection::all().map(move |direction| coord + direction.coord())
+            })
+            .filter(|&coord| coord.is_valid(self.world.size()) && !self.is_known_wall_at(coord))
+            .collect::<Vec<_>>();
+        threatened_cells.sort_by_key(|coord| (coord.y, coord.x));
+        threatened_cells.dedup();
+        threatened_cells
+    }
+    // Returns the coordinate of `entity` if it is a living npc which the player can currently see
+    pub fn visible_npc_coord(&self, entity: Entity) -> Option<Coord> {
+        if !self.world.is_living_character(entity) {
+            return None;
+        }
+        let coord = self.world.entity_coord(entity)?;
+        if self.visible_npc_at(coord) == Some(entity) {
+            Some(coord)
+        } else {
+            None
+        }
+    }
+    // Every action the player can take in their current cell or against an adjacent cell
+    pub fn player_context_actions(&self) -> Vec<ContextAction> {
+        let player_coord = self.player_coord();
+        let mut actions = Vec::new();
+        if let Some(item_type) = self.item_under_player() {
+            actions.push(ContextAction::GetItem(item_type));
+        }
+        if self.is_player_on_stairs() {
+            actions.push(ContextAction::Descend);
+        }
+        if self.is_player_on_up_stairs() {
+            actions.push(ContextAction::Ascend);
+        }
+        if self.world.coord_contains_unused_altar(player_coord) {
+            actions.push(ContextAction::Pray);
+        }
+        if self.world.coord_contains_fountain(player_coord) {
+            actions.push(ContextAction::Drink);
+        }
+        for direction in CardinalDirection::all() {
+            let coord = player_coord + direction.coord();
+            if !self.world.is_hostile_character_at(coord, Faction::Player) {
+                continue;
+            }
+            if let Some(npc) = self
+                .visible_npc_at(coord)
+                .and_then(|entity| self.world.npc(entity))
+            {
+                actions.push(ContextAction::Attack(npc, direction));
+                actions.push(ContextAction::Kick(npc, direction));
+            }
+        }
+        actions.push(ContextAction::Wait);
+        actions
+    }
+    pub fn examine_cell(&self, coord: Coord) -> Vec<ExamineCell> {
         match self.visibility_grid.cell_visibility(coord) {
             CellVisibility::Currently => self.world.examine_cell(coord),
-            _ => None,
+            _ => Vec::new(),
         }
     }
     pub fn player_strength(&self) -> i32 {
@@ -296,10 +2016,152 @@ impl GameState {
             .intelligence(self.player_entity)
             .expect("player missing intelligence")
     }
+    pub fn time_of_day(&self) -> TimeOfDay {
+        TimeOfDay::from_turn(self.turn_count)
+    }
+    pub fn weather(&self) -> Weather {
+        self.weather
+    }
+    pub fn tints(&self) -> impl Iterator<Item = Tint> {
+        Tint::of_theme(self.level_name.theme)
+            .into_iter()
+            .chain(Some(Tint::TimeOfDay(self.time_of_day())))
+            .chain(Tint::of_hit_points(self.player_hit_points()))
+    }
     pub fn dungeon_level(&self) -> u32 {
         self.dungeon_level
     }
+    pub fn level_name(&self) -> LevelName {
+        self.level_name
+    }
+    pub fn score_inputs(&self) -> ScoreInputs {
+        // The player may have climbed back up from the deepest level they reached
+        let depth = self
+            .previous_levels
+            .keys()
+            .next_back()
+            .map_or(self.dungeon_level, |&depth| depth.max(self.dungeon_level));
+        ScoreInputs {
+            depth,
+            kills: self.stats.kills,
+            turns: self.turn_count,
+            endless: self.is_endless(),
+            challenges: self.challenges.count(),
+        }
+    }
+    pub fn player_hotbar_inventory_indices(&self) -> [Option<usize>; NUM_HOTBAR_SLOTS] {
+        self.world.hotbar_inventory_indices(self.player_entity)
+    }
     pub fn player_equipped_inventory_indices(&self) -> EquippedInventoryIndices {
         self.world.equipped_inventory_indices(self.player_entity)
     }
 }
+
+// An npc taking part in the current turn
+struct ActingAgent<'a> {
+    entity: Entity,
+    agent: &'a mut Agent,
+    num_actions: u32,
+    rng: Isaac64Rng,
+}
+
+// An action chosen by an npc, along with what the npc saw when choosing it. If something relevant
+// has changed by the time it's the npc's turn to act, the npc chooses again.
+struct PlannedAction {
+    // Index of the npc in the list of acting agents
+    index: usize,
+    entity: Entity,
+    npc_action: NpcAction,
+    // Where the npc was
+    coord: Coord,
+    // The character in the cell the npc is moving into or attacking, if any
+    target: Option<Entity>,
+    attacks: u32,
+}
+
+fn character_at(world: &World, coord: Coord) -> Option<Entity> {
+    world
+        .spatial_table
+        .layers_at(coord)
+        .and_then(|layers| layers.character)
+}
+
+// Each npc chooses its action by looking at the world as it was at the start of the round, so the
+// choices don't depend on one another and, with the "parallel-ai" feature, are made on multiple
+// threads. The actions are returned in the same order as the agents.
+fn plan_npc_action(
+    index: usize,
+    acting_agent: &mut ActingAgent,
+    player: Entity,
+    world: &World,
+    behaviour_context: &BehaviourContext,
+) -> Option<PlannedAction> {
+    let entity = acting_agent.entity;
+    if !world.is_living_character(entity) {
+        return None;
+    }
+    let coord = world.entity_coord(entity)?;
+    let npc_action = acting_agent.agent.act(
+        entity,
+        player,
+        world,
+        behaviour_context,
+        &mut acting_agent.rng,
+    );
+    let target = match npc_action {
+        NpcAction::Move(direction) | NpcAction::Attack(direction) => {
+            character_at(world, coord + direction.coord())
+        }
+        NpcAction::Wait | NpcAction::EnterPhase(_) => None,
+    };
+    Some(PlannedAction {
+        index,
+        entity,
+        npc_action,
+        coord,
+        target,
+        attacks: acting_agent.agent.attacks_per_action(),
+    })
+}
+
+fn plan_npc_actions(
+    acting_agents: &mut [ActingAgent],
+    player: Entity,
+    world: &World,
+    behaviour_context: &BehaviourContext,
+) -> Vec<PlannedAction> {
+    let plan = |(index, acting_agent): (usize, &mut ActingAgent)| {
+        plan_npc_action(index, acting_agent, player, world, behaviour_context)
+    };
+    #[cfg(feature = "parallel-ai")]
+    {
+        use rayon::prelude::*;
+        acting_agents
+            .par_iter_mut()
+            .enumerate()
+            .filter_map(plan)
+            .collect()
+    }
+    #[cfg(not(feature = "parallel-ai"))]
+    {
+        acting_agents
+            .iter_mut()
+            .enumerate()
+            .filter_map(plan)
+            .collect()
+    }
+}
+
+// Whether an action planned at the start of the round no longer fits the world, because the npc
+// has been moved or the cell it was moving into or attacking has changed
+fn is_planned_action_stale(world: &World, planned_action: &PlannedAction) -> bool {
+    if world.entity_coord(planned_action.entity) != Some(planned_action.coord) {
+        return true;
+    }
+    match planned_action.npc_action {
+        NpcAction::Move(direction) | NpcAction::Attack(direction) => {
+            character_at(world, planned_action.coord + direction.coord()) != planned_action.target
+        }
+        NpcAction::Wait | NpcAction::EnterPhase(_) => false,
+    }
+}
@@ -0,0 +1,99 @@
+use crate::controls::Controls;
+use crate::messages::Language;
+use crate::options::Options;
+use chargrid_roguelike_tutorial_2020::{
+    CellVisibility, GameState, HighScores, ItemCategory, MacroStep, Tile,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// A tip explaining a game mechanic, shown the first time the player encounters it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Hint {
+    GetItem,
+    BumpAttack,
+    Aim,
+    Hotbar,
+}
+
+impl Hint {
+    pub fn text(self) -> &'static str {
+        match self {
+            Self::GetItem => "There's an item in sight. Walk onto it and press 'g' to pick it up.",
+            Self::BumpAttack => "An enemy approaches! Attack it by moving into it.",
+            Self::Aim => {
+                "Scrolls must be aimed. Read one from the inventory ('i'), then move the cursor \
+                 to a target and press Enter or click."
+            }
+            Self::Hotbar => {
+                "Items can be used from the hotbar. Select one in the inventory ('i') and press \
+                 F1-F5 (or 1 or 3) to put it in that slot, then press the same key to use it."
+            }
+        }
+    }
+}
+
+// Settings and progress which belong to the player rather than to any one game, so they outlive
+// saved games
+#[derive(Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub hints_shown: HashSet<Hint>,
+    // Profiles written before controls could be configured get the default controls
+    #[serde(default)]
+    pub controls: Controls,
+    #[serde(default)]
+    pub language: Language,
+    // The deepest level reached by any endless run
+    #[serde(default)]
+    pub deepest_endless_level: u32,
+    #[serde(default)]
+    pub high_scores: HighScores,
+    #[serde(default)]
+    pub options: Options,
+    // Recorded macros, each named by the key which plays it
+    #[serde(default)]
+    pub macros: HashMap<char, Vec<MacroStep>>,
+}
+
+impl Profile {
+    // Returns the first hint which the current state of the game calls for and which hasn't been
+    // shown before
+    pub fn triggered_hint(&self, game_state: &GameState) -> Option<Hint> {
+        let mut item_visible = false;
+        let mut npc_visible = false;
+        for entity in game_state.entities_to_render() {
+            if let CellVisibility::Currently = entity.visibility {
+                match entity.tile {
+                    Tile::Item(_) => item_visible = true,
+                    Tile::Npc(_) => npc_visible = true,
+                    _ => (),
+                }
+            }
+        }
+        let scroll_carried = game_state
+            .player_inventory()
+            .slots()
+            .iter()
+            .flatten()
+            .filter_map(|&entity| game_state.item_type(entity))
+            .any(|item_type| item_type.category() == ItemCategory::Scroll);
+        let item_carried = game_state
+            .player_inventory()
+            .slots()
+            .iter()
+            .any(Option::is_some);
+        let hotbar_empty = game_state
+            .player_hotbar_inventory_indices()
+            .iter()
+            .all(Option::is_none);
+        [
+            (Hint::GetItem, item_visible),
+            (Hint::BumpAttack, npc_visible),
+            (Hint::Aim, scroll_carried),
+            (Hint::Hotbar, item_carried && hotbar_empty),
+        ]
+        .iter()
+        .find(|(hint, triggered)| *triggered && !self.hints_shown.contains(hint))
+        .map(|&(hint, _)| hint)
+    }
+}
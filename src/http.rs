@@ -0,0 +1,123 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// A minimal http client for talking to the daily challenge server and to WebDAV servers. Only
+// plain http is supported, to avoid depending on a tls implementation.
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// Makes a http/1.0 request (so the response won't use chunked encoding) to `path` relative to
+// `url`, and returns the response whatever its status
+pub fn request(
+    url: &str,
+    path: &str,
+    method: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<Response> {
+    let url = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid_input("only http:// urls are supported"))?;
+    let (host, base_path) = match url.find('/') {
+        Some(index) => url.split_at(index),
+        None => (url, ""),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid_input("failed to resolve host"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "{} {}{} HTTP/1.0\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        base_path.trim_end_matches('/'),
+        path,
+        host,
+        content_type,
+        body.len(),
+    )?;
+    stream.write_all(body)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let status_line = String::from_utf8_lossy(&response[..header_end])
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_string();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed response: {}", status_line),
+            )
+        })?;
+    let body = response
+        .get((header_end + 4)..)
+        .map(<[u8]>::to_vec)
+        .unwrap_or_default();
+    Ok(Response { status, body })
+}
+
+// Returns the body of the response if its status was 200
+fn request_ok(
+    url: &str,
+    path: &str,
+    method: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<Vec<u8>> {
+    let response = request(url, path, method, content_type, body)?;
+    if response.status != 200 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("unexpected response status: {}", response.status),
+        ));
+    }
+    Ok(response.body)
+}
+
+// Parses the body of the response as json
+pub fn get_json<T: DeserializeOwned>(url: &str, path: &str) -> io::Result<T> {
+    let body = request_ok(url, path, "GET", "application/json", &[])?;
+    serde_json::from_slice(&body).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+// Sends `body` as json, ignoring the body of the response
+pub fn post_json<T: Serialize>(url: &str, path: &str, body: &T) -> io::Result<()> {
+    let body = serde_json::to_vec(body)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    request_ok(url, path, "POST", "application/json", &body).map(|_| ())
+}
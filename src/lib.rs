@@ -0,0 +1,37 @@
+// The game logic, independent of chargrid. The chargrid frontend lives in the binary target and
+// only talks to the game through `GameState` and the types re-exported below, along with the
+// `bot` and `save` modules, whose functions are used through the module name.
+mod ambient;
+mod behaviour;
+pub mod bot;
+mod chunk;
+mod game;
+pub mod save;
+mod score;
+mod stats;
+mod task;
+mod terrain;
+mod trace;
+mod visibility;
+mod world;
+
+pub use bot::{Bot, BotOutcome, Observation, SimpleBot};
+pub use chunk::{ActiveWindow, ChunkedGrid, CHUNK_SIZE};
+pub use game::{
+    ActionResult, Challenges, ContextAction, EntityToRender, ExamineCell, GameEvent, GameState,
+    LevelUp, LogEntry, LogMessage, PlayerAction, TimeOfDay, Tint, VisibleNpc,
+};
+pub use score::{HighScore, HighScores, ScoreInputs, NUM_HIGH_SCORES};
+pub use stats::Stats;
+pub use task::{InterruptReason, MacroStep, Task};
+pub use terrain::{
+    generate_dungeon, Dungeon, DungeonConfig, DungeonStats, LevelConstraint, LevelName, LevelTheme,
+    RoomPlacement, TerrainTile, Weather,
+};
+pub use trace::Trace;
+pub use visibility::{CellVisibility, VisibilityAlgorithm};
+pub use world::{
+    ActiveStatusEffect, Buc, BumpAttackOutcome, Combatants, Durability, HitPoints, ItemCategory,
+    ItemCondition, ItemType, ItemUsage, Layer, MotionTrail, Npc, NpcType, ProjectileType,
+    SpeedEffect, SpeedStatus, StatusEffect, Tile, MOTION_TRAIL_FRAMES, NUM_HOTBAR_SLOTS,
+};
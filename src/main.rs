@@ -1,22 +1,59 @@
-use app::app;
+use app::{app, AnimationSpeed};
 use chargrid_graphical::{Config, Context, Dimensions, FontBytes};
+use chargrid_roguelike_tutorial_2020::{
+    bot, Challenges, DungeonConfig, GameState, RoomPlacement, SimpleBot, Trace, VisibilityAlgorithm,
+};
 use coord_2d::Size;
+use daily::DailyChallenge;
 use meap;
 use rand::Rng;
+use std::time::Duration;
+use sync::SaveSync;
+use ui::{Layout, UiLayout};
 
 mod app;
-mod behaviour;
-mod game;
-mod terrain;
+mod bug_report;
+mod controls;
+mod daily;
+mod hint;
+mod http;
+mod messages;
+mod options;
+mod rescue;
+#[cfg(feature = "spectate")]
+mod spectate;
+mod sync;
 mod ui;
-mod visibility;
-mod world;
 
-use visibility::VisibilityAlgorithm;
+// Arguments are parsed the same way with or without the "spectate" feature, so that a helpful
+// error can be printed if spectating is requested but not supported
+#[cfg_attr(not(feature = "spectate"), allow(dead_code))]
+pub enum SpectateMode {
+    Host(String),
+    Watch(String),
+}
 
 struct Args {
     rng_seed: u64,
     visibility_algorithm: VisibilityAlgorithm,
+    debug_fov_diff: bool,
+    wizard: bool,
+    hide_damage_numbers: bool,
+    box_drawing_walls: bool,
+    animation_speed: Option<AnimationSpeed>,
+    npc_respawning: bool,
+    dungeon_config: DungeonConfig,
+    key_repeat_delay: Duration,
+    key_repeat_interval: Duration,
+    spectate_mode: Option<SpectateMode>,
+    daily_challenge_url: Option<String>,
+    trace_path: Option<String>,
+    ui_layout: UiLayout,
+    enemy_list: bool,
+    bot_games: Option<u64>,
+    export_save_path: Option<String>,
+    dump_world_path: Option<String>,
+    sync_location: Option<String>,
 }
 
 impl Args {
@@ -26,21 +63,236 @@ impl Args {
                 rng_seed = opt_opt::<u64, _>("INT", 'r').name("rng-seed").desc("seed for random number generator")
                     .with_default_lazy("randomly chosen seed", || rand::thread_rng().gen());
                 visibility_algorithm = flag("debug-omniscient").some_if(VisibilityAlgorithm::Omniscient)
+                    .choose_at_most_one(flag("symmetric-shadowcast")
+                        .desc("use symmetric shadowcasting to compute what the player can see")
+                        .some_if(VisibilityAlgorithm::SymmetricShadowcast))
                     .with_default_general(VisibilityAlgorithm::Shadowcast);
+                debug_fov_diff = flag("debug-fov-diff")
+                    .desc("highlight cells where shadowcasting and symmetric shadowcasting disagree");
+                wizard = flag("wizard")
+                    .desc("enable debugging keys, such as '`' to cycle through coordinate rulers and a grid over the map");
+                hide_damage_numbers = flag("hide-damage-numbers")
+                    .desc("don't show numbers floating above characters when they take damage (overrides the options screen)");
+                ascii_walls = flag("ascii-walls")
+                    .desc("draw walls as '#' rather than joining them up with box-drawing characters");
+                animation_speed = flag("fast-animations").desc("play projectile and effect animations at triple speed (overrides the options screen)")
+                    .some_if(AnimationSpeed::Fast)
+                    .choose_at_most_one(flag("instant-animations")
+                        .desc("resolve projectile and effect animations immediately (overrides the options screen)")
+                        .some_if(AnimationSpeed::Instant));
+                no_respawn = flag("no-respawn")
+                    .desc("don't spawn new monsters on levels the player lingers on");
+                bsp_dungeon = flag("bsp-dungeon")
+                    .desc("place the rooms of new games' levels by binary space partitioning rather than at random");
+                key_repeat_delay_ms = opt_opt::<u64, _>("MS", "key-repeat-delay")
+                    .desc("when holding a movement key, wait this long before moving again")
+                    .with_default_general(0);
+                key_repeat_interval_ms = opt_opt::<u64, _>("MS", "key-repeat-interval")
+                    .desc("when holding a movement key, move at most once per this many milliseconds")
+                    .with_default_general(80);
+                spectate_mode = opt_opt::<String, _>("ADDRESS", "spectate-host")
+                    .desc("accept spectators on this address (requires the \"spectate\" feature)")
+                    .map(|address| address.map(SpectateMode::Host))
+                    .choose_at_most_one(opt_opt::<String, _>("ADDRESS", "spectate")
+                        .desc("watch a game hosted on this address (requires the \"spectate\" feature)")
+                        .map(|address| address.map(SpectateMode::Watch)));
+                daily_challenge_url = opt_opt::<String, _>("URL", "daily-challenge")
+                    .desc("play today's challenge from this server and upload the score (overrides --rng-seed)");
+                trace_path = opt_opt::<String, _>("PATH", "trace")
+                    .desc("write each action, the number of random numbers drawn and a hash of the world to this file");
+                ui_layout = flag("large-log").desc("make room for more messages below the map")
+                    .some_if(UiLayout::LargeLog)
+                    .choose_at_most_one(flag("log-on-right")
+                        .desc("show the message log and status to the right of the map instead of below it")
+                        .some_if(UiLayout::Right))
+                    .choose_at_most_one(flag("hide-hud")
+                        .desc("only show the map")
+                        .some_if(UiLayout::Hidden))
+                    .with_default_general(UiLayout::Bottom);
+                enemy_list = flag("enemy-list")
+                    .desc("list the enemies in sight, with their health and distance, to the right of everything else");
+                bot_games = opt_opt::<u64, _>("INT", "bot")
+                    .desc("let the built-in bot play this many games, with consecutive seeds, without opening a window, and print statistics");
+                export_save_path = opt_opt::<String, _>("PATH", "export-save")
+                    .desc("write the saved game to this file as json, whichever format it was saved in, and exit");
+                dump_world_path = opt_opt::<String, _>("PATH", "dump-world")
+                    .desc("write a map of the saved game's current level and a listing of its entities to this file, and exit");
+                sync_location = opt_opt::<String, _>("PATH_OR_URL", "sync")
+                    .desc("copy saves to this directory or WebDAV url after saving, and fetch newer copies from it on startup");
             } in {
-                Self { rng_seed, visibility_algorithm }
+                Self {
+                    rng_seed,
+                    visibility_algorithm,
+                    debug_fov_diff,
+                    wizard,
+                    hide_damage_numbers,
+                    box_drawing_walls: !ascii_walls,
+                    animation_speed,
+                    npc_respawning: !no_respawn,
+                    dungeon_config: DungeonConfig {
+                        room_placement: if bsp_dungeon {
+                            RoomPlacement::Bsp
+                        } else {
+                            RoomPlacement::Random
+                        },
+                        ..DungeonConfig::default()
+                    },
+                    key_repeat_delay: Duration::from_millis(key_repeat_delay_ms),
+                    key_repeat_interval: Duration::from_millis(key_repeat_interval_ms),
+                    spectate_mode,
+                    daily_challenge_url,
+                    trace_path,
+                    ui_layout,
+                    enemy_list,
+                    bot_games,
+                    export_save_path,
+                    dump_world_path,
+                    sync_location,
+                }
             }
         }
     }
 }
 
+// Games played by the bot are abandoned after this many actions, in case it gets stuck
+const MAX_BOT_ACTIONS: u64 = 20000;
+
+fn run_bot(
+    first_rng_seed: u64,
+    num_games: u64,
+    npc_respawning: bool,
+    dungeon_config: DungeonConfig,
+) {
+    let mut total_depth = 0;
+    let mut total_turns = 0;
+    let mut total_kills = 0;
+    let mut deaths = 0;
+    let mut deepest = 0;
+    for rng_seed in first_rng_seed..first_rng_seed.wrapping_add(num_games) {
+        let mut game_state = GameState::new(
+            Size::new(40, 24),
+            rng_seed,
+            VisibilityAlgorithm::Shadowcast,
+            false,
+            dungeon_config,
+            Challenges::default(),
+        );
+        game_state.set_npc_respawning(npc_respawning);
+        let outcome = bot::play(&mut SimpleBot, &mut game_state, MAX_BOT_ACTIONS);
+        println!(
+            "seed {}: depth {}, {} turns, {} kills, {}",
+            rng_seed,
+            outcome.depth,
+            outcome.turns,
+            outcome.kills,
+            if outcome.died { "died" } else { "survived" }
+        );
+        total_depth += outcome.depth as u64;
+        total_turns += outcome.turns;
+        total_kills += outcome.kills as u64;
+        deepest = deepest.max(outcome.depth);
+        if outcome.died {
+            deaths += 1;
+        }
+    }
+    if num_games == 0 {
+        return;
+    }
+    println!("games: {}", num_games);
+    println!("deaths: {}", deaths);
+    println!("deepest: {}", deepest);
+    println!("mean depth: {:.2}", total_depth as f64 / num_games as f64);
+    println!("mean turns: {:.1}", total_turns as f64 / num_games as f64);
+    println!("mean kills: {:.2}", total_kills as f64 / num_games as f64);
+}
+
 fn main() {
     use meap::Parser;
     let Args {
         rng_seed,
         visibility_algorithm,
+        debug_fov_diff,
+        wizard,
+        hide_damage_numbers,
+        box_drawing_walls,
+        animation_speed,
+        npc_respawning,
+        dungeon_config,
+        key_repeat_delay,
+        key_repeat_interval,
+        spectate_mode,
+        daily_challenge_url,
+        trace_path,
+        ui_layout,
+        enemy_list,
+        bot_games,
+        export_save_path,
+        dump_world_path,
+        sync_location,
     } = Args::parser().with_help_default().parse_env_or_exit();
+    if let Some(num_games) = bot_games {
+        run_bot(rng_seed, num_games, npc_respawning, dungeon_config);
+        return;
+    }
+    if let Some(path) = export_save_path {
+        if let Err(error) = app::export_save(&path) {
+            eprintln!("Failed to export saved game: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(path) = dump_world_path {
+        if let Err(error) = app::dump_world(&path) {
+            eprintln!("Failed to dump world: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // The debug overlay compares the chosen algorithm against the other variety of shadowcasting
+    let fov_comparison = if debug_fov_diff {
+        Some(match visibility_algorithm {
+            VisibilityAlgorithm::SymmetricShadowcast => VisibilityAlgorithm::Shadowcast,
+            _ => VisibilityAlgorithm::SymmetricShadowcast,
+        })
+    } else {
+        None
+    };
+    let daily_challenge = daily_challenge_url.map(DailyChallenge::fetch);
+    let rng_seed = daily_challenge
+        .as_ref()
+        .map_or(rng_seed, |daily_challenge| daily_challenge.seed());
+    // Everyone playing the daily challenge must get the same dungeon
+    let dungeon_config = if daily_challenge.is_some() {
+        DungeonConfig::default()
+    } else {
+        dungeon_config
+    };
+    let save_sync = sync_location.map(SaveSync::new);
+    let trace = match trace_path.map(Trace::create) {
+        None => None,
+        Some(Ok(trace)) => Some(trace),
+        Some(Err(error)) => {
+            eprintln!("Failed to create trace file: {}", error);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(feature = "spectate")]
+    let spectate = match spectate_mode.as_ref().map(spectate::Spectate::new) {
+        None => None,
+        Some(Ok(spectate)) => Some(spectate),
+        Some(Err(error)) => {
+            eprintln!("Failed to set up spectating: {}", error);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(not(feature = "spectate"))]
+    if spectate_mode.is_some() {
+        eprintln!("Spectating requires building with the \"spectate\" feature");
+        std::process::exit(1);
+    }
+    rescue::install_panic_hook();
     const CELL_SIZE_PX: f64 = 24.;
+    let layout = Layout::new(ui_layout, enemy_list, Size::new(40, 24));
     let context = Context::new(Config {
         font_bytes: FontBytes {
             normal: include_bytes!("./fonts/PxPlus_IBM_CGAthin.ttf").to_vec(),
@@ -48,8 +300,8 @@ fn main() {
         },
         title: "Chargrid Tutorial".to_string(),
         window_dimensions_px: Dimensions {
-            width: 960.,
-            height: 720.,
+            width: layout.screen_size.width() as f64 * CELL_SIZE_PX,
+            height: layout.screen_size.height() as f64 * CELL_SIZE_PX,
         },
         cell_dimensions_px: Dimensions {
             width: CELL_SIZE_PX,
@@ -63,7 +315,42 @@ fn main() {
         underline_top_offset_cell_ratio: 0.8,
         resizable: false,
     });
-    let screen_size = Size::new(40, 30);
-    let app = app(screen_size, rng_seed, visibility_algorithm);
+    #[cfg(feature = "spectate")]
+    let app = app(
+        layout,
+        rng_seed,
+        visibility_algorithm,
+        fov_comparison,
+        wizard,
+        hide_damage_numbers,
+        box_drawing_walls,
+        animation_speed,
+        npc_respawning,
+        dungeon_config,
+        key_repeat_delay,
+        key_repeat_interval,
+        daily_challenge,
+        trace,
+        save_sync,
+        spectate,
+    );
+    #[cfg(not(feature = "spectate"))]
+    let app = app(
+        layout,
+        rng_seed,
+        visibility_algorithm,
+        fov_comparison,
+        wizard,
+        hide_damage_numbers,
+        box_drawing_walls,
+        animation_speed,
+        npc_respawning,
+        dungeon_config,
+        key_repeat_delay,
+        key_repeat_interval,
+        daily_challenge,
+        trace,
+        save_sync,
+    );
     context.run_app(app);
 }
@@ -0,0 +1,162 @@
+use chargrid_roguelike_tutorial_2020::{Buc, InterruptReason, LogMessage, SpeedEffect};
+use serde::{Deserialize, Serialize};
+
+// Placeholders which may appear in message templates. The subject is the npc, item, projectile or
+// level which the message is about, and is rendered in its own colour. The argument is any other
+// value carried by the message, such as an amount of damage.
+pub const SUBJECT: &str = "{subject}";
+pub const SUBJECT_UPPERCASE: &str = "{SUBJECT}";
+pub const ARG: &str = "{arg}";
+
+// The language that text of the message log is displayed in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Language {
+    English,
+}
+
+impl Default for Language {
+    fn default() -> Self {
+        Self::English
+    }
+}
+
+impl Language {
+    pub fn template(self, message: LogMessage) -> &'static str {
+        match self {
+            Self::English => english::template(message),
+        }
+    }
+    pub fn speed_effect_adjective(self, effect: SpeedEffect) -> &'static str {
+        match self {
+            Self::English => english::speed_effect_adjective(effect),
+        }
+    }
+}
+
+mod english {
+    use super::*;
+
+    pub fn template(message: LogMessage) -> &'static str {
+        use LogMessage::*;
+        match message {
+            PlayerAttacksNpc(_) => "You attack the {subject}.",
+            NpcAttacksPlayer(_) => "The {subject} attacks you.",
+            PlayerKillsNpc(_) => "You kill the {subject}.",
+            NpcKillsPlayer(_) => "THE {SUBJECT} KILLS YOU!",
+            PlayerGets(_) => "You get the {subject}.",
+            PlayerSearchesCorpse(_) => "You search the corpse and find the {subject}.",
+            PlayerInventoryIsFull => "Inventory is full!",
+            NoItemUnderPlayer => "Nothing to get!",
+            NoItemInInventorySlot => "No item in inventory slot!",
+            PlayerHeals => "You feel slightly better.",
+            PlayerDrops(_) => "You drop the {subject}.",
+            NoSpaceToDropItem => "No space to drop item!",
+            PlayerLaunchesProjectile(_) => "You launch a {subject}!",
+            NpcDies(_) => "The {subject} dies.",
+            NpcBecomesConfused(_) => "The {subject} is confused.",
+            NpcIsNoLongerConfused(_) => "The {subject}'s confusion passes.",
+            PlayerDodges(_) => "You dodge the {subject}'s attack.",
+            NpcDodges(_) => "The {subject} dodges your attack.",
+            PlayerEquips(_) => "You equip the {subject}.",
+            Tremor => "The ground shakes and walls crumble!",
+            MonstersMigrate => "You hear footsteps near the stairs...",
+            ItemsTeleport => "The air shimmers. Things have moved.",
+            NoAltarUnderPlayer => "There is no altar here!",
+            AltarIsSilent => "The altar is silent.",
+            AltarHeals => "You pray. You feel much better!",
+            AltarEnchants(_) => "You pray. Your {subject} glows!",
+            AltarRevealsMap => "You pray. A vision of the level fills your mind.",
+            AltarSmites => "You pray. You are smitten!",
+            AltarSummons => "You pray. Something answers...",
+            AltarWeakens => "You pray. You feel weaker.",
+            NoFountainUnderPlayer => "There is no fountain here!",
+            FountainHeals => "You drink. The water is refreshing!",
+            FountainPoisons => "You drink. The water is poisoned!",
+            FountainRevealsMap => "You drink. The level is reflected in the water.",
+            FountainDriesUp => "The fountain runs dry.",
+            FountainEvaporates => "A fountain boils away in a cloud of steam!",
+            PlayerRecoversFromPoison => "The poison wears off.",
+            AmmoMustBeFired => "Hold a bow and press f to fire arrows.",
+            NoRangedWeaponHeld => "You aren't holding a ranged weapon!",
+            NoAmmo => "You have no arrows!",
+            ArrowGlancesOff(_) => "The arrow glances off the {subject}.",
+            NpcBurns(_) => "The {subject} burns!",
+            NpcIsKnockedBack(_) => "The {subject} is knocked back!",
+            AllyAttacksNpc(_) => "Your ally attacks the {subject}.",
+            AllyKillsNpc(_) => "Your ally kills the {subject}.",
+            NpcDodgesAlly(_) => "The {subject} dodges your ally's attack.",
+            NpcAttacksAlly(_) => "The {subject} attacks your ally.",
+            NpcKillsAlly(_) => "The {subject} kills your ally!",
+            AllyDodges(_) => "Your ally dodges the {subject}'s attack.",
+            NpcAttacksNpc(..) => "The {subject} attacks the {arg}!",
+            NpcKillsNpc(..) => "The {subject} kills the {arg}!",
+            NpcDodgesNpc(..) => "The {arg} dodges the {subject}'s attack.",
+            BossSummonsMinions(_) => "The {subject} bellows for reinforcements!",
+            BossBecomesEnraged(_) => "The {subject} flies into a rage!",
+            AllyAppears(_) => "A {subject} appears to fight by your side!",
+            AllyVanishes(_) => "The {subject} fades away.",
+            NoSpaceToSummon => "There's no space to summon anything!",
+            PlayerBecomesInvisible => "You fade from sight.",
+            PlayerInvisibilityFading => "You are starting to reappear...",
+            PlayerSpeedChanges(_) => "You feel {arg}.",
+            NpcSpeedChanges(..) => "The {subject} looks {arg}.",
+            PlayerSpeedReturnsToNormal => "Your speed returns to normal.",
+            NpcSpeedReturnsToNormal(_) => "The {subject}'s speed returns to normal.",
+            PlayerStealsLife(_) => "You drain {arg} health from the wound.",
+            NpcStealsLife(..) => "The {subject} drains {arg} health from the wound.",
+            PlayerStartsRegenerating => "Your wounds begin to close.",
+            PlayerStopsRegenerating => "You stop regenerating.",
+            PlayerDefends => "You raise your guard.",
+            PlayerCharges => "You charge!",
+            ChargeNotReady(_) => "You can't charge again for {arg} turns.",
+            NoRoomToCharge => "There's no room to charge!",
+            PlayerKicksNpc(_) => "You kick the {subject} back!",
+            NpcDoesNotBudge(_) => "You kick the {subject}, but it doesn't budge.",
+            PlayerKicksWall => "Ouch! That hurts!",
+            PlayerKicksNothing => "You kick at empty space.",
+            PlayerAttacksNothing => "There's nothing there to attack.",
+            PlayerTaunts => "You shout a challenge into the dark!",
+            ItemBreaks(_) => "Your {subject} breaks!",
+            PlayerRepairs => "You repair your equipment.",
+            NothingToRepair => "You have nothing equipped to repair!",
+            HostileSummonAppears(_) => "A {subject} appears, and it looks angry!",
+            PlayerEntersLevel(_) => "You enter the {subject}.",
+            PlayerFallsThroughTrapdoor(_) => {
+                "A trapdoor opens beneath you! You fall to the level below, taking {arg} damage."
+            }
+            PlayerSpringsSlowingTrap => {
+                "You spring a hidden snare, and it tangles around your legs!"
+            }
+            ScrollBackfires => "The scroll backfires!",
+            ItemIsCursed(_) => "The {subject} is cursed!",
+            CursedItemIsStuck(_) => "You can't remove the cursed {subject}!",
+            ItemBucRevealed(_, Buc::Blessed) => "The {subject} glows with a soft light.",
+            ItemBucRevealed(_, Buc::Uncursed) => "The {subject} rests quietly on the altar.",
+            ItemBucRevealed(_, Buc::Cursed) => "The {subject} flashes black!",
+            PlayerIsVisible => "You are visible again!",
+            PlayerFires(_) => "You fire an {subject}.",
+            ChallengeForbidsItems => "You have sworn off items!",
+            NpcAppears(_) | ItemAppears(_) => "A {subject} appears.",
+            TaskInterrupted(InterruptReason::EnemySeen) => "You stop. There's an enemy in sight!",
+            TaskInterrupted(InterruptReason::Damaged) => "You stop. You've been hurt!",
+            TaskInterrupted(InterruptReason::FullyRested) => "You finish resting.",
+            TaskInterrupted(InterruptReason::RestedTooLong) => {
+                "You give up resting. Nothing seems to be getting better."
+            }
+            TaskInterrupted(InterruptReason::NothingToExplore) => {
+                "There's nothing left to explore here."
+            }
+            TaskInterrupted(_) => "You stop.",
+            MacroRecordingStarted => "You start recording a macro.",
+            MacroRecorded(_) => "You record a macro of {arg} steps.",
+            NoMacroRecorded => "There's no macro recorded there.",
+        }
+    }
+
+    pub fn speed_effect_adjective(effect: SpeedEffect) -> &'static str {
+        match effect {
+            SpeedEffect::Haste => "quick",
+            SpeedEffect::Slow => "sluggish",
+        }
+    }
+}
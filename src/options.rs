@@ -0,0 +1,156 @@
+use crate::app::AnimationSpeed;
+use chargrid_roguelike_tutorial_2020::{ItemCategory, LogMessage};
+use rgb24::Rgb24;
+use serde::{Deserialize, Serialize};
+
+// Settings chosen on the options screen which are stored in the profile, so they apply to every
+// game. Options added after a profile was written take their default values.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    pub animation_speed: AnimationSpeed,
+    pub show_damage_numbers: bool,
+    pub combat_verbosity: CombatVerbosity,
+    pub auto_pickup: AutoPickup,
+    pub palette: Palette,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            animation_speed: AnimationSpeed::Normal,
+            show_damage_numbers: true,
+            combat_verbosity: CombatVerbosity::Full,
+            auto_pickup: AutoPickup::Off,
+            palette: Palette::Standard,
+        }
+    }
+}
+
+// Options given on the command line. These take the place of the stored options until the game is
+// closed, and are never written to the profile.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OptionOverrides {
+    pub animation_speed: Option<AnimationSpeed>,
+    pub hide_damage_numbers: bool,
+}
+
+impl OptionOverrides {
+    pub fn apply(self, options: Options) -> Options {
+        Options {
+            animation_speed: self.animation_speed.unwrap_or(options.animation_speed),
+            show_damage_numbers: options.show_damage_numbers && !self.hide_damage_numbers,
+            ..options
+        }
+    }
+}
+
+// How much of the fighting is reported in the message log
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CombatVerbosity {
+    Full,
+    // Dodges, and fights which the player isn't part of, are left out
+    Brief,
+}
+
+impl CombatVerbosity {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Full => Self::Brief,
+            Self::Brief => Self::Full,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Full => "Full",
+            Self::Brief => "Brief",
+        }
+    }
+    pub fn shows(self, message: LogMessage) -> bool {
+        match self {
+            Self::Full => true,
+            Self::Brief => !message.is_minor_combat(),
+        }
+    }
+}
+
+// Which items are picked up by walking onto them, without pressing 'g'. Picking up an item takes
+// a turn either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AutoPickup {
+    Off,
+    Potions,
+    PotionsAndScrolls,
+    Everything,
+}
+
+impl AutoPickup {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Potions,
+            Self::Potions => Self::PotionsAndScrolls,
+            Self::PotionsAndScrolls => Self::Everything,
+            Self::Everything => Self::Off,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Potions => "Potions",
+            Self::PotionsAndScrolls => "Potions and scrolls",
+            Self::Everything => "Everything",
+        }
+    }
+    pub fn includes(self, category: ItemCategory) -> bool {
+        match self {
+            Self::Off => false,
+            Self::Potions => category == ItemCategory::Potion,
+            Self::PotionsAndScrolls => {
+                category == ItemCategory::Potion || category == ItemCategory::Scroll
+            }
+            Self::Everything => true,
+        }
+    }
+}
+
+// A transformation applied to every colour in the game area
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Palette {
+    Standard,
+    // Bright colours are brighter and dark colours are darker
+    HighContrast,
+    Greyscale,
+}
+
+impl Palette {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Standard => Self::HighContrast,
+            Self::HighContrast => Self::Greyscale,
+            Self::Greyscale => Self::Standard,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Standard => "Standard",
+            Self::HighContrast => "High contrast",
+            Self::Greyscale => "Greyscale",
+        }
+    }
+    pub fn apply(self, colour: Rgb24) -> Rgb24 {
+        match self {
+            Self::Standard => colour,
+            Self::HighContrast => {
+                // Doubles each channel's distance from the middle of its range
+                let stretch = |channel: u8| (channel as i32 * 2 - 127).max(0).min(255) as u8;
+                Rgb24::new(stretch(colour.r), stretch(colour.g), stretch(colour.b))
+            }
+            Self::Greyscale => {
+                // Weighted by how bright each primary colour appears
+                let luminance =
+                    (colour.r as u32 * 77 + colour.g as u32 * 150 + colour.b as u32 * 29) >> 8;
+                Rgb24::new_grey(luminance as u8)
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::app;
+use chargrid_roguelike_tutorial_2020::GameState;
+use std::panic;
+use std::sync::Mutex;
+
+// A copy of the game in progress as of the start of the current turn, which the panic hook saves
+// so that a crash doesn't cost the player their run. It's encoded with bincode, which is quick
+// enough to do every turn, and only decoded again if the game crashes.
+static SNAPSHOT: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+pub fn update(game_state: &GameState) {
+    let encoded = match bincode::serialize(game_state) {
+        Ok(encoded) => encoded,
+        Err(error) => {
+            eprintln!("Failed to take rescue snapshot: {}", error);
+            return;
+        }
+    };
+    if let Ok(mut snapshot) = SNAPSHOT.lock() {
+        *snapshot = Some(encoded);
+    }
+}
+
+// Called when the game in progress isn't one which should be rescued, such as the arena
+pub fn clear() {
+    if let Ok(mut snapshot) = SNAPSHOT.lock() {
+        *snapshot = None;
+    }
+}
+
+// Decodes the snapshot, if there is one. Uses `try_lock`, as the panic may have happened while
+// the snapshot was being updated.
+fn snapshot_game_state() -> Option<GameState> {
+    let snapshot = SNAPSHOT.try_lock().ok()?;
+    bincode::deserialize(snapshot.as_ref()?).ok()
+}
+
+// After the usual panic message, explains what happened and saves the snapshot to a separate
+// rescue file, which is loaded in place of the regular save the next time the game starts
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        eprintln!();
+        match info.location() {
+            Some(location) => eprintln!(
+                "Sorry, the game crashed at {}:{}.",
+                location.file(),
+                location.line()
+            ),
+            None => eprintln!("Sorry, the game crashed."),
+        }
+        match snapshot_game_state() {
+            Some(game_state) => match app::store_rescued_game(&game_state) {
+                Ok(path) => eprintln!(
+                    "Your game as of the start of the turn was saved to {:?}, and will be loaded \
+                     the next time the game starts.",
+                    path
+                ),
+                Err(error) => eprintln!("Failed to save your game: {}", error),
+            },
+            None => eprintln!("There was no game in progress to save."),
+        }
+    }));
+}
@@ -0,0 +1,172 @@
+use crate::game::GameState;
+use entity_table::ComponentTable;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// Saved games are wrapped in an envelope recording the version of the save format they were written
+// with. Whenever a change to `GameState` would stop existing saves from loading, bump this and add a
+// migration to `MIGRATIONS` which rewrites saves from the previous version.
+pub const SAVE_VERSION: u32 = 4;
+
+// `MIGRATIONS[n]` upgrades the serialized game state of a version `n` save to version `n + 1`
+const MIGRATIONS: &[fn(Value) -> Value] = &[
+    from_unversioned,
+    from_separate_message_turns,
+    from_missing_up_stairs,
+    from_missing_slowing_traps,
+];
+
+// Version 0 saves are the serialized game state without an envelope, which is otherwise unchanged
+// in version 1
+fn from_unversioned(game_state: Value) -> Value {
+    game_state
+}
+
+// Version 1 saves keep the turn on which each message was logged in a list alongside the message
+// log, rather than in each entry of the log. Messages without a turn are given turn 0.
+fn from_separate_message_turns(mut game_state: Value) -> Value {
+    if let Value::Object(fields) = &mut game_state {
+        let turns = match fields.remove("message_turns") {
+            Some(Value::Array(turns)) => turns,
+            _ => Vec::new(),
+        };
+        if let Some(Value::Array(messages)) = fields.remove("message_log") {
+            let entries = messages
+                .into_iter()
+                .enumerate()
+                .map(|(i, message)| {
+                    json!({
+                        "message": message,
+                        "turn": turns.get(i).cloned().unwrap_or_else(|| json!(0)),
+                    })
+                })
+                .collect();
+            fields.insert("message_log".to_string(), Value::Array(entries));
+        }
+    }
+    game_state
+}
+
+// Adds an empty table of a kind of component which didn't exist when the save was written to the
+// world of every level in the save, as nothing in those worlds has that component
+fn insert_empty_component_table(game_state: &mut Value, name: &str) {
+    let fields = match game_state.as_object_mut() {
+        Some(fields) => fields,
+        None => return,
+    };
+    for (field, value) in fields.iter_mut() {
+        let worlds = match field.as_str() {
+            "world" => vec![value],
+            "previous_levels" => value
+                .as_object_mut()
+                .into_iter()
+                .flat_map(|levels| levels.values_mut())
+                .filter_map(|level| level.get_mut("world"))
+                .collect(),
+            _ => continue,
+        };
+        for world in worlds {
+            if let Some(Value::Object(components)) = world.get_mut("components") {
+                components
+                    .entry(name)
+                    .or_insert_with(|| json!(ComponentTable::<()>::default()));
+            }
+        }
+    }
+}
+
+// Version 2 saves predate up stairs
+fn from_missing_up_stairs(mut game_state: Value) -> Value {
+    insert_empty_component_table(&mut game_state, "up_stairs");
+    game_state
+}
+
+// Version 3 saves predate slowing traps
+fn from_missing_slowing_traps(mut game_state: Value) -> Value {
+    insert_empty_component_table(&mut game_state, "slowing_trap");
+    game_state
+}
+
+// How saves are encoded. Json saves can be read by hand, and are migrated when the save format
+// changes. Binary saves are smaller and quicker to read and write, but only load in the version of
+// the game which wrote them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    Json,
+    Binary,
+}
+
+// The envelope of a binary save. The game state is encoded separately so that the version can be
+// read even when the game state's encoding has changed.
+#[derive(Serialize, Deserialize)]
+pub struct BinarySave {
+    version: u32,
+    // The game state, encoded with bincode
+    game_state: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    // The save was written by a newer version of the game
+    TooNew { version: u32 },
+    // A binary save was written by an older version of the game. Unlike json saves, these can't be
+    // migrated.
+    Outdated { version: u32 },
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+impl From<bincode::Error> for LoadError {
+    fn from(error: bincode::Error) -> Self {
+        Self::Binary(error)
+    }
+}
+
+pub fn to_json(game_state: &GameState) -> Value {
+    json!({
+        "version": SAVE_VERSION,
+        "game_state": game_state,
+    })
+}
+
+pub fn from_json(save: Value) -> Result<GameState, LoadError> {
+    let (version, mut game_state) = match save {
+        Value::Object(mut envelope) if envelope.contains_key("version") => {
+            let version = serde_json::from_value(envelope.remove("version").unwrap())?;
+            let game_state = envelope.remove("game_state").unwrap_or(Value::Null);
+            (version, game_state)
+        }
+        game_state => (0, game_state),
+    };
+    if version > SAVE_VERSION {
+        return Err(LoadError::TooNew { version });
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        game_state = migration(game_state);
+    }
+    Ok(serde_json::from_value(game_state)?)
+}
+
+pub fn to_binary(game_state: &GameState) -> BinarySave {
+    BinarySave {
+        version: SAVE_VERSION,
+        game_state: bincode::serialize(game_state).expect("failed to serialize game state"),
+    }
+}
+
+pub fn from_binary(save: BinarySave) -> Result<GameState, LoadError> {
+    let version = save.version;
+    if version > SAVE_VERSION {
+        return Err(LoadError::TooNew { version });
+    }
+    if version < SAVE_VERSION {
+        return Err(LoadError::Outdated { version });
+    }
+    Ok(bincode::deserialize(&save.game_state)?)
+}
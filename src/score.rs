@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+// Everything about a run which contributes to its score. To make something new count towards
+// the score, add a field here and a term to `ScoreInputs::score`.
+//
+// There are no terms for gold or for winning. The game has no gold to collect, and no victory
+// condition, so every run ends with the player's death. Speed is rewarded instead, with a bonus for
+// each level reached which shrinks with every turn taken.
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreInputs {
+    pub depth: u32,
+    pub kills: u32,
+    pub turns: u64,
+    pub endless: bool,
+    // The number of challenges the run was played with
+    pub challenges: u32,
+}
+
+const POINTS_PER_DEPTH: u64 = 1000;
+const POINTS_PER_KILL: u64 = 50;
+
+// Each level reached adds this much to a speed bonus, which loses a point for each turn taken
+const SPEED_BONUS_PER_DEPTH: u64 = 500;
+
+// Endless runs are scored mostly by how deep they get
+const ENDLESS_DEPTH_MULTIPLIER: u64 = 3;
+
+// Each challenge increases the score by this percentage
+const PERCENT_BONUS_PER_CHALLENGE: u64 = 50;
+
+impl ScoreInputs {
+    pub fn score(&self) -> u64 {
+        let depth_points = if self.endless {
+            self.depth as u64 * POINTS_PER_DEPTH * ENDLESS_DEPTH_MULTIPLIER
+        } else {
+            self.depth as u64 * POINTS_PER_DEPTH
+        };
+        let kill_points = self.kills as u64 * POINTS_PER_KILL;
+        let speed_points = (self.depth as u64 * SPEED_BONUS_PER_DEPTH).saturating_sub(self.turns);
+        let challenge_percent = 100 + self.challenges as u64 * PERCENT_BONUS_PER_CHALLENGE;
+        (depth_points + kill_points + speed_points) * challenge_percent / 100
+    }
+}
+
+pub const NUM_HIGH_SCORES: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScore {
+    pub score: u64,
+    pub depth: u32,
+    pub turns: u64,
+    pub endless: bool,
+}
+
+// The best scores of past runs, best first
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HighScores {
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    // Adds the score of a finished run to the table, returning its rank if it was high enough to
+    // be kept. Ties rank below the existing score.
+    pub fn insert(&mut self, score_inputs: ScoreInputs) -> Option<usize> {
+        let high_score = HighScore {
+            score: score_inputs.score(),
+            depth: score_inputs.depth,
+            turns: score_inputs.turns,
+            endless: score_inputs.endless,
+        };
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| high_score.score > entry.score)
+            .unwrap_or(self.entries.len());
+        if rank >= NUM_HIGH_SCORES {
+            return None;
+        }
+        self.entries.insert(rank, high_score);
+        self.entries.truncate(NUM_HIGH_SCORES);
+        Some(rank)
+    }
+    pub fn entries(&self) -> &[HighScore] {
+        &self.entries
+    }
+}
@@ -0,0 +1,164 @@
+use crate::SpectateMode;
+use chargrid_roguelike_tutorial_2020::GameState;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Game states are sent as json, one per line
+const BETWEEN_SENDS: Duration = Duration::from_millis(100);
+
+// Spectators with this many game states waiting to be written have fallen behind, and are dropped
+const MAX_QUEUED_SENDS: usize = 8;
+
+pub enum Spectate {
+    Host(SpectateHost),
+    Watch(SpectateWatch),
+}
+
+impl Spectate {
+    pub fn new(mode: &SpectateMode) -> io::Result<Self> {
+        match mode {
+            SpectateMode::Host(address) => SpectateHost::bind(address.as_str()).map(Self::Host),
+            SpectateMode::Watch(address) => {
+                SpectateWatch::connect(address.as_str()).map(Self::Watch)
+            }
+        }
+    }
+}
+
+// A connected spectator. Game states are written to its stream on a thread of its own so that a
+// slow connection never holds up the game.
+struct Spectator {
+    address: SocketAddr,
+    sender: SyncSender<Arc<Vec<u8>>>,
+}
+
+impl Spectator {
+    fn spawn(mut stream: TcpStream, address: SocketAddr) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Arc<Vec<u8>>>(MAX_QUEUED_SENDS);
+        thread::spawn(move || {
+            for bytes in receiver {
+                if let Err(error) = stream.write_all(&bytes) {
+                    println!("Spectator {} disconnected: {}", address, error);
+                    return;
+                }
+            }
+        });
+        Self { address, sender }
+    }
+
+    // Returns false if the spectator has disconnected or fallen behind
+    fn send(&self, bytes: &Arc<Vec<u8>>) -> bool {
+        match self.sender.try_send(Arc::clone(bytes)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                println!("Dropping spectator {} which fell behind", self.address);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+// Accepts connections from spectators and sends them the current game state
+pub struct SpectateHost {
+    listener: TcpListener,
+    spectators: Vec<Spectator>,
+    until_next_send: Duration,
+}
+
+impl SpectateHost {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        println!("Hosting spectators on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            spectators: Vec::new(),
+            until_next_send: Duration::from_millis(0),
+        })
+    }
+
+    // Called once per frame. Game states are sent less often than frames are rendered.
+    pub fn tick(&mut self, period: Duration, game_state: &GameState) {
+        if let Some(until_next_send) = self.until_next_send.checked_sub(period) {
+            self.until_next_send = until_next_send;
+            return;
+        }
+        self.until_next_send = BETWEEN_SENDS;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    // on some platforms accepted streams inherit the listener's non-blocking mode,
+                    // but writes happen on the spectator's own thread so may block
+                    if let Err(error) = stream.set_nonblocking(false) {
+                        eprintln!("Failed to configure spectator stream: {}", error);
+                        continue;
+                    }
+                    println!("Spectator connected from {}", address);
+                    self.spectators.push(Spectator::spawn(stream, address));
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    eprintln!("Failed to accept spectator: {}", error);
+                    break;
+                }
+            }
+        }
+        if self.spectators.is_empty() {
+            return;
+        }
+        let mut bytes = match serde_json::to_vec(game_state) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                eprintln!("Failed to serialize game state: {}", error);
+                return;
+            }
+        };
+        bytes.push(b'\n');
+        let bytes = Arc::new(bytes);
+        self.spectators.retain(|spectator| spectator.send(&bytes));
+    }
+}
+
+// Receives game states from a host on a background thread
+pub struct SpectateWatch {
+    receiver: Receiver<GameState>,
+}
+
+impl SpectateWatch {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        println!("Spectating {}", stream.peer_addr()?);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => {
+                        eprintln!("Lost connection to host: {}", error);
+                        return;
+                    }
+                };
+                match serde_json::from_str(&line) {
+                    Ok(game_state) => {
+                        if sender.send(game_state).is_err() {
+                            return;
+                        }
+                    }
+                    Err(error) => eprintln!("Failed to deserialize game state: {}", error),
+                }
+            }
+            println!("Host closed the connection");
+        });
+        Ok(Self { receiver })
+    }
+
+    // Returns the most recent game state received since the last call, if any
+    pub fn latest_game_state(&self) -> Option<GameState> {
+        self.receiver.try_iter().last()
+    }
+}
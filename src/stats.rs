@@ -0,0 +1,50 @@
+use crate::world::{ItemType, NpcType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Counters covering the whole run, which are saved with the game and shown on the character sheet
+// and the game over screen
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub steps_taken: u64,
+    // Npcs killed by the player, or by something other than a character such as burning
+    #[serde(default)]
+    pub kills: u32,
+    // Damage done to each type of npc by the player and their allies
+    pub damage_dealt: BTreeMap<NpcType, u32>,
+    // Damage done to the player by each type of npc
+    pub damage_taken: BTreeMap<NpcType, u32>,
+    // Damage done to the player by anything other than an npc, such as falling through a trapdoor
+    pub damage_taken_other: u32,
+    pub items_used: BTreeMap<ItemType, u32>,
+    // Scrolls read while no enemy was in sight
+    pub scrolls_wasted: u32,
+}
+
+impl Stats {
+    pub(crate) fn record_damage_dealt(&mut self, npc_type: NpcType, damage: u32) {
+        *self.damage_dealt.entry(npc_type).or_insert(0) += damage;
+    }
+    // The npc type is `None` for damage which didn't come from an npc
+    pub(crate) fn record_damage_taken(&mut self, npc_type: Option<NpcType>, damage: u32) {
+        match npc_type {
+            Some(npc_type) => *self.damage_taken.entry(npc_type).or_insert(0) += damage,
+            None => self.damage_taken_other += damage,
+        }
+    }
+    pub(crate) fn record_kill(&mut self) {
+        self.kills += 1;
+    }
+    pub(crate) fn record_item_use(&mut self, item_type: ItemType) {
+        *self.items_used.entry(item_type).or_insert(0) += 1;
+    }
+    pub fn total_damage_dealt(&self) -> u32 {
+        self.damage_dealt.values().sum()
+    }
+    pub fn total_damage_taken(&self) -> u32 {
+        self.damage_taken.values().sum::<u32>() + self.damage_taken_other
+    }
+    pub fn total_items_used(&self) -> u32 {
+        self.items_used.values().sum()
+    }
+}
@@ -0,0 +1,310 @@
+use crate::http;
+use general_storage_file::FileStorage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Files from the save directory are copied to a sync location after they're written, and newer
+// copies are fetched from it when the game starts, so that a game can be carried on from another
+// machine. Alongside each file, the location stores a "<file>.timestamp" file holding the time at
+// which it was written in milliseconds since the unix epoch. Comparing these with the timestamps
+// recorded locally at the last sync reveals when a file has changed in both places since then. In
+// that case the newer copy is kept, and the older one is set aside as "<file>.conflict". Pushes
+// happen on a background thread so that saving never waits on the sync location.
+
+const STATE_FILE: &str = "sync-state";
+const TIMESTAMP_SUFFIX: &str = ".timestamp";
+const CONFLICT_SUFFIX: &str = ".conflict";
+
+// Somewhere files can be copied to and from
+pub trait SyncLocation: Send {
+    // The contents of the file called `name`, or `None` if there's no such file
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()>;
+}
+
+// A local directory, such as one kept in sync by a file hosting service's desktop client
+pub struct FolderLocation {
+    path: PathBuf,
+}
+
+impl SyncLocation for FolderLocation {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path.join(name)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.path)?;
+        fs::write(self.path.join(name), contents)
+    }
+}
+
+// A collection on a WebDAV server, whose files are read with GET and written with PUT
+pub struct WebDavLocation {
+    url: String,
+}
+
+impl SyncLocation for WebDavLocation {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let response = http::request(
+            &self.url,
+            &format!("/{}", name),
+            "GET",
+            "application/octet-stream",
+            &[],
+        )?;
+        match response.status {
+            404 => Ok(None),
+            _ if response.is_success() => Ok(Some(response.body)),
+            status => Err(unexpected_status(status)),
+        }
+    }
+    fn write(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let response = http::request(
+            &self.url,
+            &format!("/{}", name),
+            "PUT",
+            "application/octet-stream",
+            contents,
+        )?;
+        if response.is_success() {
+            Ok(())
+        } else {
+            Err(unexpected_status(response.status))
+        }
+    }
+}
+
+fn unexpected_status(status: u16) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("unexpected response status: {}", status),
+    )
+}
+
+// The timestamps of a file when it was last pushed or pulled
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct SyncRecord {
+    // The timestamp stored at the sync location
+    remote: u64,
+    // When the local copy was last modified
+    local: u64,
+}
+
+fn timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// When the local copy of a file was last modified, or `None` if there's no local copy
+fn local_timestamp(path: &Path) -> io::Result<Option<u64>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(timestamp(metadata.modified()?))),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+// The local paths involved in syncing a file
+struct LocalFile {
+    name: String,
+    path: PathBuf,
+    state_path: PathBuf,
+}
+
+impl LocalFile {
+    fn new(file_storage: &FileStorage, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            path: file_storage.full_path(name),
+            state_path: file_storage.full_path(STATE_FILE),
+        }
+    }
+    fn conflict_path(&self) -> PathBuf {
+        self.path
+            .with_file_name(format!("{}{}", self.name, CONFLICT_SUFFIX))
+    }
+}
+
+pub struct SaveSync {
+    location: Arc<Mutex<Box<dyn SyncLocation>>>,
+    pushes: Option<Sender<LocalFile>>,
+    pusher: Option<JoinHandle<()>>,
+}
+
+impl SaveSync {
+    // Urls starting with "http://" are treated as WebDAV collections, and anything else as a
+    // local directory
+    pub fn new(location: String) -> Self {
+        let location: Box<dyn SyncLocation> = if location.starts_with("http://") {
+            Box::new(WebDavLocation { url: location })
+        } else {
+            Box::new(FolderLocation {
+                path: PathBuf::from(location),
+            })
+        };
+        let location = Arc::new(Mutex::new(location));
+        let (sender, receiver) = mpsc::channel::<LocalFile>();
+        let pusher = {
+            let location = Arc::clone(&location);
+            thread::spawn(move || {
+                for file in receiver {
+                    let location = location.lock().unwrap();
+                    if let Err(error) = try_push(location.as_ref(), &file) {
+                        eprintln!("Failed to sync {} to remote: {}", file.name, error);
+                    }
+                }
+            })
+        };
+        Self {
+            location,
+            pushes: Some(sender),
+            pusher: Some(pusher),
+        }
+    }
+
+    // Queues the local file `name` to be copied to the sync location. Never fails, as a game
+    // shouldn't be lost because it couldn't be synced.
+    pub fn push(&self, file_storage: &FileStorage, name: &str) {
+        if let Some(pushes) = self.pushes.as_ref() {
+            let _ = pushes.send(LocalFile::new(file_storage, name));
+        }
+    }
+
+    // Replaces the local file `name` with the copy at the sync location, if that copy has changed
+    // since the last sync and is newer. Never fails, as the local copy can always be played. This
+    // only happens before the game is loaded, so there's nothing to be kept waiting.
+    pub fn pull(&self, file_storage: &FileStorage, name: &str) {
+        let location = self.location.lock().unwrap();
+        if let Err(error) = try_pull(location.as_ref(), &LocalFile::new(file_storage, name)) {
+            eprintln!("Failed to sync {} from remote: {}", name, error);
+        }
+    }
+}
+
+// Files still waiting to be pushed when the game exits are pushed before it closes
+impl Drop for SaveSync {
+    fn drop(&mut self) {
+        self.pushes = None;
+        if let Some(pusher) = self.pusher.take() {
+            let _ = pusher.join();
+        }
+    }
+}
+
+fn remote_timestamp(location: &dyn SyncLocation, name: &str) -> io::Result<Option<u64>> {
+    match location.read(&format!("{}{}", name, TIMESTAMP_SUFFIX))? {
+        Some(contents) => String::from_utf8_lossy(&contents)
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+        None => Ok(None),
+    }
+}
+
+fn try_push(location: &dyn SyncLocation, file: &LocalFile) -> io::Result<()> {
+    let name = file.name.as_str();
+    let local = match local_timestamp(&file.path)? {
+        Some(local) => local,
+        None => return Ok(()),
+    };
+    let mut records = load_records(&file.state_path);
+    let record = records.get(name).cloned();
+    if let Some(remote) = remote_timestamp(location, name)? {
+        let changed_elsewhere = record.map_or(true, |record| record.remote != remote);
+        if changed_elsewhere {
+            if remote > local {
+                // The copy written elsewhere is newer, so this one is set aside instead
+                eprintln!(
+                    "{} was changed more recently elsewhere; not syncing it",
+                    name
+                );
+                let contents = fs::read(&file.path)?;
+                return location.write(&format!("{}{}", name, CONFLICT_SUFFIX), &contents);
+            }
+            eprintln!(
+                "{} was also changed elsewhere; setting that copy aside as {}{}",
+                name, name, CONFLICT_SUFFIX
+            );
+            if let Some(contents) = location.read(name)? {
+                location.write(&format!("{}{}", name, CONFLICT_SUFFIX), &contents)?;
+            }
+        }
+    }
+    let contents = fs::read(&file.path)?;
+    location.write(name, &contents)?;
+    location.write(
+        &format!("{}{}", name, TIMESTAMP_SUFFIX),
+        local.to_string().as_bytes(),
+    )?;
+    records.insert(
+        name.to_string(),
+        SyncRecord {
+            remote: local,
+            local,
+        },
+    );
+    store_records(&file.state_path, &records)
+}
+
+fn try_pull(location: &dyn SyncLocation, file: &LocalFile) -> io::Result<()> {
+    let name = file.name.as_str();
+    let remote = match remote_timestamp(location, name)? {
+        Some(remote) => remote,
+        None => return Ok(()),
+    };
+    let mut records = load_records(&file.state_path);
+    let record = records.get(name).cloned();
+    if record.map_or(false, |record| record.remote == remote) {
+        return Ok(());
+    }
+    if let Some(local) = local_timestamp(&file.path)? {
+        let changed_here = record.map_or(true, |record| record.local != local);
+        if changed_here {
+            if local > remote {
+                // The next push will set the remote copy aside
+                eprintln!("{} was changed more recently here; not syncing it", name);
+                return Ok(());
+            }
+            eprintln!(
+                "{} was also changed here; setting the local copy aside as {}{}",
+                name, name, CONFLICT_SUFFIX
+            );
+            fs::copy(&file.path, file.conflict_path())?;
+        }
+    }
+    let contents = match location.read(name)? {
+        Some(contents) => contents,
+        None => return Ok(()),
+    };
+    fs::write(&file.path, contents)?;
+    println!("Synced {} from remote", name);
+    let local = local_timestamp(&file.path)?.unwrap_or(remote);
+    records.insert(name.to_string(), SyncRecord { remote, local });
+    store_records(&file.state_path, &records)
+}
+
+// The records of the last sync of each file. These are only used to detect conflicts, so a
+// missing or unreadable state file is treated as there having been no syncs.
+fn load_records(state_path: &Path) -> HashMap<String, SyncRecord> {
+    fs::read(state_path)
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn store_records(state_path: &Path, records: &HashMap<String, SyncRecord>) -> io::Result<()> {
+    let contents = serde_json::to_vec(records)?;
+    fs::write(state_path, contents)
+}
@@ -0,0 +1,156 @@
+use crate::game::PlayerAction;
+use coord_2d::{Coord, Size};
+use direction::CardinalDirection;
+use grid_2d::Grid;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// An action which takes many turns, such as running down a corridor. The game takes one step of
+// the task at a time, so the player can see what's happening and can stop it at any point.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Task {
+    // Keep moving in one direction until something interesting happens
+    Run(CardinalDirection),
+    // Wait until the player is at full health and their harmful status effects have worn off
+    Rest,
+    // Walk to the nearest part of the level which hasn't been seen yet
+    Explore,
+    // Walk to a cell which the player has already seen
+    Travel(Coord),
+}
+
+// Why a task stopped. Some of these are just the task coming to its natural end, but the ones
+// which interrupt it early are reported in the message log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InterruptReason {
+    EnemySeen,
+    Damaged,
+    // Something was logged which the player should read before carrying on
+    Message,
+    Blocked,
+    // The player reached a junction, or moved between a room and a corridor, while running
+    Junction,
+    // There's an item, the stairs, an altar or a fountain under the player
+    FoundSomething,
+    Arrived,
+    FullyRested,
+    // Resting reached its turn limit before the player recovered
+    RestedTooLong,
+    NothingToExplore,
+    Cancelled,
+    // A macro played all of its steps
+    Finished,
+}
+
+impl InterruptReason {
+    // Whether the task stopped for a reason which the player might not have expected
+    pub(crate) fn is_logged(self) -> bool {
+        match self {
+            Self::EnemySeen
+            | Self::Damaged
+            | Self::FullyRested
+            | Self::RestedTooLong
+            | Self::NothingToExplore => true,
+            Self::Message
+            | Self::Blocked
+            | Self::Junction
+            | Self::FoundSomething
+            | Self::Arrived
+            | Self::Cancelled
+            | Self::Finished => false,
+        }
+    }
+    // Whether a macro should stop when a task it started stops for this reason. Other reasons
+    // are just the task coming to its end, after which the macro carries on.
+    pub(crate) fn stops_macro(self) -> bool {
+        matches!(self, Self::EnemySeen | Self::Damaged | Self::Cancelled)
+    }
+}
+
+// One step of a macro, which replays a sequence of the player's actions. A task is recorded as a
+// single step, rather than as each of the actions it takes.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum MacroStep {
+    Action(PlayerAction),
+    Task(Task),
+}
+
+// A macro being played, and the index of the step to take next
+pub(crate) struct MacroPlayback {
+    pub(crate) steps: Vec<MacroStep>,
+    pub(crate) next_step: usize,
+}
+
+// A task along with the state carried between its steps
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum TaskState {
+    Run {
+        direction: CardinalDirection,
+        // Whether the cells to the player's left and right are open. A change means the player
+        // has reached a junction, or has moved between a room and a corridor.
+        side_openings: (bool, bool),
+    },
+    Rest {
+        turns_remaining: u32,
+    },
+    Explore,
+    Travel {
+        destination: Coord,
+    },
+}
+
+// Resting gives up eventually, as nothing else restores health over time
+pub(crate) const MAX_REST_TURNS: u32 = 100;
+
+// Dijkstra's algorithm from `start`, where each step costs the cost of the cell being entered and
+// cells without a cost can't be entered. Returns the direction of the first step along the
+// cheapest path to the nearest goal, or `None` if no goal can be reached or `start` is itself a
+// goal.
+pub(crate) fn first_step_towards<C, G>(
+    start: Coord,
+    size: Size,
+    cost: C,
+    is_goal: G,
+) -> Option<CardinalDirection>
+where
+    C: Fn(Coord) -> Option<u32>,
+    G: Fn(Coord) -> bool,
+{
+    if is_goal(start) {
+        return None;
+    }
+    // The cheapest known cost of reaching each cell, and the first step taken on the way there
+    let mut best = Grid::new_copy(size, None);
+    let mut queue = BinaryHeap::new();
+    for direction in CardinalDirection::all() {
+        let coord = start + direction.coord();
+        if let (Some(cell), Some(cost)) = (best.get_mut(coord), cost(coord)) {
+            *cell = Some((cost, direction));
+            queue.push(Reverse((cost, coord)));
+        }
+    }
+    while let Some(Reverse((total_cost, coord))) = queue.pop() {
+        let (best_cost, first_step) = best.get_checked(coord).expect("queued cell has no cost");
+        if total_cost > best_cost {
+            continue;
+        }
+        if is_goal(coord) {
+            return Some(first_step);
+        }
+        for direction in CardinalDirection::all() {
+            let next = coord + direction.coord();
+            if next == start {
+                continue;
+            }
+            if let (Some(cell), Some(step_cost)) = (best.get_mut(next), cost(next)) {
+                let next_cost = total_cost + step_cost;
+                if cell.map_or(true, |(cost, _)| next_cost < cost) {
+                    *cell = Some((next_cost, first_step));
+                    queue.push(Reverse((next_cost, next)));
+                }
+            }
+        }
+    }
+    None
+}
@@ -1,15 +1,259 @@
-use crate::world::{ItemType, NpcType};
+use crate::world::{Buc, ItemType, Npc, NpcType};
+use direction::CardinalDirection;
 use grid_2d::{Coord, Grid, Size};
 use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum TerrainTile {
     Player,
     Floor,
     Wall,
-    Npc(NpcType),
+    Npc(Npc),
     Item(ItemType),
     Stairs,
+    Altar,
+    Fountain,
+    Trapdoor,
+    SlowingTrap,
+}
+
+// Each level has a theme which biases the terrain features and monsters generated there
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelTheme {
+    Halls,
+    FloodedWarrens,
+    OrcishBarracks,
+    TrollDen,
+    Sanctum,
+    FrozenCaverns,
+    MagmaForge,
+    // Only used for the handcrafted arena, so never chosen for generated levels
+    Arena,
+}
+
+impl LevelTheme {
+    const ALL: &'static [Self] = &[
+        Self::Halls,
+        Self::FloodedWarrens,
+        Self::OrcishBarracks,
+        Self::TrollDen,
+        Self::Sanctum,
+        Self::FrozenCaverns,
+        Self::MagmaForge,
+    ];
+
+    // Adjectives and nouns which are combined to name levels with this theme
+    fn name_words(self) -> (&'static [&'static str], &'static [&'static str]) {
+        match self {
+            Self::Halls => (
+                &["Dusty", "Dim", "Crumbling"],
+                &["Halls", "Chambers", "Vaults"],
+            ),
+            Self::FloodedWarrens => (
+                &["Flooded", "Dripping", "Sodden"],
+                &["Warrens", "Tunnels", "Burrows"],
+            ),
+            Self::OrcishBarracks => (
+                &["Orcish", "Muddy", "Bloodied"],
+                &["Barracks", "Encampment", "Stronghold"],
+            ),
+            Self::TrollDen => (
+                &["Troll", "Reeking", "Bone-strewn"],
+                &["Den", "Lair", "Caves"],
+            ),
+            Self::Sanctum => (
+                &["Forgotten", "Hallowed", "Silent"],
+                &["Sanctum", "Shrine", "Chapel"],
+            ),
+            Self::FrozenCaverns => (
+                &["Frozen", "Icy", "Frostbitten"],
+                &["Caverns", "Grottoes", "Hollows"],
+            ),
+            Self::MagmaForge => (
+                &["Smouldering", "Molten", "Scorched"],
+                &["Forge", "Foundry", "Furnace"],
+            ),
+            Self::Arena => (&["Training"], &["Arena"]),
+        }
+    }
+
+    // Rotten floors make trapdoors more common in flooded levels
+    fn trapdoor_percent_chance_per_room(self) -> u32 {
+        match self {
+            Self::FloodedWarrens => 30,
+            _ => 10,
+        }
+    }
+
+    // Orcs set snares around their barracks
+    fn slowing_trap_percent_chance_per_room(self) -> u32 {
+        match self {
+            Self::OrcishBarracks => 30,
+            _ => 10,
+        }
+    }
+
+    fn altar_percent_chance_per_room(self) -> u32 {
+        match self {
+            Self::Sanctum => 40,
+            _ => 10,
+        }
+    }
+
+    fn fountain_percent_chance_per_room(self) -> u32 {
+        match self {
+            Self::FloodedWarrens => 30,
+            _ => 10,
+        }
+    }
+
+    // Only cave-like levels are open enough to the elements to have weather
+    fn weather_percent_chances(self) -> &'static [(Weather, u32)] {
+        match self {
+            Self::FloodedWarrens => &[(Weather::Rain, 60), (Weather::Fog, 20)],
+            Self::TrollDen => &[(Weather::Fog, 50)],
+            Self::FrozenCaverns => &[(Weather::Fog, 30)],
+            Self::Halls | Self::OrcishBarracks | Self::Sanctum | Self::MagmaForge | Self::Arena => {
+                &[]
+            }
+        }
+    }
+
+    pub fn choose_weather<R: Rng>(self, rng: &mut R) -> Weather {
+        let mut roll = rng.gen_range(0..100);
+        for &(weather, percent_chance) in self.weather_percent_chances() {
+            if roll < percent_chance {
+                return weather;
+            }
+            roll -= percent_chance;
+        }
+        Weather::Clear
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Weather {
+    Clear,
+    Rain,
+    Fog,
+}
+
+impl Weather {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clear => "Clear",
+            Self::Rain => "Rain",
+            Self::Fog => "Fog",
+        }
+    }
+    // The furthest the player can see in this weather, regardless of the time of day
+    pub fn vision_distance_squared(self) -> u32 {
+        match self {
+            Self::Clear => 100,
+            Self::Rain => 64,
+            Self::Fog => 16,
+        }
+    }
+    // The distance which noises carry. Rain drowns out all but nearby sounds.
+    pub fn noise_distance_squared(self) -> u32 {
+        match self {
+            Self::Clear => 225,
+            Self::Rain => 64,
+            Self::Fog => 144,
+        }
+    }
+}
+
+fn choose_level_theme<R: Rng>(rng: &mut R) -> LevelTheme {
+    *LevelTheme::ALL.choose(rng).unwrap()
+}
+
+// A generated name for a level such as "Flooded Warrens", stored as indices into its theme's
+// word lists so it can be copied around freely
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelName {
+    pub theme: LevelTheme,
+    adjective_index: usize,
+    noun_index: usize,
+}
+
+impl LevelName {
+    pub fn choose<R: Rng>(rng: &mut R) -> Self {
+        let theme = choose_level_theme(rng);
+        let (adjectives, nouns) = theme.name_words();
+        Self {
+            theme,
+            adjective_index: rng.gen_range(0..adjectives.len()),
+            noun_index: rng.gen_range(0..nouns.len()),
+        }
+    }
+    pub fn arena() -> Self {
+        Self {
+            theme: LevelTheme::Arena,
+            adjective_index: 0,
+            noun_index: 0,
+        }
+    }
+}
+
+impl fmt::Display for LevelName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (adjectives, nouns) = self.theme.name_words();
+        write!(
+            f,
+            "{} {}",
+            adjectives[self.adjective_index], nouns[self.noun_index]
+        )
+    }
+}
+
+// The technique used to decide where the rooms of a level go
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomPlacement {
+    // Rooms are placed at random, discarding any which overlap a room already placed
+    Random,
+    // The map is recursively split in two (binary space partitioning) and a room is placed in each
+    // part, which spreads rooms more evenly across the map
+    Bsp,
+}
+
+// Settings which control how levels are generated, fixed for the duration of a game
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct DungeonConfig {
+    pub room_placement: RoomPlacement,
+    // The average number of npcs and items placed per 1000 floor cells of a room on the first
+    // level, so that bigger maps get more of each
+    pub npcs_per_thousand_cells: u32,
+    pub items_per_thousand_cells: u32,
+    // Npcs become more common deeper in the dungeon, by this percentage per level
+    pub npc_density_percent_increase_per_level: u32,
+    // In endless mode the dungeon keeps getting harder past the depth where it normally levels
+    // off. Games saved before endless mode existed aren't endless.
+    #[serde(default)]
+    pub endless: bool,
+}
+
+impl Default for DungeonConfig {
+    fn default() -> Self {
+        Self {
+            room_placement: RoomPlacement::Random,
+            npcs_per_thousand_cells: 40,
+            items_per_thousand_cells: 30,
+            npc_density_percent_increase_per_level: 5,
+            endless: false,
+        }
+    }
+}
+
+impl DungeonConfig {
+    fn npcs_per_thousand_cells(&self, level: u32) -> u32 {
+        let percent = 100 + level.saturating_sub(1) * self.npc_density_percent_increase_per_level;
+        (self.npcs_per_thousand_cells * percent) / 100
+    }
 }
 
 // A rectangular area of the map
@@ -43,9 +287,22 @@ impl Room {
             .map(move |coord| self.top_left + coord)
     }
 
-    // Returns true if and only if each cell of `grid` overlapping this room is `None`
-    fn only_intersects_empty(&self, grid: &Grid<Option<TerrainTile>>) -> bool {
-        self.coords().all(|coord| grid.get_checked(coord).is_none())
+    fn contains(&self, coord: Coord) -> bool {
+        (coord - self.top_left).is_valid(self.size)
+    }
+
+    // Returns the number of cells of the room which aren't walls
+    fn floor_area(&self) -> u32 {
+        (self.size.width() - 1) * (self.size.height() - 1)
+    }
+
+    // Returns true if `coord` is on the top or left side of the room, which are walls
+    fn is_wall(&self, coord: Coord) -> bool {
+        self.contains(coord) && (coord.x == self.top_left.x || coord.y == self.top_left.y)
+    }
+
+    fn overlaps(&self, other: &Room) -> bool {
+        self.coords().any(|coord| other.contains(coord))
     }
 
     // Updates `grid`, setting each cell overlapping this room to `Some(TerrainTile::Floor)`.
@@ -67,6 +324,7 @@ impl Room {
         &self,
         n: usize,
         probability_distribution: &[(NpcType, u32)],
+        elite_percent_chance: u32,
         grid: &mut Grid<Option<TerrainTile>>,
         rng: &mut R,
     ) {
@@ -76,7 +334,46 @@ impl Room {
             .choose_multiple(rng, n)
         {
             let &npc_type = choose_from_probability_distribution(probability_distribution, rng);
-            *grid.get_checked_mut(coord) = Some(TerrainTile::Npc(npc_type));
+            let elite = rng.gen_range(0..100) < elite_percent_chance;
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Npc(Npc { npc_type, elite }));
+        }
+    }
+
+    // Place an altar at a random position within the room
+    fn place_altar<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        if let Some(coord) = self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose(rng)
+        {
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Altar);
+        }
+    }
+
+    // Place a fountain at a random position within the room
+    fn place_fountain<R: Rng>(&self, grid: &mut Grid<Option<TerrainTile>>, rng: &mut R) {
+        if let Some(coord) = self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose(rng)
+        {
+            *grid.get_checked_mut(coord) = Some(TerrainTile::Fountain);
+        }
+    }
+
+    // Place a hidden trap at a random position within the room
+    fn place_trap<R: Rng>(
+        &self,
+        grid: &mut Grid<Option<TerrainTile>>,
+        trap: TerrainTile,
+        rng: &mut R,
+    ) {
+        if let Some(coord) = self
+            .coords()
+            .filter(|&coord| grid.get_checked(coord).unwrap() == TerrainTile::Floor)
+            .choose(rng)
+        {
+            *grid.get_checked_mut(coord) = Some(trap);
         }
     }
 
@@ -99,20 +396,273 @@ impl Room {
     }
 }
 
+// Returns rooms at random positions, skipping any which would overlap a room already chosen
+fn place_rooms_randomly<R: Rng>(size: Size, rng: &mut R) -> Vec<Room> {
+    // Attempt to add a room a constant number of times
+    const NUM_ATTEMPTS: usize = 100;
+    let mut rooms: Vec<Room> = Vec::new();
+    for _ in 0..NUM_ATTEMPTS {
+        let room = Room::choose(size, rng);
+        if rooms.iter().all(|other| !room.overlaps(other)) {
+            rooms.push(room);
+        }
+    }
+    rooms
+}
+
+// Returns a room in each part of the area after recursively splitting it in two. Rooms are in the
+// order their parts appear in the tree of splits, so consecutive rooms are close together.
+fn place_rooms_bsp<R: Rng>(top_left: Coord, size: Size, rng: &mut R) -> Vec<Room> {
+    // Parts must be big enough to hold the largest room `Room::choose` can return
+    const MIN_PART_WIDTH: u32 = 11;
+    const MIN_PART_HEIGHT: u32 = 9;
+    let can_split_horizontally = size.width() >= MIN_PART_WIDTH * 2;
+    let can_split_vertically = size.height() >= MIN_PART_HEIGHT * 2;
+    // Prefer to split the longer side, so that parts don't become long and thin
+    let split_horizontally = match (can_split_horizontally, can_split_vertically) {
+        (false, false) => {
+            let room = Room::choose(size, rng);
+            return vec![Room {
+                top_left: top_left + room.top_left,
+                size: room.size,
+            }];
+        }
+        (true, false) => true,
+        (false, true) => false,
+        (true, true) => rng.gen_range(0..size.width() + size.height()) < size.width(),
+    };
+    let (first_size, second_size, second_offset) = if split_horizontally {
+        let width = rng.gen_range(MIN_PART_WIDTH..=size.width() - MIN_PART_WIDTH);
+        (
+            size.set_width(width),
+            size.set_width(size.width() - width),
+            Coord::new(width as i32, 0),
+        )
+    } else {
+        let height = rng.gen_range(MIN_PART_HEIGHT..=size.height() - MIN_PART_HEIGHT);
+        (
+            size.set_height(height),
+            size.set_height(size.height() - height),
+            Coord::new(0, height as i32),
+        )
+    };
+    let mut rooms = place_rooms_bsp(top_left, first_size, rng);
+    rooms.extend(place_rooms_bsp(top_left + second_offset, second_size, rng));
+    rooms
+}
+
+// Returns a random number of things to place in an area, averaging `per_thousand_cells` for each
+// 1000 cells of the area
+fn choose_count_for_area<R: Rng>(area: u32, per_thousand_cells: u32, rng: &mut R) -> usize {
+    (0..area)
+        .filter(|_| rng.gen_range(0..1000) < per_thousand_cells)
+        .count()
+}
+
+// The algorithm used to connect rooms, chosen once per level
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CorridorStyle {
+    Straight,
+    Winding,
+    Wide,
+}
+
+fn choose_corridor_style<R: Rng>(theme: LevelTheme, rng: &mut R) -> CorridorStyle {
+    use CorridorStyle::*;
+    let probability_distribution = match theme {
+        LevelTheme::FloodedWarrens | LevelTheme::TrollDen | LevelTheme::FrozenCaverns => {
+            [(Straight, 1), (Winding, 3), (Wide, 1)]
+        }
+        LevelTheme::OrcishBarracks => [(Straight, 1), (Winding, 1), (Wide, 3)],
+        _ => [(Straight, 3), (Winding, 1), (Wide, 1)],
+    };
+    *choose_from_probability_distribution(&probability_distribution, rng)
+}
+
+// Turn an empty or wall cell into floor, leaving the outer edge of the map intact
+fn carve_cell(coord: Coord, grid: &mut Grid<Option<TerrainTile>>) {
+    let size = grid.size();
+    if coord.x < 1
+        || coord.y < 1
+        || coord.x >= size.width() as i32 - 1
+        || coord.y >= size.height() as i32 - 1
+    {
+        return;
+    }
+    let cell = grid.get_checked_mut(coord);
+    if *cell == None || *cell == Some(TerrainTile::Wall) {
+        *cell = Some(TerrainTile::Floor);
+    }
+}
+
+// carve out a corridor between a pair of coordinates in the given style
+fn carve_corridor<R: Rng>(
+    start: Coord,
+    end: Coord,
+    style: CorridorStyle,
+    rooms: &[Room],
+    grid: &mut Grid<Option<TerrainTile>>,
+    rng: &mut R,
+) {
+    match style {
+        CorridorStyle::Straight => carve_routed_corridor(start, end, 1, rooms, grid),
+        CorridorStyle::Winding => carve_winding_corridor(start, end, grid, rng),
+        CorridorStyle::Wide => {
+            // Only some corridors are widened so that there are still chokepoints to fight in
+            const WIDE_PERCENT_CHANCE: u32 = 50;
+            let width = if rng.gen_range(0..100) < WIDE_PERCENT_CHANCE {
+                2
+            } else {
+                1
+            };
+            carve_routed_corridor(start, end, width, rooms, grid);
+        }
+    }
+}
+
+// Returns the cheapest path of cells from `start` to `end` (inclusive), where carving through
+// rooms other than the ones being connected is expensive. Corridors go around rooms that are in
+// the way where possible, and otherwise enter them through their walls rather than along them.
+fn route_corridor(start: Coord, end: Coord, rooms: &[Room], size: Size) -> Vec<Coord> {
+    const OPEN_COST: u32 = 1;
+    const ROOM_WALL_COST: u32 = 4;
+    const ROOM_INTERIOR_COST: u32 = 20;
+    const STEPS: [Coord; 4] = [
+        Coord { x: 1, y: 0 },
+        Coord { x: -1, y: 0 },
+        Coord { x: 0, y: 1 },
+        Coord { x: 0, y: -1 },
+    ];
+    let cell_cost = |coord: Coord| match rooms.iter().find(|room| room.contains(coord)) {
+        Some(room) if room.contains(start) || room.contains(end) => OPEN_COST,
+        Some(room) if room.is_wall(coord) => ROOM_WALL_COST,
+        Some(_) => ROOM_INTERIOR_COST,
+        None => OPEN_COST,
+    };
+    // A* search, using manhattan distance as the heuristic since no cell is cheaper than open
+    // ground
+    let mut cost_so_far = Grid::new_copy(size, u32::MAX);
+    let mut came_from = Grid::new_copy(size, None);
+    let mut queue = BinaryHeap::new();
+    *cost_so_far.get_checked_mut(start) = 0;
+    queue.push(Reverse((start.manhattan_distance(end) * OPEN_COST, start)));
+    while let Some(Reverse((_, coord))) = queue.pop() {
+        if coord == end {
+            break;
+        }
+        let cost = *cost_so_far.get_checked(coord);
+        for &step in STEPS.iter() {
+            let next = coord + step;
+            // Leave the outer edge of the map intact
+            if next.x < 1
+                || next.y < 1
+                || next.x >= size.width() as i32 - 1
+                || next.y >= size.height() as i32 - 1
+            {
+                continue;
+            }
+            let next_cost = cost + cell_cost(next);
+            if next_cost < *cost_so_far.get_checked(next) {
+                *cost_so_far.get_checked_mut(next) = next_cost;
+                *came_from.get_checked_mut(next) = Some(coord);
+                queue.push(Reverse((
+                    next_cost + next.manhattan_distance(end) * OPEN_COST,
+                    next,
+                )));
+            }
+        }
+    }
+    let mut path = vec![end];
+    let mut coord = end;
+    while let Some(&Some(previous)) = came_from.get(coord) {
+        path.push(previous);
+        coord = previous;
+    }
+    path.reverse();
+    path
+}
+
+// carve out a corridor along the cheapest route between a pair of coordinates
+fn carve_routed_corridor(
+    start: Coord,
+    end: Coord,
+    width: i32,
+    rooms: &[Room],
+    grid: &mut Grid<Option<TerrainTile>>,
+) {
+    let path = route_corridor(start, end, rooms, grid.size());
+    if path.first() != Some(&start) {
+        // No route was found, which can only happen if the map has no interior
+        carve_straight_corridor(start, end, width, grid);
+        return;
+    }
+    for coord in path {
+        for offset in 0..width {
+            carve_cell(coord + Coord::new(offset, offset), grid);
+        }
+    }
+}
+
 // carve out an L-shaped corridor between a pair of coordinates
-fn carve_corridor(start: Coord, end: Coord, grid: &mut Grid<Option<TerrainTile>>) {
-    for i in start.x.min(end.x)..=start.x.max(end.x) {
-        let cell = grid.get_checked_mut(Coord { x: i, ..start });
-        if *cell == None || *cell == Some(TerrainTile::Wall) {
-            *cell = Some(TerrainTile::Floor);
+fn carve_straight_corridor(
+    start: Coord,
+    end: Coord,
+    width: i32,
+    grid: &mut Grid<Option<TerrainTile>>,
+) {
+    for offset in 0..width {
+        for i in start.x.min(end.x)..=start.x.max(end.x) + offset {
+            carve_cell(Coord::new(i, start.y + offset), grid);
+        }
+        for i in start.y.min(end.y)..start.y.max(end.y) {
+            carve_cell(Coord::new(end.x + offset, i), grid);
         }
     }
-    for i in start.y.min(end.y)..start.y.max(end.y) {
-        let cell = grid.get_checked_mut(Coord { y: i, ..end });
-        if *cell == None || *cell == Some(TerrainTile::Wall) {
-            *cell = Some(TerrainTile::Floor);
+}
+
+// carve out a corridor which stumbles towards its destination, wandering off course at random
+fn carve_winding_corridor<R: Rng>(
+    start: Coord,
+    end: Coord,
+    grid: &mut Grid<Option<TerrainTile>>,
+    rng: &mut R,
+) {
+    const MAX_STEPS: usize = 1000;
+    const WANDER_PERCENT_CHANCE: u32 = 30;
+    const STEPS: [Coord; 4] = [
+        Coord { x: 1, y: 0 },
+        Coord { x: -1, y: 0 },
+        Coord { x: 0, y: 1 },
+        Coord { x: 0, y: -1 },
+    ];
+    let size = grid.size();
+    let mut coord = start;
+    for _ in 0..MAX_STEPS {
+        carve_cell(coord, grid);
+        if coord == end {
+            return;
+        }
+        let step = if rng.gen_range(0..100) < WANDER_PERCENT_CHANCE {
+            *STEPS.choose(rng).unwrap()
+        } else {
+            let delta = end - coord;
+            if delta.y == 0 || (delta.x != 0 && rng.gen::<bool>()) {
+                Coord::new(delta.x.signum(), 0)
+            } else {
+                Coord::new(0, delta.y.signum())
+            }
+        };
+        let next = coord + step;
+        if next.x >= 1
+            && next.y >= 1
+            && next.x < size.width() as i32 - 1
+            && next.y < size.height() as i32 - 1
+        {
+            coord = next;
         }
     }
+    // Guarantee that the rooms are connected even if the walk didn't make it
+    carve_straight_corridor(coord, end, 1, grid);
 }
 
 fn choose_from_probability_distribution<'a, T, R: Rng>(
@@ -131,9 +681,51 @@ fn choose_from_probability_distribution<'a, T, R: Rng>(
     unreachable!()
 }
 
-fn make_npc_probability_distribution(level: u32) -> Vec<(NpcType, u32)> {
+fn make_npc_probability_distribution(level: u32, theme: LevelTheme) -> Vec<(NpcType, u32)> {
     use NpcType::*;
-    vec![(Orc, 20), (Troll, level)]
+    match theme {
+        LevelTheme::OrcishBarracks => vec![(Orc, 60), (Troll, level)],
+        LevelTheme::TrollDen => vec![(Orc, 20), (Troll, level * 3 + 5)],
+        _ => vec![(Orc, 20), (Troll, level)],
+    }
+}
+
+// Elite npcs become more common deeper in the dungeon, up to a limit unless the dungeon is endless
+fn elite_percent_chance(level: u32, config: DungeonConfig) -> u32 {
+    const MAX_ELITE_PERCENT_CHANCE: u32 = 25;
+    const MAX_ENDLESS_ELITE_PERCENT_CHANCE: u32 = 90;
+    let max = if config.endless {
+        MAX_ENDLESS_ELITE_PERCENT_CHANCE
+    } else {
+        MAX_ELITE_PERCENT_CHANCE
+    };
+    (level.saturating_sub(1) * 3).min(max)
+}
+
+// The level from which npcs in an endless dungeon start getting stronger
+const ENDLESS_SCALING_START_LEVEL: u32 = 5;
+const ENDLESS_LEVELS_PER_STAT_BONUS: u32 = 3;
+
+// Added to the hit points, strength and dexterity of npcs on a level. Only endless dungeons have
+// a bonus, which grows without limit.
+pub fn npc_stat_bonus(level: u32, config: DungeonConfig) -> i32 {
+    if config.endless {
+        (level.saturating_sub(ENDLESS_SCALING_START_LEVEL) / ENDLESS_LEVELS_PER_STAT_BONUS) as i32
+    } else {
+        0
+    }
+}
+
+pub fn choose_npc<R: Rng>(
+    level: u32,
+    theme: LevelTheme,
+    config: DungeonConfig,
+    rng: &mut R,
+) -> Npc {
+    let &npc_type =
+        choose_from_probability_distribution(&make_npc_probability_distribution(level, theme), rng);
+    let elite = rng.gen_range(0..100) < elite_percent_chance(level, config);
+    Npc { npc_type, elite }
 }
 
 fn make_item_probability_distribution(level: u32) -> Vec<(ItemType, u32)> {
@@ -145,6 +737,16 @@ fn make_item_probability_distribution(level: u32) -> Vec<(ItemType, u32)> {
     };
     vec![
         (HealthPotion, 200),
+        (
+            InvisibilityPotion,
+            match level {
+                0..=1 => 5,
+                2..=4 => 15,
+                _ => 25,
+            },
+        ),
+        (HastePotion, 5 + level * 3),
+        (RegenerationPotion, 20 + level * 5),
         (
             FireballScroll,
             match level {
@@ -161,60 +763,387 @@ fn make_item_probability_distribution(level: u32) -> Vec<(ItemType, u32)> {
                 _ => 50,
             },
         ),
+        (
+            SlowScroll,
+            match level {
+                0..=1 => 5,
+                2..=4 => 20,
+                _ => 30,
+            },
+        ),
+        (
+            SummonScroll,
+            match level {
+                0..=1 => 5,
+                2..=4 => 20,
+                _ => 30,
+            },
+        ),
         (Sword, item_chance),
+        (FireSword, item_chance / 2),
+        (CursedBlade, item_chance / 2),
         (Staff, item_chance),
         (Armour, item_chance),
         (Robe, item_chance),
+        (Bow, item_chance),
+        (Arrows, item_chance * 3),
+        (RepairKit, item_chance),
     ]
 }
 
-pub fn generate_dungeon<R: Rng>(size: Size, level: u32, rng: &mut R) -> Grid<TerrainTile> {
+// `None` entries are the chance of dropping nothing
+fn make_npc_drop_table(npc: Npc) -> Vec<(Option<ItemType>, u32)> {
+    use ItemType::*;
+    let drop_table = match npc.npc_type {
+        NpcType::Orc => vec![
+            (None, 80),
+            (Some(HealthPotion), 10),
+            (Some(Arrows), 5),
+            (Some(Sword), 5),
+        ],
+        NpcType::Troll => vec![
+            (None, 50),
+            (Some(HealthPotion), 20),
+            (Some(FireballScroll), 10),
+            (Some(Armour), 10),
+            (Some(FireSword), 10),
+        ],
+        // Spirits are summoned rather than found in the dungeon, and leave nothing behind
+        NpcType::Spirit => vec![(None, 1)],
+        NpcType::Warlord => vec![
+            (Some(FireSword), 1),
+            (Some(Armour), 1),
+            (Some(HealthPotion), 1),
+        ],
+    };
+    // Elite npcs always drop something
+    if npc.elite {
+        drop_table
+            .into_iter()
+            .filter(|(item_type, _)| item_type.is_some())
+            .collect()
+    } else {
+        drop_table
+    }
+}
+
+pub fn choose_npc_drop<R: Rng>(npc: Npc, rng: &mut R) -> Option<ItemType> {
+    *choose_from_probability_distribution(&make_npc_drop_table(npc), rng)
+}
+
+pub fn choose_buc<R: Rng>(rng: &mut R) -> Buc {
+    *choose_from_probability_distribution(
+        &[(Buc::Blessed, 10), (Buc::Uncursed, 80), (Buc::Cursed, 10)],
+        rng,
+    )
+}
+
+// Some npcs patrol a loop of nearby room centres rather than wandering. The route starts from the
+// centre of the room nearest to the npc.
+pub fn choose_patrol_route<R: Rng>(
+    npc_coord: Coord,
+    room_centres: &[Coord],
+    rng: &mut R,
+) -> Option<Vec<Coord>> {
+    const PATROL_PERCENT_CHANCE: u32 = 25;
+    const MAX_WAYPOINTS: usize = 3;
+    if room_centres.len() < 2 || rng.gen_range(0..100) >= PATROL_PERCENT_CHANCE {
+        return None;
+    }
+    let mut waypoints = room_centres.to_vec();
+    waypoints.sort_by_key(|coord| coord.manhattan_distance(npc_coord));
+    waypoints.truncate(rng.gen_range(2..=MAX_WAYPOINTS.min(room_centres.len())));
+    Some(waypoints)
+}
+
+pub struct Dungeon {
+    pub grid: Grid<TerrainTile>,
+    // In the order the rooms were connected by corridors, so consecutive rooms are close together
+    pub room_centres: Vec<Coord>,
+    rooms: Vec<Room>,
+}
+
+pub fn generate_dungeon<R: Rng>(
+    size: Size,
+    level: u32,
+    theme: LevelTheme,
+    config: DungeonConfig,
+    rng: &mut R,
+) -> Dungeon {
     let mut grid = Grid::new_copy(size, None);
     let mut room_centres = Vec::new();
 
-    const NPCS_PER_ROOM_DISTRIBUTION: &[usize] = &[0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 3, 3, 4];
-    const ITEMS_PER_ROOM_DISTRIBUTION: &[usize] = &[0, 0, 1, 1, 1, 1, 1, 2, 2];
-
-    let npc_probability_distribution = make_npc_probability_distribution(level);
+    let npc_probability_distribution = make_npc_probability_distribution(level, theme);
+    let elite_percent_chance = elite_percent_chance(level, config);
     let item_probability_distribution = make_item_probability_distribution(level);
+    let corridor_style = choose_corridor_style(theme, rng);
+    let npcs_per_thousand_cells = config.npcs_per_thousand_cells(level);
 
-    // Attempt to add a room a constant number of times
-    const NUM_ATTEMPTS: usize = 100;
-    for _ in 0..NUM_ATTEMPTS {
-        // Make a random room
-        let room = Room::choose(size, rng);
+    let rooms = match config.room_placement {
+        RoomPlacement::Random => place_rooms_randomly(size, rng),
+        RoomPlacement::Bsp => place_rooms_bsp(Coord::new(0, 0), size, rng),
+    };
+    for room in rooms.iter() {
+        // Carve out the room
+        room.carve_out(&mut grid);
 
-        // Carve out the room unless it overlaps with an existing room
-        if room.only_intersects_empty(&grid) {
-            room.carve_out(&mut grid);
+        let room_centre = room.centre();
 
-            let room_centre = room.centre();
+        // Add the player to the centre of the room if it's the first room
+        if room_centres.is_empty() {
+            *grid.get_checked_mut(room_centre) = Some(TerrainTile::Player);
+        }
 
-            // Add the player to the centre of the room if it's the first room
-            if room_centres.is_empty() {
-                *grid.get_checked_mut(room_centre) = Some(TerrainTile::Player);
-            }
+        // Build up a list of all room centres for use in constructing corridors
+        room_centres.push(room_centre);
+
+        // Add npcs to the room
+        let num_npcs = choose_count_for_area(room.floor_area(), npcs_per_thousand_cells, rng);
+        room.place_npcs(
+            num_npcs,
+            &npc_probability_distribution,
+            elite_percent_chance,
+            &mut grid,
+            rng,
+        );
 
-            // Build up a list of all room centres for use in constructing corridors
-            room_centres.push(room_centre);
+        // Add items to the room
+        let num_items =
+            choose_count_for_area(room.floor_area(), config.items_per_thousand_cells, rng);
+        room.place_items(num_items, &item_probability_distribution, &mut grid, rng);
 
-            // Add npcs to the room
-            let &num_npcs = NPCS_PER_ROOM_DISTRIBUTION.choose(rng).unwrap();
-            room.place_npcs(num_npcs, &npc_probability_distribution, &mut grid, rng);
+        // Occasionally add an altar to the room
+        if rng.gen_range(0..100) < theme.altar_percent_chance_per_room() {
+            room.place_altar(&mut grid, rng);
+        }
+
+        // Occasionally add a fountain to the room
+        if rng.gen_range(0..100) < theme.fountain_percent_chance_per_room() {
+            room.place_fountain(&mut grid, rng);
+        }
+
+        // Occasionally add a trapdoor to the room, but never to the room the player starts in
+        if room_centres.len() > 1
+            && rng.gen_range(0..100) < theme.trapdoor_percent_chance_per_room()
+        {
+            room.place_trap(&mut grid, TerrainTile::Trapdoor, rng);
+        }
 
-            // Add items to the room
-            let &num_items = ITEMS_PER_ROOM_DISTRIBUTION.choose(rng).unwrap();
-            room.place_items(num_items, &item_probability_distribution, &mut grid, rng);
+        // Occasionally add a slowing trap to the room, but never to the room the player starts in
+        if room_centres.len() > 1
+            && rng.gen_range(0..100) < theme.slowing_trap_percent_chance_per_room()
+        {
+            room.place_trap(&mut grid, TerrainTile::SlowingTrap, rng);
         }
     }
 
     // Add corridors connecting every adjacent pair of room centres
     for window in room_centres.windows(2) {
-        carve_corridor(window[0], window[1], &mut grid);
+        carve_corridor(window[0], window[1], corridor_style, &rooms, &mut grid, rng);
     }
 
-    // Add stairs to the centre of the last room placed
-    *grid.get_checked_mut(*room_centres.last().unwrap()) = Some(TerrainTile::Stairs);
+    // Add stairs to the centre of the last room placed, unless that's too close to where the
+    // player starts, in which case use the centre of the room furthest from the player
+    const MIN_STAIRS_DISTANCE: u32 = 15;
+    let player_coord = room_centres[0];
+    let mut stairs_coord = *room_centres.last().unwrap();
+    if stairs_coord.manhattan_distance(player_coord) < MIN_STAIRS_DISTANCE {
+        stairs_coord = *room_centres
+            .iter()
+            .max_by_key(|coord| coord.manhattan_distance(player_coord))
+            .unwrap();
+    }
+    *grid.get_checked_mut(stairs_coord) = Some(TerrainTile::Stairs);
+
+    // Every few levels a boss guards the stairs
+    const BOSS_LEVEL_INTERVAL: u32 = 5;
+    if level % BOSS_LEVEL_INTERVAL == 0 {
+        if let Some(room) = rooms.iter().find(|room| room.centre() == stairs_coord) {
+            room.place_npcs(1, &[(NpcType::Warlord, 1)], 0, &mut grid, rng);
+        }
+    }
+
+    // Repair levels which came out without enough content. Rooms can be too full to add to, so
+    // each guarantee is attempted a bounded number of times.
+    const MAX_REPAIR_ATTEMPTS: usize = 100;
+
+    // Make sure there's something to heal with
+    for _ in 0..MAX_REPAIR_ATTEMPTS {
+        let has_healing = grid.iter().any(|&tile| {
+            matches!(
+                tile,
+                Some(TerrainTile::Item(ItemType::HealthPotion))
+                    | Some(TerrainTile::Item(ItemType::RegenerationPotion))
+            )
+        });
+        if has_healing {
+            break;
+        }
+        let room = rooms.choose(rng).unwrap();
+        room.place_items(1, &[(ItemType::HealthPotion, 1)], &mut grid, rng);
+    }
 
-    grid.map(|t| t.unwrap_or(TerrainTile::Wall))
+    // Make sure there are some npcs to fight, outside the room where the player starts
+    for _ in 0..MAX_REPAIR_ATTEMPTS {
+        let num_npcs = grid
+            .iter()
+            .filter(|&&tile| matches!(tile, Some(TerrainTile::Npc(_))))
+            .count();
+        if num_npcs >= MIN_NPCS as usize || rooms.len() < 2 {
+            break;
+        }
+        let room = rooms[1..].choose(rng).unwrap();
+        room.place_npcs(
+            1,
+            &npc_probability_distribution,
+            elite_percent_chance,
+            &mut grid,
+            rng,
+        );
+    }
+
+    Dungeon {
+        grid: grid.map(|t| t.unwrap_or(TerrainTile::Wall)),
+        room_centres,
+        rooms,
+    }
+}
+
+// Levels are repaired until they have at least this many npcs, if they have room for them
+const MIN_NPCS: u32 = 3;
+
+// Measurements of a generated level, for judging the quality of level generation
+#[derive(Clone, Copy, Debug)]
+pub struct DungeonStats {
+    pub num_rooms: u32,
+    // The number of cells which aren't walls, including those with something on them
+    pub floor_area: u32,
+    // The number of floor cells outside every room
+    pub corridor_length: u32,
+    pub num_npcs: u32,
+    pub num_items: u32,
+    // Health and regeneration potions
+    pub num_healing_items: u32,
+    // The number of floor cells the player can't walk to from where they start
+    pub unreachable_area: u32,
+    // The number of steps from where the player starts to the stairs, or `None` if the stairs
+    // can't be reached or are missing
+    pub stairs_distance: Option<u32>,
+}
+
+impl Dungeon {
+    pub fn stats(&self) -> DungeonStats {
+        let is_floor = |tile: TerrainTile| tile != TerrainTile::Wall;
+        let mut stats = DungeonStats {
+            num_rooms: self.rooms.len() as u32,
+            floor_area: 0,
+            corridor_length: 0,
+            num_npcs: 0,
+            num_items: 0,
+            num_healing_items: 0,
+            unreachable_area: 0,
+            stairs_distance: None,
+        };
+        for (coord, &tile) in self.grid.enumerate() {
+            if !is_floor(tile) {
+                continue;
+            }
+            stats.floor_area += 1;
+            if !self.rooms.iter().any(|room| room.contains(coord)) {
+                stats.corridor_length += 1;
+            }
+            match tile {
+                TerrainTile::Npc(_) => stats.num_npcs += 1,
+                TerrainTile::Item(item_type) => {
+                    stats.num_items += 1;
+                    if item_type == ItemType::HealthPotion
+                        || item_type == ItemType::RegenerationPotion
+                    {
+                        stats.num_healing_items += 1;
+                    }
+                }
+                _ => (),
+            }
+        }
+        // Breadth first search from the player, since every step costs the same
+        let player_coord = self
+            .grid
+            .enumerate()
+            .find(|&(_, &tile)| tile == TerrainTile::Player)
+            .map(|(coord, _)| coord);
+        let mut distances = Grid::new_copy(self.grid.size(), None);
+        let mut queue = VecDeque::new();
+        if let Some(player_coord) = player_coord {
+            *distances.get_checked_mut(player_coord) = Some(0);
+            queue.push_back(player_coord);
+        }
+        let mut reachable_area = 0;
+        while let Some(coord) = queue.pop_front() {
+            reachable_area += 1;
+            let distance = distances
+                .get_checked(coord)
+                .expect("queued cell has no distance");
+            if *self.grid.get_checked(coord) == TerrainTile::Stairs {
+                stats.stairs_distance = Some(distance);
+            }
+            for direction in CardinalDirection::all() {
+                let next = coord + direction.coord();
+                if let (Some(&tile), Some(next_distance)) =
+                    (self.grid.get(next), distances.get_mut(next))
+                {
+                    if is_floor(tile) && next_distance.is_none() {
+                        *next_distance = Some(distance + 1);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        stats.unreachable_area = stats.floor_area - reachable_area;
+        stats
+    }
+}
+
+// A property every generated level should have
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelConstraint {
+    EveryFloorCellReachable,
+    StairsReachable,
+    HasHealing,
+    // Only levels with a room besides the one the player starts in are expected to have npcs
+    EnoughNpcs,
+}
+
+impl LevelConstraint {
+    pub const ALL: &'static [Self] = &[
+        Self::EveryFloorCellReachable,
+        Self::StairsReachable,
+        Self::HasHealing,
+        Self::EnoughNpcs,
+    ];
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::EveryFloorCellReachable => "every floor cell is reachable from the player",
+            Self::StairsReachable => "the stairs are reachable from the player",
+            Self::HasHealing => "there is at least one healing item",
+            Self::EnoughNpcs => "there are enough npcs to fight",
+        }
+    }
+    pub fn is_met(self, stats: &DungeonStats) -> bool {
+        match self {
+            Self::EveryFloorCellReachable => stats.unreachable_area == 0,
+            Self::StairsReachable => stats.stairs_distance.is_some(),
+            Self::HasHealing => stats.num_healing_items > 0,
+            Self::EnoughNpcs => stats.num_npcs >= MIN_NPCS || stats.num_rooms < 2,
+        }
+    }
+}
+
+impl DungeonStats {
+    pub fn unmet_constraints(&self) -> Vec<LevelConstraint> {
+        LevelConstraint::ALL
+            .iter()
+            .cloned()
+            .filter(|constraint| !constraint.is_met(self))
+            .collect()
+    }
 }
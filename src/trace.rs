@@ -0,0 +1,111 @@
+use crate::game::{ActionResult, PlayerAction};
+use rand::{Error, RngCore};
+use rand_isaac::Isaac64Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+// The game's random number generator, which counts how many times it's drawn from so that traces
+// show where two runs of the same seed start to diverge. The count isn't saved, so it starts from
+// zero again when a game is loaded.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CountingRng {
+    rng: Isaac64Rng,
+    #[serde(skip)]
+    draws: u64,
+}
+
+impl CountingRng {
+    pub fn new(rng: Isaac64Rng) -> Self {
+        Self { rng, draws: 0 }
+    }
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+impl RngCore for CountingRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.draws += 1;
+        self.rng.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.draws += 1;
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+// FNV-1a, which unlike the standard library's hasher gives the same result on every platform and
+// compiler version, so hashes from different builds of the game can be compared
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+// A file with a line for each action the player takes, recording the number of random numbers
+// drawn so far and a hash of the world after the action. Together with the seed, this is enough to
+// find the exact turn on which two runs of the game diverge.
+pub struct Trace {
+    writer: LineWriter<File>,
+    // The most recently written lines, which are included in bug reports
+    recent_lines: VecDeque<String>,
+}
+
+impl Trace {
+    pub const NUM_RECENT_LINES: usize = 200;
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            writer: LineWriter::new(file),
+            recent_lines: VecDeque::new(),
+        })
+    }
+    fn write_line(&mut self, line: String) {
+        if let Err(error) = writeln!(self.writer, "{}", line) {
+            eprintln!("Failed to write trace: {}", error);
+        }
+        if self.recent_lines.len() == Self::NUM_RECENT_LINES {
+            self.recent_lines.pop_front();
+        }
+        self.recent_lines.push_back(line);
+    }
+    pub fn recent_lines(&self) -> impl Iterator<Item = &str> {
+        self.recent_lines.iter().map(String::as_str)
+    }
+    // The seed is `None` for games loaded from a save file, as their seed isn't known
+    pub fn record_game_start(&mut self, rng_seed: Option<u64>) {
+        let line = match rng_seed {
+            Some(rng_seed) => format!("new game seed {}", rng_seed),
+            None => "loaded game".to_string(),
+        };
+        self.write_line(line);
+    }
+    pub(crate) fn record_action(
+        &mut self,
+        turn: u64,
+        action: PlayerAction,
+        result: ActionResult,
+        rng_draws: u64,
+        world_hash: u64,
+    ) {
+        self.write_line(format!(
+            "turn {} action {:?} result {:?} rng_draws {} world_hash {:016x}",
+            turn, action, result, rng_draws, world_hash
+        ));
+    }
+}
@@ -1,13 +1,20 @@
 use crate::app::colours;
-use crate::game::{ExamineCell, LogMessage};
-use crate::world::HitPoints;
+use crate::messages::{self, Language};
 use chargrid::{
     decorator::{AlignView, Alignment, AlignmentX, AlignmentY, BoundView},
     render::{ColModify, Frame, Style, View, ViewCell, ViewContext},
-    text::{wrap, RichTextPartOwned, RichTextViewSingleLine, StringView, StringViewSingleLine},
+    text::{
+        wrap, RichTextPart, RichTextPartOwned, RichTextViewSingleLine, StringView,
+        StringViewSingleLine,
+    },
+};
+use chargrid_roguelike_tutorial_2020::{
+    ActiveStatusEffect, ExamineCell, HitPoints, ItemType, LevelName, LogEntry, LogMessage,
+    SpeedEffect, StatusEffect, TimeOfDay,
 };
 use coord_2d::{Coord, Size};
 use rgb24::Rgb24;
+use std::iter;
 
 const HEALTH_WIDTH: u32 = 10;
 const HEALTH_FILL_COLOUR: Rgb24 = Rgb24::new(200, 0, 0);
@@ -61,6 +68,7 @@ impl View<HitPoints> for HealthView {
 
 struct MessagesView {
     buf: Vec<RichTextPartOwned>,
+    arg_buf: String,
 }
 
 impl Default for MessagesView {
@@ -68,130 +76,187 @@ impl Default for MessagesView {
         let common = RichTextPartOwned::new(String::new(), Style::new());
         Self {
             buf: vec![common.clone(), common.clone(), common],
+            arg_buf: String::new(),
+        }
+    }
+}
+
+// Writes a message into a buffer of three parts, the middle of which names the subject of the
+// message. The text comes from the language's message catalog, with placeholders substituted.
+// `arg` holds the text of the placeholder, and is passed in so that it can be reused between
+// messages.
+fn format_message(
+    buf: &mut [RichTextPartOwned],
+    arg: &mut String,
+    message: LogMessage,
+    language: Language,
+) {
+    use std::fmt::Write;
+    buf[0].text.clear();
+    buf[1].text.clear();
+    buf[2].text.clear();
+    let colour = message_colour(message);
+    buf[0].style.foreground = Some(colour);
+    buf[1].style.bold = Some(true);
+    buf[2].style.foreground = Some(colour);
+    arg.clear();
+    match message {
+        LogMessage::PlayerSpeedChanges(effect) | LogMessage::NpcSpeedChanges(_, effect) => {
+            arg.push_str(language.speed_effect_adjective(effect))
+        }
+        LogMessage::PlayerStealsLife(amount)
+        | LogMessage::NpcStealsLife(_, amount)
+        | LogMessage::ChargeNotReady(amount)
+        | LogMessage::MacroRecorded(amount)
+        | LogMessage::PlayerFallsThroughTrapdoor(amount) => write!(arg, "{}", amount).unwrap(),
+        LogMessage::NpcAttacksNpc(_, victim)
+        | LogMessage::NpcKillsNpc(_, victim)
+        | LogMessage::NpcDodgesNpc(_, victim) => write!(arg, "{}", victim.name()).unwrap(),
+        _ => (),
+    }
+    let template = language.template(message);
+    let (before, after, uppercase) = if let Some(index) = template.find(messages::SUBJECT) {
+        let (before, after) = template.split_at(index);
+        (before, &after[messages::SUBJECT.len()..], false)
+    } else if let Some(index) = template.find(messages::SUBJECT_UPPERCASE) {
+        let (before, after) = template.split_at(index);
+        (before, &after[messages::SUBJECT_UPPERCASE.len()..], true)
+    } else {
+        (template, "", false)
+    };
+    write_substituting_arg(&mut buf[0].text, before, arg);
+    write_substituting_arg(&mut buf[2].text, after, arg);
+    if let Some(subject_colour) = write_message_subject(&mut buf[1].text, message) {
+        buf[1].style.foreground = Some(subject_colour);
+    }
+    if uppercase {
+        buf[1].text.make_ascii_uppercase();
+    }
+}
+
+// A message as plain text, as it reads in the message log
+pub fn message_text(message: LogMessage, language: Language) -> String {
+    let mut buf = MessagesView::default().buf;
+    format_message(&mut buf, &mut String::new(), message, language);
+    buf.iter().map(|part| part.text.as_str()).collect()
+}
+
+fn write_substituting_arg(buf: &mut String, text: &str, arg: &str) {
+    let mut parts = text.split(messages::ARG);
+    if let Some(first) = parts.next() {
+        buf.push_str(first);
+    }
+    for part in parts {
+        buf.push_str(arg);
+        buf.push_str(part);
+    }
+}
+
+// Writes the name of the subject of a message, returning the colour to display it in
+fn write_message_subject(buf: &mut String, message: LogMessage) -> Option<Rgb24> {
+    use std::fmt::Write;
+    use LogMessage::*;
+    match message {
+        PlayerAttacksNpc(npc)
+        | NpcAttacksPlayer(npc)
+        | PlayerKillsNpc(npc)
+        | NpcKillsPlayer(npc)
+        | NpcDies(npc)
+        | NpcBecomesConfused(npc)
+        | NpcIsNoLongerConfused(npc)
+        | PlayerDodges(npc)
+        | NpcDodges(npc)
+        | ArrowGlancesOff(npc)
+        | NpcBurns(npc)
+        | NpcIsKnockedBack(npc)
+        | AllyAttacksNpc(npc)
+        | AllyKillsNpc(npc)
+        | NpcDodgesAlly(npc)
+        | NpcAttacksAlly(npc)
+        | NpcKillsAlly(npc)
+        | AllyDodges(npc)
+        | AllyAppears(npc)
+        | AllyVanishes(npc)
+        | NpcSpeedChanges(npc, _)
+        | NpcSpeedReturnsToNormal(npc)
+        | NpcStealsLife(npc, _)
+        | NpcAttacksNpc(npc, _)
+        | NpcKillsNpc(npc, _)
+        | NpcDodgesNpc(npc, _)
+        | BossSummonsMinions(npc)
+        | BossBecomesEnraged(npc)
+        | PlayerKicksNpc(npc)
+        | NpcDoesNotBudge(npc)
+        | HostileSummonAppears(npc)
+        | NpcAppears(npc) => {
+            write!(buf, "{}", npc.name()).unwrap();
+            Some(colours::npc_colour(npc))
+        }
+        PlayerGets(item_type)
+        | PlayerSearchesCorpse(item_type)
+        | PlayerDrops(item_type)
+        | PlayerEquips(item_type)
+        | AltarEnchants(item_type)
+        | ItemBreaks(item_type)
+        | ItemIsCursed(item_type)
+        | CursedItemIsStuck(item_type)
+        | ItemBucRevealed(item_type, _)
+        | ItemAppears(item_type) => {
+            write!(buf, "{}", item_type.name()).unwrap();
+            Some(colours::item_colour(item_type))
+        }
+        PlayerLaunchesProjectile(projectile) | PlayerFires(projectile) => {
+            write!(buf, "{}", projectile.name()).unwrap();
+            Some(colours::projectile_colour(projectile))
         }
+        PlayerEntersLevel(level_name) => {
+            write!(buf, "{}", level_name).unwrap();
+            Some(Rgb24::new(255, 255, 127))
+        }
+        _ => None,
+    }
+}
+
+// The colour of the parts of a message other than its subject
+fn message_colour(message: LogMessage) -> Rgb24 {
+    use LogMessage::*;
+    match message {
+        NpcKillsPlayer(_)
+        | AltarSmites
+        | AltarSummons
+        | AltarWeakens
+        | PlayerKicksWall
+        | ItemBreaks(_)
+        | HostileSummonAppears(_)
+        | ScrollBackfires
+        | ItemIsCursed(_)
+        | PlayerIsVisible
+        | BossSummonsMinions(_)
+        | BossBecomesEnraged(_) => Rgb24::new(255, 0, 0),
+        PlayerHeals | AltarHeals | FountainHeals => Rgb24::new(0, 187, 0),
+        FountainPoisons => Rgb24::new(127, 187, 0),
+        Tremor => Rgb24::new(187, 127, 63),
+        ItemsTeleport => colours::CONFUSION_SCROLL,
+        PlayerBecomesInvisible | PlayerInvisibilityFading => colours::INVISIBILITY_POTION,
+        PlayerSpeedChanges(effect) => speed_effect_colour(effect),
+        PlayerStealsLife(_) => colours::CURSED_BLADE,
+        PlayerStartsRegenerating => colours::REGENERATION_POTION,
+        PlayerFallsThroughTrapdoor(_) | PlayerSpringsSlowingTrap => Rgb24::new(255, 127, 0),
+        _ => Rgb24::new_grey(255),
     }
 }
 
-impl<'a> View<&'a [LogMessage]> for MessagesView {
+// Shows the most recent messages, one per row
+impl<'a> View<(&'a [LogEntry], Language, usize)> for MessagesView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
-        messages: &'a [LogMessage],
+        (messages, language, num_rows): (&'a [LogEntry], Language, usize),
         context: ViewContext<C>,
         frame: &mut F,
     ) {
-        fn format_message(buf: &mut [RichTextPartOwned], message: LogMessage) {
-            use std::fmt::Write;
-            use LogMessage::*;
-            buf[0].text.clear();
-            buf[1].text.clear();
-            buf[2].text.clear();
-            buf[0].style.foreground = Some(Rgb24::new_grey(255));
-            buf[1].style.bold = Some(true);
-            buf[2].style.foreground = Some(Rgb24::new_grey(255));
-            match message {
-                PlayerAttacksNpc(npc_type) => {
-                    write!(&mut buf[0].text, "You attack the ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                NpcAttacksPlayer(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " attacks you.").unwrap();
-                }
-                PlayerKillsNpc(npc_type) => {
-                    write!(&mut buf[0].text, "You kill the ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                NpcKillsPlayer(npc_type) => {
-                    write!(&mut buf[0].text, "THE ").unwrap();
-                    buf[0].style.foreground = Some(Rgb24::new(255, 0, 0));
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].text.make_ascii_uppercase();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " KILLS YOU!").unwrap();
-                    buf[2].style.foreground = Some(Rgb24::new(255, 0, 0));
-                }
-                PlayerGets(item_type) => {
-                    write!(&mut buf[0].text, "You get the ").unwrap();
-                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::item_colour(item_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                PlayerInventoryIsFull => {
-                    write!(&mut buf[0].text, "Inventory is full!").unwrap();
-                }
-                NoItemUnderPlayer => {
-                    write!(&mut buf[0].text, "Nothing to get!").unwrap();
-                }
-                NoItemInInventorySlot => {
-                    write!(&mut buf[0].text, "No item in inventory slot!").unwrap();
-                }
-                PlayerHeals => {
-                    write!(&mut buf[0].text, "You feel slightly better.").unwrap();
-                    buf[0].style.foreground = Some(Rgb24::new(0, 187, 0));
-                }
-                PlayerDrops(item_type) => {
-                    write!(&mut buf[0].text, "You drop the ").unwrap();
-                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::item_colour(item_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-                NoSpaceToDropItem => {
-                    write!(&mut buf[0].text, "No space to drop item!").unwrap();
-                }
-                PlayerLaunchesProjectile(projectile) => {
-                    write!(&mut buf[0].text, "You launch a ").unwrap();
-                    write!(&mut buf[1].text, "{}", projectile.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::projectile_colour(projectile));
-                    write!(&mut buf[2].text, "!").unwrap();
-                }
-                NpcDies(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " dies.").unwrap();
-                }
-                NpcBecomesConfused(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " is confused.").unwrap();
-                }
-                NpcIsNoLongerConfused(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, "'s confusion passes.").unwrap();
-                }
-                PlayerDodges(npc_type) => {
-                    write!(&mut buf[0].text, "You dodge the ").unwrap();
-                    write!(&mut buf[1].text, "{}'s", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " attack.").unwrap();
-                }
-                NpcDodges(npc_type) => {
-                    write!(&mut buf[0].text, "The ").unwrap();
-                    write!(&mut buf[1].text, "{}", npc_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::npc_colour(npc_type));
-                    write!(&mut buf[2].text, " dodges your attack.").unwrap();
-                }
-                PlayerEquips(item_type) => {
-                    write!(&mut buf[0].text, "You equip the ").unwrap();
-                    write!(&mut buf[1].text, "{}", item_type.name()).unwrap();
-                    buf[1].style.foreground = Some(colours::item_colour(item_type));
-                    write!(&mut buf[2].text, ".").unwrap();
-                }
-            }
-        }
-        const NUM_MESSAGES: usize = 4;
-        let start_index = messages.len().saturating_sub(NUM_MESSAGES);
-        for (i, &message) in (&messages[start_index..]).iter().enumerate() {
-            format_message(&mut self.buf, message);
+        let start_index = messages.len().saturating_sub(num_rows);
+        for (i, entry) in (&messages[start_index..]).iter().enumerate() {
+            format_message(&mut self.buf, &mut self.arg_buf, entry.message, language);
             let offset = Coord::new(0, i as i32);
             RichTextViewSingleLine.view(
                 self.buf.iter().map(|part| part.as_rich_text_part()),
@@ -202,11 +267,132 @@ impl<'a> View<&'a [LogMessage]> for MessagesView {
     }
 }
 
+pub struct MessageHistory<'a> {
+    pub messages: &'a [LogEntry],
+    // How many of the most recent messages are scrolled out of view below the bottom row
+    pub scroll: usize,
+    pub num_rows: usize,
+    pub language: Language,
+}
+
+// The whole message log, with the most recent message at the bottom, and each message prefixed
+// with the turn it was logged on
+pub struct MessageHistoryView {
+    buf: Vec<RichTextPartOwned>,
+    arg_buf: String,
+    turn_buf: String,
+}
+
+impl Default for MessageHistoryView {
+    fn default() -> Self {
+        let MessagesView { buf, arg_buf } = MessagesView::default();
+        Self {
+            buf,
+            arg_buf,
+            turn_buf: String::new(),
+        }
+    }
+}
+
+impl<'a> View<MessageHistory<'a>> for MessageHistoryView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        history: MessageHistory<'a>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        use std::fmt::Write;
+        let end_index = history.messages.len().saturating_sub(history.scroll);
+        let start_index = end_index.saturating_sub(history.num_rows);
+        for (i, entry) in history.messages[start_index..end_index].iter().enumerate() {
+            format_message(
+                &mut self.buf,
+                &mut self.arg_buf,
+                entry.message,
+                history.language,
+            );
+            self.turn_buf.clear();
+            write!(&mut self.turn_buf, "T{}: ", entry.turn).unwrap();
+            let turn_part = RichTextPart::new(
+                &self.turn_buf,
+                Style::new().with_foreground(Rgb24::new_grey(127)),
+            );
+            RichTextViewSingleLine.view(
+                iter::once(turn_part).chain(self.buf.iter().map(|part| part.as_rich_text_part())),
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+        }
+    }
+}
+
+fn speed_effect_colour(effect: SpeedEffect) -> Rgb24 {
+    match effect {
+        SpeedEffect::Haste => colours::HASTE_POTION,
+        SpeedEffect::Slow => colours::SLOW_SCROLL,
+    }
+}
+
 fn examine_cell_str(examine_cell: ExamineCell) -> &'static str {
     match examine_cell {
-        ExamineCell::Npc(npc_type) | ExamineCell::NpcCorpse(npc_type) => npc_type.name(),
+        ExamineCell::Npc(npc) | ExamineCell::NpcCorpse(npc) => npc.name(),
         ExamineCell::Item(item_type) => item_type.name(),
         ExamineCell::Player => "yourself",
+        ExamineCell::Altar => "altar",
+        ExamineCell::Fountain => "fountain",
+    }
+}
+
+pub struct ExamineStack<'a> {
+    pub examine_cells: &'a [ExamineCell],
+    pub selected_index: usize,
+    // Whether the examined cell contains an enemy which can see the player
+    pub sees_player: bool,
+}
+
+#[derive(Default)]
+struct ExamineStackView {
+    buf: String,
+}
+
+impl<'a> View<&'a ExamineStack<'a>> for ExamineStackView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        data: &'a ExamineStack<'a>,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        use std::fmt::Write;
+        for (i, &examine_cell) in data.examine_cells.iter().enumerate() {
+            let (prefix, style) = if i == data.selected_index {
+                (
+                    ">",
+                    Style::new()
+                        .with_foreground(Rgb24::new_grey(255))
+                        .with_bold(true),
+                )
+            } else {
+                (" ", Style::new().with_foreground(Rgb24::new_grey(187)))
+            };
+            self.buf.clear();
+            write!(
+                &mut self.buf,
+                "{} {}",
+                prefix,
+                examine_cell_str(examine_cell)
+            )
+            .unwrap();
+            StringViewSingleLine::new(style).view(
+                &self.buf,
+                context.add_offset(Coord::new(0, i as i32)),
+                frame,
+            );
+        }
+        StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(127))).view(
+            "</>: cycle",
+            context.add_offset(Coord::new(0, data.examine_cells.len() as i32)),
+            frame,
+        );
     }
 }
 
@@ -246,28 +432,326 @@ struct DungeonLevelView {
     buf: String,
 }
 
-impl View<u32> for DungeonLevelView {
+impl View<(u32, LevelName)> for DungeonLevelView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
-        dungeon_level: u32,
+        (dungeon_level, level_name): (u32, LevelName),
         context: ViewContext<C>,
         frame: &mut F,
     ) {
         use std::fmt::Write;
         self.buf.clear();
-        write!(&mut self.buf, "Level: {}", dungeon_level).unwrap();
+        write!(&mut self.buf, "Level {}: {}", dungeon_level, level_name).unwrap();
         StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(187)))
             .view(&self.buf, context, frame);
     }
 }
 
+#[derive(Default)]
+struct AmmoView {
+    buf: String,
+}
+
+impl View<u32> for AmmoView {
+    fn view<F: Frame, C: ColModify>(&mut self, ammo: u32, context: ViewContext<C>, frame: &mut F) {
+        use std::fmt::Write;
+        self.buf.clear();
+        write!(&mut self.buf, "Arrows: {}", ammo).unwrap();
+        let foreground = if ammo == 0 {
+            Rgb24::new(255, 0, 0)
+        } else {
+            colours::ARROWS
+        };
+        StringViewSingleLine::new(Style::new().with_foreground(foreground))
+            .view(&self.buf, context, frame);
+    }
+}
+
+fn status_effect_name(effect: StatusEffect) -> &'static str {
+    match effect {
+        StatusEffect::Confused => "conf",
+        StatusEffect::Poisoned => "pois",
+        StatusEffect::Invisible => "invis",
+        StatusEffect::Regenerating => "regen",
+        StatusEffect::Speed(SpeedEffect::Haste) => "haste",
+        StatusEffect::Speed(SpeedEffect::Slow) => "slow",
+    }
+}
+
+fn status_effect_colour(effect: StatusEffect) -> Rgb24 {
+    match effect {
+        StatusEffect::Confused => colours::CONFUSION_SCROLL,
+        StatusEffect::Poisoned => Rgb24::new(127, 255, 0),
+        StatusEffect::Invisible => colours::INVISIBILITY_POTION,
+        StatusEffect::Regenerating => colours::REGENERATION_POTION,
+        StatusEffect::Speed(effect) => speed_effect_colour(effect),
+    }
+}
+
+// Each active effect is a short name followed by the turns it has left, e.g. "conf 3 pois 5"
+#[derive(Default)]
+struct StatusEffectsView {
+    buf: String,
+}
+
+impl<'a> View<&'a [ActiveStatusEffect]> for StatusEffectsView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        status_effects: &'a [ActiveStatusEffect],
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        use std::fmt::Write;
+        let mut x = 0;
+        for status_effect in status_effects {
+            self.buf.clear();
+            write!(
+                &mut self.buf,
+                "{} {}",
+                status_effect_name(status_effect.effect),
+                status_effect.turns_remaining
+            )
+            .unwrap();
+            StringViewSingleLine::new(
+                Style::new().with_foreground(status_effect_colour(status_effect.effect)),
+            )
+            .view(&self.buf, context.add_offset(Coord::new(x, 0)), frame);
+            x += self.buf.len() as i32 + 1;
+        }
+    }
+}
+
+fn render_item_under_player_hint<F: Frame, C: ColModify>(
+    item_type: ItemType,
+    context: ViewContext<C>,
+    frame: &mut F,
+) {
+    let text = [
+        RichTextPart::new(
+            "g: pick up ",
+            Style::new().with_foreground(Rgb24::new_grey(187)),
+        ),
+        RichTextPart::new(
+            item_type.name(),
+            Style::new()
+                .with_foreground(colours::item_colour(item_type))
+                .with_bold(true),
+        ),
+    ];
+    RichTextViewSingleLine.view(text.iter().cloned(), context, frame);
+}
+
 pub struct UiData<'a> {
     pub player_hit_points: HitPoints,
-    pub messages: &'a [LogMessage],
+    pub messages: &'a [LogEntry],
+    pub language: Language,
+    pub time_of_day: TimeOfDay,
+    pub item_under_player: Option<ItemType>,
     pub name: Option<&'static str>,
-    pub examine_cell: Option<ExamineCell>,
+    pub examine_stack: ExamineStack<'a>,
     pub stats_data: StatsData,
     pub dungeon_level: u32,
+    pub level_name: LevelName,
+    pub ranged_ammo: Option<u32>,
+    pub status_effects: &'a [ActiveStatusEffect],
+    pub hotbar: &'a [Option<HotbarSlot>],
+    pub on_stairs: bool,
+    pub num_message_rows: usize,
+}
+
+// How the hud is arranged around the game area
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UiLayout {
+    // A panel below the game area with room for 4 messages
+    Bottom,
+    // A taller panel below the game area with room for 6 messages
+    LargeLog,
+    // A panel the height of the game area to its right
+    Right,
+    // No hud at all, leaving the whole screen to the game area
+    Hidden,
+}
+
+// A part of the screen beside the game area. In the hud, the left column holds the health bar, stats
+// and hotbar, the message log fills the rest of the panel to its right, and the buttons are along
+// the bottom.
+#[derive(Clone, Copy, Debug)]
+pub struct UiPanel {
+    pub offset: Coord,
+    pub size: Size,
+}
+
+impl UiPanel {
+    // Below the health bar, down to the status effects
+    pub fn num_message_rows(&self) -> usize {
+        self.size.height() as usize - 3
+    }
+    // The row of the panel containing the clickable buttons
+    pub fn button_bar_row(&self) -> i32 {
+        self.size.height() as i32 - 1
+    }
+}
+
+// Where the game area and hud go on the screen. The game area is the same size in every layout so
+// that a game saved with one layout can be loaded with another.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub screen_size: Size,
+    pub game_area_size: Size,
+    pub ui_panel: Option<UiPanel>,
+    pub enemy_list_panel: Option<UiPanel>,
+}
+
+const ENEMY_LIST_WIDTH: u32 = 20;
+
+impl Layout {
+    // The enemy list goes on the far right of the screen, beyond the hud if that's also on the right
+    pub fn new(ui_layout: UiLayout, enemy_list: bool, game_area_size: Size) -> Self {
+        let below_game_area = |height| UiPanel {
+            offset: Coord::new(0, game_area_size.height() as i32),
+            size: game_area_size.set_height(height),
+        };
+        let ui_panel = match ui_layout {
+            UiLayout::Bottom => Some(below_game_area(7)),
+            UiLayout::LargeLog => Some(below_game_area(9)),
+            UiLayout::Right => Some(UiPanel {
+                offset: Coord::new(game_area_size.width() as i32, 0),
+                size: game_area_size,
+            }),
+            UiLayout::Hidden => None,
+        };
+        let screen_size = match ui_panel {
+            Some(UiPanel { offset, size }) => Size::new(
+                game_area_size.width().max(offset.x as u32 + size.width()),
+                game_area_size.height().max(offset.y as u32 + size.height()),
+            ),
+            None => game_area_size,
+        };
+        let enemy_list_panel = if enemy_list {
+            Some(UiPanel {
+                offset: Coord::new(screen_size.width() as i32, 0),
+                size: game_area_size.set_width(ENEMY_LIST_WIDTH),
+            })
+        } else {
+            None
+        };
+        let screen_size = match enemy_list_panel {
+            Some(UiPanel { size, .. }) => screen_size.set_width(screen_size.width() + size.width()),
+            None => screen_size,
+        };
+        Self {
+            screen_size,
+            game_area_size,
+            ui_panel,
+            enemy_list_panel,
+        }
+    }
+}
+
+// Buttons which let the game be played with the mouse alone, each doing the same thing as a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Inventory,
+    Drop,
+    Examine,
+    Wait,
+    Descend,
+}
+
+impl Button {
+    const ALL: [Button; 5] = [
+        Button::Inventory,
+        Button::Drop,
+        Button::Examine,
+        Button::Wait,
+        Button::Descend,
+    ];
+    fn label(self) -> &'static str {
+        match self {
+            Self::Inventory => "Inv",
+            Self::Drop => "Drop",
+            Self::Examine => "Examine",
+            Self::Wait => "Wait",
+            Self::Descend => "Descend",
+        }
+    }
+}
+
+// Each button is its label in square brackets, separated from the next button by a space. Yields
+// each button along with the x coordinate of its first and last cells.
+fn button_bar_layout() -> impl Iterator<Item = (Button, i32, i32)> {
+    Button::ALL.iter().scan(0, |x, &button| {
+        let start = *x;
+        let end = start + button.label().len() as i32 + 1;
+        *x = end + 2;
+        Some((button, start, end))
+    })
+}
+
+pub fn button_at_x(x: i32) -> Option<Button> {
+    button_bar_layout()
+        .find(|&(_, start, end)| x >= start && x <= end)
+        .map(|(button, _, _)| button)
+}
+
+fn render_button_bar<F: Frame, C: ColModify>(
+    on_stairs: bool,
+    context: ViewContext<C>,
+    frame: &mut F,
+) {
+    for (button, start, _) in button_bar_layout() {
+        // Descending is only possible while standing on the stairs
+        let enabled = button != Button::Descend || on_stairs;
+        let style = if enabled {
+            Style::new().with_foreground(Rgb24::new_grey(255))
+        } else {
+            Style::new().with_foreground(Rgb24::new_grey(63))
+        };
+        StringViewSingleLine::new(style).view(
+            format!("[{}]", button.label()),
+            context.add_offset(Coord::new(start, 0)),
+            frame,
+        );
+    }
+}
+
+pub struct HotbarSlot {
+    pub view_cell: ViewCell,
+    pub count: u32,
+}
+
+// Each hotbar slot is drawn as the item's glyph followed by how many are carried, if more than one
+fn render_hotbar<F: Frame, C: ColModify>(
+    hotbar: &[Option<HotbarSlot>],
+    context: ViewContext<C>,
+    frame: &mut F,
+) {
+    for (i, slot) in hotbar.iter().enumerate() {
+        let coord = Coord::new(i as i32 * 2, 0);
+        match slot {
+            Some(HotbarSlot { view_cell, count }) => {
+                frame.set_cell_relative(coord, 0, *view_cell, context);
+                if *count > 1 {
+                    frame.set_cell_relative(
+                        coord + Coord::new(1, 0),
+                        0,
+                        ViewCell::new()
+                            .with_character(std::char::from_digit((*count).min(9), 10).unwrap())
+                            .with_foreground(Rgb24::new_grey(187)),
+                        context,
+                    );
+                }
+            }
+            None => frame.set_cell_relative(
+                coord,
+                0,
+                ViewCell::new()
+                    .with_character('-')
+                    .with_foreground(Rgb24::new_grey(63)),
+                context,
+            ),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -276,6 +760,9 @@ pub struct UiView {
     messages_view: MessagesView,
     stats_view: StatsView,
     dungeon_level_view: DungeonLevelView,
+    ammo_view: AmmoView,
+    status_effects_view: StatusEffectsView,
+    examine_stack_view: ExamineStackView,
 }
 
 fn centre_health_width<T: Clone>(view: impl View<T>, height: u32) -> impl View<T> {
@@ -291,6 +778,35 @@ fn centre_health_width<T: Clone>(view: impl View<T>, height: u32) -> impl View<T
     }
 }
 
+impl UiView {
+    fn render_messages<F: Frame, C: ColModify>(
+        &mut self,
+        data: &UiData,
+        offset: Coord,
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        // When standing on an item, the bottom row of the message log is replaced with a hint
+        // explaining how to pick it up.
+        let num_rows = if let Some(item_type) = data.item_under_player {
+            let num_rows = data.num_message_rows - 1;
+            render_item_under_player_hint(
+                item_type,
+                context.add_offset(offset + Coord::new(0, num_rows as i32)),
+                frame,
+            );
+            num_rows
+        } else {
+            data.num_message_rows
+        };
+        self.messages_view.view(
+            (data.messages, data.language, num_rows),
+            context.add_offset(offset),
+            frame,
+        );
+    }
+}
+
 impl<'a> View<UiData<'a>> for UiView {
     fn view<F: Frame, C: ColModify>(
         &mut self,
@@ -305,14 +821,69 @@ impl<'a> View<UiData<'a>> for UiView {
             context.add_offset(Coord::new(HEALTH_WIDTH as i32 + 1, 0)),
             frame,
         );
-        centre_health_width(&mut self.dungeon_level_view, 1).view(
-            data.dungeon_level,
-            context.add_offset(Coord::new(0, 1)),
+        // The time of day is right-aligned in the top row, after the stats
+        AlignView {
+            alignment: Alignment {
+                x: AlignmentX::Right,
+                y: AlignmentY::Top,
+            },
+            view: StringViewSingleLine::new(
+                Style::new().with_foreground(colours::time_of_day_tint(data.time_of_day)),
+            ),
+        }
+        .view(data.time_of_day.name(), context, frame);
+        let message_log_offset = Coord::new(HEALTH_WIDTH as i32 + 1, 1);
+        // When examining a cell containing multiple entities, list them all in place of the
+        // message log.
+        if data.examine_stack.examine_cells.len() > 1 {
+            self.examine_stack_view.view(
+                &data.examine_stack,
+                context.add_offset(message_log_offset),
+                frame,
+            );
+        } else {
+            self.render_messages(&data, message_log_offset, context, frame);
+        }
+        // The name of the examined entity takes the place of the ammo count while examining
+        if let (Some(ammo), None) = (data.ranged_ammo, data.name) {
+            centre_health_width(&mut self.ammo_view, 1).view(
+                ammo,
+                context.add_offset(Coord::new(0, 2)),
+                frame,
+            );
+        }
+        // The level and its name are shown below the message log, followed by the status effects,
+        // which are cut off at the edge of the panel if there are too many to fit
+        let status_row = message_log_offset + Coord::new(0, data.num_message_rows as i32);
+        self.dungeon_level_view.view(
+            (data.dungeon_level, data.level_name),
+            context.add_offset(status_row),
+            frame,
+        );
+        let status_effects_offset =
+            status_row + Coord::new(self.dungeon_level_view.buf.len() as i32 + 2, 0);
+        BoundView {
+            size: Size::new(
+                (context.size.width() as i32 - status_effects_offset.x).max(0) as u32,
+                1,
+            ),
+            view: &mut self.status_effects_view,
+        }
+        .view(
+            data.status_effects,
+            context.add_offset(status_effects_offset),
+            frame,
+        );
+        // The hotbar sits at the bottom of the left column, which is taken by the examined entity's
+        // description while examining
+        if data.name.is_none() {
+            render_hotbar(data.hotbar, context.add_offset(Coord::new(0, 4)), frame);
+        }
+        render_button_bar(
+            data.on_stairs,
+            context.add_offset(Coord::new(0, data.num_message_rows as i32 + 2)),
             frame,
         );
-        let message_log_offset = Coord::new(HEALTH_WIDTH as i32 + 1, 1);
-        self.messages_view
-            .view(data.messages, context.add_offset(message_log_offset), frame);
         if let Some(name) = data.name {
             BoundView {
                 size: Size::new(HEALTH_WIDTH, 1),
@@ -325,7 +896,11 @@ impl<'a> View<UiData<'a>> for UiView {
             }
             .view(name, context.add_offset(Coord::new(0, 2)), frame);
         }
-        if let Some(examine_cell) = data.examine_cell {
+        if let Some(&examine_cell) = data
+            .examine_stack
+            .examine_cells
+            .get(data.examine_stack.selected_index)
+        {
             centre_health_width(
                 StringView::new(
                     Style::new().with_foreground(Rgb24::new_grey(187)),
@@ -339,5 +914,110 @@ impl<'a> View<UiData<'a>> for UiView {
                 frame,
             );
         }
+        if data.examine_stack.sees_player {
+            centre_health_width(
+                StringViewSingleLine::new(
+                    Style::new()
+                        .with_foreground(Rgb24::new(255, 0, 0))
+                        .with_bold(true),
+                ),
+                1,
+            )
+            .view("sees you!", context.add_offset(Coord::new(0, 5)), frame);
+        }
+    }
+}
+
+pub struct EnemyListEntry {
+    pub view_cell: ViewCell,
+    pub name: &'static str,
+    pub hit_points: HitPoints,
+    pub distance: u32,
+}
+
+// Lists the enemies the player can see, nearest first. Each enemy takes two rows: its glyph and
+// name with its distance right-aligned, then a health bar the width of the panel.
+#[derive(Default)]
+pub struct EnemyListView {
+    buf: String,
+}
+
+impl<'a> View<&'a [EnemyListEntry]> for EnemyListView {
+    fn view<F: Frame, C: ColModify>(
+        &mut self,
+        entries: &'a [EnemyListEntry],
+        context: ViewContext<C>,
+        frame: &mut F,
+    ) {
+        use std::fmt::Write;
+        const ROWS_PER_ENTRY: usize = 2;
+        let title_style = Style::new()
+            .with_bold(true)
+            .with_foreground(Rgb24::new_grey(255));
+        StringViewSingleLine::new(title_style).view("Enemies", context, frame);
+        if entries.is_empty() {
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(127))).view(
+                "None in sight",
+                context.add_offset(Coord::new(0, 1)),
+                frame,
+            );
+            return;
+        }
+        let width = context.size.width();
+        let max_entries = (context.size.height() as usize - 1) / ROWS_PER_ENTRY;
+        // If not everyone fits, the last entry's space is used to say how many were left out
+        let num_shown = if entries.len() > max_entries {
+            max_entries.saturating_sub(1)
+        } else {
+            entries.len()
+        };
+        for (i, entry) in entries.iter().take(num_shown).enumerate() {
+            let y = 1 + (i * ROWS_PER_ENTRY) as i32;
+            frame.set_cell_relative(Coord::new(0, y), 0, entry.view_cell, context);
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(255))).view(
+                entry.name,
+                context.add_offset(Coord::new(2, y)),
+                frame,
+            );
+            self.buf.clear();
+            write!(&mut self.buf, "{}", entry.distance).unwrap();
+            BoundView {
+                size: Size::new(width, 1),
+                view: AlignView {
+                    alignment: Alignment {
+                        x: AlignmentX::Right,
+                        y: AlignmentY::Top,
+                    },
+                    view: StringViewSingleLine::new(
+                        Style::new().with_foreground(Rgb24::new_grey(187)),
+                    ),
+                },
+            }
+            .view(&self.buf, context.add_offset(Coord::new(0, y)), frame);
+            let bar_width = width - 2;
+            let fill_width = (entry.hit_points.current * bar_width) / entry.hit_points.max.max(1);
+            for x in 0..bar_width {
+                let colour = if x < fill_width.max(1) {
+                    HEALTH_FILL_COLOUR
+                } else {
+                    HEALTH_EMPTY_COLOUR
+                };
+                frame.set_cell_relative(
+                    Coord::new(x as i32 + 2, y + 1),
+                    0,
+                    ViewCell::new().with_background(colour),
+                    context,
+                );
+            }
+        }
+        if num_shown < entries.len() {
+            self.buf.clear();
+            write!(&mut self.buf, "...and {} more", entries.len() - num_shown).unwrap();
+            StringViewSingleLine::new(Style::new().with_foreground(Rgb24::new_grey(127))).view(
+                &self.buf,
+                context.add_offset(Coord::new(0, 1 + (num_shown * ROWS_PER_ENTRY) as i32)),
+                frame,
+            );
+        }
     }
 }
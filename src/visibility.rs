@@ -1,17 +1,54 @@
+use crate::chunk::ChunkedGrid;
 use crate::world::World;
 use coord_2d::{Coord, Size};
 use grid_2d::Grid;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VisibilityAlgorithm {
     Shadowcast,
+    SymmetricShadowcast,
     Omniscient,
 }
 
-const VISION_DISTANCE_SQUARED: u32 = 100;
-const VISION_DISTANCE: shadowcast::vision_distance::Circle =
-    shadowcast::vision_distance::Circle::new_squared(VISION_DISTANCE_SQUARED);
+impl VisibilityAlgorithm {
+    pub fn for_each_visible<F: FnMut(Coord)>(
+        self,
+        eye: Coord,
+        vision_distance_squared: u32,
+        world: &World,
+        shadowcast_context: &mut shadowcast::Context<u8>,
+        f: F,
+    ) {
+        match self {
+            Self::Shadowcast => Shadowcast {
+                context: shadowcast_context,
+            }
+            .for_each_visible(eye, vision_distance_squared, world, f),
+            Self::SymmetricShadowcast => {
+                SymmetricShadowcast.for_each_visible(eye, vision_distance_squared, world, f)
+            }
+            Self::Omniscient => Omniscient.for_each_visible(eye, vision_distance_squared, world, f),
+        }
+    }
+}
+
+// A field of view algorithm. To add a new algorithm, implement this trait and add a variant to
+// `VisibilityAlgorithm` which selects it.
+pub trait FieldOfView {
+    // Call `f` on each cell visible from `eye` within the given distance. Cells may be visited
+    // more than once.
+    fn for_each_visible<F: FnMut(Coord)>(
+        &mut self,
+        eye: Coord,
+        vision_distance_squared: u32,
+        world: &World,
+        f: F,
+    );
+}
+
+// How far the player can see in daylight
+pub const DEFAULT_VISION_DISTANCE_SQUARED: u32 = 100;
 
 struct Visibility;
 
@@ -26,34 +63,289 @@ impl shadowcast::InputGrid for Visibility {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+pub struct Shadowcast<'a> {
+    pub context: &'a mut shadowcast::Context<u8>,
+}
+
+impl<'a> FieldOfView for Shadowcast<'a> {
+    fn for_each_visible<F: FnMut(Coord)>(
+        &mut self,
+        eye: Coord,
+        vision_distance_squared: u32,
+        world: &World,
+        mut f: F,
+    ) {
+        self.context.for_each_visible(
+            eye,
+            &Visibility,
+            world,
+            shadowcast::vision_distance::Circle::new_squared(vision_distance_squared),
+            255,
+            |coord, _visible_directions, _visibility| f(coord),
+        );
+    }
+}
+
+// Every cell is visible
+pub struct Omniscient;
+
+impl FieldOfView for Omniscient {
+    fn for_each_visible<F: FnMut(Coord)>(
+        &mut self,
+        _eye: Coord,
+        _vision_distance_squared: u32,
+        world: &World,
+        mut f: F,
+    ) {
+        let size = world.size();
+        for y in 0..size.height() as i32 {
+            for x in 0..size.width() as i32 {
+                f(Coord::new(x, y));
+            }
+        }
+    }
+}
+
+// Shadowcasting in which a cell is visible from another exactly when the other is visible from
+// it. Based on https://www.albertford.com/shadowcasting/
+pub struct SymmetricShadowcast;
+
+// A rational slope, relative to the centre of the row being scanned
+#[derive(Clone, Copy)]
+struct Slope {
+    numerator: i32,
+    denominator: i32,
+}
+
+impl Slope {
+    fn new(numerator: i32, denominator: i32) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+    // The slope through the left edge of the cell at `column` of the row at `depth`
+    fn of_cell(depth: i32, column: i32) -> Self {
+        Self::new(2 * column - 1, 2 * depth)
+    }
+}
+
+// A row of cells at a given distance from the eye, bounded on either side by a slope
+#[derive(Clone, Copy)]
+struct Row {
+    depth: i32,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    // Columns whose centres lie within the slopes, rounding ties outwards at the start and inwards
+    // at the end
+    fn columns(&self) -> std::ops::RangeInclusive<i32> {
+        let start = &self.start_slope;
+        let end = &self.end_slope;
+        let min_column = (2 * self.depth * start.numerator + start.denominator)
+            .div_euclid(2 * start.denominator);
+        let max_column =
+            -(end.denominator - 2 * self.depth * end.numerator).div_euclid(2 * end.denominator);
+        min_column..=max_column
+    }
+    fn next(&self) -> Self {
+        Self {
+            depth: self.depth + 1,
+            ..*self
+        }
+    }
+    // A floor cell is only visible if its centre lies between the slopes
+    fn is_symmetric(&self, column: i32) -> bool {
+        column * self.start_slope.denominator >= self.depth * self.start_slope.numerator
+            && column * self.end_slope.denominator <= self.depth * self.end_slope.numerator
+    }
+}
+
+// Maps (depth, column) coordinates in a quadrant onto the map
+#[derive(Clone, Copy)]
+struct Quadrant {
+    eye: Coord,
+    depth_axis: Coord,
+    column_axis: Coord,
+}
+
+impl Quadrant {
+    fn transform(&self, depth: i32, column: i32) -> Coord {
+        self.eye + self.depth_axis * depth + self.column_axis * column
+    }
+}
+
+impl SymmetricShadowcast {
+    fn is_wall(world: &World, coord: Coord) -> bool {
+        !coord.is_valid(world.size()) || world.opacity_at(coord) > 0
+    }
+    fn scan<F: FnMut(Coord)>(
+        quadrant: Quadrant,
+        mut row: Row,
+        vision_distance_squared: u32,
+        world: &World,
+        f: &mut F,
+    ) {
+        if (row.depth * row.depth) as u32 > vision_distance_squared {
+            return;
+        }
+        let mut previous_is_wall = None;
+        for column in row.columns() {
+            let coord = quadrant.transform(row.depth, column);
+            let is_wall = Self::is_wall(world, coord);
+            let in_range = coord.is_valid(world.size())
+                && (coord - quadrant.eye).magnitude2() <= vision_distance_squared;
+            if in_range && (is_wall || row.is_symmetric(column)) {
+                f(coord);
+            }
+            match (previous_is_wall, is_wall) {
+                (Some(true), false) => row.start_slope = Slope::of_cell(row.depth, column),
+                (Some(false), true) => {
+                    let mut next_row = row.next();
+                    next_row.end_slope = Slope::of_cell(row.depth, column);
+                    Self::scan(quadrant, next_row, vision_distance_squared, world, f);
+                }
+                _ => (),
+            }
+            previous_is_wall = Some(is_wall);
+        }
+        if previous_is_wall == Some(false) {
+            Self::scan(quadrant, row.next(), vision_distance_squared, world, f);
+        }
+    }
+}
+
+impl FieldOfView for SymmetricShadowcast {
+    fn for_each_visible<F: FnMut(Coord)>(
+        &mut self,
+        eye: Coord,
+        vision_distance_squared: u32,
+        world: &World,
+        mut f: F,
+    ) {
+        f(eye);
+        let quadrants = [
+            (Coord::new(0, -1), Coord::new(1, 0)),
+            (Coord::new(0, 1), Coord::new(1, 0)),
+            (Coord::new(1, 0), Coord::new(0, 1)),
+            (Coord::new(-1, 0), Coord::new(0, 1)),
+        ];
+        for &(depth_axis, column_axis) in quadrants.iter() {
+            let quadrant = Quadrant {
+                eye,
+                depth_axis,
+                column_axis,
+            };
+            let first_row = Row {
+                depth: 1,
+                start_slope: Slope::new(-1, 1),
+                end_slope: Slope::new(1, 1),
+            };
+            Self::scan(quadrant, first_row, vision_distance_squared, world, &mut f);
+        }
+    }
+}
+
+// Returns the cells which are visible to exactly one of a pair of algorithms, for highlighting
+// where they disagree
+pub fn visibility_disagreements(
+    eye: Coord,
+    world: &World,
+    a: VisibilityAlgorithm,
+    b: VisibilityAlgorithm,
+) -> Vec<Coord> {
+    const SEEN_BY_A: u8 = 1;
+    const SEEN_BY_B: u8 = 2;
+    let mut shadowcast_context = shadowcast::Context::default();
+    let mut seen_by = Grid::new_copy(world.size(), 0u8);
+    a.for_each_visible(
+        eye,
+        DEFAULT_VISION_DISTANCE_SQUARED,
+        world,
+        &mut shadowcast_context,
+        |coord| {
+            *seen_by.get_checked_mut(coord) |= SEEN_BY_A;
+        },
+    );
+    b.for_each_visible(
+        eye,
+        DEFAULT_VISION_DISTANCE_SQUARED,
+        world,
+        &mut shadowcast_context,
+        |coord| {
+            *seen_by.get_checked_mut(coord) |= SEEN_BY_B;
+        },
+    );
+    seen_by
+        .enumerate()
+        .filter(|&(_, &seen_by)| seen_by == SEEN_BY_A || seen_by == SEEN_BY_B)
+        .map(|(coord, _)| coord)
+        .collect()
+}
+
+// The cells visible from `eye`, each listed once. Unlike `VisibilityGrid`, which remembers what
+// the player has seen over time, this is a one-off query which can be made from any cell.
+pub fn cells_visible_from(
+    eye: Coord,
+    vision_distance_squared: u32,
+    world: &World,
+    algorithm: VisibilityAlgorithm,
+) -> Vec<Coord> {
+    let mut shadowcast_context = shadowcast::Context::default();
+    let mut visible = Grid::new_copy(world.size(), false);
+    algorithm.for_each_visible(
+        eye,
+        vision_distance_squared,
+        world,
+        &mut shadowcast_context,
+        |coord| {
+            *visible.get_checked_mut(coord) = true;
+        },
+    );
+    visible
+        .enumerate()
+        .filter(|&(_, &visible)| visible)
+        .map(|(coord, _)| coord)
+        .collect()
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct VisibilityCell {
     last_seen: u64,
+    // The game turn on which the cell was last seen, used to fade out old memories
+    last_seen_turn: u64,
 }
 
 impl Default for VisibilityCell {
     fn default() -> Self {
-        Self { last_seen: 0 }
+        Self {
+            last_seen: 0,
+            last_seen_turn: 0,
+        }
     }
 }
 
 pub enum CellVisibility {
     Currently,
-    Previously,
+    Previously { turns_ago: u64 },
     Never,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct VisibilityGrid {
-    grid: Grid<VisibilityCell>,
+    grid: ChunkedGrid<VisibilityCell>,
     count: u64,
+    turn: u64,
 }
 
 impl VisibilityGrid {
     pub fn new(size: Size) -> Self {
         Self {
-            grid: Grid::new_default(size),
+            grid: ChunkedGrid::new(size),
             count: 1,
+            turn: 0,
         }
     }
     pub fn cell_visibility(&self, coord: Coord) -> CellVisibility {
@@ -63,47 +355,47 @@ impl VisibilityGrid {
             } else if cell.last_seen == 0 {
                 CellVisibility::Never
             } else {
-                CellVisibility::Previously
+                CellVisibility::Previously {
+                    turns_ago: self.turn.saturating_sub(cell.last_seen_turn),
+                }
             }
         } else {
             CellVisibility::Never
         }
     }
-    pub fn clear(&mut self) {
-        self.count = 1;
-        for cell in self.grid.iter_mut() {
-            *cell = Default::default();
+    // Mark every cell which has never been seen as previously seen, as if seen this turn
+    pub fn reveal_all(&mut self) {
+        for coord in self.grid.size().coord_iter_row_major() {
+            let cell = self.grid.get_checked_mut(coord);
+            if cell.last_seen == 0 {
+                cell.last_seen = 1;
+                cell.last_seen_turn = self.turn;
+            }
         }
     }
     pub fn update(
         &mut self,
         player_coord: Coord,
+        turn: u64,
+        vision_distance_squared: u32,
         world: &World,
         shadowcast_context: &mut shadowcast::Context<u8>,
         algorithm: VisibilityAlgorithm,
     ) {
         self.count += 1;
-        match algorithm {
-            VisibilityAlgorithm::Omniscient => {
-                for cell in self.grid.iter_mut() {
-                    cell.last_seen = self.count;
-                }
-            }
-            VisibilityAlgorithm::Shadowcast => {
-                let count = self.count;
-                let grid = &mut self.grid;
-                shadowcast_context.for_each_visible(
-                    player_coord,
-                    &Visibility,
-                    world,
-                    VISION_DISTANCE,
-                    255,
-                    |coord, _visible_directions, _visibility| {
-                        let cell = grid.get_checked_mut(coord);
-                        cell.last_seen = count;
-                    },
-                );
-            }
-        }
+        self.turn = turn;
+        let count = self.count;
+        let grid = &mut self.grid;
+        algorithm.for_each_visible(
+            player_coord,
+            vision_distance_squared,
+            world,
+            shadowcast_context,
+            |coord| {
+                let cell = grid.get_checked_mut(coord);
+                cell.last_seen = count;
+                cell.last_seen_turn = turn;
+            },
+        );
     }
 }
@@ -1,12 +1,14 @@
 use crate::behaviour::Agent;
-use crate::game::{ExamineCell, LevelUp, LogMessage};
-use crate::terrain::{self, TerrainTile};
+use crate::game::{ExamineCell, GameEvent, LevelUp, LogMessage};
+use crate::terrain::{self, DungeonConfig, LevelTheme, TerrainTile};
+use crate::trace;
 use coord_2d::{Coord, Size};
 use direction::CardinalDirection;
 use entity_table::{ComponentTable, Entity, EntityAllocator};
 use line_2d::CardinalStepIter;
-use rand::Rng;
+use rand::{seq::IteratorRandom, seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
+use std::fmt::Write;
 
 pub struct EquippedInventoryIndices {
     pub worn: Option<usize>,
@@ -22,12 +24,16 @@ pub struct CharacterData {
 pub enum ItemUsage {
     Immediate,
     Aim,
+    // An ally was summoned, and needs to be given an agent
+    Summon(Entity),
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum ProjectileType {
     Fireball { damage: u32 },
     Confusion { duration: u32 },
+    Slow { duration: u32 },
+    Arrow { damage: u32 },
 }
 
 impl ProjectileType {
@@ -35,6 +41,8 @@ impl ProjectileType {
         match self {
             Self::Fireball { .. } => "fireball",
             Self::Confusion { .. } => "confusion spell",
+            Self::Slow { .. } => "slowing spell",
+            Self::Arrow { .. } => "arrow",
         }
     }
 }
@@ -44,6 +52,8 @@ pub struct Inventory {
     slots: Vec<Option<Entity>>,
 }
 
+pub const NUM_HOTBAR_SLOTS: usize = 5;
+
 pub struct InventoryIsFull;
 
 #[derive(Debug)]
@@ -72,6 +82,28 @@ impl Inventory {
             Err(InventorySlotIsEmpty)
         }
     }
+    // Move all items to the front of the inventory, ordered by `key`. Items with equal keys keep
+    // their relative order. Returns the new index of each item, indexed by its old index.
+    pub fn sort_by_key<K, F>(&mut self, mut key: F) -> Vec<Option<usize>>
+    where
+        K: Ord,
+        F: FnMut(Entity) -> K,
+    {
+        let mut items = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| slot.map(|item| (index, item)))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|&(_, item)| key(item));
+        let mut new_indices = vec![None; self.slots.len()];
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        for (new_index, (old_index, item)) in items.into_iter().enumerate() {
+            self.slots[new_index] = Some(item);
+            new_indices[old_index] = Some(new_index);
+        }
+        new_indices
+    }
     pub fn get(&self, index: usize) -> Result<Entity, InventorySlotIsEmpty> {
         self.slots
             .get(index)
@@ -81,31 +113,280 @@ impl Inventory {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ItemType {
     HealthPotion,
+    InvisibilityPotion,
+    HastePotion,
+    RegenerationPotion,
     FireballScroll,
     ConfusionScroll,
+    SlowScroll,
+    SummonScroll,
     Sword,
+    FireSword,
+    CursedBlade,
     Staff,
     Armour,
     Robe,
+    Bow,
+    Arrows,
+    RepairKit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ItemCategory {
+    Potion,
+    Scroll,
+    Weapon,
+    Armour,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum OnHitEffect {
+    Burn,
+    Confuse,
+    KnockBack,
 }
 
 impl ItemType {
+    pub const ALL: &'static [Self] = &[
+        Self::HealthPotion,
+        Self::InvisibilityPotion,
+        Self::HastePotion,
+        Self::RegenerationPotion,
+        Self::FireballScroll,
+        Self::ConfusionScroll,
+        Self::SlowScroll,
+        Self::SummonScroll,
+        Self::Sword,
+        Self::FireSword,
+        Self::CursedBlade,
+        Self::Staff,
+        Self::Armour,
+        Self::Robe,
+        Self::Bow,
+        Self::Arrows,
+        Self::RepairKit,
+    ];
     pub fn name(self) -> &'static str {
         match self {
             Self::HealthPotion => "health potion",
+            Self::InvisibilityPotion => "invisibility potion",
+            Self::HastePotion => "haste potion",
+            Self::RegenerationPotion => "regeneration potion",
             Self::FireballScroll => "fireball scroll",
             Self::ConfusionScroll => "confusion scroll",
+            Self::SlowScroll => "slow scroll",
+            Self::SummonScroll => "summon scroll",
             Self::Sword => "sword",
+            Self::FireSword => "fire sword",
+            Self::CursedBlade => "cursed blade",
             Self::Staff => "staff",
             Self::Armour => "armour",
             Self::Robe => "robe",
+            Self::Bow => "bow",
+            Self::Arrows => "arrows",
+            Self::RepairKit => "repair kit",
+        }
+    }
+    // The character representing the item in text renderings of levels
+    pub fn ascii_char(self) -> char {
+        match self {
+            Self::HealthPotion
+            | Self::InvisibilityPotion
+            | Self::HastePotion
+            | Self::RegenerationPotion => '!',
+            Self::FireballScroll
+            | Self::ConfusionScroll
+            | Self::SummonScroll
+            | Self::SlowScroll => '?',
+            Self::Sword | Self::FireSword | Self::CursedBlade => '/',
+            Self::Staff => '\\',
+            Self::Armour => ']',
+            Self::Robe => '}',
+            Self::Bow => ')',
+            Self::Arrows => '|',
+            Self::RepairKit => '(',
+        }
+    }
+    pub fn category(self) -> ItemCategory {
+        match self {
+            Self::HealthPotion
+            | Self::InvisibilityPotion
+            | Self::HastePotion
+            | Self::RegenerationPotion => ItemCategory::Potion,
+            Self::FireballScroll
+            | Self::ConfusionScroll
+            | Self::SlowScroll
+            | Self::SummonScroll => ItemCategory::Scroll,
+            Self::Sword | Self::FireSword | Self::CursedBlade | Self::Staff | Self::Bow => {
+                ItemCategory::Weapon
+            }
+            Self::Armour | Self::Robe => ItemCategory::Armour,
+            Self::Arrows | Self::RepairKit => ItemCategory::Other,
+        }
+    }
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::HealthPotion => "Restores some health when drunk.",
+            Self::InvisibilityPotion => "Hides you from monsters that aren't right next to you.",
+            Self::HastePotion => "Lets you act twice as often for a while.",
+            Self::RegenerationPotion => "Slowly restores health over several turns.",
+            Self::FireballScroll => "Hurls a ball of fire which burns whatever it hits.",
+            Self::ConfusionScroll => "Confuses the first creature the spell hits.",
+            Self::SlowScroll => "Slows the first creature the spell hits.",
+            Self::SummonScroll => "Calls a spirit to fight alongside you for a while.",
+            Self::Sword => "+1 damage when held. Hits may knock enemies back.",
+            Self::FireSword => "+1 damage when held. Hits may set enemies alight.",
+            Self::CursedBlade => "+1 damage when held. Hits steal life from enemies.",
+            Self::Staff => "+1 magic when held. Hits may confuse enemies.",
+            Self::Armour => "+1 defense when worn.",
+            Self::Robe => "+1 magic when worn.",
+            Self::Bow => "Fires arrows at distant enemies.",
+            Self::Arrows => "Ammunition for a bow.",
+            Self::RepairKit => "Restores your held and worn equipment to full condition.",
+        }
+    }
+    // Effects which may be triggered when hitting with this item held, and the percent chance of
+    // each being triggered by a hit
+    fn on_hit_effects(self) -> &'static [(OnHitEffect, u32)] {
+        match self {
+            Self::Sword => &[(OnHitEffect::KnockBack, 15)],
+            Self::FireSword => &[(OnHitEffect::Burn, 30)],
+            Self::Staff => &[(OnHitEffect::Confuse, 20)],
+            _ => &[],
+        }
+    }
+    // The number of times this item can be used before it breaks, for items which wear out
+    fn max_durability(self) -> Option<u32> {
+        match self {
+            Self::Sword | Self::FireSword | Self::CursedBlade | Self::Armour => Some(40),
+            Self::Staff | Self::Bow => Some(30),
+            Self::Robe => Some(25),
+            _ => None,
+        }
+    }
+    // The percentage of damage dealt while holding this item which heals the attacker
+    fn life_steal_percent(self) -> u32 {
+        match self {
+            Self::CursedBlade => 50,
+            _ => 0,
+        }
+    }
+}
+
+// Hasted characters act twice per turn, and slowed characters act every other turn
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpeedEffect {
+    Haste,
+    Slow,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SpeedStatus {
+    pub effect: SpeedEffect,
+    pub turns_remaining: u32,
+}
+
+// Effects which wear off after a number of turns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusEffect {
+    Confused,
+    Poisoned,
+    Invisible,
+    Regenerating,
+    Speed(SpeedEffect),
+}
+
+impl StatusEffect {
+    // Effects which the player would rather wait out than carry into a fight
+    pub fn is_harmful(self) -> bool {
+        match self {
+            Self::Confused | Self::Poisoned | Self::Speed(SpeedEffect::Slow) => true,
+            Self::Invisible | Self::Regenerating | Self::Speed(SpeedEffect::Haste) => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveStatusEffect {
+    pub effect: StatusEffect,
+    pub turns_remaining: u32,
+}
+
+// Whether an item is blessed, uncursed or cursed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Buc {
+    Blessed,
+    Uncursed,
+    Cursed,
+}
+
+impl Buc {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Blessed => "blessed",
+            Self::Uncursed => "uncursed",
+            Self::Cursed => "cursed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Durability {
+    pub current: u32,
+    pub max: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemCondition {
+    Good,
+    Worn,
+    Fragile,
+}
+
+impl Durability {
+    pub fn condition(self) -> ItemCondition {
+        if self.current * 5 <= self.max {
+            ItemCondition::Fragile
+        } else if self.current * 2 <= self.max {
+            ItemCondition::Worn
+        } else {
+            ItemCondition::Good
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Altar {
+    pub prayed_at: bool,
+}
+
+// Fountains can be drunk from a limited number of times before they run dry
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Fountain {
+    pub uses_remaining: u32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum FountainOutcome {
+    Heal,
+    Poison,
+    RevealMap,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PrayerOutcome {
+    Heal,
+    Enchant(ItemType),
+    RevealMap,
+    Smite,
+    Summon,
+    Weaken,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct HitPoints {
     pub current: u32,
@@ -118,51 +399,158 @@ impl HitPoints {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum NpcType {
     Orc,
     Troll,
+    Spirit,
+    // A boss guarding the stairs of every few levels
+    Warlord,
 }
 
 impl NpcType {
+    pub const ALL: &'static [Self] = &[Self::Orc, Self::Troll, Self::Spirit, Self::Warlord];
     pub fn name(self) -> &'static str {
         match self {
             Self::Orc => "orc",
             Self::Troll => "troll",
+            Self::Spirit => "spirit",
+            Self::Warlord => "orc warlord",
+        }
+    }
+    pub fn is_nocturnal(self) -> bool {
+        match self {
+            Self::Spirit => true,
+            Self::Orc | Self::Troll | Self::Warlord => false,
+        }
+    }
+    pub fn is_boss(self) -> bool {
+        self == Self::Warlord
+    }
+}
+
+// An npc type along with its modifiers, which affect how the npc is described and rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Npc {
+    pub npc_type: NpcType,
+    pub elite: bool,
+}
+
+impl Npc {
+    pub fn name(self) -> &'static str {
+        match (self.npc_type, self.elite) {
+            (npc_type, false) => npc_type.name(),
+            (NpcType::Orc, true) => "elite orc",
+            (NpcType::Troll, true) => "elite troll",
+            (NpcType::Spirit, true) => "elite spirit",
+            (NpcType::Warlord, true) => "elite orc warlord",
+        }
+    }
+    // The character representing the npc in text renderings of levels
+    pub fn ascii_char(self) -> char {
+        match (self.npc_type, self.elite) {
+            (NpcType::Orc, false) => 'o',
+            (NpcType::Orc, true) => 'O',
+            (NpcType::Troll, _) => 'T',
+            (NpcType::Spirit, _) => 's',
+            (NpcType::Warlord, _) => 'W',
+        }
+    }
+}
+
+// Something on a cell which hurts or hinders characters that step onto it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hazard {
+    // Trapdoors are hidden until something falls through them
+    Trapdoor { revealed: bool },
+    // Slowing traps are hidden until something springs them
+    SlowingTrap { revealed: bool },
+}
+
+impl Hazard {
+    // Npcs know where every hazard is, but the player can't see hidden ones
+    pub fn is_hidden(self) -> bool {
+        match self {
+            Self::Trapdoor { revealed } | Self::SlowingTrap { revealed } => !revealed,
         }
     }
 }
 
+// Paths go around a hazard unless the way around takes this many more steps
+pub const HAZARD_MOVE_COST: u32 = 10;
+
+// Characters only attack characters of a different faction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Faction {
+    Player,
+    Monster,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Tile {
     Player,
     PlayerCorpse,
     Floor,
     Wall,
-    Npc(NpcType),
-    NpcCorpse(NpcType),
+    Npc(Npc),
+    NpcCorpse(Npc),
     Item(ItemType),
     Projectile(ProjectileType),
     Stairs,
+    UpStairs,
+    // A trapdoor which has been revealed. Hidden trapdoors have a floor tile.
+    Trapdoor,
+    // Like trapdoors, slowing traps have a floor tile until they're revealed
+    SlowingTrap,
+    Altar,
+    Fountain,
+    DashTrail,
+    DamageNumber { damage: u32, frames_remaining: u32 },
 }
 
 entity_table::declare_entity_module! {
     components {
         tile: Tile,
         npc_type: NpcType,
+        elite: (),
+        // Nocturnal npcs are stronger at night
+        empowered_by_night: (),
+        faction: Faction,
         hit_points: HitPoints,
         item: ItemType,
         inventory: Inventory,
         trajectory: CardinalStepIter,
         projectile: ProjectileType,
         confusion_countdown: u32,
+        summon_countdown: u32,
+        invisibility_countdown: u32,
+        speed_status: SpeedStatus,
+        regeneration_countdown: u32,
+        poison_countdown: u32,
+        defending: (),
+        charge_cooldown: u32,
+        fade_countdown: u32,
+        float_countdown: u32,
+        durability: Durability,
+        buc: Buc,
+        buc_known: (),
         stairs: (),
+        up_stairs: (),
         base_damage: i32,
         strength: i32,
         dexterity: i32,
         intelligence: i32,
         equipment_worn_inventory_index: usize,
         equipment_held_inventory_index: usize,
+        altar: Altar,
+        fountain: Fountain,
+        trapdoor: (),
+        slowing_trap: (),
+        enchantment: i32,
+        ammo: u32,
+        hotbar_slot: usize,
+        // An item crushed beneath a corpse, which can be found by searching the corpse
+        buried_item: Entity,
     }
 }
 
@@ -183,11 +571,53 @@ pub use layers::Layer;
 type SpatialTable = spatial_table::SpatialTable<layers::Layers>;
 pub type Location = spatial_table::Location<Layer>;
 
+// A character briefly drawn a cell closer to the character it just attacked
+#[derive(Clone, Copy, Debug)]
+struct Lunge {
+    entity: Entity,
+    direction: CardinalDirection,
+    frames_remaining: u32,
+}
+
+const LUNGE_FRAMES: u32 = 2;
+
+// A faint copy of an npc left in the cell it just moved out of, so it's easier to follow where
+// enemies went in a crowded fight
+#[derive(Clone, Copy, Debug)]
+pub struct MotionTrail {
+    pub coord: Coord,
+    pub tile: Tile,
+    pub frames_remaining: u32,
+}
+
+pub const MOTION_TRAIL_FRAMES: u32 = 6;
+
+// Buffers used while updating projectiles and effects each animation tick. They're emptied after
+// each use but keep their capacity, so updates don't allocate once the buffers have grown.
+#[derive(Default)]
+struct Scratch {
+    entities: Vec<Entity>,
+    damaged: Vec<(Entity, u32)>,
+    shot: Vec<(Entity, u32)>,
+    confused: Vec<(Entity, u32)>,
+    slowed: Vec<(Entity, u32)>,
+    coords: Vec<Coord>,
+    fountains: Vec<Entity>,
+    rising: Vec<Entity>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct World {
     pub entity_allocator: EntityAllocator,
     pub components: Components,
     pub spatial_table: SpatialTable,
+    // Lunges are purely cosmetic, so they aren't saved
+    #[serde(skip)]
+    lunges: Vec<Lunge>,
+    #[serde(skip)]
+    motion_trails: Vec<MotionTrail>,
+    #[serde(skip)]
+    scratch: Scratch,
 }
 
 pub struct Populate {
@@ -195,7 +625,18 @@ pub struct Populate {
     pub ai_state: ComponentTable<Agent>,
 }
 
-enum BumpAttackOutcome {
+// Who took part in a bump attack, from the player's point of view
+#[derive(Clone, Copy, Debug)]
+pub enum Combatants {
+    PlayerVsNpc(Npc),
+    NpcVsPlayer(Npc),
+    AllyVsNpc(Npc),
+    NpcVsAlly(Npc),
+    NpcVsNpc { attacker: Npc, victim: Npc },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BumpAttackOutcome {
     Hit,
     Dodge,
     Kill,
@@ -212,12 +653,18 @@ impl World {
             entity_allocator,
             components,
             spatial_table,
+            lunges: Vec::new(),
+            motion_trails: Vec::new(),
+            scratch: Scratch::default(),
         }
     }
-    pub fn clear(&mut self) {
-        self.entity_allocator.clear();
-        self.components.clear();
-        self.spatial_table.clear();
+    // A hash of the components and spatial table which is the same on every platform. The entity
+    // allocator is left out as its free list doesn't affect how the game plays.
+    pub fn stable_hash(&self) -> u64 {
+        let components = serde_json::to_vec(&self.components).expect("failed to serialize world");
+        let spatial_table =
+            serde_json::to_vec(&self.spatial_table).expect("failed to serialize world");
+        trace::stable_hash(&[components, spatial_table].concat())
     }
     fn spawn_wall(&mut self, coord: Coord) {
         let entity = self.entity_allocator.alloc();
@@ -232,7 +679,7 @@ impl World {
             .unwrap();
         self.components.tile.insert(entity, Tile::Wall);
     }
-    fn spawn_floor(&mut self, coord: Coord) {
+    fn spawn_floor(&mut self, coord: Coord) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(
@@ -244,6 +691,26 @@ impl World {
             )
             .unwrap();
         self.components.tile.insert(entity, Tile::Floor);
+        entity
+    }
+    // Trapdoors look like ordinary floor until something falls through them
+    fn spawn_trapdoor(&mut self, coord: Coord) {
+        let entity = self.spawn_floor(coord);
+        self.components.trapdoor.insert(entity, ());
+    }
+    // Slowing traps look like ordinary floor until they're sprung
+    fn spawn_slowing_trap(&mut self, coord: Coord) {
+        let entity = self.spawn_floor(coord);
+        self.components.slowing_trap.insert(entity, ());
+    }
+    pub fn reveal_trap(&mut self, coord: Coord) {
+        if let Some(floor_entity) = self.spatial_table.layers_at_checked(coord).floor {
+            if self.components.trapdoor.contains(floor_entity) {
+                self.components.tile.insert(floor_entity, Tile::Trapdoor);
+            } else if self.components.slowing_trap.contains(floor_entity) {
+                self.components.tile.insert(floor_entity, Tile::SlowingTrap);
+            }
+        }
     }
     fn spawn_player(&mut self, coord: Coord) -> Entity {
         let entity = self.entity_allocator.alloc();
@@ -257,6 +724,7 @@ impl World {
             )
             .unwrap();
         self.components.tile.insert(entity, Tile::Player);
+        self.components.faction.insert(entity, Faction::Player);
         self.components
             .hit_points
             .insert(entity, HitPoints::new_full(20));
@@ -267,7 +735,9 @@ impl World {
         self.components.inventory.insert(entity, Inventory::new(10));
         entity
     }
-    fn spawn_npc(&mut self, coord: Coord, npc_type: NpcType) -> Entity {
+    // `stat_bonus` makes the npc tougher than usual, for npcs deep in an endless dungeon
+    fn spawn_npc(&mut self, coord: Coord, npc: Npc, stat_bonus: i32) -> Entity {
+        let Npc { npc_type, elite } = npc;
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(
@@ -278,24 +748,60 @@ impl World {
                 },
             )
             .unwrap();
-        self.components.tile.insert(entity, Tile::Npc(npc_type));
+        self.components.tile.insert(entity, Tile::Npc(npc));
         self.components.npc_type.insert(entity, npc_type);
-        let hit_points = match npc_type {
-            NpcType::Orc => HitPoints::new_full(2),
-            NpcType::Troll => HitPoints::new_full(6),
+        self.components.faction.insert(entity, Faction::Monster);
+        let max_hit_points = match npc_type {
+            NpcType::Orc => 2,
+            NpcType::Troll => 6,
+            NpcType::Spirit => 4,
+            NpcType::Warlord => 20,
         };
-        self.components.hit_points.insert(entity, hit_points);
-        self.components.base_damage.insert(entity, 1);
         let (strength, dexterity) = match npc_type {
             NpcType::Orc => (1, 1),
             NpcType::Troll => (2, 0),
+            NpcType::Spirit => (1, 1),
+            NpcType::Warlord => (3, 1),
         };
+        // Elite npcs are tougher in every way
+        let (max_hit_points, strength, dexterity) = if elite {
+            self.components.elite.insert(entity, ());
+            (max_hit_points * 2, strength + 1, dexterity + 1)
+        } else {
+            (max_hit_points, strength, dexterity)
+        };
+        let (max_hit_points, strength, dexterity) = (
+            max_hit_points + stat_bonus as u32 * 2,
+            strength + stat_bonus,
+            dexterity + stat_bonus,
+        );
+        self.components
+            .hit_points
+            .insert(entity, HitPoints::new_full(max_hit_points));
+        self.components.base_damage.insert(entity, 1);
         self.components.strength.insert(entity, strength);
         self.components.dexterity.insert(entity, dexterity);
         entity
     }
-    fn spawn_item(&mut self, coord: Coord, item_type: ItemType) {
-        let entity = self.entity_allocator.alloc();
+    // Summoned spirits fight on the player's side until their countdown runs out
+    fn spawn_summon(&mut self, coord: Coord) -> Entity {
+        const SUMMON_DURATION: u32 = 20;
+        let entity = self.spawn_npc(
+            coord,
+            Npc {
+                npc_type: NpcType::Spirit,
+                elite: false,
+            },
+            0,
+        );
+        self.components.faction.insert(entity, Faction::Player);
+        self.components
+            .summon_countdown
+            .insert(entity, SUMMON_DURATION);
+        entity
+    }
+    fn spawn_item(&mut self, coord: Coord, item_type: ItemType) -> Entity {
+        let entity = self.spawn_unplaced_item(item_type);
         self.spatial_table
             .update(
                 entity,
@@ -305,8 +811,23 @@ impl World {
                 },
             )
             .unwrap();
+        entity
+    }
+    // An item with no location, such as one buried under a corpse
+    fn spawn_unplaced_item(&mut self, item_type: ItemType) -> Entity {
+        let entity = self.entity_allocator.alloc();
         self.components.tile.insert(entity, Tile::Item(item_type));
         self.components.item.insert(entity, item_type);
+        if item_type == ItemType::Arrows {
+            const ARROWS_PER_BUNDLE: u32 = 8;
+            self.components.ammo.insert(entity, ARROWS_PER_BUNDLE);
+        }
+        if let Some(max) = item_type.max_durability() {
+            self.components
+                .durability
+                .insert(entity, Durability { current: max, max });
+        }
+        entity
     }
     fn spawn_projectile(&mut self, from: Coord, to: Coord, projectile_type: ProjectileType) {
         let entity = self.entity_allocator.alloc();
@@ -327,6 +848,36 @@ impl World {
             .trajectory
             .insert(entity, CardinalStepIter::new(to - from));
     }
+    // A purely cosmetic entity which disappears after `frames` animation ticks. The player can act
+    // before it's gone, so like damage numbers it isn't on any layer, and never gets in the way of
+    // projectiles.
+    fn spawn_dash_trail(&mut self, coord: Coord, frames: u32) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(entity, Location { coord, layer: None })
+            .unwrap();
+        self.components.tile.insert(entity, Tile::DashTrail);
+        self.components.fade_countdown.insert(entity, frames);
+    }
+    // A number showing damage dealt, which floats upwards from `coord` and then disappears. It
+    // isn't on any layer so it never gets in the way of anything else.
+    fn spawn_damage_number(&mut self, coord: Coord, damage: u32) {
+        const DAMAGE_NUMBER_FRAMES: u32 = 15;
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(entity, Location { coord, layer: None })
+            .unwrap();
+        self.components.tile.insert(
+            entity,
+            Tile::DamageNumber {
+                damage,
+                frames_remaining: DAMAGE_NUMBER_FRAMES,
+            },
+        );
+        self.components
+            .float_countdown
+            .insert(entity, DAMAGE_NUMBER_FRAMES);
+    }
     fn spawn_stairs(&mut self, coord: Coord) {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
@@ -341,29 +892,145 @@ impl World {
         self.components.tile.insert(entity, Tile::Stairs);
         self.components.stairs.insert(entity, ());
     }
-    pub fn populate<R: Rng>(&mut self, level: u32, rng: &mut R) -> Populate {
-        let terrain = terrain::generate_dungeon(self.spatial_table.grid_size(), level, rng);
+    // Leads back to the level above, so only appears on levels below the first
+    fn spawn_up_stairs(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::UpStairs);
+        self.components.up_stairs.insert(entity, ());
+    }
+    fn spawn_altar(&mut self, coord: Coord) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Altar);
+        self.components
+            .altar
+            .insert(entity, Altar { prayed_at: false });
+    }
+    fn spawn_fountain(&mut self, coord: Coord, uses_remaining: u32) {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.components.tile.insert(entity, Tile::Fountain);
+        self.components
+            .fountain
+            .insert(entity, Fountain { uses_remaining });
+    }
+    // A fountain which runs dry or evaporates leaves behind ordinary floor
+    fn dry_up_fountain(&mut self, entity: Entity) {
+        self.components.fountain.remove(entity);
+        self.components.tile.insert(entity, Tile::Floor);
+    }
+    // A walled room with a few pillars in the middle of the map, for testing combat
+    pub fn populate_arena(&mut self) -> Populate {
+        const ARENA: &[&str] = &[
+            "###############",
+            "#.............#",
+            "#..#.......#..#",
+            "#.............#",
+            "#......@......#",
+            "#.............#",
+            "#..#.......#..#",
+            "#.............#",
+            "###############",
+        ];
+        let size = self.size();
+        let top_left = Coord::new(
+            (size.width() as i32 - ARENA[0].len() as i32) / 2,
+            (size.height() as i32 - ARENA.len() as i32) / 2,
+        );
+        let mut player_entity = None;
+        for (y, row) in ARENA.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                let coord = top_left + Coord::new(x as i32, y as i32);
+                self.spawn_floor(coord);
+                match ch {
+                    '#' => self.spawn_wall(coord),
+                    '@' => player_entity = Some(self.spawn_player(coord)),
+                    _ => (),
+                }
+            }
+        }
+        Populate {
+            player_entity: player_entity.unwrap(),
+            ai_state: ComponentTable::default(),
+        }
+    }
+    pub fn populate<R: Rng>(
+        &mut self,
+        level: u32,
+        theme: LevelTheme,
+        config: DungeonConfig,
+        rng: &mut R,
+    ) -> Populate {
+        let dungeon =
+            terrain::generate_dungeon(self.spatial_table.grid_size(), level, theme, config, rng);
         let mut player_entity = None;
         let mut ai_state = ComponentTable::default();
-        for (coord, &terrain_tile) in terrain.enumerate() {
+        for (coord, &terrain_tile) in dungeon.grid.enumerate() {
             match terrain_tile {
                 TerrainTile::Player => {
-                    self.spawn_floor(coord);
+                    if level > 1 {
+                        self.spawn_up_stairs(coord);
+                    } else {
+                        self.spawn_floor(coord);
+                    }
                     player_entity = Some(self.spawn_player(coord));
                 }
-                TerrainTile::Floor => self.spawn_floor(coord),
+                TerrainTile::Floor => {
+                    self.spawn_floor(coord);
+                }
                 TerrainTile::Stairs => self.spawn_stairs(coord),
+                TerrainTile::Altar => self.spawn_altar(coord),
+                TerrainTile::Fountain => {
+                    const MAX_FOUNTAIN_USES: u32 = 3;
+                    self.spawn_fountain(coord, rng.gen_range(1..=MAX_FOUNTAIN_USES))
+                }
+                TerrainTile::Trapdoor => self.spawn_trapdoor(coord),
+                TerrainTile::SlowingTrap => self.spawn_slowing_trap(coord),
                 TerrainTile::Wall => {
                     self.spawn_floor(coord);
                     self.spawn_wall(coord);
                 }
-                TerrainTile::Npc(npc_type) => {
-                    let entity = self.spawn_npc(coord, npc_type);
+                TerrainTile::Npc(npc) => {
+                    let entity = self.spawn_npc(coord, npc, terrain::npc_stat_bonus(level, config));
                     self.spawn_floor(coord);
-                    ai_state.insert(entity, Agent::new());
+                    let agent = if npc.npc_type.is_boss() {
+                        Agent::new_boss()
+                    } else {
+                        match terrain::choose_patrol_route(coord, &dungeon.room_centres, rng) {
+                            Some(patrol_route) => Agent::new_patrolling(patrol_route),
+                            None => Agent::new(),
+                        }
+                    };
+                    ai_state.insert(entity, agent);
                 }
                 TerrainTile::Item(item_type) => {
-                    self.spawn_item(coord, item_type);
+                    let entity = self.spawn_item(coord, item_type);
+                    self.components.buc.insert(entity, terrain::choose_buc(rng));
                     self.spawn_floor(coord);
                 }
             }
@@ -373,37 +1040,21 @@ impl World {
             ai_state,
         }
     }
-    fn write_combat_log_messages(
-        attacker_is_player: bool,
-        outcome: BumpAttackOutcome,
-        npc_type: NpcType,
-        message_log: &mut Vec<LogMessage>,
-    ) {
-        if attacker_is_player {
-            match outcome {
-                BumpAttackOutcome::Kill => message_log.push(LogMessage::PlayerKillsNpc(npc_type)),
-                BumpAttackOutcome::Hit => message_log.push(LogMessage::PlayerAttacksNpc(npc_type)),
-                BumpAttackOutcome::Dodge => message_log.push(LogMessage::NpcDodges(npc_type)),
-            }
-        } else {
-            match outcome {
-                BumpAttackOutcome::Kill => message_log.push(LogMessage::NpcKillsPlayer(npc_type)),
-                BumpAttackOutcome::Hit => message_log.push(LogMessage::NpcAttacksPlayer(npc_type)),
-                BumpAttackOutcome::Dodge => message_log.push(LogMessage::PlayerDodges(npc_type)),
-            }
-        }
-    }
     pub fn maybe_move_character<R: Rng>(
         &mut self,
         character_entity: Entity,
         direction: CardinalDirection,
-        message_log: &mut Vec<LogMessage>,
+        events: &mut Vec<GameEvent>,
         rng: &mut R,
     ) {
         let character_coord = self
             .spatial_table
             .coord_of(character_entity)
             .expect("character has no coord");
+        let confused = self
+            .components
+            .confusion_countdown
+            .contains(character_entity);
         let direction = if let Some(confusion_countdown) = self
             .components
             .confusion_countdown
@@ -411,8 +1062,8 @@ impl World {
         {
             if *confusion_countdown == 0 {
                 self.components.confusion_countdown.remove(character_entity);
-                if let Some(&npc_type) = self.components.npc_type.get(character_entity) {
-                    message_log.push(LogMessage::NpcIsNoLongerConfused(npc_type));
+                if let Some(npc) = self.npc(character_entity) {
+                    events.push(GameEvent::Message(LogMessage::NpcIsNoLongerConfused(npc)));
                 }
             } else {
                 *confusion_countdown -= 1;
@@ -425,18 +1076,15 @@ impl World {
         if new_character_coord.is_valid(self.spatial_table.grid_size()) {
             let dest_layers = self.spatial_table.layers_at_checked(new_character_coord);
             if let Some(dest_character_entity) = dest_layers.character {
-                let character_is_npc = self.components.npc_type.get(character_entity).cloned();
-                let dest_character_is_npc =
-                    self.components.npc_type.get(dest_character_entity).cloned();
-                if character_is_npc.is_some() != dest_character_is_npc.is_some() {
-                    let outcome =
-                        self.character_bump_attack(dest_character_entity, character_entity, rng);
-                    let npc_type = character_is_npc.or(dest_character_is_npc).unwrap();
-                    Self::write_combat_log_messages(
-                        character_is_npc.is_none(),
-                        outcome,
-                        npc_type,
-                        message_log,
+                // Confused characters lash out at whoever they stumble into
+                if confused || self.faction(character_entity) != self.faction(dest_character_entity)
+                {
+                    self.character_bump_attack(
+                        dest_character_entity,
+                        character_entity,
+                        0,
+                        events,
+                        rng,
                     );
                 }
             } else if dest_layers.feature.is_none() {
@@ -446,6 +1094,27 @@ impl World {
             }
         }
     }
+    // Attack whichever character is in the given direction, even one on the same side
+    pub fn character_attack<R: Rng>(
+        &mut self,
+        attacker: Entity,
+        direction: CardinalDirection,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) {
+        let coord = self
+            .spatial_table
+            .coord_of(attacker)
+            .expect("character has no coord")
+            + direction.coord();
+        if let Some(victim) = self
+            .spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.character)
+        {
+            self.character_bump_attack(victim, attacker, 0, events, rng);
+        }
+    }
     fn inventory_item_type(&self, entity: Entity, index: usize) -> Option<ItemType> {
         self.components.inventory.get(entity).and_then(|inventory| {
             inventory
@@ -454,6 +1123,14 @@ impl World {
                 .and_then(|held_entity| self.components.item.get(held_entity).cloned())
         })
     }
+    fn inventory_item_enchantment(&self, entity: Entity, index: usize) -> i32 {
+        self.components
+            .inventory
+            .get(entity)
+            .and_then(|inventory| inventory.get(index).ok())
+            .and_then(|item_entity| self.components.enchantment.get(item_entity).cloned())
+            .unwrap_or(0)
+    }
     fn damage_modifier(&self, entity: Entity) -> i32 {
         self.components
             .equipment_held_inventory_index
@@ -461,11 +1138,13 @@ impl World {
             .and_then(|&held_index| {
                 self.inventory_item_type(entity, held_index)
                     .map(|item_type| match item_type {
-                        ItemType::Sword => 1,
+                        ItemType::Sword | ItemType::FireSword | ItemType::CursedBlade => 1,
                         _ => 0,
                     })
+                    .map(|modifier| modifier + self.inventory_item_enchantment(entity, held_index))
             })
             .unwrap_or(0)
+            + self.components.empowered_by_night.contains(entity) as i32
     }
     fn defense_modifier(&self, entity: Entity) -> i32 {
         self.components
@@ -477,8 +1156,10 @@ impl World {
                         ItemType::Armour => 1,
                         _ => 0,
                     })
+                    .map(|modifier| modifier + self.inventory_item_enchantment(entity, held_index))
             })
             .unwrap_or(0)
+            + self.components.empowered_by_night.contains(entity) as i32
     }
     fn magic_modifier(&self, entity: Entity) -> i32 {
         let held = self
@@ -511,8 +1192,28 @@ impl World {
         &mut self,
         victim: Entity,
         attacker: Entity,
+        bonus_damage: i32,
+        events: &mut Vec<GameEvent>,
         rng: &mut R,
-    ) -> BumpAttackOutcome {
+    ) {
+        events.push(GameEvent::Attack { attacker, victim });
+        let combatants = match (self.npc(attacker), self.npc(victim)) {
+            (None, Some(npc)) => Combatants::PlayerVsNpc(npc),
+            (Some(npc), None) => Combatants::NpcVsPlayer(npc),
+            (Some(attacker_npc), Some(victim_npc)) => {
+                if self.faction(attacker) == self.faction(victim) {
+                    Combatants::NpcVsNpc {
+                        attacker: attacker_npc,
+                        victim: victim_npc,
+                    }
+                } else if self.faction(attacker) == Some(Faction::Player) {
+                    Combatants::AllyVsNpc(victim_npc)
+                } else {
+                    Combatants::NpcVsAlly(attacker_npc)
+                }
+            }
+            (None, None) => panic!("neither attacker nor victim is an npc"),
+        };
         let &attacker_base_damage = self.components.base_damage.get(attacker).unwrap();
         let &attacker_strength = self.components.strength.get(attacker).unwrap();
         let attacker_damage_modifier = self.damage_modifier(attacker);
@@ -520,39 +1221,279 @@ impl World {
         let victim_defense_modifier = self.defense_modifier(victim);
         let gross_damage = attacker_base_damage
             + rng.gen_range(0..(attacker_strength + 1))
-            + attacker_damage_modifier;
+            + attacker_damage_modifier
+            + bonus_damage;
         let damage_reduction = rng.gen_range(0..(victim_dexterity + 1)) + victim_defense_modifier;
+        // Defending characters are twice as good at avoiding damage
+        let damage_reduction = if self.components.defending.contains(victim) {
+            damage_reduction * 2
+        } else {
+            damage_reduction
+        };
         let net_damage = gross_damage.saturating_sub(damage_reduction).max(0) as u32;
-        if net_damage == 0 {
+        let outcome = if net_damage == 0 {
             BumpAttackOutcome::Dodge
         } else {
-            if self.character_damage(victim, net_damage).is_some() {
+            if self
+                .character_damage(victim, Some(attacker), net_damage, events, rng)
+                .is_some()
+            {
                 BumpAttackOutcome::Kill
             } else {
                 BumpAttackOutcome::Hit
             }
+        };
+        events.push(GameEvent::BumpAttack {
+            combatants,
+            outcome,
+        });
+        if net_damage > 0 {
+            self.steal_life(attacker, net_damage, events);
         }
-    }
-    fn character_damage(&mut self, victim: Entity, damage: u32) -> Option<VictimDies> {
-        if let Some(hit_points) = self.components.hit_points.get_mut(victim) {
-            hit_points.current = hit_points.current.saturating_sub(damage);
-            if hit_points.current == 0 {
-                self.character_die(victim);
-                return Some(VictimDies);
+        if let BumpAttackOutcome::Hit = outcome {
+            if let Some(held_item_type) = self.held_item_type(attacker) {
+                for &(effect, percent_chance) in held_item_type.on_hit_effects() {
+                    if rng.gen_range(0..100) < percent_chance {
+                        self.apply_on_hit_effect(effect, victim, attacker, events, rng);
+                    }
+                }
+            }
+        }
+        // Weapons wear out by hitting things, and armour by being hit
+        if net_damage > 0 {
+            if let Some(&held_index) = self.components.equipment_held_inventory_index.get(attacker)
+            {
+                self.wear_item(attacker, held_index, events);
+            }
+            if let Some(&worn_index) = self.components.equipment_worn_inventory_index.get(victim) {
+                self.wear_item(victim, worn_index, events);
             }
         }
-        None
     }
-    fn character_die(&mut self, entity: Entity) {
-        if let Some(occpied_by_entity) = self
-            .spatial_table
-            .update_layer(entity, Layer::Object)
-            .err()
-            .map(|e| e.unwrap_occupied_by())
-        {
-            // If a character dies on a cell which contains an object, remove the existing object
-            // from existence and replace it with the character's corpse.
-            self.remove_entity(occpied_by_entity);
+    // Reduces the durability of an item in a character's inventory, destroying it if it breaks
+    fn wear_item(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        events: &mut Vec<GameEvent>,
+    ) {
+        let item = match self
+            .components
+            .inventory
+            .get(character)
+            .and_then(|inventory| inventory.get(inventory_index).ok())
+        {
+            Some(item) => item,
+            None => return,
+        };
+        let durability = match self.components.durability.get_mut(item) {
+            Some(durability) => durability,
+            None => return,
+        };
+        durability.current = durability.current.saturating_sub(1);
+        if durability.current > 0 {
+            return;
+        }
+        self.components
+            .inventory
+            .get_mut(character)
+            .unwrap()
+            .remove(inventory_index)
+            .unwrap();
+        if self
+            .components
+            .equipment_held_inventory_index
+            .get(character)
+            == Some(&inventory_index)
+        {
+            self.components
+                .equipment_held_inventory_index
+                .remove(character);
+        }
+        if self
+            .components
+            .equipment_worn_inventory_index
+            .get(character)
+            == Some(&inventory_index)
+        {
+            self.components
+                .equipment_worn_inventory_index
+                .remove(character);
+        }
+        let &item_type = self.components.item.get(item).unwrap();
+        self.remove_entity(item);
+        events.push(GameEvent::Message(LogMessage::ItemBreaks(item_type)));
+    }
+    fn life_steal_percent(&self, entity: Entity) -> u32 {
+        // Elite trolls feed on the wounds they inflict
+        let npc_life_steal_percent = match self.npc(entity) {
+            Some(Npc {
+                npc_type: NpcType::Troll,
+                elite: true,
+            }) => 30,
+            _ => 0,
+        };
+        let held_life_steal_percent = self
+            .held_item_type(entity)
+            .map_or(0, |item_type| item_type.life_steal_percent());
+        npc_life_steal_percent + held_life_steal_percent
+    }
+    fn steal_life(&mut self, attacker: Entity, damage: u32, events: &mut Vec<GameEvent>) {
+        let heal = (damage * self.life_steal_percent(attacker)) / 100;
+        if heal == 0 {
+            return;
+        }
+        let hit_points = match self.components.hit_points.get_mut(attacker) {
+            Some(hit_points) => hit_points,
+            None => return,
+        };
+        let healed = heal.min(hit_points.max - hit_points.current);
+        if healed == 0 {
+            return;
+        }
+        hit_points.current += healed;
+        events.push(GameEvent::Message(match self.npc(attacker) {
+            Some(npc) => LogMessage::NpcStealsLife(npc, healed),
+            None => LogMessage::PlayerStealsLife(healed),
+        }));
+    }
+    fn apply_on_hit_effect<R: Rng>(
+        &mut self,
+        effect: OnHitEffect,
+        victim: Entity,
+        attacker: Entity,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) {
+        // a previous effect may have killed the victim
+        if !self.is_living_character(victim) {
+            return;
+        }
+        let victim_npc = self.npc(victim);
+        match effect {
+            OnHitEffect::Burn => {
+                const BURN_DAMAGE: u32 = 2;
+                if let Some(npc) = victim_npc {
+                    events.push(GameEvent::Message(LogMessage::NpcBurns(npc)));
+                }
+                if let Some(VictimDies) =
+                    self.character_damage(victim, Some(attacker), BURN_DAMAGE, events, rng)
+                {
+                    if let Some(npc) = victim_npc {
+                        events.push(GameEvent::NpcDies(npc));
+                    }
+                }
+            }
+            OnHitEffect::Confuse => {
+                const CONFUSION_DURATION: u32 = 3;
+                self.components
+                    .confusion_countdown
+                    .insert(victim, CONFUSION_DURATION);
+                if let Some(npc) = victim_npc {
+                    events.push(GameEvent::Message(LogMessage::NpcBecomesConfused(npc)));
+                }
+            }
+            OnHitEffect::KnockBack => {
+                let victim_coord = self.spatial_table.coord_of(victim).unwrap();
+                let attacker_coord = self.spatial_table.coord_of(attacker).unwrap();
+                let dest_coord = victim_coord + (victim_coord - attacker_coord);
+                let dest_is_empty = self
+                    .spatial_table
+                    .layers_at(dest_coord)
+                    .map_or(false, |layers| {
+                        layers.character.is_none() && layers.feature.is_none()
+                    });
+                if dest_is_empty {
+                    self.spatial_table.update_coord(victim, dest_coord).unwrap();
+                    if let Some(npc) = victim_npc {
+                        events.push(GameEvent::Message(LogMessage::NpcIsKnockedBack(npc)));
+                    }
+                }
+            }
+        }
+    }
+    // The attacker is `None` for damage which doesn't come from a character, such as fireballs
+    fn character_damage<R: Rng>(
+        &mut self,
+        victim: Entity,
+        attacker: Option<Entity>,
+        damage: u32,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) -> Option<VictimDies> {
+        if let Some(&coord) = self.spatial_table.coord_of(victim) {
+            self.spawn_damage_number(coord, damage);
+        }
+        self.report_damage(victim, attacker, damage, events);
+        if let Some(attacker) = attacker {
+            let infighting = attacker != victim
+                && self.npc(attacker).is_some()
+                && self.npc(victim).is_some()
+                && self.faction(attacker) == self.faction(victim);
+            if infighting {
+                events.push(GameEvent::Provoked { victim, attacker });
+            }
+        }
+        if let Some(hit_points) = self.components.hit_points.get_mut(victim) {
+            hit_points.current = hit_points.current.saturating_sub(damage);
+            if hit_points.current == 0 {
+                self.character_die(victim, rng);
+                return Some(VictimDies);
+            }
+        }
+        None
+    }
+    // Only damage involving the player's side is reported, as that's all the statistics count. The
+    // player is the only character which isn't an npc.
+    fn report_damage(
+        &self,
+        victim: Entity,
+        attacker: Option<Entity>,
+        damage: u32,
+        events: &mut Vec<GameEvent>,
+    ) {
+        // Damage from anything other than a character comes from the player's scrolls
+        let by_player_side = attacker.map_or(true, |attacker| {
+            self.faction(attacker) == Some(Faction::Player)
+        });
+        let event = match self.npc(victim) {
+            None => GameEvent::DamageTaken {
+                npc_type: attacker
+                    .and_then(|attacker| self.npc(attacker))
+                    .map(|npc| npc.npc_type),
+                damage,
+            },
+            Some(npc) if by_player_side && self.faction(victim) != Some(Faction::Player) => {
+                GameEvent::DamageDealt {
+                    npc_type: npc.npc_type,
+                    damage,
+                }
+            }
+            Some(_) => return,
+        };
+        events.push(event);
+    }
+    fn character_die<R: Rng>(&mut self, entity: Entity, rng: &mut R) {
+        if let Some(occpied_by_entity) = self
+            .spatial_table
+            .update_layer(entity, Layer::Object)
+            .err()
+            .map(|e| e.unwrap_occupied_by())
+        {
+            // If a character dies on a cell which contains an object, the corpse takes its place.
+            // An item is buried under the corpse, while an existing corpse is removed but anything
+            // buried under it is kept.
+            if self.components.item.contains(occpied_by_entity) {
+                self.spatial_table.remove(occpied_by_entity);
+                self.components
+                    .buried_item
+                    .insert(entity, occpied_by_entity);
+            } else {
+                if let Some(buried_item) = self.components.buried_item.remove(occpied_by_entity) {
+                    self.components.buried_item.insert(entity, buried_item);
+                }
+                self.remove_entity(occpied_by_entity);
+            }
             self.spatial_table
                 .update_layer(entity, Layer::Object)
                 .unwrap();
@@ -560,46 +1501,102 @@ impl World {
         let current_tile = self.components.tile.get(entity).unwrap();
         let corpse_tile = match current_tile {
             Tile::Player => Tile::PlayerCorpse,
-            Tile::Npc(npc_type) => Tile::NpcCorpse(*npc_type),
+            Tile::Npc(npc) => Tile::NpcCorpse(*npc),
             other => panic!("unexpected tile on character {:?}", other),
         };
         self.components.tile.insert(entity, corpse_tile);
+        if let Some(npc) = self.npc(entity) {
+            self.drop_loot(entity, npc, rng);
+        }
+    }
+    // The corpse occupies the object layer of the cell where the character died, so loot is
+    // dropped in a random adjacent cell with space for it instead. If there's no space, it's
+    // buried under the corpse, or failing that dropped in the nearest empty cell.
+    fn drop_loot<R: Rng>(&mut self, entity: Entity, npc: Npc, rng: &mut R) {
+        let item_type = match terrain::choose_npc_drop(npc, rng) {
+            Some(item_type) => item_type,
+            None => return,
+        };
+        let coord = self.spatial_table.coord_of(entity).unwrap();
+        let spatial_table = &self.spatial_table;
+        let drop_coord = CardinalDirection::all()
+            .map(|direction| coord + direction.coord())
+            .filter(|&coord| {
+                spatial_table.layers_at(coord).map_or(false, |layers| {
+                    layers.object.is_none() && layers.feature.is_none()
+                })
+            })
+            .choose(rng);
+        let item_entity = if let Some(drop_coord) = drop_coord {
+            self.spawn_item(drop_coord, item_type)
+        } else if !self.components.buried_item.contains(entity) {
+            let item_entity = self.spawn_unplaced_item(item_type);
+            self.components.buried_item.insert(entity, item_entity);
+            item_entity
+        } else if let Some(drop_coord) = self.nearest_empty_floor_coord(coord, rng) {
+            self.spawn_item(drop_coord, item_type)
+        } else {
+            return;
+        };
+        self.components
+            .buc
+            .insert(item_entity, terrain::choose_buc(rng));
     }
     pub fn maybe_get_item(
         &mut self,
         character: Entity,
-        message_log: &mut Vec<LogMessage>,
+        events: &mut Vec<GameEvent>,
     ) -> Result<(), ()> {
         let coord = self
             .spatial_table
             .coord_of(character)
             .expect("character has no coord");
         if let Some(object_entity) = self.spatial_table.layers_at_checked(coord).object {
-            if let Some(&item_type) = self.components.item.get(object_entity) {
+            // Getting an item from a cell with a corpse searches the corpse for a buried item
+            let (item_entity, buried) = match self.components.buried_item.get(object_entity) {
+                Some(&item_entity) => (item_entity, true),
+                None => (object_entity, false),
+            };
+            if let Some(&item_type) = self.components.item.get(item_entity) {
+                let event = GameEvent::ItemPickedUp {
+                    item_type,
+                    from_corpse: buried,
+                };
+                // Ammo is added to the existing stack of the same type if there is one
+                if let Some(&ammo) = self.components.ammo.get(item_entity) {
+                    if let Some(stack_entity) = self.inventory_ammo_stack(character) {
+                        *self.components.ammo.get_mut(stack_entity).unwrap() += ammo;
+                        self.components.buried_item.remove(object_entity);
+                        self.remove_entity(item_entity);
+                        events.push(event);
+                        return Ok(());
+                    }
+                }
                 // this assumes that the only character that can get items is the player
                 let inventory = self
                     .components
                     .inventory
                     .get_mut(character)
                     .expect("character has no inventory");
-                if inventory.insert(object_entity).is_ok() {
-                    self.spatial_table.remove(object_entity);
-                    message_log.push(LogMessage::PlayerGets(item_type));
+                if inventory.insert(item_entity).is_ok() {
+                    self.spatial_table.remove(item_entity);
+                    self.components.buried_item.remove(object_entity);
+                    events.push(event);
                     return Ok(());
                 } else {
-                    message_log.push(LogMessage::PlayerInventoryIsFull);
+                    events.push(GameEvent::Message(LogMessage::PlayerInventoryIsFull));
                     return Err(());
                 }
             }
         }
-        message_log.push(LogMessage::NoItemUnderPlayer);
+        events.push(GameEvent::Message(LogMessage::NoItemUnderPlayer));
         Err(())
     }
     pub fn maybe_use_item(
         &mut self,
         character: Entity,
         inventory_index: usize,
-        message_log: &mut Vec<LogMessage>,
+        events: &mut Vec<GameEvent>,
     ) -> Result<ItemUsage, ()> {
         let inventory = self
             .components
@@ -609,7 +1606,7 @@ impl World {
         let item = match inventory.get(inventory_index) {
             Ok(item) => item,
             Err(InventorySlotIsEmpty) => {
-                message_log.push(LogMessage::NoItemInInventorySlot);
+                events.push(GameEvent::Message(LogMessage::NoItemInInventorySlot));
                 return Err(());
             }
         };
@@ -618,6 +1615,12 @@ impl World {
             .item
             .get(item)
             .expect("non-item in inventory");
+        let buc = self
+            .components
+            .buc
+            .get(item)
+            .cloned()
+            .unwrap_or(Buc::Uncursed);
         let usage = match item_type {
             ItemType::HealthPotion => {
                 let mut hit_points = self
@@ -625,25 +1628,147 @@ impl World {
                     .hit_points
                     .get_mut(character)
                     .expect("character has no hit points");
-                const HEALTH_TO_HEAL: u32 = 5;
-                hit_points.current = hit_points.max.min(hit_points.current + HEALTH_TO_HEAL);
+                let health_to_heal = match buc {
+                    Buc::Blessed => 10,
+                    Buc::Uncursed => 5,
+                    Buc::Cursed => 2,
+                };
+                hit_points.current = hit_points.max.min(hit_points.current + health_to_heal);
+                inventory.remove(inventory_index).unwrap();
+                self.remove_entity(item);
+                events.push(GameEvent::Message(LogMessage::PlayerHeals));
+                ItemUsage::Immediate
+            }
+            ItemType::InvisibilityPotion => {
+                const INVISIBILITY_DURATION: u32 = 15;
+                self.components
+                    .invisibility_countdown
+                    .insert(character, INVISIBILITY_DURATION);
+                inventory.remove(inventory_index).unwrap();
+                self.remove_entity(item);
+                events.push(GameEvent::Message(LogMessage::PlayerBecomesInvisible));
+                ItemUsage::Immediate
+            }
+            ItemType::HastePotion => {
+                const HASTE_DURATION: u32 = 10;
+                inventory.remove(inventory_index).unwrap();
+                self.remove_entity(item);
+                self.apply_speed_effect(character, SpeedEffect::Haste, HASTE_DURATION, events);
+                ItemUsage::Immediate
+            }
+            ItemType::RegenerationPotion => {
+                const REGENERATION_DURATION: u32 = 10;
+                let countdown = self
+                    .components
+                    .regeneration_countdown
+                    .get(character)
+                    .cloned()
+                    .unwrap_or(0);
+                self.components
+                    .regeneration_countdown
+                    .insert(character, countdown.max(REGENERATION_DURATION));
+                inventory.remove(inventory_index).unwrap();
+                self.remove_entity(item);
+                events.push(GameEvent::Message(LogMessage::PlayerStartsRegenerating));
+                ItemUsage::Immediate
+            }
+            ItemType::FireballScroll | ItemType::ConfusionScroll | ItemType::SlowScroll => {
+                ItemUsage::Aim
+            }
+            ItemType::SummonScroll => {
+                let character_coord = self.spatial_table.coord_of(character).unwrap();
+                let summon_coord = match CardinalDirection::all()
+                    .map(|direction| character_coord + direction.coord())
+                    .find(|&coord| self.can_summon_at(coord))
+                {
+                    Some(summon_coord) => summon_coord,
+                    None => {
+                        events.push(GameEvent::Message(LogMessage::NoSpaceToSummon));
+                        return Err(());
+                    }
+                };
+                self.components
+                    .inventory
+                    .get_mut(character)
+                    .unwrap()
+                    .remove(inventory_index)
+                    .unwrap();
+                self.remove_entity(item);
+                let entity = self.spawn_summon(summon_coord);
+                let npc = self.npc(entity).unwrap();
+                // Cursed summon scrolls call something which fights against the player instead
+                if buc == Buc::Cursed {
+                    self.components.faction.insert(entity, Faction::Monster);
+                    events.push(GameEvent::Message(LogMessage::HostileSummonAppears(npc)));
+                } else {
+                    events.push(GameEvent::Message(LogMessage::AllyAppears(npc)));
+                }
+                ItemUsage::Summon(entity)
+            }
+            ItemType::Arrows => {
+                events.push(GameEvent::Message(LogMessage::AmmoMustBeFired));
+                return Err(());
+            }
+            ItemType::RepairKit => {
+                let durability = &self.components.durability;
+                let items_to_repair = [
+                    self.components
+                        .equipment_held_inventory_index
+                        .get(character),
+                    self.components
+                        .equipment_worn_inventory_index
+                        .get(character),
+                ]
+                .iter()
+                .flatten()
+                .filter_map(|&&index| inventory.get(index).ok())
+                .filter(|&item_entity| durability.contains(item_entity))
+                .collect::<Vec<_>>();
+                if items_to_repair.is_empty() {
+                    events.push(GameEvent::Message(LogMessage::NothingToRepair));
+                    return Err(());
+                }
+                for item_entity in items_to_repair {
+                    let durability = self.components.durability.get_mut(item_entity).unwrap();
+                    durability.current = durability.max;
+                }
                 inventory.remove(inventory_index).unwrap();
-                message_log.push(LogMessage::PlayerHeals);
+                self.remove_entity(item);
+                events.push(GameEvent::Message(LogMessage::PlayerRepairs));
                 ItemUsage::Immediate
             }
-            ItemType::FireballScroll | ItemType::ConfusionScroll => ItemUsage::Aim,
-            ItemType::Sword | ItemType::Staff => {
+            ItemType::Sword
+            | ItemType::FireSword
+            | ItemType::CursedBlade
+            | ItemType::Staff
+            | ItemType::Bow => {
+                if let Some(&held_index) = self
+                    .components
+                    .equipment_held_inventory_index
+                    .get(character)
+                {
+                    self.check_not_stuck(character, held_index, events)?;
+                }
                 self.components
                     .equipment_held_inventory_index
                     .insert(character, inventory_index);
-                message_log.push(LogMessage::PlayerEquips(item_type));
+                events.push(GameEvent::Message(LogMessage::PlayerEquips(item_type)));
+                self.reveal_curse(item, events);
                 ItemUsage::Immediate
             }
             ItemType::Armour | ItemType::Robe => {
+                if let Some(&worn_index) = self
+                    .components
+                    .equipment_worn_inventory_index
+                    .get(character)
+                {
+                    self.check_not_stuck(character, worn_index, events)?;
+                }
                 self.components
                     .equipment_worn_inventory_index
                     .insert(character, inventory_index);
-                message_log.push(LogMessage::PlayerEquips(item_type));
+                events.push(GameEvent::Message(LogMessage::PlayerEquips(item_type)));
+                self.reveal_curse(item, events);
                 ItemUsage::Immediate
             }
         };
@@ -662,7 +1787,7 @@ impl World {
         character: Entity,
         inventory_index: usize,
         target: Coord,
-        message_log: &mut Vec<LogMessage>,
+        events: &mut Vec<GameEvent>,
     ) -> Result<(), ()> {
         let character_coord = self.spatial_table.coord_of(character).unwrap();
         if character_coord == target {
@@ -675,43 +1800,430 @@ impl World {
             .expect("character has no inventory");
         let item_entity = inventory.remove(inventory_index).unwrap();
         let &item_type = self.components.item.get(item_entity).unwrap();
+        // Cursed scrolls send their spell back at the reader
+        let (from, to) = if self.item_buc(item_entity) == Buc::Cursed {
+            events.push(GameEvent::Message(LogMessage::ScrollBackfires));
+            (target, character_coord)
+        } else {
+            (character_coord, target)
+        };
         match item_type {
             ItemType::HealthPotion
+            | ItemType::InvisibilityPotion
+            | ItemType::HastePotion
+            | ItemType::RegenerationPotion
+            | ItemType::SummonScroll
             | ItemType::Sword
+            | ItemType::FireSword
+            | ItemType::CursedBlade
             | ItemType::Staff
             | ItemType::Armour
-            | ItemType::Robe => panic!("invalid item for aim"),
+            | ItemType::Robe
+            | ItemType::Bow
+            | ItemType::Arrows
+            | ItemType::RepairKit => panic!("invalid item for aim"),
             ItemType::FireballScroll => {
                 let fireball = ProjectileType::Fireball {
                     damage: self.magic(character).max(0) as u32,
                 };
-                message_log.push(LogMessage::PlayerLaunchesProjectile(fireball));
-                self.spawn_projectile(character_coord, target, fireball);
+                events.push(GameEvent::Message(LogMessage::PlayerLaunchesProjectile(
+                    fireball,
+                )));
+                self.spawn_projectile(from, to, fireball);
             }
             ItemType::ConfusionScroll => {
                 let confusion = ProjectileType::Confusion {
                     duration: self.magic(character).max(0) as u32 * 3,
                 };
-                message_log.push(LogMessage::PlayerLaunchesProjectile(confusion));
-                self.spawn_projectile(character_coord, target, confusion);
+                events.push(GameEvent::Message(LogMessage::PlayerLaunchesProjectile(
+                    confusion,
+                )));
+                self.spawn_projectile(from, to, confusion);
+            }
+            ItemType::SlowScroll => {
+                // Even without any magic, the spell slows its target for a turn
+                let slow = ProjectileType::Slow {
+                    duration: (self.magic(character).max(0) as u32 * 3).max(1),
+                };
+                events.push(GameEvent::Message(LogMessage::PlayerLaunchesProjectile(
+                    slow,
+                )));
+                self.spawn_projectile(from, to, slow);
+            }
+        }
+        self.remove_entity(item_entity);
+        Ok(())
+    }
+    pub fn item_buc(&self, entity: Entity) -> Buc {
+        self.components
+            .buc
+            .get(entity)
+            .cloned()
+            .unwrap_or(Buc::Uncursed)
+    }
+    // Returns the item's blessed/uncursed/cursed status if the player has discovered it
+    pub fn known_item_buc(&self, entity: Entity) -> Option<Buc> {
+        if self.components.buc_known.contains(entity) {
+            Some(self.item_buc(entity))
+        } else {
+            None
+        }
+    }
+    // Cursed items are discovered by equipping them
+    fn reveal_curse(&mut self, item: Entity, events: &mut Vec<GameEvent>) {
+        if self.item_buc(item) == Buc::Cursed {
+            self.components.buc_known.insert(item, ());
+            events.push(GameEvent::Message(LogMessage::ItemIsCursed(
+                self.components.item.get(item).cloned().unwrap(),
+            )));
+        }
+    }
+    // Cursed equipment can't be removed once it's equipped
+    fn check_not_stuck(
+        &self,
+        character: Entity,
+        inventory_index: usize,
+        events: &mut Vec<GameEvent>,
+    ) -> Result<(), ()> {
+        let item = match self
+            .components
+            .inventory
+            .get(character)
+            .and_then(|inventory| inventory.get(inventory_index).ok())
+        {
+            Some(item) => item,
+            None => return Ok(()),
+        };
+        if self.item_buc(item) == Buc::Cursed {
+            events.push(GameEvent::Message(LogMessage::CursedItemIsStuck(
+                self.components.item.get(item).cloned().unwrap(),
+            )));
+            return Err(());
+        }
+        Ok(())
+    }
+    fn held_item_type(&self, entity: Entity) -> Option<ItemType> {
+        let &held_index = self.components.equipment_held_inventory_index.get(entity)?;
+        self.inventory_item_type(entity, held_index)
+    }
+    // Returns the index of the inventory slot containing the character's ammo, if any
+    fn inventory_ammo_index(&self, entity: Entity) -> Option<usize> {
+        self.components
+            .inventory
+            .get(entity)?
+            .slots()
+            .iter()
+            .position(|slot| slot.map_or(false, |item| self.components.ammo.contains(item)))
+    }
+    fn inventory_ammo_stack(&self, entity: Entity) -> Option<Entity> {
+        let index = self.inventory_ammo_index(entity)?;
+        self.components.inventory.get(entity)?.get(index).ok()
+    }
+    // Returns the number of arrows the character can fire, or `None` if they aren't holding a bow
+    pub fn ranged_ammo(&self, entity: Entity) -> Option<u32> {
+        if self.held_item_type(entity) != Some(ItemType::Bow) {
+            return None;
+        }
+        Some(
+            self.inventory_ammo_stack(entity)
+                .and_then(|stack_entity| self.components.ammo.get(stack_entity).cloned())
+                .unwrap_or(0),
+        )
+    }
+    pub fn maybe_fire(
+        &mut self,
+        character: Entity,
+        target: Coord,
+        events: &mut Vec<GameEvent>,
+    ) -> Result<(), ()> {
+        let character_coord = self.spatial_table.coord_of(character).unwrap();
+        if character_coord == target {
+            return Err(());
+        }
+        if self.held_item_type(character) != Some(ItemType::Bow) {
+            events.push(GameEvent::Message(LogMessage::NoRangedWeaponHeld));
+            return Err(());
+        }
+        let ammo_index = match self.inventory_ammo_index(character) {
+            Some(ammo_index) => ammo_index,
+            None => {
+                events.push(GameEvent::Message(LogMessage::NoAmmo));
+                return Err(());
             }
+        };
+        let inventory = self
+            .components
+            .inventory
+            .get_mut(character)
+            .expect("character has no inventory");
+        let stack_entity = inventory.get(ammo_index).unwrap();
+        let ammo = self.components.ammo.get_mut(stack_entity).unwrap();
+        *ammo -= 1;
+        if *ammo == 0 {
+            inventory.remove(ammo_index).unwrap();
+            self.remove_entity(stack_entity);
         }
+        let &held_index = self
+            .components
+            .equipment_held_inventory_index
+            .get(character)
+            .unwrap();
+        let arrow = ProjectileType::Arrow {
+            damage: (self
+                .components
+                .dexterity
+                .get(character)
+                .cloned()
+                .unwrap_or(0)
+                + 1
+                + self.inventory_item_enchantment(character, held_index))
+            .max(0) as u32,
+        };
+        events.push(GameEvent::Message(LogMessage::PlayerFires(arrow)));
+        self.spawn_projectile(character_coord, target, arrow);
+        self.wear_item(character, held_index, events);
         Ok(())
     }
+    // An arrow which misses lands on the floor where it can be picked up again. It joins any arrows
+    // already there, and if there's a different object in the way it lands in the nearest empty
+    // cell instead.
+    fn land_arrow<R: Rng>(&mut self, coord: Coord, rng: &mut R) {
+        let coord = match self.spatial_table.layers_at_checked(coord).object {
+            None => coord,
+            Some(object_entity) => {
+                if let Some(ammo) = self.components.ammo.get_mut(object_entity) {
+                    *ammo += 1;
+                    return;
+                }
+                match self.nearest_empty_floor_coord(coord, rng) {
+                    Some(coord) => coord,
+                    None => return,
+                }
+            }
+        };
+        let entity = self.spawn_item(coord, ItemType::Arrows);
+        self.components.ammo.insert(entity, 1);
+    }
+    // Unlike spells, arrows are stopped by armour. One which doesn't get through drops to the
+    // floor.
+    fn arrow_hit<R: Rng>(
+        &mut self,
+        victim: Entity,
+        damage: u32,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) {
+        let victim_npc = self.npc(victim);
+        let coord = self.spatial_table.coord_of(victim).unwrap();
+        let damage_reduction = self.defense_modifier(victim).max(0) as u32;
+        // Defending characters are twice as good at avoiding damage
+        let damage_reduction = if self.components.defending.contains(victim) {
+            damage_reduction * 2
+        } else {
+            damage_reduction
+        };
+        let net_damage = damage.saturating_sub(damage_reduction);
+        if net_damage == 0 {
+            if let Some(npc) = victim_npc {
+                events.push(GameEvent::Message(LogMessage::ArrowGlancesOff(npc)));
+            }
+            self.land_arrow(coord, rng);
+            return;
+        }
+        if let Some(VictimDies) = self.character_damage(victim, None, net_damage, events, rng) {
+            if let Some(npc) = victim_npc {
+                events.push(GameEvent::NpcDies(npc));
+            }
+        }
+    }
+    fn equipped_item_entity(&self, entity: Entity) -> Option<Entity> {
+        let inventory = self.components.inventory.get(entity)?;
+        self.components
+            .equipment_held_inventory_index
+            .get(entity)
+            .or_else(|| self.components.equipment_worn_inventory_index.get(entity))
+            .and_then(|&index| inventory.get(index).ok())
+    }
+    pub fn maybe_pray<R: Rng>(
+        &mut self,
+        character: Entity,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) -> Result<PrayerOutcome, ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let floor_entity = self.spatial_table.layers_at_checked(coord).floor;
+        let altar = match floor_entity.and_then(|entity| self.components.altar.get_mut(entity)) {
+            Some(altar) => altar,
+            None => {
+                events.push(GameEvent::Message(LogMessage::NoAltarUnderPlayer));
+                return Err(());
+            }
+        };
+        if altar.prayed_at {
+            events.push(GameEvent::Message(LogMessage::AltarIsSilent));
+            return Err(());
+        }
+        altar.prayed_at = true;
+        const BLESSING_PERCENT_CHANCE: u32 = 60;
+        let outcome = if rng.gen_range(0..100) < BLESSING_PERCENT_CHANCE {
+            match rng.gen_range(0..3) {
+                0 => PrayerOutcome::Heal,
+                1 => match self.equipped_item_entity(character) {
+                    Some(item_entity) => {
+                        let enchantment = self
+                            .components
+                            .enchantment
+                            .get(item_entity)
+                            .cloned()
+                            .unwrap_or(0);
+                        self.components
+                            .enchantment
+                            .insert(item_entity, enchantment + 1);
+                        PrayerOutcome::Enchant(*self.components.item.get(item_entity).unwrap())
+                    }
+                    // With nothing to enchant, the blessing takes the form of healing instead
+                    None => PrayerOutcome::Heal,
+                },
+                _ => PrayerOutcome::RevealMap,
+            }
+        } else {
+            match rng.gen_range(0..3) {
+                0 => PrayerOutcome::Smite,
+                1 => PrayerOutcome::Summon,
+                _ => PrayerOutcome::Weaken,
+            }
+        };
+        match outcome {
+            PrayerOutcome::Heal => {
+                let hit_points = self
+                    .components
+                    .hit_points
+                    .get_mut(character)
+                    .expect("character has no hit points");
+                hit_points.current = hit_points.max;
+                events.push(GameEvent::Message(LogMessage::AltarHeals));
+            }
+            PrayerOutcome::Enchant(item_type) => {
+                events.push(GameEvent::Message(LogMessage::AltarEnchants(item_type)));
+            }
+            PrayerOutcome::RevealMap => {
+                events.push(GameEvent::Message(LogMessage::AltarRevealsMap))
+            }
+            PrayerOutcome::Smite => {
+                let hit_points = self
+                    .components
+                    .hit_points
+                    .get_mut(character)
+                    .expect("character has no hit points");
+                // Smiting hurts, but is never fatal
+                let damage = (hit_points.max / 4).max(1);
+                hit_points.current = hit_points.current.saturating_sub(damage).max(1);
+                events.push(GameEvent::Message(LogMessage::AltarSmites));
+            }
+            PrayerOutcome::Summon => events.push(GameEvent::Message(LogMessage::AltarSummons)),
+            PrayerOutcome::Weaken => {
+                let strength = self
+                    .components
+                    .strength
+                    .get_mut(character)
+                    .expect("character lacks strength");
+                *strength = (*strength - 1).max(0);
+                events.push(GameEvent::Message(LogMessage::AltarWeakens));
+            }
+        }
+        Ok(outcome)
+    }
+    pub fn maybe_drink<R: Rng>(
+        &mut self,
+        character: Entity,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) -> Result<FountainOutcome, ()> {
+        let coord = self
+            .spatial_table
+            .coord_of(character)
+            .expect("character has no coord");
+        let fountain_entity = match self
+            .spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .filter(|&entity| self.components.fountain.contains(entity))
+        {
+            Some(fountain_entity) => fountain_entity,
+            None => {
+                events.push(GameEvent::Message(LogMessage::NoFountainUnderPlayer));
+                return Err(());
+            }
+        };
+        const HEAL_PERCENT_CHANCE: u32 = 50;
+        const REVEAL_MAP_PERCENT_CHANCE: u32 = 20;
+        let roll = rng.gen_range(0..100);
+        let outcome = if roll < HEAL_PERCENT_CHANCE {
+            FountainOutcome::Heal
+        } else if roll < HEAL_PERCENT_CHANCE + REVEAL_MAP_PERCENT_CHANCE {
+            FountainOutcome::RevealMap
+        } else {
+            FountainOutcome::Poison
+        };
+        match outcome {
+            FountainOutcome::Heal => {
+                let hit_points = self
+                    .components
+                    .hit_points
+                    .get_mut(character)
+                    .expect("character has no hit points");
+                hit_points.current = hit_points.max.min(hit_points.current + hit_points.max / 2);
+                events.push(GameEvent::Message(LogMessage::FountainHeals));
+            }
+            FountainOutcome::Poison => {
+                const POISON_DURATION: u32 = 8;
+                self.components
+                    .poison_countdown
+                    .insert(character, POISON_DURATION);
+                events.push(GameEvent::Message(LogMessage::FountainPoisons));
+            }
+            FountainOutcome::RevealMap => {
+                events.push(GameEvent::Message(LogMessage::FountainRevealsMap))
+            }
+        }
+        let fountain = self.components.fountain.get_mut(fountain_entity).unwrap();
+        fountain.uses_remaining = fountain.uses_remaining.saturating_sub(1);
+        if fountain.uses_remaining == 0 {
+            self.dry_up_fountain(fountain_entity);
+            events.push(GameEvent::Message(LogMessage::FountainDriesUp));
+        }
+        Ok(outcome)
+    }
     pub fn maybe_drop_item(
         &mut self,
         character: Entity,
         inventory_index: usize,
-        message_log: &mut Vec<LogMessage>,
+        events: &mut Vec<GameEvent>,
     ) -> Result<(), ()> {
         let coord = self
             .spatial_table
             .coord_of(character)
             .expect("character has no coord");
         if self.spatial_table.layers_at_checked(coord).object.is_some() {
-            message_log.push(LogMessage::NoSpaceToDropItem);
+            events.push(GameEvent::Message(LogMessage::NoSpaceToDropItem));
             return Err(());
         }
+        let equipped = self
+            .components
+            .equipment_held_inventory_index
+            .get(character)
+            == Some(&inventory_index)
+            || self
+                .components
+                .equipment_worn_inventory_index
+                .get(character)
+                == Some(&inventory_index);
+        if equipped {
+            self.check_not_stuck(character, inventory_index, events)?;
+        }
         let inventory = self
             .components
             .inventory
@@ -720,10 +2232,11 @@ impl World {
         let item = match inventory.remove(inventory_index) {
             Ok(item) => item,
             Err(InventorySlotIsEmpty) => {
-                message_log.push(LogMessage::NoItemInInventorySlot);
+                events.push(GameEvent::Message(LogMessage::NoItemInInventorySlot));
                 return Err(());
             }
         };
+        self.components.hotbar_slot.remove(item);
         self.spatial_table
             .update(
                 item,
@@ -749,77 +2262,647 @@ impl World {
                 .equipment_held_inventory_index
                 .remove(character);
         }
-        if self
-            .components
-            .equipment_worn_inventory_index
-            .get(character)
-            .cloned()
-            == Some(inventory_index)
-        {
-            self.components
-                .equipment_worn_inventory_index
-                .remove(character);
+        if self
+            .components
+            .equipment_worn_inventory_index
+            .get(character)
+            .cloned()
+            == Some(inventory_index)
+        {
+            self.components
+                .equipment_worn_inventory_index
+                .remove(character);
+        }
+        events.push(GameEvent::Message(LogMessage::PlayerDrops(item_type)));
+        // Items dropped on an altar reveal whether they are blessed or cursed
+        let on_altar = self
+            .spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map_or(false, |floor_entity| {
+                self.components.altar.contains(floor_entity)
+            });
+        if on_altar {
+            self.components.buc_known.insert(item, ());
+            events.push(GameEvent::Message(LogMessage::ItemBucRevealed(
+                item_type,
+                self.item_buc(item),
+            )));
+        }
+        Ok(())
+    }
+    pub fn move_projectiles<R: Rng>(&mut self, events: &mut Vec<GameEvent>, rng: &mut R) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        let Scratch {
+            entities: entities_to_remove,
+            damaged: fireball_hit,
+            shot: arrow_hit,
+            confused: confusion_hit,
+            slowed: slow_hit,
+            coords: arrows_landed,
+            fountains: fountains_evaporated,
+            ..
+        } = &mut scratch;
+        for (entity, trajectory) in self.components.trajectory.iter_mut() {
+            let current_coord = self.spatial_table.coord_of(entity).unwrap();
+            let is_arrow = matches!(
+                self.components.projectile.get(entity),
+                Some(ProjectileType::Arrow { .. })
+            );
+            if let Some(direction) = trajectory.next() {
+                let new_coord = current_coord + direction.coord();
+                let dest_layers = self.spatial_table.layers_at_checked(new_coord);
+                // Fireballs evaporate fountains they pass over
+                let is_fireball = matches!(
+                    self.components.projectile.get(entity),
+                    Some(ProjectileType::Fireball { .. })
+                );
+                if let Some(floor_entity) = dest_layers.floor {
+                    if is_fireball && self.components.fountain.contains(floor_entity) {
+                        fountains_evaporated.push(floor_entity);
+                    }
+                }
+                if dest_layers.feature.is_some() {
+                    entities_to_remove.push(entity);
+                    if is_arrow {
+                        arrows_landed.push(current_coord);
+                    }
+                } else if let Some(character) = dest_layers.character {
+                    entities_to_remove.push(entity);
+                    if let Some(&projectile_type) = self.components.projectile.get(entity) {
+                        match projectile_type {
+                            ProjectileType::Fireball { damage } => {
+                                fireball_hit.push((character, damage));
+                            }
+                            ProjectileType::Arrow { damage } => {
+                                arrow_hit.push((character, damage));
+                            }
+                            ProjectileType::Confusion { duration } => {
+                                confusion_hit.push((character, duration));
+                            }
+                            ProjectileType::Slow { duration } => {
+                                slow_hit.push((character, duration));
+                            }
+                        }
+                    }
+                }
+
+                // ignore collisiosns of projectiles
+                let _ = self.spatial_table.update_coord(entity, new_coord);
+            } else {
+                entities_to_remove.push(entity);
+                if is_arrow {
+                    arrows_landed.push(current_coord);
+                }
+            }
+        }
+        for entity in entities_to_remove.drain(..) {
+            self.remove_entity(entity);
+        }
+        for coord in arrows_landed.drain(..) {
+            self.land_arrow(coord, rng);
+        }
+        for entity in fountains_evaporated.drain(..) {
+            if self.components.fountain.contains(entity) {
+                self.dry_up_fountain(entity);
+                events.push(GameEvent::Message(LogMessage::FountainEvaporates));
+            }
+        }
+        for (entity, damage) in fireball_hit.drain(..) {
+            let maybe_npc = self.npc(entity);
+            if let Some(VictimDies) = self.character_damage(entity, None, damage, events, rng) {
+                if let Some(npc) = maybe_npc {
+                    events.push(GameEvent::NpcDies(npc));
+                }
+            }
+        }
+        for (entity, damage) in arrow_hit.drain(..) {
+            self.arrow_hit(entity, damage, events, rng);
+        }
+        for (entity, duration) in confusion_hit.drain(..) {
+            self.components.confusion_countdown.insert(entity, duration);
+            if let Some(npc) = self.npc(entity) {
+                events.push(GameEvent::Message(LogMessage::NpcBecomesConfused(npc)));
+            }
+        }
+        for (entity, duration) in slow_hit.drain(..) {
+            self.apply_speed_effect(entity, SpeedEffect::Slow, duration, events);
+        }
+        self.scratch = scratch;
+    }
+    // Haste and slow cancel each other out, so applying one to a character affected by the other
+    // returns them to normal speed
+    fn apply_speed_effect(
+        &mut self,
+        entity: Entity,
+        effect: SpeedEffect,
+        duration: u32,
+        events: &mut Vec<GameEvent>,
+    ) {
+        let npc = self.npc(entity);
+        let cancels_existing = self
+            .components
+            .speed_status
+            .get(entity)
+            .map_or(false, |status| status.effect != effect);
+        if cancels_existing {
+            self.components.speed_status.remove(entity);
+            events.push(GameEvent::Message(match npc {
+                Some(npc) => LogMessage::NpcSpeedReturnsToNormal(npc),
+                None => LogMessage::PlayerSpeedReturnsToNormal,
+            }));
+        } else {
+            self.components.speed_status.insert(
+                entity,
+                SpeedStatus {
+                    effect,
+                    turns_remaining: duration,
+                },
+            );
+            events.push(GameEvent::Message(match npc {
+                Some(npc) => LogMessage::NpcSpeedChanges(npc, effect),
+                None => LogMessage::PlayerSpeedChanges(effect),
+            }));
+        }
+    }
+    fn tick_speed_effects(&mut self, events: &mut Vec<GameEvent>) {
+        let mut expired = Vec::new();
+        for (entity, status) in self.components.speed_status.iter_mut() {
+            status.turns_remaining = status.turns_remaining.saturating_sub(1);
+            if status.turns_remaining == 0 {
+                expired.push(entity);
+            }
+        }
+        for entity in expired {
+            self.components.speed_status.remove(entity);
+            if !self.is_living_character(entity) {
+                continue;
+            }
+            events.push(GameEvent::Message(match self.npc(entity) {
+                Some(npc) => LogMessage::NpcSpeedReturnsToNormal(npc),
+                None => LogMessage::PlayerSpeedReturnsToNormal,
+            }));
+        }
+    }
+    pub fn speed_status(&self, entity: Entity) -> Option<SpeedStatus> {
+        self.components.speed_status.get(entity).cloned()
+    }
+    // The number of times an npc acts on the given npc turn
+    pub fn num_actions(&self, entity: Entity, npc_turn_count: u64) -> u32 {
+        match self.speed_status(entity).map(|status| status.effect) {
+            Some(SpeedEffect::Haste) => 2,
+            Some(SpeedEffect::Slow) => (npc_turn_count % 2 == 0) as u32,
+            None => 1,
+        }
+    }
+    pub fn has_projectiles(&self) -> bool {
+        !self.components.trajectory.is_empty()
+    }
+    pub fn fade_effects(&mut self) {
+        let mut faded = std::mem::take(&mut self.scratch.entities);
+        for (entity, countdown) in self.components.fade_countdown.iter_mut() {
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == 0 {
+                faded.push(entity);
+            }
+        }
+        for entity in faded.drain(..) {
+            self.remove_entity(entity);
+        }
+        self.scratch.entities = faded;
+    }
+    pub fn start_lunge(&mut self, attacker: Entity, victim: Entity) {
+        let (attacker_coord, victim_coord) =
+            match (self.entity_coord(attacker), self.entity_coord(victim)) {
+                (Some(attacker_coord), Some(victim_coord)) => (attacker_coord, victim_coord),
+                _ => return,
+            };
+        let delta = victim_coord - attacker_coord;
+        let direction = match (delta.x.signum(), delta.y.signum()) {
+            (0, -1) => CardinalDirection::North,
+            (1, 0) => CardinalDirection::East,
+            (0, 1) => CardinalDirection::South,
+            (-1, 0) => CardinalDirection::West,
+            _ => return,
+        };
+        self.lunges.retain(|lunge| lunge.entity != attacker);
+        self.lunges.push(Lunge {
+            entity: attacker,
+            direction,
+            frames_remaining: LUNGE_FRAMES,
+        });
+    }
+    // Like damage numbers, lunges don't hold up the player
+    pub fn advance_lunges(&mut self) {
+        for lunge in self.lunges.iter_mut() {
+            lunge.frames_remaining = lunge.frames_remaining.saturating_sub(1);
+        }
+        self.lunges.retain(|lunge| lunge.frames_remaining > 0);
+    }
+    // Leaves a trail at `from` if `entity` has moved away from it
+    pub fn leave_motion_trail(&mut self, entity: Entity, from: Coord) {
+        if self.entity_coord(entity) == Some(from) {
+            return;
+        }
+        if let Some(&tile) = self.components.tile.get(entity) {
+            self.motion_trails.push(MotionTrail {
+                coord: from,
+                tile,
+                frames_remaining: MOTION_TRAIL_FRAMES,
+            });
+        }
+    }
+    // Like lunges, motion trails don't hold up the player
+    pub fn advance_motion_trails(&mut self) {
+        for motion_trail in self.motion_trails.iter_mut() {
+            motion_trail.frames_remaining = motion_trail.frames_remaining.saturating_sub(1);
+        }
+        self.motion_trails
+            .retain(|motion_trail| motion_trail.frames_remaining > 0);
+    }
+    pub fn motion_trails(&self) -> impl '_ + Iterator<Item = &MotionTrail> {
+        self.motion_trails.iter()
+    }
+    // The direction `entity` is lunging in, if it attacked something in the last few frames
+    pub fn lunge_direction(&self, entity: Entity) -> Option<CardinalDirection> {
+        self.lunges
+            .iter()
+            .find(|lunge| lunge.entity == entity)
+            .map(|lunge| lunge.direction)
+    }
+    // Damage numbers rise a cell every few frames. They don't count as fading effects, so the
+    // player can keep acting while they float away.
+    pub fn float_damage_numbers(&mut self) {
+        const FRAMES_PER_CELL: u32 = 5;
+        let mut finished = std::mem::take(&mut self.scratch.entities);
+        let mut rising = std::mem::take(&mut self.scratch.rising);
+        for (entity, countdown) in self.components.float_countdown.iter_mut() {
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == 0 {
+                finished.push(entity);
+            } else {
+                if *countdown % FRAMES_PER_CELL == 0 {
+                    rising.push(entity);
+                }
+                if let Some(Tile::DamageNumber {
+                    frames_remaining, ..
+                }) = self.components.tile.get_mut(entity)
+                {
+                    *frames_remaining = *countdown;
+                }
+            }
+        }
+        for entity in rising.drain(..) {
+            let coord = self.spatial_table.coord_of(entity).unwrap();
+            let above = coord - Coord::new(0, 1);
+            if above.is_valid(self.size()) {
+                self.spatial_table.update_coord(entity, above).unwrap();
+            }
+        }
+        for entity in finished.drain(..) {
+            self.remove_entity(entity);
+        }
+        self.scratch.entities = finished;
+        self.scratch.rising = rising;
+    }
+    // Damage numbers, dash trails, lunges and motion trails, which change how the map looks each
+    // animation tick but don't hold up the game
+    pub fn has_cosmetic_animations(&self) -> bool {
+        !self.components.float_countdown.is_empty()
+            || !self.components.fade_countdown.is_empty()
+            || !self.lunges.is_empty()
+            || !self.motion_trails.is_empty()
+    }
+    // Dash up to a few cells in a straight line, attacking the first hostile character in the way
+    // with bonus damage for each cell travelled
+    pub fn maybe_charge<R: Rng>(
+        &mut self,
+        character: Entity,
+        direction: CardinalDirection,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) -> Result<(), ()> {
+        const MAX_CHARGE_DISTANCE: u32 = 3;
+        const CHARGE_COOLDOWN: u32 = 10;
+        if let Some(&cooldown) = self.components.charge_cooldown.get(character) {
+            events.push(GameEvent::Message(LogMessage::ChargeNotReady(cooldown)));
+            return Err(());
+        }
+        let faction = self.faction(character);
+        let start_coord = self.spatial_table.coord_of(character).unwrap();
+        let mut coord = start_coord;
+        let mut distance = 0;
+        let mut target = None;
+        while distance < MAX_CHARGE_DISTANCE {
+            let next_coord = coord + direction.coord();
+            let layers = match self.spatial_table.layers_at(next_coord) {
+                Some(layers) => layers,
+                None => break,
+            };
+            if let Some(character_entity) = layers.character {
+                if self.faction(character_entity) != faction {
+                    target = Some(character_entity);
+                }
+                break;
+            }
+            if layers.feature.is_some() {
+                break;
+            }
+            coord = next_coord;
+            distance += 1;
+        }
+        if distance == 0 && target.is_none() {
+            events.push(GameEvent::Message(LogMessage::NoRoomToCharge));
+            return Err(());
+        }
+        self.spatial_table.update_coord(character, coord).unwrap();
+        // Cells further along the path fade later, so the trail appears to follow the character
+        for step in 0..distance {
+            let trail_coord = start_coord + direction.coord() * step as i32;
+            self.spawn_dash_trail(trail_coord, step + 1);
+        }
+        events.push(GameEvent::Message(LogMessage::PlayerCharges));
+        if let Some(target) = target {
+            self.character_bump_attack(target, character, distance as i32, events, rng);
+        }
+        self.components
+            .charge_cooldown
+            .insert(character, CHARGE_COOLDOWN);
+        Ok(())
+    }
+    // Kicked characters are pushed back a cell if there's space behind them. There's nothing to
+    // break yet, so kicking anything solid just hurts.
+    pub fn kick(
+        &mut self,
+        character: Entity,
+        direction: CardinalDirection,
+        events: &mut Vec<GameEvent>,
+    ) {
+        let target_coord = self.spatial_table.coord_of(character).unwrap() + direction.coord();
+        let layers = match self.spatial_table.layers_at(target_coord) {
+            Some(layers) => layers,
+            None => {
+                events.push(GameEvent::Message(LogMessage::PlayerKicksNothing));
+                return;
+            }
+        };
+        if let Some(victim) = layers.character {
+            let npc = match self.npc(victim) {
+                Some(npc) => npc,
+                None => return,
+            };
+            let dest_coord = target_coord + direction.coord();
+            let dest_is_empty = self
+                .spatial_table
+                .layers_at(dest_coord)
+                .map_or(false, |layers| {
+                    layers.character.is_none() && layers.feature.is_none()
+                });
+            if dest_is_empty {
+                self.spatial_table.update_coord(victim, dest_coord).unwrap();
+                events.push(GameEvent::Message(LogMessage::PlayerKicksNpc(npc)));
+            } else {
+                events.push(GameEvent::Message(LogMessage::NpcDoesNotBudge(npc)));
+            }
+        } else if layers.feature.is_some() {
+            // Like smiting, kicking a wall hurts but is never fatal
+            if let Some(hit_points) = self.components.hit_points.get_mut(character) {
+                hit_points.current = hit_points.current.saturating_sub(1).max(1);
+            }
+            events.push(GameEvent::Message(LogMessage::PlayerKicksWall));
+        } else {
+            events.push(GameEvent::Message(LogMessage::PlayerKicksNothing));
+        }
+    }
+    fn tick_charge_cooldown(&mut self) {
+        let mut ready = Vec::new();
+        for (entity, cooldown) in self.components.charge_cooldown.iter_mut() {
+            *cooldown = cooldown.saturating_sub(1);
+            if *cooldown == 0 {
+                ready.push(entity);
+            }
+        }
+        for entity in ready {
+            self.components.charge_cooldown.remove(entity);
+        }
+    }
+    pub fn inventory(&self, entity: Entity) -> Option<&Inventory> {
+        self.components.inventory.get(entity)
+    }
+    pub fn item_type(&self, entity: Entity) -> Option<ItemType> {
+        self.components.item.get(entity).cloned()
+    }
+    pub fn item_enchantment(&self, entity: Entity) -> i32 {
+        self.components
+            .enchantment
+            .get(entity)
+            .cloned()
+            .unwrap_or(0)
+    }
+    pub fn item_durability(&self, entity: Entity) -> Option<Durability> {
+        self.components.durability.get(entity).cloned()
+    }
+    // Includes items buried under corpses, since they can be got by searching the corpse
+    pub fn item_at(&self, coord: Coord) -> Option<ItemType> {
+        let object_entity = self.spatial_table.layers_at(coord)?.object?;
+        let item_entity = self
+            .components
+            .buried_item
+            .get(object_entity)
+            .cloned()
+            .unwrap_or(object_entity);
+        self.item_type(item_entity)
+    }
+    pub fn npc(&self, entity: Entity) -> Option<Npc> {
+        let &npc_type = self.components.npc_type.get(entity)?;
+        Some(Npc {
+            npc_type,
+            elite: self.components.elite.contains(entity),
+        })
+    }
+    pub fn faction(&self, entity: Entity) -> Option<Faction> {
+        self.components.faction.get(entity).cloned()
+    }
+    // Returns the coordinates of all living characters which aren't in the given faction
+    pub fn hostile_character_coords<'a>(
+        &'a self,
+        faction: Faction,
+    ) -> impl 'a + Iterator<Item = Coord> {
+        self.components
+            .faction
+            .iter()
+            .filter(move |&(entity, &other_faction)| {
+                other_faction != faction && self.is_living_character(entity)
+            })
+            .filter_map(move |(entity, _)| self.spatial_table.coord_of(entity))
+    }
+    pub fn is_hostile_character_at(&self, coord: Coord, faction: Faction) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.character)
+            .and_then(|entity| self.faction(entity))
+            .map_or(false, |other_faction| other_faction != faction)
+    }
+    fn can_summon_at(&self, coord: Coord) -> bool {
+        self.spatial_table.layers_at(coord).map_or(false, |layers| {
+            layers.character.is_none() && layers.feature.is_none()
+        })
+    }
+    // Counts down the remaining turns of each summoned ally, removing those whose time is up
+    pub fn tick_summons(&mut self, events: &mut Vec<GameEvent>) {
+        let mut expired = Vec::new();
+        for (entity, countdown) in self.components.summon_countdown.iter_mut() {
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == 0 {
+                expired.push(entity);
+            }
+        }
+        for entity in expired {
+            // the corpses of summoned allies vanish too, but silently
+            if self.is_living_character(entity) {
+                if let Some(npc) = self.npc(entity) {
+                    events.push(GameEvent::Message(LogMessage::AllyVanishes(npc)));
+                }
+            }
+            let coord = self.spatial_table.coord_of(entity);
+            let buried_item = self.components.buried_item.get(entity).cloned();
+            self.remove_entity(entity);
+            // an item buried under a vanishing corpse is left where the corpse was
+            if let (Some(coord), Some(buried_item)) = (coord, buried_item) {
+                let location = Location {
+                    coord,
+                    layer: Some(Layer::Object),
+                };
+                if self.spatial_table.update(buried_item, location).is_err() {
+                    self.remove_entity(buried_item);
+                }
+            }
+        }
+    }
+    // Counts down the remaining turns of invisibility, warning the player shortly before it ends.
+    // Only the player can become invisible.
+    fn tick_invisibility(&mut self, events: &mut Vec<GameEvent>) {
+        const WARNING_TURNS: u32 = 3;
+        let mut expired = Vec::new();
+        for (entity, countdown) in self.components.invisibility_countdown.iter_mut() {
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == WARNING_TURNS {
+                events.push(GameEvent::Message(LogMessage::PlayerInvisibilityFading));
+            } else if *countdown == 0 {
+                expired.push(entity);
+            }
+        }
+        for entity in expired {
+            self.components.invisibility_countdown.remove(entity);
+            events.push(GameEvent::Message(LogMessage::PlayerIsVisible));
         }
-        message_log.push(LogMessage::PlayerDrops(item_type));
-        Ok(())
     }
-    pub fn move_projectiles(&mut self, message_log: &mut Vec<LogMessage>) {
-        let mut entities_to_remove = Vec::new();
-        let mut fireball_hit = Vec::new();
-        let mut confusion_hit = Vec::new();
-        for (entity, trajectory) in self.components.trajectory.iter_mut() {
-            if let Some(direction) = trajectory.next() {
-                let current_coord = self.spatial_table.coord_of(entity).unwrap();
-                let new_coord = current_coord + direction.coord();
-                let dest_layers = self.spatial_table.layers_at_checked(new_coord);
-                if dest_layers.feature.is_some() {
-                    entities_to_remove.push(entity);
-                } else if let Some(character) = dest_layers.character {
-                    entities_to_remove.push(entity);
-                    if let Some(&projectile_type) = self.components.projectile.get(entity) {
-                        match projectile_type {
-                            ProjectileType::Fireball { damage } => {
-                                fireball_hit.push((character, damage));
-                            }
-                            ProjectileType::Confusion { duration } => {
-                                confusion_hit.push((character, duration));
-                            }
-                        }
-                    }
+    // Heals each regenerating character by a hit point. Only the player can regenerate.
+    fn tick_regeneration(&mut self, events: &mut Vec<GameEvent>) {
+        let mut expired = Vec::new();
+        for (entity, countdown) in self.components.regeneration_countdown.iter_mut() {
+            if let Some(hit_points) = self.components.hit_points.get_mut(entity) {
+                if hit_points.current > 0 {
+                    hit_points.current = hit_points.max.min(hit_points.current + 1);
                 }
-
-                // ignore collisiosns of projectiles
-                let _ = self.spatial_table.update_coord(entity, new_coord);
-            } else {
-                entities_to_remove.push(entity);
+            }
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == 0 {
+                expired.push(entity);
             }
         }
-        for entity in entities_to_remove {
-            self.remove_entity(entity);
+        for entity in expired {
+            self.components.regeneration_countdown.remove(entity);
+            events.push(GameEvent::Message(LogMessage::PlayerStopsRegenerating));
         }
-        for (entity, damage) in fireball_hit {
-            let maybe_npc = self.components.npc_type.get(entity).cloned();
-            if let Some(VictimDies) = self.character_damage(entity, damage) {
-                if let Some(npc) = maybe_npc {
-                    message_log.push(LogMessage::NpcDies(npc));
+    }
+    // Hurts each poisoned character by a hit point. Poison weakens but never kills.
+    fn tick_poison(&mut self, events: &mut Vec<GameEvent>) {
+        let mut expired = Vec::new();
+        for (entity, countdown) in self.components.poison_countdown.iter_mut() {
+            if let Some(hit_points) = self.components.hit_points.get_mut(entity) {
+                if hit_points.current > 1 {
+                    hit_points.current -= 1;
                 }
             }
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == 0 {
+                expired.push(entity);
+            }
         }
-        for (entity, duration) in confusion_hit {
-            self.components.confusion_countdown.insert(entity, duration);
-            if let Some(&npc_type) = self.components.npc_type.get(entity) {
-                message_log.push(LogMessage::NpcBecomesConfused(npc_type));
+        for entity in expired {
+            self.components.poison_countdown.remove(entity);
+            events.push(GameEvent::Message(LogMessage::PlayerRecoversFromPoison));
+        }
+    }
+    // Status effects wear off over time, and some have an effect each turn
+    pub fn set_night_for_nocturnal_npcs(&mut self, night: bool) {
+        for (entity, npc_type) in self.components.npc_type.iter() {
+            if npc_type.is_nocturnal() && night {
+                self.components.empowered_by_night.insert(entity, ());
+            } else {
+                self.components.empowered_by_night.remove(entity);
             }
         }
     }
-    pub fn has_projectiles(&self) -> bool {
-        !self.components.trajectory.is_empty()
+    pub fn tick_status_effects(&mut self, events: &mut Vec<GameEvent>) {
+        self.tick_invisibility(events);
+        self.tick_speed_effects(events);
+        self.tick_regeneration(events);
+        self.tick_poison(events);
+        self.tick_charge_cooldown();
     }
-    pub fn inventory(&self, entity: Entity) -> Option<&Inventory> {
-        self.components.inventory.get(entity)
+    pub fn defend(&mut self, entity: Entity) {
+        self.components.defending.insert(entity, ());
     }
-    pub fn item_type(&self, entity: Entity) -> Option<ItemType> {
-        self.components.item.get(entity).cloned()
+    pub fn stop_defending(&mut self, entity: Entity) {
+        self.components.defending.remove(entity);
+    }
+    pub fn status_effects(&self, entity: Entity) -> Vec<ActiveStatusEffect> {
+        let components = &self.components;
+        let countdowns = [
+            (
+                StatusEffect::Confused,
+                components.confusion_countdown.get(entity),
+            ),
+            (
+                StatusEffect::Poisoned,
+                components.poison_countdown.get(entity),
+            ),
+            (
+                StatusEffect::Invisible,
+                components.invisibility_countdown.get(entity),
+            ),
+            (
+                StatusEffect::Regenerating,
+                components.regeneration_countdown.get(entity),
+            ),
+        ];
+        countdowns
+            .iter()
+            .filter_map(|&(effect, countdown)| {
+                countdown.map(|&turns_remaining| ActiveStatusEffect {
+                    effect,
+                    turns_remaining,
+                })
+            })
+            .chain(self.speed_status(entity).map(|status| ActiveStatusEffect {
+                effect: StatusEffect::Speed(status.effect),
+                turns_remaining: status.turns_remaining,
+            }))
+            .collect()
+    }
+    pub fn is_regenerating(&self, entity: Entity) -> bool {
+        self.components.regeneration_countdown.contains(entity)
+    }
+    pub fn is_invisible(&self, entity: Entity) -> bool {
+        self.components.invisibility_countdown.contains(entity)
+    }
+    pub fn is_invisible_character_at(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.character)
+            .map_or(false, |entity| self.is_invisible(entity))
     }
     pub fn is_living_character(&self, entity: Entity) -> bool {
         self.spatial_table.layer_of(entity) == Some(Layer::Character)
@@ -847,6 +2930,9 @@ impl World {
     pub fn hit_points(&self, entity: Entity) -> Option<HitPoints> {
         self.components.hit_points.get(entity).cloned()
     }
+    pub fn set_hit_points(&mut self, entity: Entity, hit_points: HitPoints) {
+        self.components.hit_points.insert(entity, hit_points);
+    }
     pub fn entity_coord(&self, entity: Entity) -> Option<Coord> {
         self.spatial_table.coord_of(entity)
     }
@@ -856,6 +2942,31 @@ impl World {
             .map(|layers| layers.feature.is_none())
             .unwrap_or(false)
     }
+    // The cost of an npc stepping onto a cell when planning a path, or `None` if npcs can't enter it
+    pub fn npc_move_cost_ignoring_other_npcs(&self, coord: Coord) -> Option<u32> {
+        if !self.can_npc_enter_ignoring_other_npcs(coord) {
+            return None;
+        }
+        match self.hazard_at(coord) {
+            Some(_) => Some(HAZARD_MOVE_COST),
+            None => Some(1),
+        }
+    }
+    pub fn hazard_at(&self, coord: Coord) -> Option<Hazard> {
+        let floor_entity = self.spatial_table.layers_at(coord)?.floor?;
+        if self.components.trapdoor.contains(floor_entity) {
+            let revealed = matches!(self.components.tile.get(floor_entity), Some(Tile::Trapdoor));
+            Some(Hazard::Trapdoor { revealed })
+        } else if self.components.slowing_trap.contains(floor_entity) {
+            let revealed = matches!(
+                self.components.tile.get(floor_entity),
+                Some(Tile::SlowingTrap)
+            );
+            Some(Hazard::SlowingTrap { revealed })
+        } else {
+            None
+        }
+    }
     pub fn can_npc_enter(&self, coord: Coord) -> bool {
         self.spatial_table
             .layers_at(coord)
@@ -875,23 +2986,36 @@ impl World {
             .map(|layers| layers.feature.is_none())
             .unwrap_or(false)
     }
-    pub fn examine_cell(&self, coord: Coord) -> Option<ExamineCell> {
-        let layers = self.spatial_table.layers_at(coord)?;
-        layers
-            .character
-            .or_else(|| layers.object)
-            .and_then(|entity| {
+    // Returns a description of each entity in a cell, ordered from the topmost entity down
+    pub fn examine_cell(&self, coord: Coord) -> Vec<ExamineCell> {
+        let layers = match self.spatial_table.layers_at(coord) {
+            Some(layers) => layers,
+            None => return Vec::new(),
+        };
+        let floor_feature = layers.floor.filter(|&entity| {
+            self.components.altar.contains(entity) || self.components.fountain.contains(entity)
+        });
+        let buried_item = layers
+            .object
+            .and_then(|entity| self.components.buried_item.get(entity).cloned());
+        [layers.character, layers.object, buried_item, floor_feature]
+            .iter()
+            .filter_map(|&maybe_entity| maybe_entity)
+            .filter_map(|entity| {
                 self.components
                     .tile
                     .get(entity)
                     .and_then(|&tile| match tile {
-                        Tile::Npc(npc_type) => Some(ExamineCell::Npc(npc_type)),
-                        Tile::NpcCorpse(npc_type) => Some(ExamineCell::NpcCorpse(npc_type)),
+                        Tile::Npc(npc) => Some(ExamineCell::Npc(npc)),
+                        Tile::NpcCorpse(npc) => Some(ExamineCell::NpcCorpse(npc)),
                         Tile::Item(item_type) => Some(ExamineCell::Item(item_type)),
                         Tile::Player => Some(ExamineCell::Player),
+                        Tile::Altar => Some(ExamineCell::Altar),
+                        Tile::Fountain => Some(ExamineCell::Fountain),
                         _ => None,
                     })
             })
+            .collect()
     }
     fn remove_entity_data(&mut self, entity: Entity) -> EntityData {
         self.entity_allocator.free(entity);
@@ -945,6 +3069,38 @@ impl World {
         });
         self.components.update_entity_data(entity, entity_data);
     }
+    // Adds a character taken out of another level with `remove_character` at `coord`, returning
+    // its new entity
+    pub fn insert_character(&mut self, coord: Coord, character_data: CharacterData) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+        self.replace_character(entity, character_data);
+        entity
+    }
+    // True if there's an altar at `coord` which hasn't been prayed at yet
+    pub fn coord_contains_unused_altar(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .and_then(|floor_entity| self.components.altar.get(floor_entity))
+            .map_or(false, |altar| !altar.prayed_at)
+    }
+    pub fn coord_contains_fountain(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map_or(false, |floor_entity| {
+                self.components.fountain.contains(floor_entity)
+            })
+    }
     pub fn coord_contains_stairs(&self, coord: Coord) -> bool {
         self.spatial_table
             .layers_at_checked(coord)
@@ -952,6 +3108,52 @@ impl World {
             .map(|floor_entity| self.components.stairs.contains(floor_entity))
             .unwrap_or(false)
     }
+    pub fn coord_contains_up_stairs(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map(|floor_entity| self.components.up_stairs.contains(floor_entity))
+            .unwrap_or(false)
+    }
+    pub fn coord_contains_trapdoor(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map(|floor_entity| self.components.trapdoor.contains(floor_entity))
+            .unwrap_or(false)
+    }
+    pub fn coord_contains_slowing_trap(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at_checked(coord)
+            .floor
+            .map(|floor_entity| self.components.slowing_trap.contains(floor_entity))
+            .unwrap_or(false)
+    }
+    // Slows a character which stepped onto a slowing trap
+    pub fn spring_slowing_trap(&mut self, entity: Entity, events: &mut Vec<GameEvent>) {
+        const SLOWING_TRAP_DURATION: u32 = 10;
+        self.apply_speed_effect(entity, SpeedEffect::Slow, SLOWING_TRAP_DURATION, events);
+    }
+    // Deals damage to a character for falling down a level, returning the damage dealt
+    pub fn fall<R: Rng>(
+        &mut self,
+        entity: Entity,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) -> u32 {
+        let damage = rng.gen_range(1..=4);
+        self.report_damage(entity, None, damage, events);
+        let hit_points = self
+            .components
+            .hit_points
+            .get_mut(entity)
+            .expect("character has no hit points");
+        hit_points.current = hit_points.current.saturating_sub(damage);
+        if hit_points.current == 0 {
+            self.character_die(entity, rng);
+        }
+        damage
+    }
     pub fn strength(&self, entity: Entity) -> Option<i32> {
         self.components.strength.get(entity).cloned()
     }
@@ -996,6 +3198,570 @@ impl World {
             }
         }
     }
+    // True if there's floor at `coord` with nothing like a wall blocking it
+    pub fn is_wall_at(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.feature)
+            .map_or(false, |entity| {
+                matches!(self.components.tile.get(entity), Some(Tile::Wall))
+            })
+    }
+    pub fn is_open_floor(&self, coord: Coord) -> bool {
+        self.spatial_table.layers_at(coord).map_or(false, |layers| {
+            layers.feature.is_none() && layers.floor.is_some()
+        })
+    }
+    fn coord_is_empty_floor(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .map(|layers| {
+                layers.feature.is_none()
+                    && layers.character.is_none()
+                    && layers.object.is_none()
+                    && layers
+                        .floor
+                        .map(|floor_entity| !self.components.stairs.contains(floor_entity))
+                        .unwrap_or(false)
+            })
+            .unwrap_or(false)
+    }
+    // Spawn a single npc somewhere that `is_hidden` returns true for, such as out of the player's
+    // sight
+    pub fn spawn_npc_where_hidden<R: Rng, F: Fn(Coord) -> bool>(
+        &mut self,
+        level: u32,
+        theme: LevelTheme,
+        config: DungeonConfig,
+        is_hidden: F,
+        rng: &mut R,
+    ) -> Option<Entity> {
+        let coord = self
+            .size()
+            .coord_iter_row_major()
+            .filter(|&coord| self.coord_is_empty_floor(coord) && is_hidden(coord))
+            .choose(rng)?;
+        let npc = terrain::choose_npc(level, theme, config, rng);
+        Some(self.spawn_npc(coord, npc, terrain::npc_stat_bonus(level, config)))
+    }
+    // The empty floor cell closest to `centre`, chosen at random from equally close cells
+    fn nearest_empty_floor_coord<R: Rng>(&self, centre: Coord, rng: &mut R) -> Option<Coord> {
+        let mut coords = self
+            .size()
+            .coord_iter_row_major()
+            .filter(|&coord| self.coord_is_empty_floor(coord))
+            .collect::<Vec<_>>();
+        let min_distance2 = coords.iter().map(|&coord| coord.distance2(centre)).min()?;
+        coords.retain(|&coord| coord.distance2(centre) == min_distance2);
+        coords.choose(rng).cloned()
+    }
+    pub fn spawn_npc_near<R: Rng>(
+        &mut self,
+        centre: Coord,
+        npc: Npc,
+        rng: &mut R,
+    ) -> Option<Entity> {
+        let coord = self.nearest_empty_floor_coord(centre, rng)?;
+        Some(self.spawn_npc(coord, npc, 0))
+    }
+    pub fn spawn_item_near<R: Rng>(
+        &mut self,
+        centre: Coord,
+        item_type: ItemType,
+        rng: &mut R,
+    ) -> Option<Entity> {
+        let coord = self.nearest_empty_floor_coord(centre, rng)?;
+        let entity = self.spawn_item(coord, item_type);
+        self.components.buc.insert(entity, Buc::Uncursed);
+        Some(entity)
+    }
+    fn random_empty_floor_coord<R: Rng>(&self, rng: &mut R) -> Option<Coord> {
+        self.size()
+            .coord_iter_row_major()
+            .filter(|&coord| self.coord_is_empty_floor(coord))
+            .choose(rng)
+    }
+    fn stairs_coord(&self) -> Option<Coord> {
+        self.components
+            .stairs
+            .entities()
+            .next()
+            .and_then(|entity| self.spatial_table.coord_of(entity))
+    }
+    // Collapse a few walls which border open space, opening up new paths through the level
+    pub fn tremor<R: Rng>(&mut self, rng: &mut R) {
+        const NUM_WALLS_TO_COLLAPSE: usize = 5;
+        let size = self.size();
+        let walls_to_collapse = self
+            .components
+            .tile
+            .iter()
+            .filter_map(|(entity, &tile)| {
+                if !matches!(tile, Tile::Wall) {
+                    return None;
+                }
+                let coord = self.spatial_table.coord_of(entity)?;
+                let is_on_edge = coord.x == 0
+                    || coord.y == 0
+                    || coord.x == size.width() as i32 - 1
+                    || coord.y == size.height() as i32 - 1;
+                if is_on_edge {
+                    return None;
+                }
+                let borders_open_space = CardinalDirection::all().any(|direction| {
+                    self.spatial_table
+                        .layers_at(coord + direction.coord())
+                        .map(|layers| layers.feature.is_none())
+                        .unwrap_or(false)
+                });
+                if borders_open_space {
+                    Some(entity)
+                } else {
+                    None
+                }
+            })
+            .choose_multiple(rng, NUM_WALLS_TO_COLLAPSE);
+        for entity in walls_to_collapse {
+            self.remove_entity(entity);
+        }
+    }
+    // Spawn a group of NPCs on empty cells near the stairs, returning the new entities
+    pub fn spawn_npcs_near_stairs<R: Rng>(
+        &mut self,
+        level: u32,
+        theme: LevelTheme,
+        config: DungeonConfig,
+        rng: &mut R,
+    ) -> Vec<Entity> {
+        const SPAWN_RADIUS: i32 = 3;
+        match self.stairs_coord() {
+            Some(stairs_coord) => {
+                self.spawn_npcs_near(stairs_coord, SPAWN_RADIUS, level, theme, config, rng)
+            }
+            None => Vec::new(),
+        }
+    }
+    // Spawn orcs on empty cells around a boss, returning the new entities
+    pub fn summon_minions<R: Rng>(
+        &mut self,
+        boss: Entity,
+        events: &mut Vec<GameEvent>,
+        rng: &mut R,
+    ) -> Vec<Entity> {
+        const NUM_MINIONS: usize = 2;
+        const SUMMON_RADIUS: i32 = 2;
+        let boss_coord = match self.spatial_table.coord_of(boss) {
+            Some(&boss_coord) => boss_coord,
+            None => return Vec::new(),
+        };
+        if let Some(npc) = self.npc(boss) {
+            events.push(GameEvent::Message(LogMessage::BossSummonsMinions(npc)));
+        }
+        let coords = (-SUMMON_RADIUS..=SUMMON_RADIUS)
+            .flat_map(|y| (-SUMMON_RADIUS..=SUMMON_RADIUS).map(move |x| Coord::new(x, y)))
+            .map(|offset| boss_coord + offset)
+            .filter(|&coord| self.coord_is_empty_floor(coord))
+            .choose_multiple(rng, NUM_MINIONS);
+        let minion = Npc {
+            npc_type: NpcType::Orc,
+            elite: false,
+        };
+        coords
+            .into_iter()
+            .map(|coord| self.spawn_npc(coord, minion, 0))
+            .collect()
+    }
+    // Spawn a group of NPCs on empty cells within `radius` of `centre`, returning the new entities
+    pub fn spawn_npcs_near<R: Rng>(
+        &mut self,
+        centre: Coord,
+        radius: i32,
+        level: u32,
+        theme: LevelTheme,
+        config: DungeonConfig,
+        rng: &mut R,
+    ) -> Vec<Entity> {
+        const MAX_NUM_NPCS: usize = 3;
+        let num_npcs = rng.gen_range(1..=MAX_NUM_NPCS);
+        let coords = (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| Coord::new(x, y)))
+            .map(|offset| centre + offset)
+            .filter(|&coord| self.coord_is_empty_floor(coord))
+            .choose_multiple(rng, num_npcs);
+        coords
+            .into_iter()
+            .map(|coord| {
+                let npc = terrain::choose_npc(level, theme, config, rng);
+                self.spawn_npc(coord, npc, terrain::npc_stat_bonus(level, config))
+            })
+            .collect()
+    }
+    // Move every item lying on the floor to a random empty floor cell
+    pub fn teleport_items<R: Rng>(&mut self, rng: &mut R) {
+        let items_on_floor = self
+            .components
+            .item
+            .entities()
+            .filter(|&entity| self.spatial_table.layer_of(entity) == Some(Layer::Object))
+            .collect::<Vec<_>>();
+        for entity in items_on_floor {
+            if let Some(coord) = self.random_empty_floor_coord(rng) {
+                self.spatial_table.update_coord(entity, coord).unwrap();
+            }
+        }
+    }
+    // A text description of the level for debugging: a map showing the top-most entity in each
+    // cell, followed by the location and components of each entity. Plain floors and walls are
+    // only shown on the map, and items in inventories or buried under corpses are listed beneath
+    // the entity holding them.
+    pub fn debug_dump(&self) -> String {
+        let mut out = String::new();
+        let size = self.size();
+        for y in 0..size.height() as i32 {
+            for x in 0..size.width() as i32 {
+                let layers = self.spatial_table.layers_at_checked(Coord::new(x, y));
+                let top = layers
+                    .projectile
+                    .or(layers.character)
+                    .or(layers.object)
+                    .or(layers.feature)
+                    .or(layers.floor);
+                out.push(top.map_or(' ', |entity| self.debug_char(entity)));
+            }
+            out.push('\n');
+        }
+        for (coord, layers) in self.spatial_table.enumerate() {
+            let layers = [
+                ("floor", layers.floor),
+                ("character", layers.character),
+                ("object", layers.object),
+                ("feature", layers.feature),
+                ("projectile", layers.projectile),
+            ];
+            for &(layer_name, entity) in layers.iter() {
+                let entity = match entity {
+                    Some(entity) => entity,
+                    None => continue,
+                };
+                let components = self.debug_components(entity, "  ");
+                let plain = match self.components.tile.get(entity) {
+                    Some(Tile::Floor) | Some(Tile::Wall) => components.lines().count() == 1,
+                    _ => false,
+                };
+                if plain {
+                    continue;
+                }
+                writeln!(
+                    out,
+                    "\n{:?} at ({}, {}) in {} layer",
+                    entity, coord.x, coord.y, layer_name
+                )
+                .unwrap();
+                out.push_str(&components);
+                let held = self
+                    .components
+                    .inventory
+                    .get(entity)
+                    .into_iter()
+                    .flat_map(|inventory| inventory.slots().iter().flatten())
+                    .chain(self.components.buried_item.get(entity));
+                for &item_entity in held {
+                    writeln!(out, "  holding {:?}", item_entity).unwrap();
+                    out.push_str(&self.debug_components(item_entity, "    "));
+                }
+            }
+        }
+        out
+    }
+    fn debug_char(&self, entity: Entity) -> char {
+        match self.components.tile.get(entity) {
+            Some(Tile::Player) => '@',
+            Some(Tile::PlayerCorpse) | Some(Tile::NpcCorpse(_)) => '%',
+            Some(Tile::Floor) if self.components.trapdoor.contains(entity) => '^',
+            Some(Tile::Floor) if self.components.slowing_trap.contains(entity) => '"',
+            Some(Tile::Floor) => '.',
+            Some(Tile::Wall) => '#',
+            Some(Tile::Npc(npc)) => npc.ascii_char(),
+            Some(Tile::Item(item_type)) => item_type.ascii_char(),
+            Some(Tile::Projectile(_)) => '*',
+            Some(Tile::Stairs) => '>',
+            Some(Tile::UpStairs) => '<',
+            Some(Tile::Trapdoor) => '^',
+            Some(Tile::SlowingTrap) => '"',
+            Some(Tile::Altar) => '_',
+            Some(Tile::Fountain) => '{',
+            Some(Tile::DashTrail) => '~',
+            Some(Tile::DamageNumber { .. }) | None => '?',
+        }
+    }
+    // One line per component of `entity`, with its value as json. Components without a value are
+    // listed by name alone.
+    fn debug_components(&self, entity: Entity, indent: &str) -> String {
+        let mut out = String::new();
+        macro_rules! write_components {
+            ($($component:ident),* $(,)?) => {
+                $(
+                    if let Some(value) = self.components.$component.get(entity) {
+                        let value = serde_json::to_string(value)
+                            .expect("failed to serialize component");
+                        if value == "null" {
+                            writeln!(out, "{}{}", indent, stringify!($component)).unwrap();
+                        } else {
+                            writeln!(out, "{}{}: {}", indent, stringify!($component), value)
+                                .unwrap();
+                        }
+                    }
+                )*
+            };
+        }
+        write_components!(
+            tile,
+            npc_type,
+            elite,
+            empowered_by_night,
+            faction,
+            hit_points,
+            item,
+            inventory,
+            trajectory,
+            projectile,
+            confusion_countdown,
+            summon_countdown,
+            invisibility_countdown,
+            speed_status,
+            regeneration_countdown,
+            poison_countdown,
+            defending,
+            charge_cooldown,
+            fade_countdown,
+            float_countdown,
+            durability,
+            buc,
+            buc_known,
+            stairs,
+            up_stairs,
+            base_damage,
+            strength,
+            dexterity,
+            intelligence,
+            equipment_worn_inventory_index,
+            equipment_held_inventory_index,
+            altar,
+            fountain,
+            trapdoor,
+            slowing_trap,
+            enchantment,
+            ammo,
+            hotbar_slot,
+            buried_item,
+        );
+        out
+    }
+    // Check that the spatial table and components are consistent with one another, panicking
+    // with a description of the first inconsistency found. This is slow, so it's intended to only
+    // be called in debug builds.
+    pub fn validate(&self) {
+        for (coord, layers) in self.spatial_table.enumerate() {
+            let layers = [
+                layers.floor,
+                layers.character,
+                layers.object,
+                layers.feature,
+                layers.projectile,
+            ];
+            for &entity in layers.iter().flatten() {
+                assert!(
+                    self.entity_allocator.exists(entity),
+                    "spatial table contains freed entity {:?} at {:?}",
+                    entity,
+                    coord
+                );
+                assert_eq!(
+                    self.spatial_table.coord_of(entity),
+                    Some(coord),
+                    "entity {:?} is in the wrong cell of the spatial table",
+                    entity
+                );
+                assert!(
+                    self.components.tile.contains(entity),
+                    "entity {:?} at {:?} has no tile",
+                    entity,
+                    coord
+                );
+            }
+        }
+        let mut inventory_entities = Vec::new();
+        for (entity, inventory) in self.components.inventory.iter() {
+            for (index, &item_entity) in inventory.slots().iter().enumerate() {
+                if let Some(item_entity) = item_entity {
+                    assert!(
+                        self.entity_allocator.exists(item_entity),
+                        "inventory of {:?} contains freed entity {:?} in slot {}",
+                        entity,
+                        item_entity,
+                        index
+                    );
+                    assert!(
+                        self.components.item.contains(item_entity),
+                        "inventory of {:?} contains non-item {:?} in slot {}",
+                        entity,
+                        item_entity,
+                        index
+                    );
+                    assert!(
+                        self.spatial_table.location_of(item_entity).is_none(),
+                        "item {:?} is in an inventory but also has a location",
+                        item_entity
+                    );
+                    inventory_entities.push(item_entity);
+                }
+            }
+            for &index in self
+                .components
+                .equipment_held_inventory_index
+                .get(entity)
+                .iter()
+                .chain(
+                    self.components
+                        .equipment_worn_inventory_index
+                        .get(entity)
+                        .iter(),
+                )
+            {
+                assert!(
+                    inventory.get(*index).is_ok(),
+                    "{:?} has empty inventory slot {} equipped",
+                    entity,
+                    index
+                );
+            }
+        }
+        // Items buried under corpses have no location either
+        for (corpse_entity, &item_entity) in self.components.buried_item.iter() {
+            assert!(
+                self.components.item.contains(item_entity),
+                "{:?} has non-item {:?} buried under it",
+                corpse_entity,
+                item_entity
+            );
+            assert!(
+                self.spatial_table.location_of(item_entity).is_none(),
+                "item {:?} is buried but also has a location",
+                item_entity
+            );
+            inventory_entities.push(item_entity);
+        }
+        for (entity, &tile) in self.components.tile.iter() {
+            assert!(
+                self.entity_allocator.exists(entity),
+                "freed entity {:?} still has a tile",
+                entity
+            );
+            if inventory_entities.contains(&entity) {
+                continue;
+            }
+            let layer = match self.spatial_table.location_of(entity) {
+                Some(location) => location.layer,
+                None => panic!(
+                    "entity {:?} with tile {:?} has no location and is not in an inventory",
+                    entity, tile
+                ),
+            };
+            let expected_layer = match tile {
+                Tile::Player | Tile::Npc(_) => Some(Layer::Character),
+                Tile::PlayerCorpse | Tile::NpcCorpse(_) | Tile::Item(_) => Some(Layer::Object),
+                Tile::Floor
+                | Tile::Stairs
+                | Tile::UpStairs
+                | Tile::Trapdoor
+                | Tile::SlowingTrap
+                | Tile::Altar
+                | Tile::Fountain => Some(Layer::Floor),
+                Tile::Wall => Some(Layer::Feature),
+                Tile::Projectile(_) => Some(Layer::Projectile),
+                Tile::DashTrail | Tile::DamageNumber { .. } => None,
+            };
+            assert_eq!(
+                layer, expected_layer,
+                "entity {:?} with tile {:?} is on the wrong layer",
+                entity, tile
+            );
+        }
+    }
+    // Assign the item in an inventory slot to a hotbar slot, replacing whichever item was there
+    pub fn maybe_assign_hotbar_slot(
+        &mut self,
+        character: Entity,
+        inventory_index: usize,
+        hotbar_slot: usize,
+        events: &mut Vec<GameEvent>,
+    ) -> Result<(), ()> {
+        let inventory = self
+            .components
+            .inventory
+            .get(character)
+            .expect("character has no inventory");
+        let item = match inventory.get(inventory_index) {
+            Ok(item) => item,
+            Err(InventorySlotIsEmpty) => {
+                events.push(GameEvent::Message(LogMessage::NoItemInInventorySlot));
+                return Err(());
+            }
+        };
+        for &other_item in inventory.slots().iter().flatten() {
+            if self.components.hotbar_slot.get(other_item) == Some(&hotbar_slot) {
+                self.components.hotbar_slot.remove(other_item);
+            }
+        }
+        self.components.hotbar_slot.insert(item, hotbar_slot);
+        Ok(())
+    }
+    // The inventory index of the item assigned to each hotbar slot
+    pub fn hotbar_inventory_indices(&self, character: Entity) -> [Option<usize>; NUM_HOTBAR_SLOTS] {
+        let mut indices = [None; NUM_HOTBAR_SLOTS];
+        let inventory = self
+            .components
+            .inventory
+            .get(character)
+            .expect("character has no inventory");
+        for (index, &slot) in inventory.slots().iter().enumerate() {
+            if let Some(&hotbar_slot) = slot.and_then(|item| self.components.hotbar_slot.get(item))
+            {
+                indices[hotbar_slot] = Some(index);
+            }
+        }
+        indices
+    }
+    // Group the items in a character's inventory by category and compact away empty slots, updating
+    // the indices of equipped items to follow them
+    pub fn sort_inventory(&mut self, entity: Entity) {
+        let item_types = &self.components.item;
+        let new_indices = self
+            .components
+            .inventory
+            .get_mut(entity)
+            .expect("character has no inventory")
+            .sort_by_key(|item| {
+                item_types
+                    .get(item)
+                    .expect("non-item in inventory")
+                    .category()
+            });
+        if let Some(held_index) = self
+            .components
+            .equipment_held_inventory_index
+            .get_mut(entity)
+        {
+            *held_index = new_indices[*held_index].expect("held item missing from inventory");
+        }
+        if let Some(worn_index) = self
+            .components
+            .equipment_worn_inventory_index
+            .get_mut(entity)
+        {
+            *worn_index = new_indices[*worn_index].expect("worn item missing from inventory");
+        }
+    }
     pub fn equipped_inventory_indices(&self, entity: Entity) -> EquippedInventoryIndices {
         let held = self
             .components
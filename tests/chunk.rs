@@ -0,0 +1,111 @@
+// Chunked grids must behave like dense grids wherever chunks start and end, including the partial
+// chunks along the right and bottom edges, and must save in the same format as dense grids.
+use chargrid_roguelike_tutorial_2020::{ActiveWindow, ChunkedGrid, CHUNK_SIZE};
+use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+
+const CHUNK: i32 = CHUNK_SIZE as i32;
+
+// The radius of the active window on a 40x26 screen
+const RADIUS: u32 = 40;
+
+// Two and a half chunks wide and one and a half tall
+fn size() -> Size {
+    Size::new(CHUNK_SIZE * 5 / 2, CHUNK_SIZE * 3 / 2)
+}
+
+// Cells either side of each chunk boundary, and the corners of the grid
+fn boundary_coords() -> Vec<Coord> {
+    let size = size();
+    let mut coords = vec![
+        Coord::new(0, 0),
+        Coord::new(size.width() as i32 - 1, 0),
+        Coord::new(0, size.height() as i32 - 1),
+        Coord::new(size.width() as i32 - 1, size.height() as i32 - 1),
+    ];
+    for &x in &[CHUNK - 1, CHUNK, CHUNK * 2 - 1, CHUNK * 2] {
+        for &y in &[0, CHUNK - 1, CHUNK] {
+            coords.push(Coord::new(x, y));
+        }
+    }
+    coords
+}
+
+#[test]
+fn cells_either_side_of_chunk_boundaries_are_independent() {
+    let mut grid = ChunkedGrid::<u32>::new(size());
+    for (i, &coord) in boundary_coords().iter().enumerate() {
+        *grid.get_checked_mut(coord) = i as u32 + 1;
+    }
+    for (i, &coord) in boundary_coords().iter().enumerate() {
+        assert_eq!(grid.get(coord), Some(&(i as u32 + 1)), "{:?}", coord);
+    }
+    let written = boundary_coords();
+    for coord in size().coord_iter_row_major() {
+        if !written.contains(&coord) {
+            assert_eq!(grid.get(coord), Some(&0), "{:?}", coord);
+        }
+    }
+}
+
+#[test]
+fn coords_outside_the_grid_are_rejected() {
+    let mut grid = ChunkedGrid::<u32>::new(size());
+    let size = size();
+    // Still inside the last chunk, but past the edge of the grid
+    let past_right = Coord::new(size.width() as i32, 0);
+    let past_bottom = Coord::new(0, size.height() as i32);
+    for &coord in &[
+        past_right,
+        past_bottom,
+        Coord::new(-1, 0),
+        Coord::new(0, -1),
+    ] {
+        assert_eq!(grid.get(coord), None, "{:?}", coord);
+        assert!(grid.get_mut(coord).is_none(), "{:?}", coord);
+    }
+}
+
+#[test]
+fn chunked_grids_save_as_dense_grids() {
+    let mut grid = ChunkedGrid::<u32>::new(size());
+    for (i, &coord) in boundary_coords().iter().enumerate() {
+        *grid.get_checked_mut(coord) = i as u32 + 1;
+    }
+    let saved = serde_json::to_value(&grid).unwrap();
+    let dense: Grid<u32> = serde_json::from_value(saved.clone()).unwrap();
+    for coord in size().coord_iter_row_major() {
+        assert_eq!(dense.get(coord), grid.get(coord), "{:?}", coord);
+    }
+    let loaded: ChunkedGrid<u32> = serde_json::from_value(saved).unwrap();
+    assert_eq!(loaded.size(), size());
+    for coord in size().coord_iter_row_major() {
+        assert_eq!(loaded.get(coord), grid.get(coord), "{:?}", coord);
+    }
+}
+
+#[test]
+fn active_window_covers_levels_which_fit_on_screen() {
+    let level_size = Size::new(40, 26);
+    for centre in level_size.coord_iter_row_major() {
+        let window = ActiveWindow::around(centre, RADIUS, level_size);
+        assert_eq!(window.top_left(), Coord::new(0, 0));
+        assert_eq!(window.size(), level_size);
+    }
+}
+
+#[test]
+fn active_window_is_clipped_to_large_levels() {
+    let radius = RADIUS as i32;
+    let level_size = Size::new(RADIUS * 5, RADIUS * 3);
+    let centre = Coord::new(radius * 2, radius);
+    let window = ActiveWindow::around(centre, RADIUS, level_size);
+    assert_eq!(window.top_left(), Coord::new(radius, 0));
+    assert_eq!(window.size(), Size::new(RADIUS * 2 + 1, RADIUS * 2 + 1));
+    assert!(window.contains(centre + Coord::new(radius, radius)));
+    assert!(!window.contains(centre + Coord::new(radius + 1, 0)));
+    assert!(!window.contains(centre - Coord::new(radius + 1, 0)));
+    let corner = ActiveWindow::around(Coord::new(0, 0), RADIUS, level_size);
+    assert_eq!(corner.top_left(), Coord::new(0, 0));
+    assert_eq!(corner.size(), Size::new(RADIUS + 1, RADIUS + 1));
+}
@@ -0,0 +1,90 @@
+// Replays and traces rely on the game being a pure function of its seed and the player's input.
+// These tests play the same scripted input twice and check that the world matches on every turn.
+use chargrid_roguelike_tutorial_2020::{
+    Challenges, DungeonConfig, GameState, PlayerAction, VisibilityAlgorithm,
+};
+use coord_2d::Size;
+use direction::CardinalDirection;
+
+const NUM_TURNS: usize = 500;
+
+fn new_game(rng_seed: u64) -> GameState {
+    GameState::new(
+        Size::new(40, 26),
+        rng_seed,
+        VisibilityAlgorithm::Shadowcast,
+        false,
+        DungeonConfig::default(),
+        Challenges::default(),
+    )
+}
+
+// A fixed sequence of actions which wanders around the level, fighting anything in the way and
+// picking up anything underfoot
+fn scripted_action(turn: usize) -> PlayerAction {
+    const SCRIPT: &[PlayerAction] = &[
+        PlayerAction::Move(CardinalDirection::North),
+        PlayerAction::Move(CardinalDirection::North),
+        PlayerAction::Move(CardinalDirection::East),
+        PlayerAction::GetItem,
+        PlayerAction::Move(CardinalDirection::East),
+        PlayerAction::Wait,
+        PlayerAction::Move(CardinalDirection::South),
+        PlayerAction::Move(CardinalDirection::South),
+        PlayerAction::Defend,
+        PlayerAction::Move(CardinalDirection::West),
+        PlayerAction::Move(CardinalDirection::East),
+        PlayerAction::Move(CardinalDirection::East),
+        PlayerAction::Move(CardinalDirection::North),
+    ];
+    SCRIPT[turn % SCRIPT.len()]
+}
+
+// Plays the script, returning the hash of the world after each turn
+fn play(game_state: &mut GameState, num_turns: usize) -> Vec<u64> {
+    let mut hashes = Vec::new();
+    for turn in 0..num_turns {
+        if !game_state.is_player_alive() {
+            break;
+        }
+        game_state.handle_action(scripted_action(turn));
+        game_state.resolve_animations();
+        game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        hashes.push(game_state.world_hash());
+    }
+    hashes
+}
+
+fn assert_same_hashes(a: &[u64], b: &[u64]) {
+    assert_eq!(a.len(), b.len(), "runs lasted a different number of turns");
+    for (turn, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+        assert_eq!(a, b, "world hashes diverged on turn {}", turn);
+    }
+}
+
+#[test]
+fn same_seed_and_input_gives_same_world_each_turn() {
+    for &rng_seed in &[0, 1, 42, 0xdeadbeef] {
+        let a = play(&mut new_game(rng_seed), NUM_TURNS);
+        let b = play(&mut new_game(rng_seed), NUM_TURNS);
+        assert!(!a.is_empty());
+        assert_same_hashes(&a, &b);
+    }
+}
+
+#[test]
+fn different_seeds_give_different_worlds() {
+    assert_ne!(new_game(0).world_hash(), new_game(1).world_hash());
+}
+
+#[test]
+fn loading_a_saved_game_does_not_change_the_outcome() {
+    let mut original = new_game(7);
+    play(&mut original, NUM_TURNS / 2);
+    let saved = serde_json::to_vec(&original).unwrap();
+    let mut loaded: GameState = serde_json::from_slice(&saved).unwrap();
+    assert_eq!(original.world_hash(), loaded.world_hash());
+    let a = play(&mut original, NUM_TURNS / 2);
+    let b = play(&mut loaded, NUM_TURNS / 2);
+    assert_same_hashes(&a, &b);
+}
@@ -0,0 +1,68 @@
+// Level generation repairs levels which come out without enough content, and relies on corridors
+// to connect every room. These tests generate levels from a range of seeds and check that each
+// one meets every constraint, and that their statistics stay within sensible bounds.
+use chargrid_roguelike_tutorial_2020::{
+    generate_dungeon, DungeonConfig, DungeonStats, LevelName, RoomPlacement,
+};
+use coord_2d::Size;
+use rand::SeedableRng;
+use rand_isaac::Isaac64Rng;
+
+const NUM_SEEDS: u64 = 200;
+const LEVELS: &[u32] = &[1, 3, 5, 10];
+
+fn generate_stats(rng_seed: u64, level: u32, dungeon_config: DungeonConfig) -> DungeonStats {
+    let mut rng = Isaac64Rng::seed_from_u64(rng_seed);
+    let level_name = LevelName::choose(&mut rng);
+    generate_dungeon(
+        Size::new(40, 25),
+        level,
+        level_name.theme,
+        dungeon_config,
+        &mut rng,
+    )
+    .stats()
+}
+
+fn check_seed_range(dungeon_config: DungeonConfig) {
+    for &level in LEVELS {
+        for rng_seed in 0..NUM_SEEDS {
+            let stats = generate_stats(rng_seed, level, dungeon_config);
+            assert_eq!(
+                stats.unmet_constraints(),
+                vec![],
+                "seed {} level {}: {:?}",
+                rng_seed,
+                level,
+                stats
+            );
+            assert!(
+                stats.num_rooms >= 2,
+                "seed {} level {}: {:?}",
+                rng_seed,
+                level,
+                stats
+            );
+            assert!(
+                stats.corridor_length < stats.floor_area,
+                "seed {} level {}: {:?}",
+                rng_seed,
+                level,
+                stats
+            );
+        }
+    }
+}
+
+#[test]
+fn randomly_placed_rooms_meet_constraints() {
+    check_seed_range(DungeonConfig::default());
+}
+
+#[test]
+fn bsp_rooms_meet_constraints() {
+    check_seed_range(DungeonConfig {
+        room_placement: RoomPlacement::Bsp,
+        ..DungeonConfig::default()
+    });
+}
@@ -0,0 +1,103 @@
+// Levels the player leaves are kept, so that climbing back up or going down again returns to the
+// level as it was rather than generating a new one. These tests let the built-in bot play in
+// peaceful mode until it reaches the second level, then move between levels by the stairs.
+use chargrid_roguelike_tutorial_2020::{
+    save, ActionResult, Bot, Challenges, DungeonConfig, GameState, LevelUp, Observation,
+    PlayerAction, SimpleBot, VisibilityAlgorithm,
+};
+use coord_2d::{Coord, Size};
+
+const MAX_ACTIONS: usize = 5000;
+
+// The game once the bot has reached the second level, along with where the player left the first
+// level, which is either the stairs or a trapdoor
+fn game_on_second_level(rng_seed: u64) -> (GameState, Coord) {
+    let mut game_state = GameState::new(
+        Size::new(40, 26),
+        rng_seed,
+        VisibilityAlgorithm::Shadowcast,
+        true,
+        DungeonConfig::default(),
+        Challenges::default(),
+    );
+    let mut bot = SimpleBot;
+    for _ in 0..MAX_ACTIONS {
+        let action = bot.act(&Observation::new(&game_state));
+        let coord = game_state.player_coord();
+        if game_state.handle_action(action) != ActionResult::Done {
+            game_state.handle_action(PlayerAction::Wait);
+        }
+        game_state.resolve_animations();
+        game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+        if game_state.dungeon_level() == 2 {
+            // Falling through a trapdoor takes a step onto it first
+            let left_from = match action {
+                PlayerAction::Move(direction) => coord + direction.coord(),
+                _ => coord,
+            };
+            return (game_state, left_from);
+        }
+    }
+    panic!("seed {}: the bot didn't reach the second level", rng_seed);
+}
+
+#[test]
+fn player_arrives_on_up_stairs() {
+    for rng_seed in 0..5 {
+        let (game_state, _) = game_on_second_level(rng_seed);
+        assert!(game_state.is_player_on_up_stairs(), "seed {}", rng_seed);
+    }
+}
+
+#[test]
+fn climbing_up_returns_to_where_the_previous_level_was_left() {
+    for rng_seed in 0..5 {
+        let (mut game_state, left_from) = game_on_second_level(rng_seed);
+        let second_level_name = game_state.level_name();
+        let arrived_at = game_state.player_coord();
+        assert_eq!(
+            game_state.handle_action(PlayerAction::Ascend),
+            ActionResult::Done
+        );
+        assert_eq!(game_state.dungeon_level(), 1);
+        assert_eq!(game_state.player_coord(), left_from, "seed {}", rng_seed);
+        if game_state.is_player_on_stairs() {
+            assert_eq!(
+                game_state.handle_action(PlayerAction::LevelUpAndDescend(LevelUp::Health)),
+                ActionResult::Done
+            );
+            assert_eq!(game_state.dungeon_level(), 2);
+            assert_eq!(game_state.level_name(), second_level_name);
+            assert_eq!(game_state.player_coord(), arrived_at);
+        } else {
+            // The bot fell through a trapdoor, which the player now knows about
+            assert!(
+                game_state.is_known_hazard_at(left_from),
+                "seed {}",
+                rng_seed
+            );
+        }
+    }
+}
+
+#[test]
+fn climbing_up_is_only_possible_on_up_stairs() {
+    let (mut game_state, _) = game_on_second_level(0);
+    game_state.handle_action(PlayerAction::Ascend);
+    assert_eq!(
+        game_state.handle_action(PlayerAction::Ascend),
+        ActionResult::Failed
+    );
+    assert_eq!(game_state.dungeon_level(), 1);
+}
+
+#[test]
+fn levels_left_behind_are_saved() {
+    let (game_state, left_from) = game_on_second_level(3);
+    let mut loaded = save::from_json(save::to_json(&game_state)).unwrap();
+    assert_eq!(
+        loaded.handle_action(PlayerAction::Ascend),
+        ActionResult::Done
+    );
+    assert_eq!(loaded.player_coord(), left_from);
+}
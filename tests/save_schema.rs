@@ -0,0 +1,241 @@
+// Saved games are plain serialized `GameState`s, so renaming a field or changing its type silently
+// stops existing saves from loading. These tests record the shape of a save file in a snapshot, so
+// that such changes fail loudly and come with a bump of `save::SAVE_VERSION` and a migration.
+//
+// After an intentional change to the save format, regenerate the snapshot by running the tests
+// with UPDATE_SNAPSHOTS=1 set.
+use chargrid_roguelike_tutorial_2020::{
+    save, GameState, ItemType, Npc, NpcType, PlayerAction, VisibilityAlgorithm,
+};
+use coord_2d::Size;
+use direction::CardinalDirection;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+const SNAPSHOT_PATH: &str = "tests/snapshots/save_schema.txt";
+
+// An arena containing one of every npc and item, with the player having fought and picked things
+// up for a while, so that as many components as possible are populated
+fn populated_game_state() -> GameState {
+    let mut game_state =
+        GameState::new_arena(Size::new(40, 26), 0, VisibilityAlgorithm::Shadowcast);
+    for &npc_type in NpcType::ALL {
+        for &elite in &[false, true] {
+            game_state.arena_spawn_npc(Npc { npc_type, elite });
+        }
+    }
+    for &item_type in ItemType::ALL {
+        game_state.arena_spawn_item(item_type);
+    }
+    let directions = [
+        CardinalDirection::North,
+        CardinalDirection::East,
+        CardinalDirection::South,
+        CardinalDirection::West,
+    ];
+    for &direction in directions.iter().cycle().take(40) {
+        if !game_state.is_player_alive() {
+            break;
+        }
+        game_state.handle_action(PlayerAction::Move(direction));
+        game_state.handle_action(PlayerAction::GetItem);
+        game_state.resolve_animations();
+        game_state.update_visibility(VisibilityAlgorithm::Shadowcast);
+    }
+    game_state
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// Every path through the json along with the type found there. Array elements all share the path
+// of their array, so the schema doesn't depend on how many entities there are.
+fn collect_schema(path: &str, value: &Value, schema: &mut BTreeSet<String>) {
+    schema.insert(format!("{}: {}", path, json_type(value)));
+    match value {
+        Value::Array(elements) => {
+            for element in elements {
+                collect_schema(&format!("{}[]", path), element, schema);
+            }
+        }
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                collect_schema(&format!("{}.{}", path, name), field, schema);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn schema_of(value: &Value) -> String {
+    let mut schema = BTreeSet::new();
+    collect_schema("", value, &mut schema);
+    schema.into_iter().map(|line| line + "\n").collect()
+}
+
+#[test]
+fn save_schema_matches_snapshot() {
+    let schema = schema_of(&save::to_json(&populated_game_state()));
+    let snapshot_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT_PATH);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(snapshot_path.parent().unwrap()).expect("failed to create snapshot dir");
+        fs::write(&snapshot_path, &schema).expect("failed to write snapshot");
+        return;
+    }
+    // A missing snapshot is a failure rather than being written, so that a checkout without one
+    // can't pass without checking anything
+    let snapshot = fs::read_to_string(&snapshot_path).unwrap_or_else(|error| {
+        panic!(
+            "Failed to read {}: {}\nGenerate it by running the tests with UPDATE_SNAPSHOTS=1.",
+            SNAPSHOT_PATH, error
+        )
+    });
+    if schema != snapshot {
+        let snapshot_lines = snapshot.lines().collect::<BTreeSet<_>>();
+        let schema_lines = schema.lines().collect::<BTreeSet<_>>();
+        let removed = snapshot_lines.difference(&schema_lines).collect::<Vec<_>>();
+        let added = schema_lines.difference(&snapshot_lines).collect::<Vec<_>>();
+        panic!(
+            "The save format has changed, which will break existing saves.\n\
+             Removed: {:#?}\nAdded: {:#?}\n\
+             Bump save::SAVE_VERSION and add a migration, then rerun with UPDATE_SNAPSHOTS=1.",
+            removed, added
+        );
+    }
+}
+
+#[test]
+fn save_round_trips() {
+    let game_state = populated_game_state();
+    let loaded = save::from_json(save::to_json(&game_state)).unwrap();
+    assert_eq!(game_state.world_hash(), loaded.world_hash());
+}
+
+#[test]
+fn binary_save_round_trips() {
+    let game_state = populated_game_state();
+    let loaded = save::from_binary(save::to_binary(&game_state)).unwrap();
+    assert_eq!(game_state.world_hash(), loaded.world_hash());
+}
+
+#[test]
+fn world_dump_is_unchanged_by_saving_and_loading() {
+    let game_state = populated_game_state();
+    let dump = game_state.debug_dump();
+    let map_rows = dump.lines().take(26).collect::<Vec<_>>();
+    assert!(map_rows.iter().all(|row| row.chars().count() == 40));
+    if game_state.is_player_alive() {
+        assert_eq!(dump.matches('@').count(), 1);
+    }
+    let loaded = save::from_json(save::to_json(&game_state)).unwrap();
+    assert_eq!(dump, loaded.debug_dump());
+}
+
+// The game state as it was serialized in version 3 saves, from before slowing traps existed
+fn version_3_game_state(game_state: &GameState) -> Value {
+    let mut value = serde_json::to_value(game_state).unwrap();
+    value["world"]["components"]
+        .as_object_mut()
+        .unwrap()
+        .remove("slowing_trap");
+    value
+}
+
+// The game state as it was serialized in version 2 saves, from before up stairs existed
+fn version_2_game_state(game_state: &GameState) -> Value {
+    let mut value = version_3_game_state(game_state);
+    value["world"]["components"]
+        .as_object_mut()
+        .unwrap()
+        .remove("up_stairs");
+    value
+}
+
+// The game state as it was serialized in version 1 saves, where the turn on which each message
+// was logged was kept in a list alongside the message log
+fn version_1_game_state(game_state: &GameState) -> Value {
+    let mut value = version_2_game_state(game_state);
+    let entries = value["message_log"].as_array().unwrap().clone();
+    value["message_log"] = entries
+        .iter()
+        .map(|entry| entry["message"].clone())
+        .collect();
+    value["message_turns"] = entries.iter().map(|entry| entry["turn"].clone()).collect();
+    value
+}
+
+#[test]
+fn unversioned_saves_are_migrated() {
+    let game_state = populated_game_state();
+    let unversioned = version_1_game_state(&game_state);
+    let loaded = save::from_json(unversioned).unwrap();
+    assert_eq!(game_state.world_hash(), loaded.world_hash());
+}
+
+#[test]
+fn version_1_saves_keep_the_turn_of_each_message() {
+    let game_state = populated_game_state();
+    let save = serde_json::json!({
+        "version": 1,
+        "game_state": version_1_game_state(&game_state),
+    });
+    let loaded = save::from_json(save).unwrap();
+    assert!(!game_state.message_log().is_empty());
+    assert_eq!(
+        loaded
+            .message_log()
+            .iter()
+            .map(|entry| entry.turn)
+            .collect::<Vec<_>>(),
+        game_state
+            .message_log()
+            .iter()
+            .map(|entry| entry.turn)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn version_2_saves_without_up_stairs_are_migrated() {
+    let game_state = populated_game_state();
+    let save = serde_json::json!({
+        "version": 2,
+        "game_state": version_2_game_state(&game_state),
+    });
+    let loaded = save::from_json(save).unwrap();
+    assert_eq!(game_state.world_hash(), loaded.world_hash());
+}
+
+#[test]
+fn version_3_saves_without_slowing_traps_are_migrated() {
+    let game_state = populated_game_state();
+    let save = serde_json::json!({
+        "version": 3,
+        "game_state": version_3_game_state(&game_state),
+    });
+    let loaded = save::from_json(save).unwrap();
+    assert_eq!(game_state.world_hash(), loaded.world_hash());
+}
+
+#[test]
+fn saves_from_newer_versions_are_rejected() {
+    let mut save = save::to_json(&populated_game_state());
+    save["version"] = (save::SAVE_VERSION + 1).into();
+    match save::from_json(save) {
+        Err(save::LoadError::TooNew { version }) => assert_eq!(version, save::SAVE_VERSION + 1),
+        other => panic!(
+            "expected the save to be rejected, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+}
@@ -0,0 +1,115 @@
+// The score is uploaded for daily challenges, so changes to how it's calculated should be
+// deliberate. These tests pin down each term of the formula.
+use chargrid_roguelike_tutorial_2020::{HighScores, ScoreInputs, NUM_HIGH_SCORES};
+
+// Enough turns that there's no speed bonus left at any of the depths used here
+const SLOW: u64 = 10000;
+
+fn inputs(depth: u32, kills: u32) -> ScoreInputs {
+    ScoreInputs {
+        depth,
+        kills,
+        turns: SLOW,
+        endless: false,
+        challenges: 0,
+    }
+}
+
+#[test]
+fn each_level_is_worth_a_thousand_points() {
+    assert_eq!(inputs(1, 0).score(), 1000);
+    assert_eq!(inputs(4, 0).score(), 4000);
+}
+
+#[test]
+fn each_kill_is_worth_fifty_points() {
+    assert_eq!(inputs(1, 3).score(), 1150);
+    assert_eq!(inputs(2, 10).score(), 2500);
+}
+
+#[test]
+fn each_level_adds_five_hundred_to_a_speed_bonus_which_loses_a_point_per_turn() {
+    let quick = ScoreInputs {
+        turns: 300,
+        ..inputs(2, 0)
+    };
+    let slower = ScoreInputs {
+        turns: 999,
+        ..inputs(2, 0)
+    };
+    let too_slow = ScoreInputs {
+        turns: 1001,
+        ..inputs(2, 0)
+    };
+    assert_eq!(quick.score(), 2700);
+    assert_eq!(slower.score(), 2001);
+    assert_eq!(too_slow.score(), 2000);
+}
+
+#[test]
+fn endless_runs_triple_the_points_for_depth_only() {
+    let endless = ScoreInputs {
+        endless: true,
+        ..inputs(2, 4)
+    };
+    let endless_quick = ScoreInputs {
+        turns: 600,
+        ..endless
+    };
+    assert_eq!(endless.score(), 6200);
+    assert_eq!(endless_quick.score(), 6600);
+}
+
+#[test]
+fn each_challenge_adds_half_the_score() {
+    let one = ScoreInputs {
+        challenges: 1,
+        ..inputs(2, 4)
+    };
+    let three = ScoreInputs {
+        challenges: 3,
+        ..inputs(2, 4)
+    };
+    assert_eq!(one.score(), 3300);
+    assert_eq!(three.score(), 5500);
+}
+
+#[test]
+fn challenge_bonus_applies_after_the_endless_multiplier_and_speed_bonus() {
+    let score_inputs = ScoreInputs {
+        depth: 3,
+        kills: 2,
+        turns: 1000,
+        endless: true,
+        challenges: 2,
+    };
+    assert_eq!(score_inputs.score(), 19200);
+}
+
+#[test]
+fn high_scores_are_kept_best_first() {
+    let mut high_scores = HighScores::default();
+    assert_eq!(high_scores.insert(inputs(2, 0)), Some(0));
+    assert_eq!(high_scores.insert(inputs(4, 0)), Some(0));
+    assert_eq!(high_scores.insert(inputs(3, 0)), Some(1));
+    // Ties rank below the score which was already there
+    assert_eq!(high_scores.insert(inputs(3, 0)), Some(2));
+    let scores = high_scores
+        .entries()
+        .iter()
+        .map(|high_score| high_score.score)
+        .collect::<Vec<_>>();
+    assert_eq!(scores, vec![4000, 3000, 3000, 2000]);
+}
+
+#[test]
+fn high_scores_only_keep_the_best_few() {
+    let mut high_scores = HighScores::default();
+    for depth in 1..=NUM_HIGH_SCORES as u32 {
+        high_scores.insert(inputs(depth + 1, 0));
+    }
+    assert_eq!(high_scores.insert(inputs(1, 0)), None);
+    assert_eq!(high_scores.insert(inputs(10, 0)), Some(0));
+    assert_eq!(high_scores.entries().len(), NUM_HIGH_SCORES);
+    assert_eq!(high_scores.entries().last().unwrap().depth, 3);
+}